@@ -0,0 +1,634 @@
+mod model;
+mod output;
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use ailia::bench::{
+    bench_classifier, bench_detector, bench_network, bench_pose_estimator, sweep, BenchConfig,
+    BenchReport,
+};
+use ailia::environment::{get_environment_count, Environment};
+use ailia::export::{CsvRow, FrameRecord};
+use ailia::prelude::*;
+use ailia::render::{draw_classes, draw_objects, draw_poses};
+use ailia::sink::open_sink;
+use ailia::video::{FrameReaderBuilder, ImageView};
+use ailia::AILIA_ENVIRONMENT_VERSION;
+
+use anyhow::{bail, Result};
+use clap::{Args, Parser, Subcommand};
+use image::RgbaImage;
+use opencv::core::Mat;
+use serde::Serialize;
+
+use model::{parse_size, ClassifierArgs, DetectorArgs, ModelArgs, PoseArgs};
+use output::{OutputFormat, ResultWriter};
+
+#[derive(Parser)]
+#[command(name = "ailia-cli", about = "Run ailia models from the command line")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// 画像分類
+    Classify {
+        #[command(flatten)]
+        classifier: ClassifierArgs,
+        #[command(flatten)]
+        input: InputArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+        /// 出力する上位の数
+        #[arg(long, default_value_t = 5)]
+        top_k: u32,
+    },
+    /// 物体検出
+    Detect {
+        #[command(flatten)]
+        detector: DetectorArgs,
+        #[command(flatten)]
+        input: InputArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[arg(long, default_value_t = 0.4)]
+        threshold: f32,
+        #[arg(long, default_value_t = 0.45)]
+        iou: f32,
+    },
+    /// 姿勢推定
+    Pose {
+        #[command(flatten)]
+        pose: PoseArgs,
+        #[command(flatten)]
+        input: InputArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Networkを直接実行して出力Blobの形状と統計を表示する
+    Run {
+        #[command(flatten)]
+        model: ModelArgs,
+        /// 入力画像、RGBの0..1のCHWとして入力する、省略した場合は0で埋める
+        #[arg(long)]
+        image: Option<PathBuf>,
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// SDKのバージョン、利用可能な環境、モデルの入出力を表示する
+    Info {
+        /// zooのモデル名またはonnxファイルのパス
+        #[arg(long, short)]
+        model: Option<String>,
+        #[arg(long)]
+        prototxt: Option<PathBuf>,
+        #[arg(long)]
+        env_id: Option<i32>,
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+    /// 推論時間を計測する
+    Bench {
+        #[command(subcommand)]
+        target: BenchTarget,
+    },
+}
+
+#[derive(Subcommand)]
+enum BenchTarget {
+    Classify {
+        #[command(flatten)]
+        classifier: ClassifierArgs,
+        #[command(flatten)]
+        bench: BenchArgs,
+    },
+    Detect {
+        #[command(flatten)]
+        detector: DetectorArgs,
+        #[command(flatten)]
+        bench: BenchArgs,
+    },
+    Pose {
+        #[command(flatten)]
+        pose: PoseArgs,
+        #[command(flatten)]
+        bench: BenchArgs,
+    },
+    /// Networkを直接実行する、入力Blobの設定、推論、出力Blobの取得の内訳を計測する
+    Run {
+        #[command(flatten)]
+        model: ModelArgs,
+        #[command(flatten)]
+        bench: BenchArgs,
+    },
+}
+
+#[derive(Args)]
+struct InputArgs {
+    /// 画像、動画、画像ディレクトリ、GIF/APNG、RTSPなどのURL、カメラ番号
+    input: String,
+    /// nフレームごとに処理する
+    #[arg(long, default_value_t = 1)]
+    step: u32,
+    #[arg(long)]
+    max_frames: Option<u64>,
+    /// 画像ディレクトリのタイムスタンプと保存する動画のフレームレート
+    #[arg(long, default_value_t = 30.)]
+    fps: f64,
+}
+
+#[derive(Args)]
+struct OutputArgs {
+    #[arg(long, value_enum, default_value_t)]
+    format: OutputFormat,
+    /// 結果の出力先、省略した場合は標準出力
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+    /// 描画結果の保存先(動画ファイル、GIF、連番画像、ディレクトリ)
+    #[arg(long)]
+    save: Option<String>,
+    /// 1行に1ラベルのファイル
+    #[arg(long)]
+    labels: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct BenchArgs {
+    /// 入力画像、省略した場合は640x480の黒画像
+    #[arg(long)]
+    image: Option<PathBuf>,
+    #[arg(long, default_value_t = 10)]
+    warmup: u32,
+    #[arg(long, default_value_t = 100)]
+    iterations: u32,
+    /// 前処理としてリサイズするサイズ(WIDTHxHEIGHT)
+    #[arg(long, value_parser = parse_size)]
+    size: Option<(u32, u32)>,
+    /// 計測する環境IDの一覧、省略した場合は--env-idのみ
+    #[arg(long, value_delimiter = ',')]
+    env_ids: Option<Vec<i32>>,
+    /// 計測するスレッド数の一覧、省略した場合は--num-threadsのみ
+    #[arg(long, value_delimiter = ',')]
+    threads: Option<Vec<i32>>,
+    #[arg(long, value_enum, default_value_t)]
+    format: OutputFormat,
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
+fn load_labels(path: Option<&Path>) -> Result<Option<Vec<String>>> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    let text = std::fs::read_to_string(path)?;
+    Ok(Some(
+        text.lines().map(|line| line.trim().to_string()).collect(),
+    ))
+}
+
+/// 入力の各フレームで推論を行い、結果の書き出しと描画結果の保存を行う
+fn process<T, F, D>(input: &InputArgs, output: &OutputArgs, mut infer: F, draw: D) -> Result<()>
+where
+    T: Serialize + CsvRow,
+    F: FnMut(&ImageView) -> Result<Vec<T>>,
+    D: Fn(&mut Mat, &[T], Option<&[&str]>) -> opencv::Result<()>,
+{
+    let labels = load_labels(output.labels.as_deref())?;
+    let labels: Option<Vec<&str>> = labels
+        .as_ref()
+        .map(|labels| labels.iter().map(String::as_str).collect());
+    let labels = labels.as_deref();
+
+    let mut reader = FrameReaderBuilder::default()
+        .input(input.input.clone())
+        .step(input.step)
+        .fps(input.fps);
+    if let Some(max_frames) = input.max_frames {
+        reader = reader.max_frames(max_frames);
+    }
+    let reader = reader.build()?;
+    let mut writer = ResultWriter::new(output.format, output.output.as_deref())?;
+    let mut sink = match &output.save {
+        Some(save) => Some(open_sink(save, input.fps)?),
+        None => None,
+    };
+
+    for frame in reader {
+        let frame = frame?;
+        let results = infer(&frame.image)?;
+        if let Some(sink) = sink.as_mut() {
+            let mut mat = frame.image.to_mat()?;
+            draw(&mut mat, &results, labels)?;
+            sink.write_frame(&ImageView::from_rgba_mat(&mat)?)?;
+        }
+        writer.write(&FrameRecord::from_frame(&frame, results), labels)?;
+    }
+
+    if let Some(mut sink) = sink {
+        sink.finish()?;
+    }
+    writer.finish()
+}
+
+fn classify(classifier: &Classifier, image: &ImageView, top_k: u32) -> Result<Vec<Class>> {
+    classifier.compute(
+        image.as_ptr(),
+        image.stride(),
+        image.width,
+        image.height,
+        image.format(),
+        top_k,
+    )?;
+    let count = classifier.get_class_count()?;
+    Ok((0..count)
+        .map(|idx| classifier.get_class(idx))
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+#[derive(Serialize)]
+struct BlobInfo {
+    index: u32,
+    name: String,
+    shape: Vec<u32>,
+}
+
+#[derive(Serialize)]
+struct BlobStats {
+    #[serde(flatten)]
+    blob: BlobInfo,
+    min: f32,
+    max: f32,
+    mean: f32,
+}
+
+fn blob_info(net: &Network, index: u32) -> Result<BlobInfo> {
+    let shape = net.get_blob_shape(index)?;
+    let dims = [shape.w, shape.z, shape.y, shape.x];
+    Ok(BlobInfo {
+        index,
+        name: net.get_blob_name(index)?,
+        shape: dims[4 - (shape.dim as usize).min(4)..].to_vec(),
+    })
+}
+
+/// Networkを作成し、入力画像をRGBの0..1のCHWに変換した入力を作る
+fn prepare_network(model: &ModelArgs, image: Option<&Path>) -> Result<(Network, Vec<f32>)> {
+    let net = model.network()?;
+    if let Some((width, height)) = model.input_size {
+        net.set_input_shape(Shape {
+            x: width,
+            y: height,
+            z: 3,
+            w: 1,
+            dim: 4,
+        })?;
+    }
+    let shape = net.get_input_shape()?;
+    let mut input = vec![0f32; shape.num_elms() as usize];
+    if let Some(path) = image {
+        if shape.z != 3 {
+            bail!("image input requires 3 channels, got {}", shape.z);
+        }
+        let img = image::open(path)?
+            .resize_exact(shape.x, shape.y, image::imageops::FilterType::Triangle)
+            .into_rgb8();
+        let plane = (shape.x * shape.y) as usize;
+        for (idx, pixel) in img.pixels().enumerate() {
+            for c in 0..3 {
+                input[c * plane + idx] = pixel[c] as f32 / 255.;
+            }
+        }
+    }
+    Ok((net, input))
+}
+
+fn run(model: &ModelArgs, image: Option<&Path>) -> Result<Vec<BlobStats>> {
+    let (net, input) = prepare_network(model, image)?;
+    let input_index = net.get_input_indexs()?[0];
+    net.set_input_data_blob(input.as_ptr(), input.len() as u32, input_index)?;
+    net.update()?;
+
+    let mut stats = Vec::new();
+    for index in net.get_output_indexs()? {
+        let data: Vec<f32> = net.get_output_blob_by_index(index)?;
+        let min = data.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = data.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let mean = data.iter().sum::<f32>() / data.len().max(1) as f32;
+        stats.push(BlobStats {
+            blob: blob_info(&net, index)?,
+            min,
+            max,
+            mean,
+        });
+    }
+    Ok(stats)
+}
+
+#[derive(Serialize)]
+struct EnvironmentInfo {
+    id: i32,
+    #[serde(rename = "type")]
+    type_: i32,
+    name: String,
+    backend: i32,
+    props: i32,
+}
+
+impl From<&Environment> for EnvironmentInfo {
+    fn from(env: &Environment) -> Self {
+        Self {
+            id: env.id(),
+            type_: env.type_(),
+            name: env.name(),
+            backend: env.backend(),
+            props: env.props(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ModelInfo {
+    onnx: PathBuf,
+    prototxt: Option<PathBuf>,
+    environment: EnvironmentInfo,
+    inputs: Vec<BlobInfo>,
+    outputs: Vec<BlobInfo>,
+    summary: String,
+}
+
+#[derive(Serialize)]
+struct Info {
+    version: String,
+    environments: Vec<EnvironmentInfo>,
+    model: Option<ModelInfo>,
+}
+
+fn info(model: Option<ModelArgs>) -> Result<Info> {
+    let mut environments = Vec::new();
+    for idx in 0..get_environment_count()? {
+        let env = Environment::get_environment(idx, AILIA_ENVIRONMENT_VERSION)?;
+        environments.push(EnvironmentInfo::from(&env));
+    }
+    let model = match model {
+        Some(model) => {
+            let resolved = model.resolve()?;
+            let net = model.network()?;
+            let env = Environment::get_selected_environment(&net, AILIA_ENVIRONMENT_VERSION)?;
+            Some(ModelInfo {
+                onnx: resolved.onnx,
+                prototxt: resolved.prototxt,
+                environment: EnvironmentInfo::from(&env),
+                inputs: net
+                    .get_input_indexs()?
+                    .into_iter()
+                    .map(|idx| blob_info(&net, idx))
+                    .collect::<Result<_>>()?,
+                outputs: net
+                    .get_output_indexs()?
+                    .into_iter()
+                    .map(|idx| blob_info(&net, idx))
+                    .collect::<Result<_>>()?,
+                summary: net.summary()?,
+            })
+        }
+        None => None,
+    };
+    Ok(Info {
+        version: ailia::get_version(),
+        environments,
+        model,
+    })
+}
+
+fn bench_image(args: &BenchArgs) -> Result<ImageView> {
+    Ok(match &args.image {
+        Some(path) => ImageView::from(image::open(path)?.into_rgba8()),
+        None => ImageView::from(RgbaImage::new(640, 480)),
+    })
+}
+
+/// 環境IDとスレッド数の組み合わせごとにモデルを作成して計測する
+fn bench_sweep<F>(model: &ModelArgs, args: &BenchArgs, mut bench: F) -> Result<()>
+where
+    F: FnMut(ModelArgs, &BenchConfig) -> Result<BenchReport>,
+{
+    let config = BenchConfig::default()
+        .warmup(args.warmup)
+        .iterations(args.iterations);
+    let env_ids = args.env_ids.clone().unwrap_or_else(|| vec![model.env_id()]);
+    let thread_counts = args
+        .threads
+        .clone()
+        .unwrap_or_else(|| vec![model.num_threads()]);
+    let entries = sweep(&env_ids, &thread_counts, |env_id, num_threads| {
+        let mut model = model.clone();
+        model.env_id = Some(env_id);
+        model.num_threads = Some(num_threads);
+        bench(model, &config)
+    });
+
+    let mut writer = ResultWriter::new(args.format, args.output.as_deref())?;
+    match &mut writer {
+        ResultWriter::Text(out) => {
+            writeln!(
+                out,
+                "{:>6} {:<16} {:>7} {:>9} {:>9} {:>9} {:>9} {:>8} {:>9} {:>9} {:>9} {:>10}",
+                "env",
+                "name",
+                "threads",
+                "mean",
+                "p50",
+                "p90",
+                "p99",
+                "fps",
+                "pre",
+                "infer",
+                "post",
+                "peak_rss"
+            )?;
+            for entry in &entries {
+                let name = entry.env_name.as_deref().unwrap_or("");
+                match (&entry.report, &entry.error) {
+                    (Some(report), _) => writeln!(
+                        out,
+                        "{:>6} {:<16} {:>7} {:>9.2} {:>9.2} {:>9.2} {:>9.2} {:>8.1} {:>9.2} {:>9.2} {:>9.2} {:>10}",
+                        entry.env_id,
+                        name,
+                        entry.num_threads,
+                        report.total.mean_ms,
+                        report.total.p50_ms,
+                        report.total.p90_ms,
+                        report.total.p99_ms,
+                        report.fps,
+                        report.preprocess.mean_ms,
+                        report.inference.mean_ms,
+                        report.postprocess.mean_ms,
+                        report
+                            .peak_rss_bytes
+                            .map(|bytes| format!("{}MB", bytes / 1024 / 1024))
+                            .unwrap_or_default()
+                    )?,
+                    (None, error) => writeln!(
+                        out,
+                        "{:>6} {:<16} {:>7} error: {}",
+                        entry.env_id,
+                        name,
+                        entry.num_threads,
+                        error.as_deref().unwrap_or_default()
+                    )?,
+                }
+            }
+        }
+        _ => writer.write_value(&entries)?,
+    }
+    writer.finish()
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    match cli.command {
+        Command::Classify {
+            classifier,
+            input,
+            output,
+            top_k,
+        } => {
+            let classifier = classifier.build()?;
+            process(
+                &input,
+                &output,
+                |image| classify(&classifier, image, top_k),
+                draw_classes,
+            )
+        }
+        Command::Detect {
+            detector,
+            input,
+            output,
+            threshold,
+            iou,
+        } => {
+            let detector = detector.build()?;
+            process(
+                &input,
+                &output,
+                |image| {
+                    Ok(detector.predict(
+                        image.as_ptr(),
+                        image.stride(),
+                        image.width,
+                        image.height,
+                        image.format(),
+                        threshold,
+                        iou,
+                    )?)
+                },
+                draw_objects,
+            )
+        }
+        Command::Pose {
+            pose,
+            input,
+            output,
+        } => {
+            let estimator = pose.build()?;
+            process(
+                &input,
+                &output,
+                |image| {
+                    Ok(estimator.predict(
+                        image.as_ptr(),
+                        image.stride(),
+                        image.width,
+                        image.height,
+                        image.format(),
+                    )?)
+                },
+                |mat, poses, _| draw_poses(mat, poses),
+            )
+        }
+        Command::Run {
+            model,
+            image,
+            format,
+            output,
+        } => {
+            let stats = run(&model, image.as_deref())?;
+            let mut writer = ResultWriter::new(format, output.as_deref())?;
+            writer.write_value(&stats)?;
+            writer.finish()
+        }
+        Command::Info {
+            model,
+            prototxt,
+            env_id,
+            format,
+        } => {
+            let model = model.map(|model| ModelArgs {
+                model,
+                prototxt,
+                env_id,
+                num_threads: None,
+                input_size: None,
+            });
+            let mut writer = ResultWriter::new(format, None)?;
+            writer.write_value(&info(model)?)?;
+            writer.finish()
+        }
+        Command::Bench { target } => match target {
+            BenchTarget::Classify {
+                classifier,
+                bench: args,
+            } => {
+                let image = bench_image(&args)?;
+                bench_sweep(&classifier.model.clone(), &args, |model, config| {
+                    let classifier = ClassifierArgs {
+                        model,
+                        ..classifier.clone()
+                    }
+                    .build()?;
+                    Ok(bench_classifier(&classifier, &image, args.size, 5, config)?)
+                })
+            }
+            BenchTarget::Detect {
+                detector,
+                bench: args,
+            } => {
+                let image = bench_image(&args)?;
+                bench_sweep(&detector.model.clone(), &args, |model, config| {
+                    let detector = DetectorArgs {
+                        model,
+                        ..detector.clone()
+                    }
+                    .build()?;
+                    Ok(bench_detector(
+                        &detector, &image, args.size, 0.4, 0.45, config,
+                    )?)
+                })
+            }
+            BenchTarget::Pose { pose, bench: args } => {
+                let image = bench_image(&args)?;
+                bench_sweep(&pose.model.clone(), &args, |model, config| {
+                    let estimator = PoseArgs {
+                        model,
+                        ..pose.clone()
+                    }
+                    .build()?;
+                    Ok(bench_pose_estimator(&estimator, &image, args.size, config)?)
+                })
+            }
+            BenchTarget::Run { model, bench: args } => {
+                bench_sweep(&model, &args, |model, config| {
+                    let (net, input) = prepare_network(&model, args.image.as_deref())?;
+                    Ok(bench_network(&net, &input, config)?)
+                })
+            }
+        },
+    }
+}
@@ -0,0 +1,316 @@
+use std::path::{Path, PathBuf};
+
+use ailia::prelude::*;
+use ailia::zoo::find_model;
+use ailia::{AILIA_ENVIRONMENT_ID_AUTO, AILIA_MULTITHREAD_AUTO};
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, ValueEnum};
+
+/// モデルの指定、zooのモデル名またはonnxファイルのパス
+#[derive(Args, Clone, Debug)]
+pub struct ModelArgs {
+    /// zooのモデル名またはonnxファイルのパス
+    #[arg(long, short)]
+    pub model: String,
+    /// 省略した場合はonnxの隣のprototxtを探す
+    #[arg(long)]
+    pub prototxt: Option<PathBuf>,
+    #[arg(long)]
+    pub env_id: Option<i32>,
+    #[arg(long)]
+    pub num_threads: Option<i32>,
+    /// 入力サイズ(WIDTHxHEIGHT)、zooのモデルでは省略できる
+    #[arg(long, value_parser = parse_size)]
+    pub input_size: Option<(u32, u32)>,
+}
+
+/// ダウンロード済みのモデルファイルとzooに登録されたタスク設定
+pub struct ResolvedModel {
+    pub onnx: PathBuf,
+    pub prototxt: Option<PathBuf>,
+    pub task: Option<TaskConfig>,
+}
+
+pub fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    let (width, height) = s
+        .split_once(['x', 'X'])
+        .ok_or_else(|| format!("expected WIDTHxHEIGHT: {}", s))?;
+    Ok((
+        width.parse().map_err(|_| format!("invalid width: {}", s))?,
+        height
+            .parse()
+            .map_err(|_| format!("invalid height: {}", s))?,
+    ))
+}
+
+impl ModelArgs {
+    pub fn resolve(&self) -> Result<ResolvedModel> {
+        if Path::new(&self.model).is_file() {
+            return Ok(ResolvedModel {
+                onnx: PathBuf::from(&self.model),
+                prototxt: self.prototxt.clone(),
+                task: None,
+            });
+        }
+        if find_model(&self.model).is_none() {
+            bail!("{} is neither a file nor a zoo model", self.model);
+        }
+        let (desc, files) = Downloader::default().fetch_by_name(&self.model)?;
+        Ok(ResolvedModel {
+            onnx: files.onnx,
+            prototxt: self.prototxt.clone().or(files.prototxt),
+            task: Some(desc.task),
+        })
+    }
+
+    pub fn env_id(&self) -> i32 {
+        self.env_id.unwrap_or(AILIA_ENVIRONMENT_ID_AUTO)
+    }
+
+    pub fn num_threads(&self) -> i32 {
+        self.num_threads
+            .unwrap_or_else(|| AILIA_MULTITHREAD_AUTO.try_into().unwrap())
+    }
+
+    pub fn network(&self) -> Result<Network> {
+        let model = self.resolve()?;
+        let net = Network::ailia_create(self.env_id(), self.num_threads())?;
+        net.open_model_files(model.prototxt, model.onnx)?;
+        Ok(net)
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum DetectorAlgorithmArg {
+    Yolov1,
+    Yolov2,
+    Yolov3,
+    Yolov4,
+    Yolox,
+    Ssd,
+}
+
+impl From<DetectorAlgorithmArg> for DetectorAlgorithm {
+    fn from(value: DetectorAlgorithmArg) -> Self {
+        match value {
+            DetectorAlgorithmArg::Yolov1 => DetectorAlgorithm::Yolov1,
+            DetectorAlgorithmArg::Yolov2 => DetectorAlgorithm::Yolov2,
+            DetectorAlgorithmArg::Yolov3 => DetectorAlgorithm::Yolov3,
+            DetectorAlgorithmArg::Yolov4 => DetectorAlgorithm::Yolov4,
+            DetectorAlgorithmArg::Yolox => DetectorAlgorithm::Yolox,
+            DetectorAlgorithmArg::Ssd => DetectorAlgorithm::Ssd,
+        }
+    }
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct DetectorArgs {
+    #[command(flatten)]
+    pub model: ModelArgs,
+    #[arg(long, value_enum)]
+    pub algorithm: Option<DetectorAlgorithmArg>,
+    #[arg(long)]
+    pub category_count: Option<u32>,
+}
+
+impl DetectorArgs {
+    pub fn build(&self) -> Result<Detector> {
+        let model = self.model.resolve()?;
+        let (mut algorithm, mut category_count, mut input_size) = (None, None, None);
+        if let Some(TaskConfig::Detector {
+            algorithm: a,
+            category_count: c,
+            input_width,
+            input_height,
+        }) = model.task
+        {
+            (algorithm, category_count, input_size) =
+                (Some(a), Some(c), Some((input_width, input_height)));
+        } else if model.task.is_some() {
+            bail!("{} is not a detector model", self.model.model);
+        }
+        let algorithm = self
+            .algorithm
+            .map(DetectorAlgorithm::from)
+            .or(algorithm)
+            .context("--algorithm is required")?;
+        let category_count = self
+            .category_count
+            .or(category_count)
+            .context("--category-count is required")?;
+
+        let mut builder = DetectorBuilder::default()
+            .onnx(model.onnx)
+            .env_id(self.model.env_id())
+            .num_threads(self.model.num_threads())
+            .algorithm(algorithm)
+            .category_count(category_count);
+        if let Some(prototxt) = model.prototxt {
+            builder = builder.prototxt(prototxt);
+        }
+        let detector = builder.build()?;
+        if let Some((width, height)) = self.model.input_size.or(input_size) {
+            detector.set_input_shape(width, height)?;
+        }
+        Ok(detector)
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ImageFormatArg {
+    Rgb,
+    Bgr,
+    Gray,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ChannelArg {
+    First,
+    Last,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum RangeArg {
+    Imagenet,
+    UnsignedInt8,
+    UnsignedFp32,
+    SignedInt8,
+    SignedFp32,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct ClassifierArgs {
+    #[command(flatten)]
+    pub model: ModelArgs,
+    #[arg(long, value_enum)]
+    pub image_format: Option<ImageFormatArg>,
+    #[arg(long, value_enum)]
+    pub channel: Option<ChannelArg>,
+    #[arg(long, value_enum)]
+    pub range: Option<RangeArg>,
+}
+
+impl ClassifierArgs {
+    pub fn build(&self) -> Result<Classifier> {
+        let model = self.model.resolve()?;
+        let (mut format, mut channel, mut range) = (None, None, None);
+        if let Some(TaskConfig::Classifier {
+            format: f,
+            channel: c,
+            range: r,
+            ..
+        }) = model.task
+        {
+            (format, channel, range) = (Some(f), Some(c), Some(r));
+        } else if model.task.is_some() {
+            bail!("{} is not a classifier model", self.model.model);
+        }
+        let format = self
+            .image_format
+            .map(|format| match format {
+                ImageFormatArg::Rgb => NetworkImageFormat::Rgb,
+                ImageFormatArg::Bgr => NetworkImageFormat::Bgr,
+                ImageFormatArg::Gray => NetworkImageFormat::Gray,
+            })
+            .or(format);
+        let channel = self
+            .channel
+            .map(|channel| match channel {
+                ChannelArg::First => ChannelOrder::First,
+                ChannelArg::Last => ChannelOrder::Last,
+            })
+            .or(channel);
+        let range = self
+            .range
+            .map(|range| match range {
+                RangeArg::Imagenet => ImageRange::ImageNet,
+                RangeArg::UnsignedInt8 => ImageRange::UnsignedInt8,
+                RangeArg::UnsignedFp32 => ImageRange::UnsignedFp32,
+                RangeArg::SignedInt8 => ImageRange::SignedInt8,
+                RangeArg::SignedFp32 => ImageRange::SignedFp32,
+            })
+            .or(range);
+
+        let mut builder = ClassifierBuilder::default()
+            .onnx(model.onnx)
+            .env_id(self.model.env_id())
+            .num_threads(self.model.num_threads());
+        if let Some(prototxt) = model.prototxt {
+            builder = builder.prototxt(prototxt);
+        }
+        if let Some(format) = format {
+            builder = builder.format(format);
+        }
+        if let Some(channel) = channel {
+            builder = builder.channel(channel);
+        }
+        if let Some(range) = range {
+            builder = builder.range(range);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum PoseAlgorithmArg {
+    LwHumanPose,
+    OpenPose,
+    OpenPoseSingleScale,
+    AcculusPose,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct PoseArgs {
+    #[command(flatten)]
+    pub model: ModelArgs,
+    #[arg(long, value_enum)]
+    pub algorithm: Option<PoseAlgorithmArg>,
+}
+
+impl PoseArgs {
+    pub fn build(&self) -> Result<PoseEstimator<Pose>> {
+        let model = self.model.resolve()?;
+        let (mut algorithm, mut input_size) = (None, None);
+        if let Some(TaskConfig::PoseEstimator {
+            algorithm: a,
+            input_width,
+            input_height,
+        }) = model.task
+        {
+            (algorithm, input_size) = (Some(a), Some((input_width, input_height)));
+        } else if model.task.is_some() {
+            bail!("{} is not a pose estimator model", self.model.model);
+        }
+        let algorithm = self
+            .algorithm
+            .map(|algorithm| match algorithm {
+                PoseAlgorithmArg::LwHumanPose => PoseAlgorithm::LwHumanPose,
+                PoseAlgorithmArg::OpenPose => PoseAlgorithm::OpenPose,
+                PoseAlgorithmArg::OpenPoseSingleScale => PoseAlgorithm::OpenPoseSingleScale,
+                PoseAlgorithmArg::AcculusPose => PoseAlgorithm::AcculusPose,
+            })
+            .or(algorithm)
+            .context("--algorithm is required")?;
+
+        let mut builder = PoseEstimatorBuilder::default()
+            .onnx(model.onnx)
+            .env_id(self.model.env_id())
+            .num_threads(self.model.num_threads())
+            .algorithm(algorithm);
+        if let Some(prototxt) = model.prototxt {
+            builder = builder.prototxt(prototxt);
+        }
+        let estimator = builder.build()?;
+        if let Some((width, height)) = self.model.input_size.or(input_size) {
+            estimator.set_input_shape(Shape {
+                x: width,
+                y: height,
+                z: 3,
+                w: 1,
+                dim: 4,
+            })?;
+        }
+        Ok(estimator)
+    }
+}
@@ -0,0 +1,96 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use ailia::export::{CsvRow, CsvWriter, FrameRecord, JsonLinesWriter};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+    Jsonl,
+    Csv,
+}
+
+/// 1フレームごとの結果を指定の形式で書き出す
+pub enum ResultWriter {
+    Text(Box<dyn Write>),
+    Json(Box<dyn Write>, Vec<serde_json::Value>),
+    JsonLines(JsonLinesWriter<Box<dyn Write>>),
+    Csv(CsvWriter<Box<dyn Write>>),
+}
+
+impl ResultWriter {
+    /// pathがNoneの場合は標準出力に書き出す
+    pub fn new(format: OutputFormat, path: Option<&Path>) -> Result<Self> {
+        let writer: Box<dyn Write> = match path {
+            Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+            None => Box::new(io::stdout()),
+        };
+        Ok(match format {
+            OutputFormat::Text => Self::Text(writer),
+            OutputFormat::Json => Self::Json(writer, Vec::new()),
+            OutputFormat::Jsonl => Self::JsonLines(JsonLinesWriter::new(writer)),
+            OutputFormat::Csv => Self::Csv(CsvWriter::new(writer)),
+        })
+    }
+
+    pub fn write<T: Serialize + CsvRow>(
+        &mut self,
+        record: &FrameRecord<T>,
+        labels: Option<&[&str]>,
+    ) -> Result<()> {
+        match self {
+            Self::Text(writer) => {
+                writeln!(
+                    writer,
+                    "frame {} ({:.1} ms): {} results",
+                    record.frame,
+                    record.timestamp_ms,
+                    record.results.len()
+                )?;
+                for result in &record.results {
+                    writeln!(writer, "  {}", result.csv_fields(labels).join(" "))?;
+                }
+            }
+            Self::Json(_, records) => records.push(serde_json::to_value(record)?),
+            Self::JsonLines(writer) => writer.write(record)?,
+            Self::Csv(writer) => writer.write(record, labels)?,
+        }
+        Ok(())
+    }
+
+    /// 値をそのまま書き出す、textの場合はpretty printしたJSONになる
+    pub fn write_value<T: Serialize>(&mut self, value: &T) -> Result<()> {
+        match self {
+            Self::Text(writer) => {
+                serde_json::to_writer_pretty(&mut *writer, value)?;
+                writeln!(writer)?;
+            }
+            Self::Json(_, records) => records.push(serde_json::to_value(value)?),
+            Self::JsonLines(writer) => writer.write(value)?,
+            Self::Csv(_) => anyhow::bail!("csv output is not supported for this command"),
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        let mut writer = match self {
+            Self::Text(writer) => writer,
+            Self::Json(mut writer, records) => {
+                serde_json::to_writer_pretty(&mut writer, &records)?;
+                writeln!(writer)?;
+                writer
+            }
+            Self::JsonLines(writer) => writer.into_inner(),
+            Self::Csv(writer) => writer.into_inner(),
+        };
+        writer.flush()?;
+        Ok(())
+    }
+}
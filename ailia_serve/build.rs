@@ -0,0 +1,12 @@
+fn main() {
+    // gRPCを使わない場合はprotocも不要
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/grpc_service.proto");
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("protoc is not available");
+        std::env::set_var("PROTOC", protoc);
+        tonic_build::configure()
+            .compile(&["proto/grpc_service.proto"], &["proto"])
+            .expect("failed to compile grpc_service.proto");
+    }
+}
@@ -0,0 +1,251 @@
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::ServeError;
+
+/// サーバーの設定ファイル(TOML)
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub server: ServerConfig,
+    #[serde(default)]
+    pub models: Vec<ModelConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub bind: String,
+    /// KServe v2のgRPCを公開するアドレス、省略した場合はHTTP/JSONのみ
+    pub grpc_bind: Option<String>,
+    /// HTTPリクエストを処理するスレッド数
+    pub http_threads: usize,
+    /// リクエストボディの最大サイズ
+    pub max_body_bytes: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1:8080".to_string(),
+            grpc_bind: None,
+            http_threads: 4,
+            max_body_bytes: 16 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Task {
+    Detect,
+    Classify,
+    Pose,
+    /// KServe v2のテンソル推論のみに対応するモデル
+    Network,
+}
+
+impl Task {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Task::Detect => "detect",
+            Task::Classify => "classify",
+            Task::Pose => "pose",
+            Task::Network => "network",
+        }
+    }
+
+    pub fn from_verb(verb: &str) -> Option<Self> {
+        match verb {
+            "detect" => Some(Task::Detect),
+            "classify" => Some(Task::Classify),
+            "pose" => Some(Task::Pose),
+            _ => None,
+        }
+    }
+}
+
+/// 1モデル分の設定
+/// zooのモデルではalgorithmや入力サイズなどを省略できる
+#[derive(Clone, Debug, Deserialize)]
+pub struct ModelConfig {
+    pub name: String,
+    pub task: Task,
+    /// zooのモデル名またはonnxファイルのパス
+    pub model: String,
+    pub prototxt: Option<PathBuf>,
+    /// detectorは`yolov1`..`yolov4`,`yolox`,`ssd`、poseは`lw_human_pose`,`open_pose`など
+    pub algorithm: Option<String>,
+    pub category_count: Option<u32>,
+    pub input_width: Option<u32>,
+    pub input_height: Option<u32>,
+    /// classifierの入力形式、`rgb`,`bgr`,`gray`
+    pub format: Option<String>,
+    /// `first`,`last`
+    pub channel: Option<String>,
+    /// `imagenet`,`unsigned_int8`,`unsigned_fp32`,`signed_int8`,`signed_fp32`
+    pub range: Option<String>,
+    pub env_id: Option<i32>,
+    pub num_threads: Option<i32>,
+    /// モデルを保持するワーカースレッドの数
+    #[serde(default = "default_workers")]
+    pub workers: usize,
+    /// ワーカーが空くのを待てるリクエストの数、超えた場合は503を返す
+    #[serde(default = "default_queue_size")]
+    pub queue_size: usize,
+    #[serde(default = "default_threshold")]
+    pub threshold: f32,
+    #[serde(default = "default_iou")]
+    pub iou: f32,
+    #[serde(default = "default_top_k")]
+    pub top_k: u32,
+    pub labels: Option<Vec<String>>,
+    /// 1行に1ラベルのファイル
+    pub labels_file: Option<PathBuf>,
+}
+
+fn default_workers() -> usize {
+    1
+}
+
+fn default_queue_size() -> usize {
+    16
+}
+
+fn default_threshold() -> f32 {
+    0.4
+}
+
+fn default_iou() -> f32 {
+    0.45
+}
+
+fn default_top_k() -> u32 {
+    5
+}
+
+impl ModelConfig {
+    /// 設定ファイルの必須項目以外を既定値にした設定
+    pub fn new(name: &str, task: Task, model: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            task,
+            model: model.to_string(),
+            prototxt: None,
+            algorithm: None,
+            category_count: None,
+            input_width: None,
+            input_height: None,
+            format: None,
+            channel: None,
+            range: None,
+            env_id: None,
+            num_threads: None,
+            workers: default_workers(),
+            queue_size: default_queue_size(),
+            threshold: default_threshold(),
+            iou: default_iou(),
+            top_k: default_top_k(),
+            labels: None,
+            labels_file: None,
+        }
+    }
+
+    /// labelsまたはlabels_fileのラベル
+    pub fn load_labels(&self) -> Result<Option<Vec<String>>, ServeError> {
+        if let Some(labels) = &self.labels {
+            return Ok(Some(labels.clone()));
+        }
+        match &self.labels_file {
+            Some(path) => Ok(Some(
+                std::fs::read_to_string(path)?
+                    .lines()
+                    .map(|line| line.trim().to_string())
+                    .collect(),
+            )),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ServeError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ServeError> {
+        let config: Config = toml::from_str(text)?;
+        for (idx, model) in config.models.iter().enumerate() {
+            if config.models[..idx].iter().any(|m| m.name == model.name) {
+                return Err(ServeError::Config(format!(
+                    "duplicate model name: {}",
+                    model.name
+                )));
+            }
+            if model.workers == 0 {
+                return Err(ServeError::Config(format!(
+                    "workers of {} must be greater than 0",
+                    model.name
+                )));
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        let config = Config::parse(
+            r#"
+            [server]
+            bind = "0.0.0.0:9000"
+
+            [[models]]
+            name = "yolox"
+            task = "detect"
+            model = "yolox_s"
+            workers = 2
+            threshold = 0.3
+
+            [[models]]
+            name = "custom"
+            task = "classify"
+            model = "./resnet.onnx"
+            format = "bgr"
+            labels = ["cat", "dog"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.server.bind, "0.0.0.0:9000");
+        assert_eq!(config.server.http_threads, 4);
+        assert_eq!(config.models.len(), 2);
+        assert_eq!(config.models[0].task, Task::Detect);
+        assert_eq!(config.models[0].workers, 2);
+        assert_eq!(config.models[0].threshold, 0.3);
+        assert_eq!(config.models[0].iou, 0.45);
+        assert_eq!(
+            config.models[1].load_labels().unwrap().unwrap(),
+            vec!["cat", "dog"]
+        );
+
+        let duplicated = r#"
+            [[models]]
+            name = "a"
+            task = "pose"
+            model = "x"
+            [[models]]
+            name = "a"
+            task = "pose"
+            model = "y"
+        "#;
+        assert!(matches!(
+            Config::parse(duplicated),
+            Err(ServeError::Config(_))
+        ));
+    }
+}
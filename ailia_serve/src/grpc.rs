@@ -0,0 +1,289 @@
+//! KServe v2のgRPC、Triton/KServeのクライアントからそのまま呼び出せる
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use tokio::sync::oneshot;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status};
+
+use crate::kserve::{self, InferRequest};
+use crate::server::{metric_label, State};
+use crate::tensor::{Datatype, Tensor};
+use crate::ServeError;
+
+pub mod proto {
+    tonic::include_proto!("inference");
+}
+
+use proto::grpc_inference_service_server::{GrpcInferenceService, GrpcInferenceServiceServer};
+use proto::model_infer_request::InferInputTensor;
+use proto::model_infer_response::InferOutputTensor;
+use proto::model_metadata_response::TensorMetadata;
+use proto::*;
+
+impl From<ServeError> for Status {
+    fn from(err: ServeError) -> Self {
+        let message = err.to_string();
+        match err.status() {
+            400 => Status::invalid_argument(message),
+            404 => Status::not_found(message),
+            413 => Status::resource_exhausted(message),
+            503 => Status::unavailable(message),
+            _ => Status::internal(message),
+        }
+    }
+}
+
+/// 型付きのcontentsからバイト列に変換する、FP16とBF16はraw_input_contentsのみ対応
+fn contents_to_bytes(input: &InferInputTensor, datatype: Datatype) -> Result<Vec<u8>, ServeError> {
+    let contents = input.contents.clone().unwrap_or_default();
+    let mut bytes = Vec::with_capacity(datatype.size());
+    macro_rules! encode {
+        ($values:expr, $ty:ty) => {
+            for value in $values {
+                let value = <$ty>::try_from(value).map_err(|_| {
+                    ServeError::BadRequest(format!("{}: {} is out of range", input.name, value))
+                })?;
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        };
+    }
+    match datatype {
+        Datatype::Bool => bytes.extend(contents.bool_contents.iter().map(|&b| b as u8)),
+        Datatype::Uint8 => encode!(contents.uint_contents, u8),
+        Datatype::Uint16 => encode!(contents.uint_contents, u16),
+        Datatype::Uint32 => encode!(contents.uint_contents, u32),
+        Datatype::Uint64 => encode!(contents.uint64_contents, u64),
+        Datatype::Int8 => encode!(contents.int_contents, i8),
+        Datatype::Int16 => encode!(contents.int_contents, i16),
+        Datatype::Int32 => encode!(contents.int_contents, i32),
+        Datatype::Int64 => encode!(contents.int64_contents, i64),
+        Datatype::Fp32 => encode!(contents.fp32_contents, f32),
+        Datatype::Fp64 => encode!(contents.fp64_contents, f64),
+        Datatype::Fp16 | Datatype::Bf16 => {
+            return Err(ServeError::BadRequest(format!(
+                "{}: {} requires raw_input_contents",
+                input.name,
+                datatype.as_str()
+            )))
+        }
+    }
+    Ok(bytes)
+}
+
+fn to_infer_request(request: ModelInferRequest) -> Result<InferRequest, ServeError> {
+    // raw_input_contentsを使う場合は全ての入力で使う
+    let raw = !request.raw_input_contents.is_empty();
+    if raw && request.raw_input_contents.len() != request.inputs.len() {
+        return Err(ServeError::BadRequest(
+            "raw_input_contents must be given for every input".to_string(),
+        ));
+    }
+    let mut raw_contents = request.raw_input_contents.into_iter();
+    let inputs = request
+        .inputs
+        .into_iter()
+        .map(|input| {
+            let datatype = Datatype::parse(&input.datatype).ok_or_else(|| {
+                ServeError::BadRequest(format!("unknown datatype: {}", input.datatype))
+            })?;
+            let data = match raw_contents.next() {
+                Some(data) => data,
+                None => contents_to_bytes(&input, datatype)?,
+            };
+            let tensor = Tensor {
+                name: input.name,
+                datatype,
+                shape: input.shape,
+                data,
+            };
+            tensor.validate()?;
+            Ok(tensor)
+        })
+        .collect::<Result<_, ServeError>>()?;
+    Ok(InferRequest {
+        id: request.id,
+        inputs,
+        outputs: request
+            .outputs
+            .into_iter()
+            .map(|output| output.name)
+            .collect(),
+    })
+}
+
+struct InferenceService {
+    state: Arc<State>,
+}
+
+#[tonic::async_trait]
+impl GrpcInferenceService for InferenceService {
+    async fn server_live(
+        &self,
+        _request: Request<ServerLiveRequest>,
+    ) -> Result<Response<ServerLiveResponse>, Status> {
+        Ok(Response::new(ServerLiveResponse { live: true }))
+    }
+
+    async fn server_ready(
+        &self,
+        _request: Request<ServerReadyRequest>,
+    ) -> Result<Response<ServerReadyResponse>, Status> {
+        Ok(Response::new(ServerReadyResponse {
+            ready: kserve::server_ready(&self.state),
+        }))
+    }
+
+    async fn model_ready(
+        &self,
+        request: Request<ModelReadyRequest>,
+    ) -> Result<Response<ModelReadyResponse>, Status> {
+        let request = request.into_inner();
+        Ok(Response::new(ModelReadyResponse {
+            ready: kserve::model_ready(&self.state, &request.name, &request.version)?,
+        }))
+    }
+
+    async fn server_metadata(
+        &self,
+        _request: Request<ServerMetadataRequest>,
+    ) -> Result<Response<ServerMetadataResponse>, Status> {
+        let metadata = kserve::server_metadata();
+        Ok(Response::new(ServerMetadataResponse {
+            name: metadata.name,
+            version: metadata.version,
+            extensions: metadata.extensions,
+        }))
+    }
+
+    async fn model_metadata(
+        &self,
+        request: Request<ModelMetadataRequest>,
+    ) -> Result<Response<ModelMetadataResponse>, Status> {
+        let request = request.into_inner();
+        let metadata = kserve::model_metadata(&self.state, &request.name, &request.version)?;
+        let convert = |tensors: Vec<crate::tensor::TensorMetadata>| {
+            tensors
+                .into_iter()
+                .map(|tensor| TensorMetadata {
+                    name: tensor.name,
+                    datatype: tensor.datatype.as_str().to_string(),
+                    shape: tensor.shape,
+                })
+                .collect()
+        };
+        Ok(Response::new(ModelMetadataResponse {
+            name: metadata.name,
+            versions: metadata.versions,
+            platform: metadata.platform,
+            inputs: convert(metadata.inputs),
+            outputs: convert(metadata.outputs),
+        }))
+    }
+
+    async fn model_infer(
+        &self,
+        request: Request<ModelInferRequest>,
+    ) -> Result<Response<ModelInferResponse>, Status> {
+        let request = request.into_inner();
+        let state = self.state.clone();
+        // ワーカーの結果を待つ間ランタイムのスレッドを塞がないようにする
+        let response = tokio::task::spawn_blocking(move || {
+            let start = Instant::now();
+            let name = request.model_name.clone();
+            let version = request.model_version.clone();
+            let result = to_infer_request(request)
+                .and_then(|request| kserve::infer(&state, &name, &version, request));
+            let status = result.as_ref().map_or_else(|err| err.status(), |_| 200);
+            state.metrics.record_request(
+                metric_label(&state, &name),
+                "infer",
+                status,
+                start.elapsed(),
+            );
+            result
+        })
+        .await
+        .map_err(|err| Status::internal(err.to_string()))??;
+
+        let mut outputs = Vec::with_capacity(response.outputs.len());
+        let mut raw_output_contents = Vec::with_capacity(response.outputs.len());
+        for output in response.outputs {
+            outputs.push(InferOutputTensor {
+                name: output.name,
+                datatype: output.datatype.as_str().to_string(),
+                shape: output.shape,
+                ..Default::default()
+            });
+            raw_output_contents.push(output.data);
+        }
+        Ok(Response::new(ModelInferResponse {
+            model_name: response.model_name,
+            model_version: response.model_version,
+            id: response.id,
+            outputs,
+            raw_output_contents,
+            ..Default::default()
+        }))
+    }
+}
+
+/// gRPCサーバーを動かしているスレッド、dropすると停止する
+pub(crate) struct GrpcHandle {
+    addr: SocketAddr,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl GrpcHandle {
+    pub(crate) fn spawn(listener: std::net::TcpListener, state: Arc<State>) -> Self {
+        let addr = listener.local_addr().expect("failed to get grpc address");
+        let (shutdown, signal) = oneshot::channel::<()>();
+        let thread = thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .expect("failed to start tokio runtime");
+            runtime.block_on(async move {
+                let result = async {
+                    listener.set_nonblocking(true)?;
+                    let listener = tokio::net::TcpListener::from_std(listener)?;
+                    let incoming = TcpIncoming::from_listener(listener, true, None)?;
+                    tonic::transport::Server::builder()
+                        .add_service(GrpcInferenceServiceServer::new(InferenceService { state }))
+                        .serve_with_incoming_shutdown(incoming, async {
+                            let _ = signal.await;
+                        })
+                        .await?;
+                    Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+                };
+                if let Err(err) = result.await {
+                    eprintln!("grpc server stopped: {}", err);
+                }
+            });
+        });
+        Self {
+            addr,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        }
+    }
+
+    pub(crate) fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for GrpcHandle {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
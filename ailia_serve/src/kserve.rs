@@ -0,0 +1,311 @@
+//! KServe v2 (Open Inference Protocol) の処理、HTTP/JSONとgRPCの両方から使う
+//!
+//! テンソルはailiaのNetworkのBlobに名前で対応付ける
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use ailia::network::Network;
+
+use crate::server::{Model, State};
+use crate::tensor::{Datatype, ModelMetadata, Tensor, TensorMetadata};
+use crate::ServeError;
+
+/// 各モデルは1つのバージョンのみを持つ
+pub const MODEL_VERSION: &str = "1";
+pub const PLATFORM: &str = "ailia";
+pub const SERVER_NAME: &str = "ailia-serve";
+
+#[derive(Clone, Debug, Default)]
+pub struct InferRequest {
+    pub id: String,
+    pub inputs: Vec<Tensor>,
+    /// 空の場合は全ての出力を返す
+    pub outputs: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct InferResponse {
+    pub model_name: String,
+    pub model_version: String,
+    pub id: String,
+    pub outputs: Vec<Tensor>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ServerMetadata {
+    pub name: String,
+    pub version: String,
+    pub extensions: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ModelMetadataResponse {
+    pub name: String,
+    pub versions: Vec<String>,
+    pub platform: String,
+    pub inputs: Vec<TensorMetadata>,
+    pub outputs: Vec<TensorMetadata>,
+}
+
+fn blob_datatype(net: &Network, idx: u32) -> Result<Datatype, ServeError> {
+    let data_type = net.get_blob_data_type(idx)?;
+    Datatype::from_ailia(data_type)
+        .ok_or_else(|| ServeError::Unsupported(format!("blob data type {:?}", data_type)))
+}
+
+fn blob_metadata(net: &Network, idx: u32) -> Result<TensorMetadata, ServeError> {
+    Ok(TensorMetadata {
+        name: net.get_blob_name(idx)?,
+        datatype: blob_datatype(net, idx)?,
+        shape: net
+            .get_blob_shape_nd(idx)?
+            .into_iter()
+            .map(|dim| dim as i64)
+            .collect(),
+    })
+}
+
+/// Networkの入出力Blobの名前と形状
+pub fn network_metadata(net: &Network) -> Result<ModelMetadata, ServeError> {
+    Ok(ModelMetadata {
+        inputs: net
+            .get_input_indexs()?
+            .into_iter()
+            .map(|idx| blob_metadata(net, idx))
+            .collect::<Result<_, _>>()?,
+        outputs: net
+            .get_output_indexs()?
+            .into_iter()
+            .map(|idx| blob_metadata(net, idx))
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+/// 入力テンソルをBlobに設定して推論し、出力Blobをテンソルとして返す
+pub fn infer_network(
+    net: &Network,
+    inputs: &[Tensor],
+    outputs: &[String],
+) -> Result<Vec<Tensor>, ServeError> {
+    let find = |name: &str| {
+        net.find_blob_idx_by_name(name)
+            .map_err(|_| ServeError::BadRequest(format!("unknown tensor: {}", name)))
+    };
+    let input_indexes = net.get_input_indexs()?;
+    let mut assigned = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        input.validate()?;
+        let idx = find(&input.name)?;
+        if !input_indexes.contains(&idx) {
+            return Err(ServeError::BadRequest(format!(
+                "{} is not an input",
+                input.name
+            )));
+        }
+        let datatype = blob_datatype(net, idx)?;
+        if datatype != input.datatype {
+            return Err(ServeError::BadRequest(format!(
+                "{} expects {}, got {}",
+                input.name,
+                datatype.as_str(),
+                input.datatype.as_str()
+            )));
+        }
+        let shape = input
+            .shape
+            .iter()
+            .map(|&dim| u32::try_from(dim))
+            .collect::<Result<_, _>>()
+            .map_err(|_| ServeError::BadRequest(format!("{}: invalid shape", input.name)))?;
+        net.set_input_blob_shape_nd(shape, idx)?;
+        net.set_input_data_blob(input.data.as_ptr(), input.data.len() as u32, idx)?;
+        assigned.push(idx);
+    }
+    if let Some(&missing) = input_indexes.iter().find(|idx| !assigned.contains(idx)) {
+        return Err(ServeError::BadRequest(format!(
+            "missing input: {}",
+            net.get_blob_name(missing)?
+        )));
+    }
+    net.update()?;
+
+    let output_indexes = if outputs.is_empty() {
+        net.get_output_indexs()?
+    } else {
+        outputs
+            .iter()
+            .map(|name| find(name))
+            .collect::<Result<_, _>>()?
+    };
+    output_indexes
+        .into_iter()
+        .map(|idx| {
+            let metadata = blob_metadata(net, idx)?;
+            let num_elements: i64 = metadata.shape.iter().product();
+            let mut data = vec![0; num_elements as usize * metadata.datatype.size()];
+            net.get_blob_data_raw(idx, &mut data)?;
+            Ok(Tensor {
+                name: metadata.name,
+                datatype: metadata.datatype,
+                shape: metadata.shape,
+                data,
+            })
+        })
+        .collect()
+}
+
+pub fn server_metadata() -> ServerMetadata {
+    ServerMetadata {
+        name: SERVER_NAME.to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        extensions: Vec::new(),
+    }
+}
+
+/// 全てのモデルで1つ以上のワーカーの読み込みが終わっているか
+pub(crate) fn server_ready(state: &State) -> bool {
+    state
+        .models
+        .values()
+        .all(|model| model.pool.ready_workers() > 0)
+}
+
+/// バージョンは省略するかMODEL_VERSIONのみ
+pub(crate) fn find_model<'a>(
+    state: &'a State,
+    name: &str,
+    version: &str,
+) -> Result<&'a Model, ServeError> {
+    match state.models.get(name) {
+        Some(model) if version.is_empty() || version == MODEL_VERSION => Ok(model),
+        Some(_) => Err(ServeError::NotFound(format!(
+            "{} version {}",
+            name, version
+        ))),
+        None => Err(ServeError::NotFound(name.to_string())),
+    }
+}
+
+pub(crate) fn model_ready(state: &State, name: &str, version: &str) -> Result<bool, ServeError> {
+    Ok(find_model(state, name, version)?.pool.ready_workers() > 0)
+}
+
+pub(crate) fn model_metadata(
+    state: &State,
+    name: &str,
+    version: &str,
+) -> Result<ModelMetadataResponse, ServeError> {
+    let model = find_model(state, name, version)?;
+    let metadata = model.pool.metadata()?;
+    Ok(ModelMetadataResponse {
+        name: model.config.name.clone(),
+        versions: vec![MODEL_VERSION.to_string()],
+        platform: PLATFORM.to_string(),
+        inputs: metadata.inputs,
+        outputs: metadata.outputs,
+    })
+}
+
+pub(crate) fn infer(
+    state: &State,
+    name: &str,
+    version: &str,
+    request: InferRequest,
+) -> Result<InferResponse, ServeError> {
+    let model = find_model(state, name, version)?;
+    let (outputs, elapsed) = model.pool.infer(request.inputs, request.outputs)?;
+    state.metrics.record_inference(&model.config.name, elapsed);
+    Ok(InferResponse {
+        model_name: model.config.name.clone(),
+        model_version: MODEL_VERSION.to_string(),
+        id: request.id,
+        outputs,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonInput {
+    name: String,
+    shape: Vec<i64>,
+    datatype: String,
+    data: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonRequestedOutput {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonInferRequest {
+    #[serde(default)]
+    id: String,
+    inputs: Vec<JsonInput>,
+    #[serde(default)]
+    outputs: Vec<JsonRequestedOutput>,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonOutput {
+    name: String,
+    shape: Vec<i64>,
+    datatype: Datatype,
+    data: Vec<Value>,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonInferResponse {
+    model_name: String,
+    model_version: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    id: String,
+    outputs: Vec<JsonOutput>,
+}
+
+/// HTTP/JSONの推論リクエスト
+pub(crate) fn infer_json(
+    state: &State,
+    name: &str,
+    version: &str,
+    body: &[u8],
+) -> Result<Value, ServeError> {
+    find_model(state, name, version)?;
+    let request: JsonInferRequest =
+        serde_json::from_slice(body).map_err(|err| ServeError::BadRequest(err.to_string()))?;
+    let inputs = request
+        .inputs
+        .into_iter()
+        .map(|input| {
+            let datatype = Datatype::parse(&input.datatype).ok_or_else(|| {
+                ServeError::BadRequest(format!("unknown datatype: {}", input.datatype))
+            })?;
+            Tensor::from_json(&input.name, datatype, input.shape, &input.data)
+        })
+        .collect::<Result<_, _>>()?;
+    let request = InferRequest {
+        id: request.id,
+        inputs,
+        outputs: request
+            .outputs
+            .into_iter()
+            .map(|output| output.name)
+            .collect(),
+    };
+    let response = infer(state, name, version, request)?;
+    Ok(serde_json::to_value(JsonInferResponse {
+        model_name: response.model_name,
+        model_version: response.model_version,
+        id: response.id,
+        outputs: response
+            .outputs
+            .into_iter()
+            .map(|output| JsonOutput {
+                data: output.to_json(),
+                name: output.name,
+                shape: output.shape,
+                datatype: output.datatype,
+            })
+            .collect(),
+    })?)
+}
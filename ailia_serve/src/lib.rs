@@ -0,0 +1,61 @@
+pub mod config;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod kserve;
+pub mod metrics;
+pub mod pool;
+pub mod predictor;
+pub mod server;
+pub mod tensor;
+
+use thiserror::Error;
+
+use ailia::AiliaError;
+
+#[derive(Debug, Error)]
+pub enum ServeError {
+    #[error("設定が不正です: {0}")]
+    Config(String),
+    #[error("ポートを開けませんでした: {0}")]
+    Bind(Box<dyn std::error::Error + Send + Sync>),
+    #[error("モデルが見つかりません: {0}")]
+    NotFound(String),
+    #[error("リクエストが不正です: {0}")]
+    BadRequest(String),
+    #[error("対応していない操作です: {0}")]
+    Unsupported(String),
+    #[error("許可されていないメソッドです")]
+    MethodNotAllowed,
+    #[error("リクエストボディが大きすぎます")]
+    PayloadTooLarge,
+    #[error("キューが一杯です")]
+    Busy,
+    #[error("モデルの読み込みが完了していません")]
+    NotReady,
+    #[error("モデルの読み込みに失敗しました: {0}")]
+    LoadFailed(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Toml(#[from] toml::de::Error),
+    #[error(transparent)]
+    Zoo(#[from] ailia::zoo::ZooError),
+    #[error(transparent)]
+    Ailia(#[from] AiliaError),
+}
+
+impl ServeError {
+    /// HTTPのステータスコード
+    pub fn status(&self) -> u16 {
+        match self {
+            ServeError::NotFound(_) => 404,
+            ServeError::BadRequest(_) | ServeError::Unsupported(_) => 400,
+            ServeError::MethodNotAllowed => 405,
+            ServeError::PayloadTooLarge => 413,
+            ServeError::Busy | ServeError::NotReady | ServeError::LoadFailed(_) => 503,
+            _ => 500,
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use std::path::PathBuf;
+
+use ailia_serve::config::Config;
+use ailia_serve::server::Server;
+
+use anyhow::Result;
+use clap::Parser;
+
+#[derive(Parser)]
+#[command(name = "ailia-serve", about = "Serve ailia models over HTTP")]
+struct Args {
+    /// モデルの設定ファイル(TOML)
+    #[arg(long, short, default_value = "serve.toml")]
+    config: PathBuf,
+    /// 設定ファイルのserver.bindを上書きする
+    #[arg(long)]
+    bind: Option<String>,
+    /// 設定ファイルのserver.grpc_bindを上書きする
+    #[arg(long)]
+    grpc_bind: Option<String>,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let mut config = Config::load(&args.config)?;
+    if let Some(bind) = args.bind {
+        config.server.bind = bind;
+    }
+    if let Some(grpc_bind) = args.grpc_bind {
+        config.server.grpc_bind = Some(grpc_bind);
+    }
+    let server = Server::new(&config)?;
+    if let Some(addr) = server.addr() {
+        eprintln!("listening on http://{}", addr);
+    }
+    if let Some(addr) = server.grpc_addr() {
+        eprintln!("listening on grpc://{}", addr);
+    }
+    server.run();
+    Ok(())
+}
@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1., 2.5, 5., 10.];
+
+#[derive(Clone, Debug, Default)]
+struct Histogram {
+    counts: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        for (bucket, count) in BUCKETS.iter().zip(self.counts.iter_mut()) {
+            if secs <= *bucket {
+                *count += 1;
+            }
+        }
+        self.sum += secs;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        for (bucket, count) in BUCKETS.iter().zip(self.counts.iter()) {
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{},le=\"{}\"}} {}",
+                name, labels, bucket, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{},le=\"+Inf\"}} {}",
+            name, labels, self.count
+        );
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    // (model, task, status) -> count
+    requests: BTreeMap<(String, String, u16), u64>,
+    request_duration: BTreeMap<String, Histogram>,
+    inference_duration: BTreeMap<String, Histogram>,
+}
+
+/// 設定にないモデルへのリクエストをまとめて記録するラベル
+pub const UNKNOWN_LABEL: &str = "unknown";
+
+/// ラベルの値をPrometheusのテキスト形式でエスケープする
+fn escape_label(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Prometheusのテキスト形式で公開するメトリクス
+#[derive(Debug, Default)]
+pub struct Metrics {
+    inner: Mutex<Inner>,
+}
+
+/// モデルごとのゲージ(リクエストのたびに集計せず、/metricsの呼び出し時に読み取る)
+pub struct ModelGauge {
+    pub model: String,
+    pub ready_workers: usize,
+    pub queue_depth: usize,
+    pub worker_restarts: usize,
+}
+
+impl Metrics {
+    /// モデル名は設定済みのものか`UNKNOWN_LABEL`を渡す(任意の文字列を渡すとラベルが増え続ける)
+    pub fn record_request(&self, model: &str, task: &str, status: u16, duration: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        *inner
+            .requests
+            .entry((model.to_string(), task.to_string(), status))
+            .or_default() += 1;
+        inner
+            .request_duration
+            .entry(model.to_string())
+            .or_default()
+            .observe(duration);
+    }
+
+    pub fn record_inference(&self, model: &str, duration: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner
+            .inference_duration
+            .entry(model.to_string())
+            .or_default()
+            .observe(duration);
+    }
+
+    pub fn render(&self, gauges: &[ModelGauge]) -> String {
+        let inner = self.inner.lock().unwrap();
+        let mut out = String::new();
+
+        out.push_str("# HELP ailia_requests_total Number of inference requests.\n");
+        out.push_str("# TYPE ailia_requests_total counter\n");
+        for ((model, task, status), count) in &inner.requests {
+            let _ = writeln!(
+                out,
+                "ailia_requests_total{{model=\"{}\",task=\"{}\",status=\"{}\"}} {}",
+                escape_label(model),
+                escape_label(task),
+                status,
+                count
+            );
+        }
+
+        out.push_str("# HELP ailia_request_duration_seconds Request latency including queueing and decoding.\n");
+        out.push_str("# TYPE ailia_request_duration_seconds histogram\n");
+        for (model, histogram) in &inner.request_duration {
+            histogram.render(
+                &mut out,
+                "ailia_request_duration_seconds",
+                &format!("model=\"{}\"", escape_label(model)),
+            );
+        }
+
+        out.push_str("# HELP ailia_inference_duration_seconds Time spent in the model.\n");
+        out.push_str("# TYPE ailia_inference_duration_seconds histogram\n");
+        for (model, histogram) in &inner.inference_duration {
+            histogram.render(
+                &mut out,
+                "ailia_inference_duration_seconds",
+                &format!("model=\"{}\"", escape_label(model)),
+            );
+        }
+
+        out.push_str("# HELP ailia_workers_ready Workers with a loaded model.\n");
+        out.push_str("# TYPE ailia_workers_ready gauge\n");
+        for gauge in gauges {
+            let _ = writeln!(
+                out,
+                "ailia_workers_ready{{model=\"{}\"}} {}",
+                escape_label(&gauge.model),
+                gauge.ready_workers
+            );
+        }
+        out.push_str("# HELP ailia_queue_depth Requests waiting for a worker.\n");
+        out.push_str("# TYPE ailia_queue_depth gauge\n");
+        for gauge in gauges {
+            let _ = writeln!(
+                out,
+                "ailia_queue_depth{{model=\"{}\"}} {}",
+                escape_label(&gauge.model),
+                gauge.queue_depth
+            );
+        }
+        out.push_str("# HELP ailia_worker_restarts_total Workers restarted after a panic.\n");
+        out.push_str("# TYPE ailia_worker_restarts_total counter\n");
+        for gauge in gauges {
+            let _ = writeln!(
+                out,
+                "ailia_worker_restarts_total{{model=\"{}\"}} {}",
+                escape_label(&gauge.model),
+                gauge.worker_restarts
+            );
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::default();
+        metrics.record_request("yolox", "detect", 200, Duration::from_millis(20));
+        metrics.record_request("yolox", "detect", 200, Duration::from_millis(200));
+        metrics.record_inference("yolox", Duration::from_millis(15));
+        let text = metrics.render(&[ModelGauge {
+            model: "yolox".to_string(),
+            ready_workers: 2,
+            queue_depth: 0,
+            worker_restarts: 1,
+        }]);
+        assert!(
+            text.contains("ailia_requests_total{model=\"yolox\",task=\"detect\",status=\"200\"} 2")
+        );
+        assert!(
+            text.contains("ailia_request_duration_seconds_bucket{model=\"yolox\",le=\"0.025\"} 1")
+        );
+        assert!(
+            text.contains("ailia_request_duration_seconds_bucket{model=\"yolox\",le=\"0.25\"} 2")
+        );
+        assert!(text.contains("ailia_request_duration_seconds_count{model=\"yolox\"} 2"));
+        assert!(text.contains("ailia_inference_duration_seconds_count{model=\"yolox\"} 1"));
+        assert!(text.contains("ailia_workers_ready{model=\"yolox\"} 2"));
+        assert!(text.contains("ailia_worker_restarts_total{model=\"yolox\"} 1"));
+    }
+
+    #[test]
+    fn escape() {
+        let metrics = Metrics::default();
+        metrics.record_request("a\"b\\c\nd", "detect", 200, Duration::from_millis(1));
+        let text = metrics.render(&[]);
+        assert!(text.contains("ailia_requests_total{model=\"a\\\"b\\\\c\\nd\",task=\"detect\""));
+        assert_eq!(escape_label(UNKNOWN_LABEL), UNKNOWN_LABEL);
+    }
+}
@@ -0,0 +1,205 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use ailia::video::ImageView;
+
+use crate::predictor::{Params, Prediction, Predictor};
+use crate::tensor::{ModelMetadata, Tensor};
+use crate::ServeError;
+
+/// ワーカースレッド上でPredictorを作成する関数
+pub type PredictorFactory = Arc<dyn Fn() -> Result<Box<dyn Predictor>, ServeError> + Send + Sync>;
+
+type Reply<T> = Result<(T, Duration), ServeError>;
+
+/// ワーカー上で実行する処理、結果は処理の中で返信する
+type Job = Box<dyn FnOnce(&mut dyn Predictor) + Send>;
+
+/// 1モデル分のワーカースレッド
+/// 各ワーカーが自分のスレッドでモデルを作成して保持し、キューからリクエストを取り出して推論する
+/// 推論中にpanicしたワーカーはモデルを作り直して処理を続ける
+pub struct WorkerPool {
+    sender: Option<SyncSender<Job>>,
+    ready: Arc<AtomicUsize>,
+    restarts: Arc<AtomicUsize>,
+    queued: Arc<AtomicUsize>,
+    metadata: Arc<OnceLock<Option<ModelMetadata>>>,
+    load_error: Arc<OnceLock<String>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    pub fn new(name: &str, workers: usize, queue_size: usize, factory: PredictorFactory) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Job>(queue_size);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let ready = Arc::new(AtomicUsize::new(0));
+        let restarts = Arc::new(AtomicUsize::new(0));
+        let queued = Arc::new(AtomicUsize::new(0));
+        let metadata = Arc::new(OnceLock::new());
+        let load_error = Arc::new(OnceLock::new());
+        let workers = (0..workers)
+            .map(|idx| {
+                let receiver = receiver.clone();
+                let ready = ready.clone();
+                let restarts = restarts.clone();
+                let queued = queued.clone();
+                let metadata = metadata.clone();
+                let load_error = load_error.clone();
+                let factory = factory.clone();
+                let name = name.to_string();
+                thread::Builder::new()
+                    .name(format!("{}-{}", name, idx))
+                    .spawn(move || loop {
+                        let predictor = match factory() {
+                            Ok(predictor) => predictor,
+                            Err(err) => {
+                                load_error.get_or_init(|| err.to_string());
+                                return;
+                            }
+                        };
+                        // 入出力の情報はどのワーカーでも同じなので最初の1つだけ保持する
+                        metadata.get_or_init(|| predictor.metadata().ok());
+                        let result = {
+                            // 推論中にpanicしてもdropで数を戻す
+                            let _ready = ReadyGuard::new(&ready);
+                            panic::catch_unwind(AssertUnwindSafe(|| {
+                                work(predictor, &receiver, &queued)
+                            }))
+                        };
+                        if result.is_ok() {
+                            // キューが閉じられた
+                            return;
+                        }
+                        restarts.fetch_add(1, Ordering::SeqCst);
+                    })
+                    .expect("failed to spawn worker thread")
+            })
+            .collect();
+        Self {
+            sender: Some(sender),
+            ready,
+            restarts,
+            queued,
+            metadata,
+            load_error,
+            workers,
+        }
+    }
+
+    /// キューに空きがない場合はServeError::Busyを返す
+    pub fn submit(&self, image: ImageView, params: Params) -> Reply<Prediction> {
+        self.execute(move |predictor| predictor.predict(&image, &params))
+    }
+
+    /// KServe v2のテンソル推論
+    pub fn infer(&self, inputs: Vec<Tensor>, outputs: Vec<String>) -> Reply<Vec<Tensor>> {
+        self.execute(move |predictor| predictor.infer(&inputs, &outputs))
+    }
+
+    /// 入出力テンソルの情報、モデルの読み込みが終わるまではNotReady
+    pub fn metadata(&self) -> Result<ModelMetadata, ServeError> {
+        match self.metadata.get() {
+            Some(Some(metadata)) => Ok(metadata.clone()),
+            Some(None) => Err(ServeError::Unsupported(
+                "tensor inference is not supported".to_string(),
+            )),
+            None => Err(self.not_ready()),
+        }
+    }
+
+    fn execute<T, F>(&self, f: F) -> Reply<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut dyn Predictor) -> Result<T, ServeError> + Send + 'static,
+    {
+        if self.ready_workers() == 0 {
+            return Err(self.not_ready());
+        }
+        let (reply, result) = mpsc::channel();
+        let job: Job = Box::new(move |predictor| {
+            let start = Instant::now();
+            let _ = reply.send(f(predictor).map(|value| (value, start.elapsed())));
+        });
+        let sender = self.sender.as_ref().ok_or(ServeError::NotReady)?;
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        if let Err(err) = sender.try_send(job) {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            return Err(match err {
+                TrySendError::Full(_) => ServeError::Busy,
+                TrySendError::Disconnected(_) => ServeError::NotReady,
+            });
+        }
+        result.recv().map_err(|_| ServeError::NotReady)?
+    }
+
+    /// モデルの読み込みが終わったワーカーの数
+    pub fn ready_workers(&self) -> usize {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    /// panicしてモデルを作り直した回数
+    pub fn restarts(&self) -> usize {
+        self.restarts.load(Ordering::SeqCst)
+    }
+
+    /// モデルの読み込みに失敗した場合はその理由
+    pub fn load_error(&self) -> Option<&str> {
+        self.load_error.get().map(String::as_str)
+    }
+
+    fn not_ready(&self) -> ServeError {
+        match self.load_error() {
+            Some(reason) => ServeError::LoadFailed(reason.to_string()),
+            None => ServeError::NotReady,
+        }
+    }
+
+    /// 処理待ちのリクエストの数
+    pub fn queue_depth(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+}
+
+/// 読み込み済みのワーカーの数を増やし、dropで減らす
+struct ReadyGuard<'a>(&'a AtomicUsize);
+
+impl<'a> ReadyGuard<'a> {
+    fn new(ready: &'a AtomicUsize) -> Self {
+        ready.fetch_add(1, Ordering::SeqCst);
+        Self(ready)
+    }
+}
+
+impl Drop for ReadyGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn work(mut predictor: Box<dyn Predictor>, receiver: &Mutex<Receiver<Job>>, queued: &AtomicUsize) {
+    loop {
+        let job = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return,
+        };
+        let Ok(job) = job else {
+            return;
+        };
+        queued.fetch_sub(1, Ordering::SeqCst);
+        job(predictor.as_mut());
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        // 送信側を閉じるとワーカーのrecvが失敗して終了する
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
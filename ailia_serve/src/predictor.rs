@@ -0,0 +1,331 @@
+use std::path::Path;
+
+use ailia::prelude::*;
+use ailia::video::ImageView;
+use ailia::zoo::find_model;
+
+use serde::Serialize;
+
+use crate::config::{ModelConfig, Task};
+use crate::kserve::{infer_network, network_metadata};
+use crate::tensor::{ModelMetadata, Tensor};
+use crate::ServeError;
+
+/// リクエストごとに指定できるパラメータ
+#[derive(Clone, Copy, Debug)]
+pub struct Params {
+    pub threshold: f32,
+    pub iou: f32,
+    pub top_k: u32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(untagged)]
+pub enum Prediction {
+    Objects(Vec<Object>),
+    Classes(Vec<Class>),
+    Poses(Vec<Pose>),
+}
+
+/// ワーカースレッド上でモデルを保持して推論する
+/// ailiaのオブジェクトはスレッド間で移動できないため、ワーカースレッド上で作成する
+pub trait Predictor {
+    fn predict(&mut self, image: &ImageView, params: &Params) -> Result<Prediction, ServeError>;
+
+    /// KServe v2のテンソル推論、outputsが空の場合は全ての出力を返す
+    fn infer(
+        &mut self,
+        _inputs: &[Tensor],
+        _outputs: &[String],
+    ) -> Result<Vec<Tensor>, ServeError> {
+        Err(ServeError::Unsupported(
+            "tensor inference is not supported".to_string(),
+        ))
+    }
+
+    /// 入出力テンソルの名前と形状
+    fn metadata(&self) -> Result<ModelMetadata, ServeError> {
+        Err(ServeError::Unsupported(
+            "tensor inference is not supported".to_string(),
+        ))
+    }
+}
+
+/// ailiaのNetworkを持つモデルはKServe v2のテンソル推論にも対応する
+macro_rules! impl_network_inference {
+    () => {
+        fn infer(
+            &mut self,
+            inputs: &[Tensor],
+            outputs: &[String],
+        ) -> Result<Vec<Tensor>, ServeError> {
+            infer_network(self, inputs, outputs)
+        }
+
+        fn metadata(&self) -> Result<ModelMetadata, ServeError> {
+            network_metadata(self)
+        }
+    };
+}
+
+impl Predictor for Network {
+    fn predict(&mut self, _image: &ImageView, _params: &Params) -> Result<Prediction, ServeError> {
+        Err(ServeError::Unsupported(
+            "network models only support tensor inference".to_string(),
+        ))
+    }
+
+    impl_network_inference!();
+}
+
+impl Predictor for Detector {
+    fn predict(&mut self, image: &ImageView, params: &Params) -> Result<Prediction, ServeError> {
+        Ok(Prediction::Objects(Detector::predict(
+            self,
+            image.as_ptr(),
+            image.stride(),
+            image.width,
+            image.height,
+            image.format(),
+            params.threshold,
+            params.iou,
+        )?))
+    }
+
+    impl_network_inference!();
+}
+
+impl Predictor for Classifier {
+    fn predict(&mut self, image: &ImageView, params: &Params) -> Result<Prediction, ServeError> {
+        self.compute(
+            image.as_ptr(),
+            image.stride(),
+            image.width,
+            image.height,
+            image.format(),
+            params.top_k,
+        )?;
+        let classes = (0..self.get_class_count()?)
+            .map(|idx| self.get_class(idx))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Prediction::Classes(classes))
+    }
+
+    impl_network_inference!();
+}
+
+impl Predictor for PoseEstimator<Pose> {
+    fn predict(&mut self, image: &ImageView, _params: &Params) -> Result<Prediction, ServeError> {
+        Ok(Prediction::Poses(PoseEstimator::predict(
+            self,
+            image.as_ptr(),
+            image.stride(),
+            image.width,
+            image.height,
+            image.format(),
+        )?))
+    }
+
+    impl_network_inference!();
+}
+
+fn parse_detector_algorithm(name: &str) -> Result<DetectorAlgorithm, ServeError> {
+    Ok(match name {
+        "yolov1" => DetectorAlgorithm::Yolov1,
+        "yolov2" => DetectorAlgorithm::Yolov2,
+        "yolov3" => DetectorAlgorithm::Yolov3,
+        "yolov4" => DetectorAlgorithm::Yolov4,
+        "yolox" => DetectorAlgorithm::Yolox,
+        "ssd" => DetectorAlgorithm::Ssd,
+        _ => return Err(ServeError::Config(format!("unknown algorithm: {}", name))),
+    })
+}
+
+fn parse_pose_algorithm(name: &str) -> Result<PoseAlgorithm, ServeError> {
+    Ok(match name {
+        "lw_human_pose" => PoseAlgorithm::LwHumanPose,
+        "open_pose" => PoseAlgorithm::OpenPose,
+        "open_pose_single_scale" => PoseAlgorithm::OpenPoseSingleScale,
+        "acculus_pose" => PoseAlgorithm::AcculusPose,
+        _ => return Err(ServeError::Config(format!("unknown algorithm: {}", name))),
+    })
+}
+
+fn parse_option<T, F>(value: &Option<String>, parse: F) -> Result<Option<T>, ServeError>
+where
+    F: Fn(&str) -> Option<T>,
+{
+    match value {
+        Some(value) => parse(value)
+            .map(Some)
+            .ok_or_else(|| ServeError::Config(format!("unknown value: {}", value))),
+        None => Ok(None),
+    }
+}
+
+/// 設定からモデルを作成する、ワーカースレッドごとに呼ばれる
+pub fn build_predictor(config: &ModelConfig) -> Result<Box<dyn Predictor>, ServeError> {
+    let (onnx, mut prototxt, task) = if Path::new(&config.model).is_file() {
+        (Path::new(&config.model).to_path_buf(), None, None)
+    } else if find_model(&config.model).is_some() {
+        let (desc, files) = Downloader::default().fetch_by_name(&config.model)?;
+        (files.onnx, files.prototxt, Some(desc.task))
+    } else {
+        return Err(ServeError::Config(format!(
+            "{} is neither a file nor a zoo model",
+            config.model
+        )));
+    };
+    if config.prototxt.is_some() {
+        prototxt = config.prototxt.clone();
+    }
+    let env_id = config.env_id.unwrap_or(ailia::AILIA_ENVIRONMENT_ID_AUTO);
+    let num_threads = config
+        .num_threads
+        .unwrap_or_else(|| ailia::AILIA_MULTITHREAD_AUTO.try_into().unwrap());
+    let input_size = match (config.input_width, config.input_height) {
+        (Some(width), Some(height)) => Some((width, height)),
+        _ => None,
+    };
+    let mismatch = || {
+        ServeError::Config(format!(
+            "{} is not a {} model",
+            config.model,
+            config.task.as_str()
+        ))
+    };
+
+    match config.task {
+        Task::Detect => {
+            let (algorithm, category_count, zoo_size) = match task {
+                Some(TaskConfig::Detector {
+                    algorithm,
+                    category_count,
+                    input_width,
+                    input_height,
+                }) => (
+                    Some(algorithm),
+                    Some(category_count),
+                    Some((input_width, input_height)),
+                ),
+                Some(_) => return Err(mismatch()),
+                None => (None, None, None),
+            };
+            let algorithm = match &config.algorithm {
+                Some(name) => parse_detector_algorithm(name)?,
+                None => algorithm
+                    .ok_or_else(|| ServeError::Config("algorithm is required".to_string()))?,
+            };
+            let category_count = config
+                .category_count
+                .or(category_count)
+                .ok_or_else(|| ServeError::Config("category_count is required".to_string()))?;
+            let mut builder = DetectorBuilder::default()
+                .onnx(onnx)
+                .env_id(env_id)
+                .num_threads(num_threads)
+                .algorithm(algorithm)
+                .category_count(category_count);
+            if let Some(prototxt) = prototxt {
+                builder = builder.prototxt(prototxt);
+            }
+            let detector = builder.build()?;
+            if let Some((width, height)) = input_size.or(zoo_size) {
+                detector.set_input_shape(width, height)?;
+            }
+            Ok(Box::new(detector))
+        }
+        Task::Classify => {
+            let (format, channel, range) = match task {
+                Some(TaskConfig::Classifier {
+                    format,
+                    channel,
+                    range,
+                    ..
+                }) => (Some(format), Some(channel), Some(range)),
+                Some(_) => return Err(mismatch()),
+                None => (None, None, None),
+            };
+            let format = parse_option(&config.format, |value| match value {
+                "rgb" => Some(NetworkImageFormat::Rgb),
+                "bgr" => Some(NetworkImageFormat::Bgr),
+                "gray" => Some(NetworkImageFormat::Gray),
+                _ => None,
+            })?
+            .or(format);
+            let channel = parse_option(&config.channel, |value| match value {
+                "first" => Some(ChannelOrder::First),
+                "last" => Some(ChannelOrder::Last),
+                _ => None,
+            })?
+            .or(channel);
+            let range = parse_option(&config.range, |value| match value {
+                "imagenet" => Some(ImageRange::ImageNet),
+                "unsigned_int8" => Some(ImageRange::UnsignedInt8),
+                "unsigned_fp32" => Some(ImageRange::UnsignedFp32),
+                "signed_int8" => Some(ImageRange::SignedInt8),
+                "signed_fp32" => Some(ImageRange::SignedFp32),
+                _ => None,
+            })?
+            .or(range);
+            let mut builder = ClassifierBuilder::default()
+                .onnx(onnx)
+                .env_id(env_id)
+                .num_threads(num_threads);
+            if let Some(prototxt) = prototxt {
+                builder = builder.prototxt(prototxt);
+            }
+            if let Some(format) = format {
+                builder = builder.format(format);
+            }
+            if let Some(channel) = channel {
+                builder = builder.channel(channel);
+            }
+            if let Some(range) = range {
+                builder = builder.range(range);
+            }
+            Ok(Box::new(builder.build()?))
+        }
+        Task::Pose => {
+            let (algorithm, zoo_size) = match task {
+                Some(TaskConfig::PoseEstimator {
+                    algorithm,
+                    input_width,
+                    input_height,
+                }) => (Some(algorithm), Some((input_width, input_height))),
+                Some(_) => return Err(mismatch()),
+                None => (None, None),
+            };
+            let algorithm = match &config.algorithm {
+                Some(name) => parse_pose_algorithm(name)?,
+                None => algorithm
+                    .ok_or_else(|| ServeError::Config("algorithm is required".to_string()))?,
+            };
+            let mut builder = PoseEstimatorBuilder::default()
+                .onnx(onnx)
+                .env_id(env_id)
+                .num_threads(num_threads)
+                .algorithm(algorithm);
+            if let Some(prototxt) = prototxt {
+                builder = builder.prototxt(prototxt);
+            }
+            let estimator: PoseEstimator<Pose> = builder.build()?;
+            if let Some((width, height)) = input_size.or(zoo_size) {
+                estimator.set_input_shape(Shape {
+                    x: width,
+                    y: height,
+                    z: 3,
+                    w: 1,
+                    dim: 4,
+                })?;
+            }
+            Ok(Box::new(estimator))
+        }
+        Task::Network => {
+            // zooのタスク付きのモデルもそのままNetworkとして扱える
+            let net = Network::ailia_create(env_id, num_threads)?;
+            net.open_model_files(prototxt, onnx)?;
+            Ok(Box::new(net))
+        }
+    }
+}
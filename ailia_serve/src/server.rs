@@ -0,0 +1,487 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use ailia::video::ImageView;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response};
+
+use crate::config::{Config, ModelConfig, ServerConfig, Task};
+#[cfg(feature = "grpc")]
+use crate::grpc::GrpcHandle;
+use crate::kserve;
+use crate::metrics::{Metrics, ModelGauge, UNKNOWN_LABEL};
+use crate::pool::{PredictorFactory, WorkerPool};
+use crate::predictor::{build_predictor, Params, Prediction};
+use crate::ServeError;
+
+pub(crate) struct Model {
+    pub(crate) config: ModelConfig,
+    pub(crate) labels: Option<Vec<String>>,
+    pub(crate) pool: WorkerPool,
+}
+
+/// HTTPとgRPCのスレッドで共有する状態
+pub(crate) struct State {
+    pub(crate) models: BTreeMap<String, Model>,
+    pub(crate) metrics: Metrics,
+    pub(crate) max_body_bytes: usize,
+}
+
+/// `{"image": "<base64>", "threshold": 0.3}`形式のリクエストボディ
+#[derive(Debug, Deserialize)]
+struct JsonRequest {
+    image: String,
+    threshold: Option<f32>,
+    iou: Option<f32>,
+    top_k: Option<u32>,
+}
+
+/// REST APIのサーバー
+///
+/// - `POST /v1/models/{name}:detect|classify|pose` 画像のバイト列またはbase64のJSON
+/// - `GET /v1/models` モデルの一覧
+/// - `GET /healthz` プロセスが動いていれば200
+/// - `GET /readyz` 全てのモデルで1つ以上のワーカーが読み込み済みなら200、読み込みに失敗したモデルは理由も返す
+/// - `GET /metrics` Prometheusのメトリクス
+/// - `/v2/...` KServe v2のHTTP/JSON、`grpc_bind`を指定した場合はgRPCも公開する
+pub struct Server {
+    http: Arc<tiny_http::Server>,
+    state: Arc<State>,
+    http_threads: usize,
+    #[cfg(feature = "grpc")]
+    grpc: Option<std::net::TcpListener>,
+}
+
+/// バックグラウンドで動いているサーバー、dropすると停止する
+pub struct ServerHandle {
+    addr: SocketAddr,
+    http: Arc<tiny_http::Server>,
+    threads: Vec<JoinHandle<()>>,
+    #[cfg(feature = "grpc")]
+    grpc: Option<GrpcHandle>,
+}
+
+impl ServerHandle {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// gRPCのアドレス、`grpc_bind`を指定していない場合はNone
+    pub fn grpc_addr(&self) -> Option<SocketAddr> {
+        #[cfg(feature = "grpc")]
+        return self.grpc.as_ref().map(|grpc| grpc.addr());
+        #[cfg(not(feature = "grpc"))]
+        None
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        for _ in &self.threads {
+            self.http.unblock();
+        }
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Server {
+    /// 設定ファイルの各モデルについてワーカーを起動する
+    pub fn new(config: &Config) -> Result<Self, ServeError> {
+        let models = config
+            .models
+            .iter()
+            .map(|model| {
+                let config = model.clone();
+                let factory: PredictorFactory = Arc::new(move || build_predictor(&config));
+                (model.clone(), factory)
+            })
+            .collect();
+        Self::with_factories(&config.server, models)
+    }
+
+    /// モデルの作成方法を指定して起動する
+    pub fn with_factories(
+        server: &ServerConfig,
+        models: Vec<(ModelConfig, PredictorFactory)>,
+    ) -> Result<Self, ServeError> {
+        let http = tiny_http::Server::http(&server.bind).map_err(ServeError::Bind)?;
+        #[cfg(feature = "grpc")]
+        let grpc = match &server.grpc_bind {
+            Some(bind) => Some(
+                std::net::TcpListener::bind(bind).map_err(|err| ServeError::Bind(err.into()))?,
+            ),
+            None => None,
+        };
+        #[cfg(not(feature = "grpc"))]
+        if server.grpc_bind.is_some() {
+            return Err(ServeError::Config(
+                "grpc_bind requires the grpc feature".to_string(),
+            ));
+        }
+        let mut entries = BTreeMap::new();
+        for (config, factory) in models {
+            let labels = config.load_labels()?;
+            let pool = WorkerPool::new(&config.name, config.workers, config.queue_size, factory);
+            entries.insert(
+                config.name.clone(),
+                Model {
+                    config,
+                    labels,
+                    pool,
+                },
+            );
+        }
+        Ok(Self {
+            http: Arc::new(http),
+            state: Arc::new(State {
+                models: entries,
+                metrics: Metrics::default(),
+                max_body_bytes: server.max_body_bytes,
+            }),
+            http_threads: server.http_threads.max(1),
+            #[cfg(feature = "grpc")]
+            grpc,
+        })
+    }
+
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.http.server_addr().to_ip()
+    }
+
+    pub fn grpc_addr(&self) -> Option<SocketAddr> {
+        #[cfg(feature = "grpc")]
+        return self
+            .grpc
+            .as_ref()
+            .and_then(|listener| listener.local_addr().ok());
+        #[cfg(not(feature = "grpc"))]
+        None
+    }
+
+    /// HTTPスレッドを起動して返る
+    pub fn spawn(self) -> ServerHandle {
+        let addr = self.addr().expect("server is not bound to an ip address");
+        let threads = (0..self.http_threads)
+            .map(|_| {
+                let http = self.http.clone();
+                let state = self.state.clone();
+                thread::spawn(move || {
+                    for request in http.incoming_requests() {
+                        handle(&state, request);
+                    }
+                })
+            })
+            .collect();
+        ServerHandle {
+            addr,
+            #[cfg(feature = "grpc")]
+            grpc: self
+                .grpc
+                .map(|listener| GrpcHandle::spawn(listener, self.state.clone())),
+            http: self.http,
+            threads,
+        }
+    }
+
+    /// 停止するまでブロックする
+    pub fn run(self) {
+        let mut handle = self.spawn();
+        for thread in handle.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn json_response(status: u16, body: &Value) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_data(body.to_string().into_bytes())
+        .with_status_code(status)
+        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+}
+
+fn text_response(
+    status: u16,
+    body: String,
+    content_type: &str,
+) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_data(body.into_bytes())
+        .with_status_code(status)
+        .with_header(Header::from_bytes("Content-Type", content_type).unwrap())
+}
+
+fn handle(state: &State, mut request: Request) {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((url.as_str(), ""));
+    let response = match (request.method(), path) {
+        (Method::Get, "/healthz") => json_response(200, &json!({ "status": "ok" })),
+        (Method::Get, "/readyz") => {
+            let not_ready: Vec<&str> = state
+                .models
+                .values()
+                .filter(|model| model.pool.ready_workers() == 0)
+                .map(|model| model.config.name.as_str())
+                .collect();
+            let errors: BTreeMap<&str, &str> = state
+                .models
+                .values()
+                .filter_map(|model| Some((model.config.name.as_str(), model.pool.load_error()?)))
+                .collect();
+            if not_ready.is_empty() {
+                json_response(200, &json!({ "status": "ready" }))
+            } else {
+                json_response(
+                    503,
+                    &json!({ "status": "loading", "models": not_ready, "errors": errors }),
+                )
+            }
+        }
+        (Method::Get, "/metrics") => {
+            let gauges: Vec<ModelGauge> = state
+                .models
+                .values()
+                .map(|model| ModelGauge {
+                    model: model.config.name.clone(),
+                    ready_workers: model.pool.ready_workers(),
+                    queue_depth: model.pool.queue_depth(),
+                    worker_restarts: model.pool.restarts(),
+                })
+                .collect();
+            text_response(
+                200,
+                state.metrics.render(&gauges),
+                "text/plain; version=0.0.4",
+            )
+        }
+        (Method::Get, "/v1/models") => {
+            let models: Vec<Value> = state
+                .models
+                .values()
+                .map(|model| {
+                    json!({
+                        "name": model.config.name,
+                        "task": model.config.task.as_str(),
+                        "ready_workers": model.pool.ready_workers(),
+                        "worker_restarts": model.pool.restarts(),
+                    })
+                })
+                .collect();
+            json_response(200, &json!({ "models": models }))
+        }
+        (method, path) if path.starts_with("/v1/models/") => {
+            if *method != Method::Post {
+                let err = ServeError::MethodNotAllowed;
+                json_response(err.status(), &json!({ "error": err.to_string() }))
+            } else {
+                let target = &path["/v1/models/".len()..];
+                let start = Instant::now();
+                let (model, task, result) = infer(state, &mut request, target, query);
+                let (status, body) = match result {
+                    Ok(body) => (200, body),
+                    Err(err) => (err.status(), json!({ "error": err.to_string() })),
+                };
+                state
+                    .metrics
+                    .record_request(model, task, status, start.elapsed());
+                json_response(status, &body)
+            }
+        }
+        (_, path) if path == "/v2" || path.starts_with("/v2/") => {
+            let (status, body) = match handle_v2(state, &mut request, path) {
+                Ok(response) => response,
+                Err(err) => (err.status(), json!({ "error": err.to_string() })),
+            };
+            json_response(status, &body)
+        }
+        _ => json_response(404, &json!({ "error": "not found" })),
+    };
+    let _ = request.respond(response);
+}
+
+/// メトリクスのラベルに使うモデル名、設定にないモデルは`UNKNOWN_LABEL`にまとめる
+pub(crate) fn metric_label<'a>(state: &State, name: &'a str) -> &'a str {
+    if state.models.contains_key(name) {
+        name
+    } else {
+        UNKNOWN_LABEL
+    }
+}
+
+/// 推論リクエストを処理する、メトリクス用にモデル名とタスク名も返す
+fn infer<'a>(
+    state: &'a State,
+    request: &mut Request,
+    target: &str,
+    query: &str,
+) -> (&'a str, &'a str, Result<Value, ServeError>) {
+    let Some((name, verb)) = target.split_once(':') else {
+        return (
+            UNKNOWN_LABEL,
+            UNKNOWN_LABEL,
+            Err(ServeError::NotFound(target.to_string())),
+        );
+    };
+    let Some(model) = state.models.get(name) else {
+        return (
+            UNKNOWN_LABEL,
+            UNKNOWN_LABEL,
+            Err(ServeError::NotFound(name.to_string())),
+        );
+    };
+    let result = match Task::from_verb(verb) {
+        Some(task) if task == model.config.task => infer_model(state, model, request, query),
+        Some(_) | None => Err(ServeError::BadRequest(format!(
+            "{} is a {} model",
+            name,
+            model.config.task.as_str()
+        ))),
+    };
+    (&model.config.name, model.config.task.as_str(), result)
+}
+
+fn read_body(state: &State, request: &mut Request) -> Result<Vec<u8>, ServeError> {
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(state.max_body_bytes as u64 + 1)
+        .read_to_end(&mut body)?;
+    if body.len() > state.max_body_bytes {
+        return Err(ServeError::PayloadTooLarge);
+    }
+    Ok(body)
+}
+
+/// KServe v2のHTTP/JSON
+///
+/// - `GET /v2` サーバーのメタデータ
+/// - `GET /v2/health/live`, `GET /v2/health/ready`
+/// - `GET /v2/models/{name}[/versions/{version}]` モデルのメタデータ
+/// - `GET /v2/models/{name}[/versions/{version}]/ready`
+/// - `POST /v2/models/{name}[/versions/{version}]/infer`
+fn handle_v2(state: &State, request: &mut Request, path: &str) -> Result<(u16, Value), ServeError> {
+    let method = request.method().clone();
+    let segments: Vec<&str> = path["/v2".len()..]
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    let ready = |ready: bool| (if ready { 200 } else { 503 }, json!({ "ready": ready }));
+    let (name, version, action) = match segments.as_slice() {
+        [] if method == Method::Get => {
+            return Ok((200, serde_json::to_value(kserve::server_metadata())?))
+        }
+        ["health", "live"] if method == Method::Get => return Ok((200, json!({ "live": true }))),
+        ["health", "ready"] if method == Method::Get => {
+            return Ok(ready(kserve::server_ready(state)))
+        }
+        ["models", name] => (*name, "", None),
+        ["models", name, action] => (*name, "", Some(*action)),
+        ["models", name, "versions", version] => (*name, *version, None),
+        ["models", name, "versions", version, action] => (*name, *version, Some(*action)),
+        [] | ["health", "live" | "ready"] => return Err(ServeError::MethodNotAllowed),
+        _ => return Err(ServeError::NotFound(path.to_string())),
+    };
+    match (action, method) {
+        (None, Method::Get) => Ok((
+            200,
+            serde_json::to_value(kserve::model_metadata(state, name, version)?)?,
+        )),
+        (Some("ready"), Method::Get) => Ok(ready(kserve::model_ready(state, name, version)?)),
+        (Some("infer"), Method::Post) => {
+            let start = Instant::now();
+            let result = read_body(state, request)
+                .and_then(|body| kserve::infer_json(state, name, version, &body));
+            let status = result.as_ref().map_or_else(|err| err.status(), |_| 200);
+            state.metrics.record_request(
+                metric_label(state, name),
+                "infer",
+                status,
+                start.elapsed(),
+            );
+            Ok((200, result?))
+        }
+        (None | Some("ready" | "infer"), _) => Err(ServeError::MethodNotAllowed),
+        (Some(_), _) => Err(ServeError::NotFound(path.to_string())),
+    }
+}
+
+fn infer_model(
+    state: &State,
+    model: &Model,
+    request: &mut Request,
+    query: &str,
+) -> Result<Value, ServeError> {
+    let mut params = Params {
+        threshold: model.config.threshold,
+        iou: model.config.iou,
+        top_k: model.config.top_k,
+    };
+    for (key, value) in query.split('&').filter_map(|kv| kv.split_once('=')) {
+        let invalid = || ServeError::BadRequest(format!("invalid {}: {}", key, value));
+        match key {
+            "threshold" => params.threshold = value.parse().map_err(|_| invalid())?,
+            "iou" => params.iou = value.parse().map_err(|_| invalid())?,
+            "top_k" => params.top_k = value.parse().map_err(|_| invalid())?,
+            _ => {}
+        }
+    }
+
+    let body = read_body(state, request)?;
+    let is_json = request.headers().iter().any(|header| {
+        header.field.equiv("Content-Type") && header.value.as_str().starts_with("application/json")
+    });
+    let bytes = if is_json {
+        let json: JsonRequest =
+            serde_json::from_slice(&body).map_err(|err| ServeError::BadRequest(err.to_string()))?;
+        params.threshold = json.threshold.unwrap_or(params.threshold);
+        params.iou = json.iou.unwrap_or(params.iou);
+        params.top_k = json.top_k.unwrap_or(params.top_k);
+        // data URLの場合は先頭を取り除く
+        let data = match json.image.split_once(";base64,") {
+            Some((_, data)) => data,
+            None => json.image.as_str(),
+        };
+        STANDARD
+            .decode(data)
+            .map_err(|err| ServeError::BadRequest(err.to_string()))?
+    } else {
+        body
+    };
+    let image = image::load_from_memory(&bytes)
+        .map_err(|err| ServeError::BadRequest(err.to_string()))?
+        .into_rgba8();
+    let image = ImageView::from(image);
+    let (width, height) = (image.width, image.height);
+
+    let (prediction, elapsed) = model.pool.submit(image, params)?;
+    state.metrics.record_inference(&model.config.name, elapsed);
+
+    let mut results = serde_json::to_value(&prediction)?;
+    if let (Some(labels), Value::Array(items)) = (&model.labels, &mut results) {
+        if !matches!(prediction, Prediction::Poses(_)) {
+            for item in items.iter_mut() {
+                let label = item["category"]
+                    .as_i64()
+                    .and_then(|category| labels.get(category as usize));
+                if let (Some(label), Value::Object(item)) = (label, item) {
+                    item.insert("label".to_string(), Value::from(label.as_str()));
+                }
+            }
+        }
+    }
+    Ok(json!({
+        "model": model.config.name,
+        "task": model.config.task.as_str(),
+        "width": width,
+        "height": height,
+        "inference_ms": elapsed.as_secs_f64() * 1000.,
+        "results": results,
+    }))
+}
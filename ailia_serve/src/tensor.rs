@@ -0,0 +1,386 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use ailia::DataType;
+
+use crate::ServeError;
+
+/// KServe v2のテンソルのデータ型
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Datatype {
+    Bool,
+    Uint8,
+    Uint16,
+    Uint32,
+    Uint64,
+    Int8,
+    Int16,
+    Int32,
+    Int64,
+    Fp16,
+    Bf16,
+    Fp32,
+    Fp64,
+}
+
+impl Datatype {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Datatype::Bool => "BOOL",
+            Datatype::Uint8 => "UINT8",
+            Datatype::Uint16 => "UINT16",
+            Datatype::Uint32 => "UINT32",
+            Datatype::Uint64 => "UINT64",
+            Datatype::Int8 => "INT8",
+            Datatype::Int16 => "INT16",
+            Datatype::Int32 => "INT32",
+            Datatype::Int64 => "INT64",
+            Datatype::Fp16 => "FP16",
+            Datatype::Bf16 => "BF16",
+            Datatype::Fp32 => "FP32",
+            Datatype::Fp64 => "FP64",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "BOOL" => Datatype::Bool,
+            "UINT8" => Datatype::Uint8,
+            "UINT16" => Datatype::Uint16,
+            "UINT32" => Datatype::Uint32,
+            "UINT64" => Datatype::Uint64,
+            "INT8" => Datatype::Int8,
+            "INT16" => Datatype::Int16,
+            "INT32" => Datatype::Int32,
+            "INT64" => Datatype::Int64,
+            "FP16" => Datatype::Fp16,
+            "BF16" => Datatype::Bf16,
+            "FP32" => Datatype::Fp32,
+            "FP64" => Datatype::Fp64,
+            _ => return None,
+        })
+    }
+
+    /// ailiaGetBlobDataTypeの値から変換する
+    pub fn from_ailia(data_type: DataType) -> Option<Self> {
+        Some(match data_type {
+            DataType::Float => Datatype::Fp32,
+            DataType::Uint8 => Datatype::Uint8,
+            DataType::Int8 => Datatype::Int8,
+            DataType::Uint16 => Datatype::Uint16,
+            DataType::Int16 => Datatype::Int16,
+            DataType::Int32 => Datatype::Int32,
+            DataType::Int64 => Datatype::Int64,
+            DataType::Bool => Datatype::Bool,
+            DataType::Float16 => Datatype::Fp16,
+            DataType::Double => Datatype::Fp64,
+            DataType::Uint32 => Datatype::Uint32,
+            DataType::Uint64 => Datatype::Uint64,
+            DataType::Bfloat16 => Datatype::Bf16,
+            _ => return None,
+        })
+    }
+
+    /// 1要素のバイト数
+    pub fn size(&self) -> usize {
+        match self {
+            Datatype::Bool | Datatype::Uint8 | Datatype::Int8 => 1,
+            Datatype::Uint16 | Datatype::Int16 | Datatype::Fp16 | Datatype::Bf16 => 2,
+            Datatype::Uint32 | Datatype::Int32 | Datatype::Fp32 => 4,
+            Datatype::Uint64 | Datatype::Int64 | Datatype::Fp64 => 8,
+        }
+    }
+}
+
+/// 名前と形状のみのテンソル、モデルのメタデータに使用する
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TensorMetadata {
+    pub name: String,
+    pub datatype: Datatype,
+    /// 可変長の次元は-1
+    pub shape: Vec<i64>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ModelMetadata {
+    pub inputs: Vec<TensorMetadata>,
+    pub outputs: Vec<TensorMetadata>,
+}
+
+/// リトルエンディアンのバイト列で保持するテンソル
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tensor {
+    pub name: String,
+    pub datatype: Datatype,
+    pub shape: Vec<i64>,
+    pub data: Vec<u8>,
+}
+
+impl Tensor {
+    /// 要素数、負の次元やu32に収まらない次元、要素数のオーバーフローはBadRequest
+    pub fn num_elements(&self) -> Result<usize, ServeError> {
+        let invalid = |reason: &str| {
+            ServeError::BadRequest(format!("{}: {} in {:?}", self.name, reason, self.shape))
+        };
+        self.shape.iter().try_fold(1usize, |count, &dim| {
+            if dim < 0 {
+                return Err(invalid("negative dimension"));
+            }
+            let dim = u32::try_from(dim).map_err(|_| invalid("dimension too large"))?;
+            count
+                .checked_mul(dim as usize)
+                .ok_or_else(|| invalid("too many elements"))
+        })
+    }
+
+    /// 形状とデータのサイズが一致しているか確認する
+    pub fn validate(&self) -> Result<(), ServeError> {
+        let expected = self
+            .num_elements()?
+            .checked_mul(self.datatype.size())
+            .ok_or_else(|| {
+                ServeError::BadRequest(format!(
+                    "{}: too many elements in {:?}",
+                    self.name, self.shape
+                ))
+            })?;
+        if self.data.len() != expected {
+            return Err(ServeError::BadRequest(format!(
+                "{}: expected {} bytes for shape {:?}, got {}",
+                self.name,
+                expected,
+                self.shape,
+                self.data.len()
+            )));
+        }
+        Ok(())
+    }
+
+    /// JSONの`data`(入れ子の配列も可)から作成する
+    pub fn from_json(
+        name: &str,
+        datatype: Datatype,
+        shape: Vec<i64>,
+        data: &Value,
+    ) -> Result<Self, ServeError> {
+        let mut values = Vec::new();
+        flatten(data, &mut values);
+        let invalid = |value: &Value| {
+            ServeError::BadRequest(format!(
+                "{}: {} is not a valid {}",
+                name,
+                value,
+                datatype.as_str()
+            ))
+        };
+        let mut bytes = Vec::with_capacity(values.len() * datatype.size());
+        macro_rules! encode {
+            ($get:expr) => {
+                for value in &values {
+                    let value = $get(*value).ok_or_else(|| invalid(value))?;
+                    bytes.extend_from_slice(&value.to_le_bytes());
+                }
+            };
+        }
+        match datatype {
+            Datatype::Bool => encode!(|v: &Value| v.as_bool().map(|b| b as u8)),
+            Datatype::Uint8 => encode!(|v: &Value| v.as_u64().and_then(|x| u8::try_from(x).ok())),
+            Datatype::Uint16 => {
+                encode!(|v: &Value| v.as_u64().and_then(|x| u16::try_from(x).ok()))
+            }
+            Datatype::Uint32 => {
+                encode!(|v: &Value| v.as_u64().and_then(|x| u32::try_from(x).ok()))
+            }
+            Datatype::Uint64 => encode!(|v: &Value| v.as_u64()),
+            Datatype::Int8 => encode!(|v: &Value| v.as_i64().and_then(|x| i8::try_from(x).ok())),
+            Datatype::Int16 => {
+                encode!(|v: &Value| v.as_i64().and_then(|x| i16::try_from(x).ok()))
+            }
+            Datatype::Int32 => {
+                encode!(|v: &Value| v.as_i64().and_then(|x| i32::try_from(x).ok()))
+            }
+            Datatype::Int64 => encode!(|v: &Value| v.as_i64()),
+            Datatype::Fp16 => encode!(|v: &Value| v.as_f64().map(|x| f32_to_f16(x as f32))),
+            Datatype::Bf16 => encode!(|v: &Value| v.as_f64().map(|x| f32_to_bf16(x as f32))),
+            Datatype::Fp32 => encode!(|v: &Value| v.as_f64().map(|x| x as f32)),
+            Datatype::Fp64 => encode!(|v: &Value| v.as_f64()),
+        }
+        let tensor = Self {
+            name: name.to_string(),
+            datatype,
+            shape,
+            data: bytes,
+        };
+        tensor.validate()?;
+        Ok(tensor)
+    }
+
+    /// JSONの`data`に使う1次元の配列
+    pub fn to_json(&self) -> Vec<Value> {
+        macro_rules! decode {
+            ($ty:ty, $map:expr) => {
+                self.data
+                    .chunks_exact(std::mem::size_of::<$ty>())
+                    .map(|chunk| Value::from($map(<$ty>::from_le_bytes(chunk.try_into().unwrap()))))
+                    .collect()
+            };
+            ($ty:ty) => {
+                decode!($ty, |x: $ty| x)
+            };
+        }
+        match self.datatype {
+            Datatype::Bool => self.data.iter().map(|&b| Value::from(b != 0)).collect(),
+            Datatype::Uint8 => decode!(u8),
+            Datatype::Uint16 => decode!(u16),
+            Datatype::Uint32 => decode!(u32),
+            Datatype::Uint64 => decode!(u64),
+            Datatype::Int8 => decode!(i8),
+            Datatype::Int16 => decode!(i16),
+            Datatype::Int32 => decode!(i32),
+            Datatype::Int64 => decode!(i64),
+            Datatype::Fp16 => decode!(u16, f16_to_f32),
+            Datatype::Bf16 => decode!(u16, bf16_to_f32),
+            Datatype::Fp32 => decode!(f32),
+            Datatype::Fp64 => decode!(f64),
+        }
+    }
+}
+
+fn flatten<'a>(value: &'a Value, out: &mut Vec<&'a Value>) {
+    match value {
+        Value::Array(values) => values.iter().for_each(|value| flatten(value, out)),
+        value => out.push(value),
+    }
+}
+
+pub(crate) fn f16_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1. } else { 1. };
+    let exp = ((half >> 10) & 0x1f) as u32;
+    let frac = (half & 0x3ff) as u32;
+    let magnitude = match exp {
+        0 => frac as f32 * 2f32.powi(-24),
+        0x1f if frac == 0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => f32::from_bits(((exp + 112) << 23) | (frac << 13)),
+    };
+    sign * magnitude
+}
+
+pub(crate) fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let abs = value.abs();
+    if value.is_nan() {
+        return sign | 0x7e00;
+    }
+    if abs >= 65520. {
+        return sign | 0x7c00;
+    }
+    if abs < 2f32.powi(-14) {
+        // 非正規化数、丸めで最小の正規化数になる場合もそのままの表現で正しい
+        return sign | (abs * 2f32.powi(24)).round() as u16;
+    }
+    let exp = ((bits >> 23) & 0xff) + 15 - 127;
+    let mantissa = bits & 0x7fffff;
+    let mut half = (exp << 10) | (mantissa >> 13);
+    let rest = mantissa & 0x1fff;
+    if rest > 0x1000 || (rest == 0x1000 && half & 1 == 1) {
+        half += 1;
+    }
+    sign | half as u16
+}
+
+pub(crate) fn bf16_to_f32(value: u16) -> f32 {
+    f32::from_bits((value as u32) << 16)
+}
+
+pub(crate) fn f32_to_bf16(value: f32) -> u16 {
+    if value.is_nan() {
+        return ((value.to_bits() >> 16) as u16) | 0x40;
+    }
+    let bits = value.to_bits();
+    ((bits + 0x7fff + ((bits >> 16) & 1)) >> 16) as u16
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn json_roundtrip() {
+        let tensor = Tensor::from_json(
+            "x",
+            Datatype::Fp32,
+            vec![2, 2],
+            &json!([[1.0, 2.5], [-3.0, 0.0]]),
+        )
+        .unwrap();
+        assert_eq!(tensor.data.len(), 16);
+        assert_eq!(
+            tensor.to_json(),
+            vec![json!(1.0), json!(2.5), json!(-3.0), json!(0.0)]
+        );
+
+        let tensor = Tensor::from_json("y", Datatype::Int64, vec![3], &json!([1, -2, 3])).unwrap();
+        assert_eq!(tensor.to_json(), vec![json!(1), json!(-2), json!(3)]);
+
+        let tensor =
+            Tensor::from_json("z", Datatype::Fp16, vec![3], &json!([1.0, -0.5, 65504.0])).unwrap();
+        assert_eq!(
+            tensor.to_json(),
+            vec![json!(1.0), json!(-0.5), json!(65504.0)]
+        );
+
+        // 要素数が形状と一致しない
+        assert!(Tensor::from_json("x", Datatype::Fp32, vec![3], &json!([1.0, 2.0])).is_err());
+        // 範囲外
+        assert!(Tensor::from_json("x", Datatype::Uint8, vec![1], &json!([256])).is_err());
+    }
+
+    #[test]
+    fn invalid_shape() {
+        let tensor = |shape: Vec<i64>| Tensor {
+            name: "x".to_string(),
+            datatype: Datatype::Fp32,
+            shape,
+            data: Vec::new(),
+        };
+        assert_eq!(tensor(vec![2, 3]).num_elements().unwrap(), 6);
+        for shape in [
+            vec![-1, 3],
+            vec![1 << 32, 1],
+            vec![u32::MAX as i64, u32::MAX as i64, u32::MAX as i64],
+        ] {
+            assert!(matches!(
+                tensor(shape.clone()).num_elements(),
+                Err(ServeError::BadRequest(_))
+            ));
+            assert!(matches!(
+                tensor(shape).validate(),
+                Err(ServeError::BadRequest(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn half_precision() {
+        for value in [
+            0.,
+            1.,
+            -2.,
+            0.333_251_95,
+            6.1035156e-5,
+            5.9604645e-8,
+            65504.,
+        ] {
+            assert_eq!(f16_to_f32(f32_to_f16(value)), value);
+        }
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(1e6), 0x7c00);
+        assert!(f16_to_f32(f32_to_f16(f32::NAN)).is_nan());
+        assert_eq!(f32_to_bf16(1.0), 0x3f80);
+        assert_eq!(bf16_to_f32(f32_to_bf16(-2.5)), -2.5);
+    }
+}
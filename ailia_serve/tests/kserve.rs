@@ -0,0 +1,244 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ailia::video::ImageView;
+
+use ailia_serve::config::{ModelConfig, ServerConfig, Task};
+use ailia_serve::pool::PredictorFactory;
+use ailia_serve::predictor::{Params, Prediction, Predictor};
+use ailia_serve::server::{Server, ServerHandle};
+use ailia_serve::tensor::{Datatype, ModelMetadata, Tensor, TensorMetadata};
+use ailia_serve::ServeError;
+
+use serde_json::{json, Value};
+
+/// 入力`x`(FP32)を2倍にして`y`として返すモデル
+struct Doubler;
+
+impl Predictor for Doubler {
+    fn predict(&mut self, _image: &ImageView, _params: &Params) -> Result<Prediction, ServeError> {
+        Err(ServeError::Unsupported("tensor only".to_string()))
+    }
+
+    fn infer(&mut self, inputs: &[Tensor], _outputs: &[String]) -> Result<Vec<Tensor>, ServeError> {
+        let x = inputs
+            .iter()
+            .find(|input| input.name == "x" && input.datatype == Datatype::Fp32)
+            .ok_or_else(|| ServeError::BadRequest("missing input: x".to_string()))?;
+        let data = x
+            .data
+            .chunks_exact(4)
+            .flat_map(|chunk| (f32::from_le_bytes(chunk.try_into().unwrap()) * 2.).to_le_bytes())
+            .collect();
+        Ok(vec![Tensor {
+            name: "y".to_string(),
+            datatype: Datatype::Fp32,
+            shape: x.shape.clone(),
+            data,
+        }])
+    }
+
+    fn metadata(&self) -> Result<ModelMetadata, ServeError> {
+        let tensor = |name: &str| TensorMetadata {
+            name: name.to_string(),
+            datatype: Datatype::Fp32,
+            shape: vec![1, -1],
+        };
+        Ok(ModelMetadata {
+            inputs: vec![tensor("x")],
+            outputs: vec![tensor("y")],
+        })
+    }
+}
+
+fn start(grpc: bool) -> (ServerHandle, String) {
+    let server = ServerConfig {
+        bind: "127.0.0.1:0".to_string(),
+        grpc_bind: grpc.then(|| "127.0.0.1:0".to_string()),
+        ..Default::default()
+    };
+    let factory: PredictorFactory = Arc::new(|| Ok(Box::new(Doubler) as Box<dyn Predictor>));
+    let model = ModelConfig::new("doubler", Task::Network, "doubler.onnx");
+    let handle = Server::with_factories(&server, vec![(model, factory)])
+        .unwrap()
+        .spawn();
+    let url = format!("http://{}", handle.addr());
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while ureq::get(&format!("{}/v2/health/ready", url))
+        .call()
+        .is_err()
+    {
+        assert!(Instant::now() < deadline, "server did not become ready");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    (handle, url)
+}
+
+fn status(result: Result<ureq::Response, ureq::Error>) -> u16 {
+    match result {
+        Ok(response) => response.status(),
+        Err(ureq::Error::Status(status, _)) => status,
+        Err(err) => panic!("{}", err),
+    }
+}
+
+#[test]
+fn http_json() {
+    let (_handle, url) = start(false);
+    let server: Value = ureq::get(&format!("{}/v2", url))
+        .call()
+        .unwrap()
+        .into_json()
+        .unwrap();
+    assert_eq!(server["name"], "ailia-serve");
+    assert_eq!(
+        status(ureq::get(&format!("{}/v2/health/live", url)).call()),
+        200
+    );
+    assert_eq!(
+        status(ureq::get(&format!("{}/v2/models/doubler/ready", url)).call()),
+        200
+    );
+
+    let metadata: Value = ureq::get(&format!("{}/v2/models/doubler", url))
+        .call()
+        .unwrap()
+        .into_json()
+        .unwrap();
+    assert_eq!(metadata["platform"], "ailia");
+    assert_eq!(metadata["versions"], json!(["1"]));
+    assert_eq!(
+        metadata["inputs"],
+        json!([{ "name": "x", "datatype": "FP32", "shape": [1, -1] }])
+    );
+
+    let response: Value = ureq::post(&format!("{}/v2/models/doubler/versions/1/infer", url))
+        .send_json(json!({
+            "id": "42",
+            "inputs": [{ "name": "x", "shape": [1, 3], "datatype": "FP32", "data": [[1.0, 2.5, -4.0]] }],
+        }))
+        .unwrap()
+        .into_json()
+        .unwrap();
+    assert_eq!(response["model_name"], "doubler");
+    assert_eq!(response["model_version"], "1");
+    assert_eq!(response["id"], "42");
+    assert_eq!(
+        response["outputs"],
+        json!([{ "name": "y", "shape": [1, 3], "datatype": "FP32", "data": [2.0, 5.0, -8.0] }])
+    );
+
+    // 要素数が形状と一致しない
+    let mismatch = json!({
+        "inputs": [{ "name": "x", "shape": [1, 4], "datatype": "FP32", "data": [1.0] }],
+    });
+    assert_eq!(
+        status(ureq::post(&format!("{}/v2/models/doubler/infer", url)).send_json(mismatch)),
+        400
+    );
+    assert_eq!(
+        status(
+            ureq::post(&format!("{}/v2/models/doubler/versions/2/infer", url)).send_json(json!({}))
+        ),
+        404
+    );
+    assert_eq!(
+        status(ureq::get(&format!("{}/v2/models/doubler/infer", url)).call()),
+        405
+    );
+    // 画像のエンドポイントは使えない
+    assert_eq!(
+        status(ureq::post(&format!("{}/v1/models/doubler:detect", url)).send_bytes(b"")),
+        400
+    );
+}
+
+#[cfg(feature = "grpc")]
+#[test]
+fn grpc() {
+    use ailia_serve::grpc::proto::grpc_inference_service_client::GrpcInferenceServiceClient;
+    use ailia_serve::grpc::proto::model_infer_request::InferInputTensor;
+    use ailia_serve::grpc::proto::*;
+
+    let (handle, _url) = start(true);
+    let addr = format!("http://{}", handle.grpc_addr().unwrap());
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let mut client = GrpcInferenceServiceClient::connect(addr).await.unwrap();
+        let ready = client
+            .server_ready(ServerReadyRequest {})
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(ready.ready);
+
+        let metadata = client
+            .model_metadata(ModelMetadataRequest {
+                name: "doubler".to_string(),
+                version: String::new(),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(metadata.inputs[0].name, "x");
+        assert_eq!(metadata.outputs[0].datatype, "FP32");
+
+        // 型付きのcontents
+        let response = client
+            .model_infer(ModelInferRequest {
+                model_name: "doubler".to_string(),
+                inputs: vec![InferInputTensor {
+                    name: "x".to_string(),
+                    datatype: "FP32".to_string(),
+                    shape: vec![1, 2],
+                    contents: Some(InferTensorContents {
+                        fp32_contents: vec![1.5, -1.0],
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.outputs[0].name, "y");
+        assert_eq!(response.outputs[0].shape, vec![1, 2]);
+        let y: Vec<f32> = response.raw_output_contents[0]
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        assert_eq!(y, vec![3.0, -2.0]);
+
+        // raw_input_contents
+        let response = client
+            .model_infer(ModelInferRequest {
+                model_name: "doubler".to_string(),
+                inputs: vec![InferInputTensor {
+                    name: "x".to_string(),
+                    datatype: "FP32".to_string(),
+                    shape: vec![1, 1],
+                    ..Default::default()
+                }],
+                raw_input_contents: vec![4.0f32.to_le_bytes().to_vec()],
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            response.raw_output_contents[0],
+            8.0f32.to_le_bytes().to_vec()
+        );
+
+        let status = client
+            .model_infer(ModelInferRequest {
+                model_name: "unknown".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+    });
+}
@@ -0,0 +1,277 @@
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ailia::prelude::*;
+use ailia::video::ImageView;
+
+use ailia_serve::config::{ModelConfig, ServerConfig, Task};
+use ailia_serve::pool::PredictorFactory;
+use ailia_serve::predictor::{Params, Prediction, Predictor};
+use ailia_serve::server::{Server, ServerHandle};
+use ailia_serve::ServeError;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::{json, Value};
+
+/// 画像全体を1つの物体、または1クラスとして返すモデル
+struct FakePredictor(Task);
+
+impl Predictor for FakePredictor {
+    fn predict(&mut self, image: &ImageView, params: &Params) -> Result<Prediction, ServeError> {
+        Ok(match self.0 {
+            Task::Detect => Prediction::Objects(vec![Object {
+                category: 1,
+                prob: params.threshold,
+                x: 0.,
+                y: 0.,
+                w: image.width as f32,
+                h: image.height as f32,
+            }]),
+            Task::Classify => Prediction::Classes(
+                (0..params.top_k as i32)
+                    .map(|category| Class {
+                        category,
+                        prob: 1. / (category + 1) as f32,
+                    })
+                    .collect(),
+            ),
+            Task::Pose | Task::Network => Prediction::Poses(Vec::new()),
+        })
+    }
+}
+
+/// 推論のたびにpanicするモデル
+struct PanicPredictor;
+
+impl Predictor for PanicPredictor {
+    fn predict(&mut self, _image: &ImageView, _params: &Params) -> Result<Prediction, ServeError> {
+        panic!("predictor panicked");
+    }
+}
+
+fn factory(task: Task) -> PredictorFactory {
+    Arc::new(move || Ok(Box::new(FakePredictor(task)) as Box<dyn Predictor>))
+}
+
+fn fixture_png(width: u32, height: u32) -> Vec<u8> {
+    let image = image::RgbImage::from_pixel(width, height, image::Rgb([128, 64, 32]));
+    let mut bytes = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut bytes), image::ImageOutputFormat::Png)
+        .unwrap();
+    bytes
+}
+
+fn start() -> (ServerHandle, String) {
+    let server = ServerConfig {
+        bind: "127.0.0.1:0".to_string(),
+        ..Default::default()
+    };
+    let mut detector = ModelConfig::new("yolox", Task::Detect, "yolox_s");
+    detector.labels = Some(vec!["person".to_string(), "bicycle".to_string()]);
+    let classifier = ModelConfig::new("resnet", Task::Classify, "resnet18");
+    let server = Server::with_factories(
+        &server,
+        vec![
+            (detector, factory(Task::Detect)),
+            (classifier, factory(Task::Classify)),
+        ],
+    )
+    .unwrap();
+    let handle = server.spawn();
+    let url = format!("http://{}", handle.addr());
+
+    // ワーカーのモデル読み込みを待つ
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while ureq::get(&format!("{}/readyz", url)).call().is_err() {
+        assert!(Instant::now() < deadline, "server did not become ready");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    (handle, url)
+}
+
+fn status(result: Result<ureq::Response, ureq::Error>) -> u16 {
+    match result {
+        Ok(response) => response.status(),
+        Err(ureq::Error::Status(status, _)) => status,
+        Err(err) => panic!("{}", err),
+    }
+}
+
+#[test]
+fn detect_raw_bytes() {
+    let (_handle, url) = start();
+    let body: Value = ureq::post(&format!("{}/v1/models/yolox:detect?threshold=0.25", url))
+        .set("Content-Type", "image/png")
+        .send_bytes(&fixture_png(64, 48))
+        .unwrap()
+        .into_json()
+        .unwrap();
+    assert_eq!(body["model"], "yolox");
+    assert_eq!(body["task"], "detect");
+    assert_eq!(body["width"], 64);
+    assert_eq!(body["height"], 48);
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0]["prob"], 0.25);
+    assert_eq!(results[0]["w"], 64.);
+    assert_eq!(results[0]["label"], "bicycle");
+}
+
+#[test]
+fn classify_base64_json() {
+    let (_handle, url) = start();
+    let image = format!(
+        "data:image/png;base64,{}",
+        STANDARD.encode(fixture_png(8, 8))
+    );
+    let body: Value = ureq::post(&format!("{}/v1/models/resnet:classify", url))
+        .send_json(json!({ "image": image, "top_k": 3 }))
+        .unwrap()
+        .into_json()
+        .unwrap();
+    assert_eq!(body["task"], "classify");
+    assert_eq!(body["results"].as_array().unwrap().len(), 3);
+    assert_eq!(body["results"][0]["category"], 0);
+    assert!(body["results"][0].get("label").is_none());
+}
+
+#[test]
+fn errors() {
+    let (_handle, url) = start();
+    let png = fixture_png(8, 8);
+    // タスクが違う
+    assert_eq!(
+        status(ureq::post(&format!("{}/v1/models/yolox:classify", url)).send_bytes(&png)),
+        400
+    );
+    // 画像ではない
+    assert_eq!(
+        status(ureq::post(&format!("{}/v1/models/yolox:detect", url)).send_bytes(b"not an image")),
+        400
+    );
+    assert_eq!(
+        status(ureq::post(&format!("{}/v1/models/unknown:detect", url)).send_bytes(&png)),
+        404
+    );
+    assert_eq!(
+        status(ureq::get(&format!("{}/v1/models/yolox:detect", url)).call()),
+        405
+    );
+}
+
+#[test]
+fn health_and_metrics() {
+    let (_handle, url) = start();
+    assert_eq!(status(ureq::get(&format!("{}/healthz", url)).call()), 200);
+    assert_eq!(status(ureq::get(&format!("{}/readyz", url)).call()), 200);
+
+    let models: Value = ureq::get(&format!("{}/v1/models", url))
+        .call()
+        .unwrap()
+        .into_json()
+        .unwrap();
+    assert_eq!(models["models"].as_array().unwrap().len(), 2);
+
+    ureq::post(&format!("{}/v1/models/yolox:detect", url))
+        .send_bytes(&fixture_png(8, 8))
+        .unwrap();
+    // 設定にないモデル名はラベルに使わない
+    let _ = ureq::post(&format!("{}/v1/models/bogus:detect", url)).send_bytes(b"");
+    let metrics = ureq::get(&format!("{}/metrics", url))
+        .call()
+        .unwrap()
+        .into_string()
+        .unwrap();
+    assert!(
+        metrics.contains("ailia_requests_total{model=\"yolox\",task=\"detect\",status=\"200\"} 1")
+    );
+    assert!(metrics.contains("ailia_inference_duration_seconds_count{model=\"yolox\"} 1"));
+    assert!(metrics.contains("ailia_workers_ready{model=\"resnet\"} 1"));
+    assert!(metrics
+        .contains("ailia_requests_total{model=\"unknown\",task=\"unknown\",status=\"404\"} 1"));
+    assert!(!metrics.contains("bogus"));
+}
+
+#[test]
+fn worker_failures() {
+    let server = ServerConfig {
+        bind: "127.0.0.1:0".to_string(),
+        ..Default::default()
+    };
+    let broken: PredictorFactory =
+        Arc::new(|| Err(ServeError::Config("missing weight".to_string())));
+    let panicking: PredictorFactory =
+        Arc::new(|| Ok(Box::new(PanicPredictor) as Box<dyn Predictor>));
+    let server = Server::with_factories(
+        &server,
+        vec![
+            (ModelConfig::new("broken", Task::Detect, "yolox_s"), broken),
+            (
+                ModelConfig::new("panic", Task::Detect, "yolox_s"),
+                panicking,
+            ),
+        ],
+    )
+    .unwrap();
+    let handle = server.spawn();
+    let url = format!("http://{}", handle.addr());
+
+    // 読み込みに失敗した理由をreadyzで返す
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let body = loop {
+        let body: Value = match ureq::get(&format!("{}/readyz", url)).call() {
+            Err(ureq::Error::Status(503, response)) => response.into_json().unwrap(),
+            res => panic!("unexpected result {:?}", res),
+        };
+        if body["errors"]["broken"].is_string() && body["models"] == json!(["broken"]) {
+            break body;
+        }
+        assert!(Instant::now() < deadline, "load error was not reported");
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    assert!(body["errors"]["broken"]
+        .as_str()
+        .unwrap()
+        .contains("missing weight"));
+    assert_eq!(
+        status(
+            ureq::post(&format!("{}/v1/models/broken:detect", url)).send_bytes(&fixture_png(8, 8))
+        ),
+        503
+    );
+
+    // panicしたリクエストは503になり、ワーカーはモデルを作り直して処理を続ける
+    let models: Value = ureq::get(&format!("{}/v1/models", url))
+        .call()
+        .unwrap()
+        .into_json()
+        .unwrap();
+    assert_eq!(models["models"][1]["ready_workers"], 1);
+    for restarts in 1..=2 {
+        assert_eq!(
+            status(
+                ureq::post(&format!("{}/v1/models/panic:detect", url))
+                    .send_bytes(&fixture_png(8, 8))
+            ),
+            503
+        );
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let models: Value = ureq::get(&format!("{}/v1/models", url))
+                .call()
+                .unwrap()
+                .into_json()
+                .unwrap();
+            if models["models"][1]["ready_workers"] == 1
+                && models["models"][1]["worker_restarts"] == restarts
+            {
+                break;
+            }
+            assert!(Instant::now() < deadline, "worker was not restarted");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+use ailia::eval::{
+    evaluate_detections, evaluate_keypoints, load_classification_list, predict_classification,
+    predict_detections, predict_keypoints, ApMetrics, CocoDataset,
+};
+use ailia::export::COCO_CATEGORY_IDS;
+use ailia::prelude::*;
+use ailia::{AILIA_ENVIRONMENT_ID_AUTO, AILIA_MULTITHREAD_AUTO};
+
+use anyhow::{bail, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde_json::json;
+
+#[derive(Parser)]
+#[command(about = "Evaluate ailia models on annotated datasets")]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+    /// 結果をJSONで書き出すファイル
+    #[arg(long, global = true)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// COCO形式のinstancesアノテーションで矩形のAPを計算する
+    Detect {
+        /// zooのモデル名、--onnxを指定した場合は結果の表示にのみ使う
+        #[arg(long, default_value = "yolox_s")]
+        model: String,
+        /// zooではなくローカルのonnxファイルを使う
+        #[arg(long)]
+        onnx: Option<PathBuf>,
+        #[arg(long, requires = "onnx")]
+        prototxt: Option<PathBuf>,
+        /// 入力サイズ(WIDTHxHEIGHT)、--onnxを指定した場合は必須
+        #[arg(long, value_parser = parse_size)]
+        input_size: Option<(u32, u32)>,
+        #[arg(long, value_enum, default_value = "yolox")]
+        algorithm: DetectorAlgorithmArg,
+        #[arg(long, default_value_t = 80)]
+        category_count: u32,
+        #[arg(long)]
+        env_id: Option<i32>,
+        #[arg(long)]
+        num_threads: Option<i32>,
+        #[arg(long)]
+        images: PathBuf,
+        #[arg(long)]
+        annotations: PathBuf,
+        #[arg(long, default_value_t = 0.01)]
+        threshold: f32,
+        #[arg(long, default_value_t = 0.65)]
+        iou: f32,
+        /// 検出結果のcategoryをCOCOの80クラスからcategory_idに変換しない
+        #[arg(long)]
+        raw_category: bool,
+    },
+    /// COCO形式のperson_keypointsアノテーションでOKSによるAPを計算する
+    Pose {
+        #[arg(long, default_value = "lightweight-human-pose-estimation")]
+        model: String,
+        #[arg(long)]
+        images: PathBuf,
+        #[arg(long)]
+        annotations: PathBuf,
+    },
+    /// `<画像のパス> <カテゴリ番号>`のリストでtop-1/top-5の正解率を計算する
+    Classify {
+        #[arg(long, default_value = "resnet18")]
+        model: String,
+        #[arg(long)]
+        images: PathBuf,
+        #[arg(long)]
+        labels: PathBuf,
+    },
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum DetectorAlgorithmArg {
+    Yolov1,
+    Yolov2,
+    Yolov3,
+    Yolov4,
+    Yolox,
+    Ssd,
+}
+
+impl From<DetectorAlgorithmArg> for DetectorAlgorithm {
+    fn from(value: DetectorAlgorithmArg) -> Self {
+        match value {
+            DetectorAlgorithmArg::Yolov1 => DetectorAlgorithm::Yolov1,
+            DetectorAlgorithmArg::Yolov2 => DetectorAlgorithm::Yolov2,
+            DetectorAlgorithmArg::Yolov3 => DetectorAlgorithm::Yolov3,
+            DetectorAlgorithmArg::Yolov4 => DetectorAlgorithm::Yolov4,
+            DetectorAlgorithmArg::Yolox => DetectorAlgorithm::Yolox,
+            DetectorAlgorithmArg::Ssd => DetectorAlgorithm::Ssd,
+        }
+    }
+}
+
+fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    let (width, height) = s
+        .split_once(['x', 'X'])
+        .ok_or_else(|| format!("expected WIDTHxHEIGHT: {}", s))?;
+    Ok((
+        width.parse().map_err(|_| format!("invalid width: {}", s))?,
+        height
+            .parse()
+            .map_err(|_| format!("invalid height: {}", s))?,
+    ))
+}
+
+/// 検出器の指定、onnxがなければzooのモデルを使う
+struct DetectorSpec {
+    model: String,
+    onnx: Option<PathBuf>,
+    prototxt: Option<PathBuf>,
+    input_size: Option<(u32, u32)>,
+    algorithm: DetectorAlgorithmArg,
+    category_count: u32,
+    env_id: Option<i32>,
+    num_threads: Option<i32>,
+}
+
+impl DetectorSpec {
+    fn build(self) -> Result<Detector> {
+        let (onnx, prototxt, algorithm, category_count, input_size) = match self.onnx {
+            Some(onnx) => {
+                let Some(input_size) = self.input_size else {
+                    bail!("--input-size is required with --onnx");
+                };
+                (
+                    onnx,
+                    self.prototxt,
+                    self.algorithm.into(),
+                    self.category_count,
+                    input_size,
+                )
+            }
+            None => {
+                let (desc, files) = Downloader::default().fetch_by_name(&self.model)?;
+                let TaskConfig::Detector {
+                    algorithm,
+                    category_count,
+                    input_width,
+                    input_height,
+                } = desc.task
+                else {
+                    bail!("{} is not a detector model", self.model);
+                };
+                (
+                    files.onnx,
+                    files.prototxt,
+                    algorithm,
+                    category_count,
+                    self.input_size.unwrap_or((input_width, input_height)),
+                )
+            }
+        };
+        let mut builder = DetectorBuilder::default()
+            .onnx(onnx)
+            .env_id(self.env_id.unwrap_or(AILIA_ENVIRONMENT_ID_AUTO))
+            .num_threads(
+                self.num_threads
+                    .unwrap_or_else(|| AILIA_MULTITHREAD_AUTO.try_into().unwrap()),
+            )
+            .algorithm(algorithm)
+            .category_count(category_count);
+        if let Some(prototxt) = prototxt {
+            builder = builder.prototxt(prototxt);
+        }
+        let detector = builder.build()?;
+        detector.set_input_shape(input_size.0, input_size.1)?;
+        Ok(detector)
+    }
+}
+
+fn print_ap(metrics: &ApMetrics) {
+    println!("mAP@[.5:.95] {:.4}", metrics.map);
+    println!("AP50         {:.4}", metrics.ap50);
+    println!("AP75         {:.4}", metrics.ap75);
+    for class in &metrics.per_class {
+        println!(
+            "{:>4} {:<20} AP {:.4} AP50 {:.4} AP75 {:.4} ({} gt)",
+            class.category_id, class.name, class.ap, class.ap50, class.ap75, class.num_gt
+        );
+    }
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let result = match args.command {
+        Command::Detect {
+            model,
+            onnx,
+            prototxt,
+            input_size,
+            algorithm,
+            category_count,
+            env_id,
+            num_threads,
+            images,
+            annotations,
+            threshold,
+            iou,
+            raw_category,
+        } => {
+            let dataset = CocoDataset::load(annotations)?;
+            let detector = DetectorSpec {
+                model: model.clone(),
+                onnx,
+                prototxt,
+                input_size,
+                algorithm,
+                category_count,
+                env_id,
+                num_threads,
+            }
+            .build()?;
+            let category_ids = (!raw_category).then_some(&COCO_CATEGORY_IDS[..]);
+            let detections =
+                predict_detections(&detector, &dataset, images, threshold, iou, category_ids)?;
+            let metrics = evaluate_detections(&dataset, &detections);
+            print_ap(&metrics);
+            json!({ "model": model, "threshold": threshold, "iou": iou, "metrics": metrics })
+        }
+        Command::Pose {
+            model,
+            images,
+            annotations,
+        } => {
+            let dataset = CocoDataset::load(annotations)?;
+            let estimator = PoseEstimator::<Pose>::from_zoo(&model)?;
+            let keypoints = predict_keypoints(&estimator, &dataset, images)?;
+            let metrics = evaluate_keypoints(&dataset, &keypoints);
+            print_ap(&metrics);
+            json!({ "model": model, "metrics": metrics })
+        }
+        Command::Classify {
+            model,
+            images,
+            labels,
+        } => {
+            let list = load_classification_list(labels)?;
+            let classifier = Classifier::from_zoo(&model)?;
+            let metrics = predict_classification(&classifier, &list, images)?;
+            println!("images {}", metrics.count);
+            println!("top-1  {:.4}", metrics.top1);
+            println!("top-5  {:.4}", metrics.top5);
+            json!({ "model": model, "metrics": metrics })
+        }
+    };
+
+    if let Some(output) = args.output {
+        serde_json::to_writer_pretty(BufWriter::new(File::create(output)?), &result)?;
+    }
+    Ok(())
+}
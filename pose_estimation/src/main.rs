@@ -1,11 +1,12 @@
 use ailia::prelude::*;
 use anyhow::Result;
 
+use ailia::video::FrameReaderBuilder;
+
 use opencv::core::{Mat, Point, Size, Scalar};
 use opencv::highgui;
-use opencv::imgproc::{cvt_color, circle, resize, COLOR_BGRA2BGR};
+use opencv::imgproc::{cvt_color, circle, COLOR_RGBA2BGR};
 use opencv::prelude::*;
-use opencv::videoio::{self, VideoCaptureTrait, VideoCaptureTraitConst};
 
 const WIDTH: u32 = 320;
 const HEIGHT: u32 = 240;
@@ -28,42 +29,39 @@ fn main() -> Result<()> {
     let pose_estimator: PoseEstimator<Pose> = PoseEstimatorBuilder::default()
         .prototxt("../models/lightweight-human-pose-estimation.onnx.prototxt")
         .onnx("../models/lightweight-human-pose-estimation.onnx")
-        .algorithm(AILIA_POSE_ESTIMATOR_ALGORITHM_LW_HUMAN_POSE)
+        .algorithm(PoseAlgorithm::LwHumanPose)
         .build()?;
     let shape = Shape { x: HEIGHT, y: WIDTH, z: 3, w: 1, dim: 4 };
     pose_estimator.set_input_shape(shape)?;
     println!("build model");
 
+    // 引数がない場合はカメラ0番、動画ファイル、画像ディレクトリ、RTSPのURLなども指定できる
+    let input = std::env::args().nth(1).unwrap_or_else(|| "0".to_string());
+    let reader = FrameReaderBuilder::default()
+        .input(input)
+        .size((WIDTH, HEIGHT))
+        .build()?;
+
     let window = "PoseEstimation infered by ailia SDK";
     highgui::named_window(window, highgui::WINDOW_AUTOSIZE)?;
-    let mut cam = videoio::VideoCapture::new(0, videoio::CAP_ANY)?; // 0 is the default camera
-    let opened = videoio::VideoCapture::is_opened(&cam)?;
-    if !opened {
-        panic!("Unable to open default camera!");
-    }
 
-    loop {
-        let mut frame = Mat::default();
-        cam.read(&mut frame)?;
-        if frame.size()?.width > 0 {
-            let mut frame_resize = Mat::default();
-            let target_size = Size::new(WIDTH.try_into()?, HEIGHT.try_into()?);
-            resize(&frame, &mut frame_resize, target_size, 0., 0., 0)?;
-            let poses = pose_estimator.predict(frame_resize.data() as *const std::os::raw::c_void, WIDTH * 3, WIDTH, HEIGHT, ailia::AILIA_IMAGE_FORMAT_BGR)?;
+    for frame in reader {
+        let image = frame?.image;
+        let poses = pose_estimator.predict(image.as_ptr(), image.stride(), image.width, image.height, image.format())?;
 
-            let size = frame.size()?;
+        let mut frame = image.to_mat()?;
+        let size = frame.size()?;
 
-            for pose in poses {
-                for keypoint in pose.points {
-                    plot_point(&mut frame, keypoint, size);
-                }
+        for pose in poses {
+            for keypoint in pose.points {
+                plot_point(&mut frame, keypoint, size);
             }
+        }
 
-            let frame_clone = frame.clone();
-            cvt_color(&frame_clone, &mut frame, COLOR_BGRA2BGR, 0)?;
+        let frame_clone = frame.clone();
+        cvt_color(&frame_clone, &mut frame, COLOR_RGBA2BGR, 0)?;
 
-            highgui::imshow(window, &frame)?;
-        }
+        highgui::imshow(window, &frame)?;
         let key = highgui::wait_key(10)?;
         if key > 0 && key != 255 {
             break;
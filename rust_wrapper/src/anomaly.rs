@@ -0,0 +1,1113 @@
+//! 正常画像のみから学習する異常検知(PaDiM, PatchCore)
+//!
+//! バックボーンの中間Blobを特徴量として読み、位置ごとのガウス分布(PaDiM)または
+//! パッチ特徴量のメモリバンク(PatchCore)を当てはめる。推論では位置ごとの異常度のマップを返す。
+
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::Deref;
+use std::path::Path;
+
+use image::{DynamicImage, RgbImage, Rgba, RgbaImage};
+
+use thiserror::Error;
+
+use crate::binary::{read_f32s, read_u32, write_f32s, write_u32};
+use crate::depth::Colormap;
+use crate::instance::BitMask;
+use crate::network::Network;
+use crate::preprocess::{
+    resample_to_source, FilterType, Normalize, Preprocess, PreprocessError, Transform,
+};
+use crate::video::{FrameSource, ImageDirSource, ImageView, VideoError};
+use crate::AiliaError;
+
+use ailia_sys::*;
+
+const MODEL_MAGIC: &[u8; 4] = b"AANM";
+const MODEL_VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum AnomalyError {
+    #[error("出力の形状が不正です: {0:?}")]
+    InvalidShape(Vec<u32>),
+    #[error("特徴量の形状が学習時と異なります")]
+    FeatureMismatch,
+    #[error("学習していません")]
+    NotFitted,
+    #[error("学習に使う画像がありません")]
+    NoImages,
+    #[error("学習方法の設定が不正です: {0}")]
+    InvalidMethod(String),
+    #[error("学習結果のファイルが不正です: {0}")]
+    InvalidModel(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Video(#[from] VideoError),
+    #[error(transparent)]
+    Preprocess(#[from] PreprocessError),
+    #[error(transparent)]
+    Ailia(#[from] AiliaError),
+}
+
+/// 位置ごとの特徴量、dataは位置(行優先)ごとにdim個ずつ並ぶ
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeatureMap {
+    pub width: u32,
+    pub height: u32,
+    pub dim: usize,
+    pub data: Vec<f32>,
+}
+
+impl FeatureMap {
+    /// 複数のBlob((1, C, H, W)または(C, H, W))を1番目のBlobの解像度にそろえて(最近傍)チャンネル方向に連結する
+    pub fn from_blobs(blobs: &[(Vec<f32>, Vec<u32>)]) -> Result<Self, AnomalyError> {
+        let mut layers = Vec::with_capacity(blobs.len());
+        for (data, shape) in blobs {
+            match shape[..] {
+                [1, c, h, w] | [c, h, w] if data.len() == (c * h * w) as usize => {
+                    layers.push((data, c as usize, h, w))
+                }
+                _ => return Err(AnomalyError::InvalidShape(shape.clone())),
+            }
+        }
+        let Some(&(_, _, height, width)) = layers.first() else {
+            return Err(AnomalyError::InvalidShape(Vec::new()));
+        };
+        let dim = layers.iter().map(|layer| layer.1).sum();
+        let mut data = Vec::with_capacity((width * height) as usize * dim);
+        for y in 0..height {
+            for x in 0..width {
+                for &(layer, channels, h, w) in &layers {
+                    let (src_x, src_y) = (x * w / width, y * h / height);
+                    let plane = (h * w) as usize;
+                    let offset = (src_y * w + src_x) as usize;
+                    data.extend((0..channels).map(|c| layer[c * plane + offset]));
+                }
+            }
+        }
+        Ok(FeatureMap {
+            width,
+            height,
+            dim,
+            data,
+        })
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> &[f32] {
+        let offset = (y * self.width + x) as usize * self.dim;
+        &self.data[offset..offset + self.dim]
+    }
+
+    /// 周囲のpatch_size四方の平均(PatchCoreの近傍の集約)、端では画像内のみで平均する
+    pub fn local_average(&self, patch_size: u32) -> FeatureMap {
+        let radius = patch_size / 2;
+        let mut data = Vec::with_capacity(self.data.len());
+        for y in 0..self.height {
+            let (y0, y1) = (y.saturating_sub(radius), (y + radius + 1).min(self.height));
+            for x in 0..self.width {
+                let (x0, x1) = (x.saturating_sub(radius), (x + radius + 1).min(self.width));
+                let mut sum = vec![0f32; self.dim];
+                for ny in y0..y1 {
+                    for nx in x0..x1 {
+                        sum.iter_mut()
+                            .zip(self.get(nx, ny))
+                            .for_each(|(sum, value)| *sum += value);
+                    }
+                }
+                let count = ((x1 - x0) * (y1 - y0)) as f32;
+                data.extend(sum.into_iter().map(|sum| sum / count));
+            }
+        }
+        FeatureMap {
+            width: self.width,
+            height: self.height,
+            dim: self.dim,
+            data,
+        }
+    }
+}
+
+/// 学習の方法
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnomalyMethod {
+    /// 位置ごとの多変量ガウス分布とのマハラノビス距離
+    Padim {
+        /// ランダムに選ぶチャンネル数、Noneは全チャンネル
+        dim: Option<usize>,
+        /// 共分散行列の対角に足す値
+        eps: f32,
+    },
+    /// メモリバンクの最近傍までのユークリッド距離
+    PatchCore {
+        /// k-center greedyでメモリバンクに残すパッチの割合
+        coreset_ratio: f32,
+        /// 近傍の集約に使う範囲
+        patch_size: u32,
+    },
+}
+
+impl Default for AnomalyMethod {
+    fn default() -> Self {
+        AnomalyMethod::Padim {
+            dim: Some(100),
+            eps: 0.01,
+        }
+    }
+}
+
+/// 重複のない乱数列(xorshift64)でn個からk個を選び、昇順に並べる
+fn random_subset(n: usize, k: usize, mut seed: u64) -> Vec<usize> {
+    let mut indices: Vec<usize> = (0..n).collect();
+    let k = k.min(n);
+    for i in 0..k {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        let j = i + (seed % (n - i) as u64) as usize;
+        indices.swap(i, j);
+    }
+    indices.truncate(k);
+    indices.sort_unstable();
+    indices
+}
+
+/// 正定値対称行列の逆行列(コレスキー分解)、正定値でない場合はNone
+fn invert_spd(matrix: &[f64], n: usize) -> Option<Vec<f64>> {
+    let mut lower = vec![0f64; n * n];
+    for i in 0..n {
+        for j in 0..=i {
+            let sum: f64 = (0..j).map(|k| lower[i * n + k] * lower[j * n + k]).sum();
+            if i == j {
+                let diagonal = matrix[i * n + i] - sum;
+                if diagonal <= 0. {
+                    return None;
+                }
+                lower[i * n + i] = diagonal.sqrt();
+            } else {
+                lower[i * n + j] = (matrix[i * n + j] - sum) / lower[j * n + j];
+            }
+        }
+    }
+    // L^-1を前進代入で求め、(L^-1)^T L^-1を逆行列とする
+    let mut inv_lower = vec![0f64; n * n];
+    for col in 0..n {
+        for i in col..n {
+            let identity = if i == col { 1. } else { 0. };
+            let sum: f64 = (col..i)
+                .map(|k| lower[i * n + k] * inv_lower[k * n + col])
+                .sum();
+            inv_lower[i * n + col] = (identity - sum) / lower[i * n + i];
+        }
+    }
+    let mut inverse = vec![0f64; n * n];
+    for i in 0..n {
+        for j in 0..=i {
+            let value: f64 = (i..n)
+                .map(|k| inv_lower[k * n + i] * inv_lower[k * n + j])
+                .sum();
+            inverse[i * n + j] = value;
+            inverse[j * n + i] = value;
+        }
+    }
+    Some(inverse)
+}
+
+/// PaDiMの学習結果
+#[derive(Clone, Debug, PartialEq)]
+pub struct GaussianModel {
+    pub width: u32,
+    pub height: u32,
+    /// 特徴量のうち使うチャンネル
+    pub channels: Vec<usize>,
+    /// 元の特徴量の次元
+    pub feature_dim: usize,
+    /// 位置ごとの平均
+    pub mean: Vec<f32>,
+    /// 位置ごとの共分散行列の逆行列
+    pub inv_cov: Vec<f32>,
+}
+
+impl GaussianModel {
+    /// 位置ごとのマハラノビス距離
+    pub fn score(&self, features: &FeatureMap) -> Result<Vec<f32>, AnomalyError> {
+        if (features.width, features.height, features.dim)
+            != (self.width, self.height, self.feature_dim)
+        {
+            return Err(AnomalyError::FeatureMismatch);
+        }
+        let dim = self.channels.len();
+        let mut diff = vec![0f32; dim];
+        let mut scores = Vec::with_capacity((self.width * self.height) as usize);
+        for pos in 0..(self.width * self.height) as usize {
+            let feature = &features.data[pos * features.dim..(pos + 1) * features.dim];
+            let mean = &self.mean[pos * dim..(pos + 1) * dim];
+            for (i, &channel) in self.channels.iter().enumerate() {
+                diff[i] = feature[channel] - mean[i];
+            }
+            let inv_cov = &self.inv_cov[pos * dim * dim..(pos + 1) * dim * dim];
+            let distance: f32 = inv_cov
+                .chunks_exact(dim)
+                .zip(&diff)
+                .map(|(row, d)| d * row.iter().zip(&diff).map(|(a, b)| a * b).sum::<f32>())
+                .sum();
+            scores.push(distance.max(0.).sqrt());
+        }
+        Ok(scores)
+    }
+}
+
+/// PatchCoreの学習結果
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemoryBank {
+    pub width: u32,
+    pub height: u32,
+    pub dim: usize,
+    pub patch_size: u32,
+    /// dim個ずつ並んだパッチ特徴量
+    pub patches: Vec<f32>,
+}
+
+impl MemoryBank {
+    pub fn len(&self) -> usize {
+        self.patches.len() / self.dim.max(1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patches.is_empty()
+    }
+
+    /// 位置ごとの最近傍のパッチまでの距離
+    pub fn score(&self, features: &FeatureMap) -> Result<Vec<f32>, AnomalyError> {
+        if (features.width, features.height, features.dim) != (self.width, self.height, self.dim) {
+            return Err(AnomalyError::FeatureMismatch);
+        }
+        let features = features.local_average(self.patch_size);
+        Ok(features
+            .data
+            .chunks_exact(self.dim)
+            .map(|feature| {
+                self.patches
+                    .chunks_exact(self.dim)
+                    .map(|patch| squared_distance(feature, patch))
+                    .fold(f32::INFINITY, f32::min)
+                    .sqrt()
+            })
+            .collect())
+    }
+}
+
+fn squared_distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum()
+}
+
+/// k-center greedyでcount個のパッチを選ぶ、距離はランダム射影した次元で計算する
+fn coreset(patches: &[f32], dim: usize, count: usize, projection_dim: usize) -> Vec<usize> {
+    let n = patches.len() / dim;
+    if count >= n {
+        return (0..n).collect();
+    }
+    // 要素が±1/sqrt(projection_dim)のランダム行列で射影する
+    let mut seed = 0x9e37_79b9_7f4a_7c15u64;
+    let scale = 1. / (projection_dim as f32).sqrt();
+    let projection: Vec<f32> = (0..dim * projection_dim)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            if seed & 1 == 0 {
+                scale
+            } else {
+                -scale
+            }
+        })
+        .collect();
+    let projected: Vec<f32> = patches
+        .chunks_exact(dim)
+        .flat_map(|patch| {
+            projection
+                .chunks_exact(dim)
+                .map(move |row| row.iter().zip(patch).map(|(a, b)| a * b).sum::<f32>())
+        })
+        .collect();
+    let point = |i: usize| &projected[i * projection_dim..(i + 1) * projection_dim];
+    let mut selected = vec![0];
+    let mut distances: Vec<f32> = (0..n)
+        .map(|i| squared_distance(point(i), point(0)))
+        .collect();
+    while selected.len() < count {
+        let (next, _) = distances
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .unwrap();
+        selected.push(next);
+        for (i, distance) in distances.iter_mut().enumerate() {
+            *distance = distance.min(squared_distance(point(i), point(next)));
+        }
+    }
+    selected.sort_unstable();
+    selected
+}
+
+/// 特徴量を1枚ずつ加えて学習する
+enum Fitter {
+    Gaussian {
+        dim: Option<usize>,
+        eps: f32,
+        count: usize,
+        channels: Vec<usize>,
+        first: Option<FeatureMap>,
+        sum: Vec<f64>,
+        outer: Vec<f64>,
+    },
+    MemoryBank {
+        coreset_ratio: f32,
+        patch_size: u32,
+        first: Option<FeatureMap>,
+        patches: Vec<f32>,
+    },
+}
+
+impl Fitter {
+    fn new(method: &AnomalyMethod) -> Result<Self, AnomalyError> {
+        Ok(match *method {
+            AnomalyMethod::Padim { dim: Some(0), .. } => {
+                return Err(AnomalyError::InvalidMethod(
+                    "dim must be positive".to_string(),
+                ))
+            }
+            AnomalyMethod::Padim { dim, eps } => Fitter::Gaussian {
+                dim,
+                eps,
+                count: 0,
+                channels: Vec::new(),
+                first: None,
+                sum: Vec::new(),
+                outer: Vec::new(),
+            },
+            AnomalyMethod::PatchCore {
+                coreset_ratio,
+                patch_size,
+            } => Fitter::MemoryBank {
+                coreset_ratio,
+                patch_size,
+                first: None,
+                patches: Vec::new(),
+            },
+        })
+    }
+
+    fn add(&mut self, features: &FeatureMap) -> Result<(), AnomalyError> {
+        // 大きさが0の特徴量では距離を計算できない
+        let positions = features.width as usize * features.height as usize;
+        if positions == 0
+            || features.dim == 0
+            || positions.checked_mul(features.dim) != Some(features.data.len())
+        {
+            return Err(AnomalyError::InvalidShape(vec![
+                features.dim as u32,
+                features.height,
+                features.width,
+            ]));
+        }
+        match self {
+            Fitter::Gaussian {
+                dim,
+                count,
+                channels,
+                first,
+                sum,
+                outer,
+                ..
+            } => {
+                let first = first.get_or_insert_with(|| {
+                    *channels = match *dim {
+                        Some(dim) => random_subset(features.dim, dim, 0x2545_f491_4f6c_dd1d),
+                        None => (0..features.dim).collect(),
+                    };
+                    let positions = (features.width * features.height) as usize;
+                    let dim = channels.len();
+                    *sum = vec![0.; positions * dim];
+                    *outer = vec![0.; positions * dim * dim];
+                    FeatureMap {
+                        data: Vec::new(),
+                        ..*features
+                    }
+                });
+                if (first.width, first.height, first.dim)
+                    != (features.width, features.height, features.dim)
+                {
+                    return Err(AnomalyError::FeatureMismatch);
+                }
+                let dim = channels.len();
+                let mut selected = vec![0f64; dim];
+                for pos in 0..(features.width * features.height) as usize {
+                    let feature = &features.data[pos * features.dim..(pos + 1) * features.dim];
+                    for (value, &channel) in selected.iter_mut().zip(channels.iter()) {
+                        *value = feature[channel] as f64;
+                    }
+                    let sum = &mut sum[pos * dim..(pos + 1) * dim];
+                    sum.iter_mut()
+                        .zip(&selected)
+                        .for_each(|(sum, value)| *sum += value);
+                    let outer = &mut outer[pos * dim * dim..(pos + 1) * dim * dim];
+                    for (row, a) in outer.chunks_exact_mut(dim).zip(&selected) {
+                        row.iter_mut()
+                            .zip(&selected)
+                            .for_each(|(value, b)| *value += a * b);
+                    }
+                }
+                *count += 1;
+            }
+            Fitter::MemoryBank {
+                patch_size,
+                first,
+                patches,
+                ..
+            } => {
+                let first = first.get_or_insert_with(|| FeatureMap {
+                    data: Vec::new(),
+                    ..*features
+                });
+                if (first.width, first.height, first.dim)
+                    != (features.width, features.height, features.dim)
+                {
+                    return Err(AnomalyError::FeatureMismatch);
+                }
+                patches.extend(features.local_average(*patch_size).data);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<AnomalyModel, AnomalyError> {
+        match self {
+            Fitter::Gaussian {
+                eps,
+                count,
+                channels,
+                first,
+                sum,
+                outer,
+                ..
+            } => {
+                let first = first.ok_or(AnomalyError::NoImages)?;
+                let dim = channels.len();
+                let positions = (first.width * first.height) as usize;
+                let n = count as f64;
+                let mut mean = Vec::with_capacity(positions * dim);
+                let mut inv_cov = Vec::with_capacity(positions * dim * dim);
+                let mut cov = vec![0f64; dim * dim];
+                for pos in 0..positions {
+                    let sum = &sum[pos * dim..(pos + 1) * dim];
+                    let outer = &outer[pos * dim * dim..(pos + 1) * dim * dim];
+                    // 不偏共分散に正則化のeps * Iを足す
+                    for i in 0..dim {
+                        for j in 0..dim {
+                            let value =
+                                (outer[i * dim + j] - sum[i] * sum[j] / n) / (n - 1.).max(1.);
+                            cov[i * dim + j] = value + if i == j { eps as f64 } else { 0. };
+                        }
+                    }
+                    let inverse = invert_spd(&cov, dim)
+                        .ok_or_else(|| AnomalyError::InvalidModel("covariance".to_string()))?;
+                    mean.extend(sum.iter().map(|&sum| (sum / n) as f32));
+                    inv_cov.extend(inverse.into_iter().map(|value| value as f32));
+                }
+                Ok(AnomalyModel::Gaussian(GaussianModel {
+                    width: first.width,
+                    height: first.height,
+                    channels,
+                    feature_dim: first.dim,
+                    mean,
+                    inv_cov,
+                }))
+            }
+            Fitter::MemoryBank {
+                coreset_ratio,
+                patch_size,
+                first,
+                patches,
+            } => {
+                let first = first.ok_or(AnomalyError::NoImages)?;
+                let n = patches.len() / first.dim;
+                let count = ((n as f32 * coreset_ratio).ceil() as usize).clamp(1, n);
+                let selected = coreset(&patches, first.dim, count, first.dim.min(128));
+                let patches = selected
+                    .into_iter()
+                    .flat_map(|i| patches[i * first.dim..(i + 1) * first.dim].to_vec())
+                    .collect();
+                Ok(AnomalyModel::MemoryBank(MemoryBank {
+                    width: first.width,
+                    height: first.height,
+                    dim: first.dim,
+                    patch_size,
+                    patches,
+                }))
+            }
+        }
+    }
+}
+
+/// 学習結果
+#[derive(Clone, Debug, PartialEq)]
+pub enum AnomalyModel {
+    Gaussian(GaussianModel),
+    MemoryBank(MemoryBank),
+}
+
+impl AnomalyModel {
+    /// 正常画像の特徴量から学習する
+    pub fn fit(method: &AnomalyMethod, features: &[FeatureMap]) -> Result<Self, AnomalyError> {
+        let mut fitter = Fitter::new(method)?;
+        for features in features {
+            fitter.add(features)?;
+        }
+        fitter.finish()
+    }
+
+    /// 位置ごとの異常度
+    pub fn score(&self, features: &FeatureMap) -> Result<Vec<f32>, AnomalyError> {
+        match self {
+            AnomalyModel::Gaussian(model) => model.score(features),
+            AnomalyModel::MemoryBank(model) => model.score(features),
+        }
+    }
+
+    /// 独自のバイナリ形式で保存する
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), AnomalyError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MODEL_MAGIC)?;
+        write_u32(&mut writer, MODEL_VERSION)?;
+        match self {
+            AnomalyModel::Gaussian(model) => {
+                writer.write_all(&[0])?;
+                write_u32(&mut writer, model.width)?;
+                write_u32(&mut writer, model.height)?;
+                write_u32(&mut writer, model.feature_dim as u32)?;
+                write_u32(&mut writer, model.channels.len() as u32)?;
+                for &channel in &model.channels {
+                    write_u32(&mut writer, channel as u32)?;
+                }
+                write_f32s(&mut writer, &model.mean)?;
+                write_f32s(&mut writer, &model.inv_cov)?;
+            }
+            AnomalyModel::MemoryBank(model) => {
+                writer.write_all(&[1])?;
+                write_u32(&mut writer, model.width)?;
+                write_u32(&mut writer, model.height)?;
+                write_u32(&mut writer, model.dim as u32)?;
+                write_u32(&mut writer, model.patch_size)?;
+                write_u32(&mut writer, model.len() as u32)?;
+                write_f32s(&mut writer, &model.patches)?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// 大きさはファイルの中身と照合し、不正な場合はInvalidModelを返す
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, AnomalyError> {
+        let invalid = |message: &str| AnomalyError::InvalidModel(message.to_string());
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MODEL_MAGIC {
+            return Err(invalid("magic"));
+        }
+        let version = read_u32(&mut reader)?;
+        if version != MODEL_VERSION {
+            return Err(AnomalyError::InvalidModel(format!("version {}", version)));
+        }
+        let mut kind = [0u8];
+        reader.read_exact(&mut kind)?;
+        let width = read_u32(&mut reader)?;
+        let height = read_u32(&mut reader)?;
+        let positions = (width as usize)
+            .checked_mul(height as usize)
+            .ok_or_else(|| invalid("size"))?;
+        match kind[0] {
+            0 => {
+                let feature_dim = read_u32(&mut reader)? as usize;
+                let dim = read_u32(&mut reader)? as usize;
+                if feature_dim == 0 || dim == 0 {
+                    return Err(invalid("dim"));
+                }
+                // 大きさはファイルの値なのでオーバーフローを確認し、先に確保しない
+                let mean_len = positions.checked_mul(dim).ok_or_else(|| invalid("size"))?;
+                let cov_len = mean_len.checked_mul(dim).ok_or_else(|| invalid("size"))?;
+                let mut channels = Vec::new();
+                for _ in 0..dim {
+                    let channel = read_u32(&mut reader)? as usize;
+                    if channel >= feature_dim {
+                        return Err(invalid("channel out of range"));
+                    }
+                    channels.push(channel);
+                }
+                Ok(AnomalyModel::Gaussian(GaussianModel {
+                    width,
+                    height,
+                    channels,
+                    feature_dim,
+                    mean: read_f32s(&mut reader, mean_len)?,
+                    inv_cov: read_f32s(&mut reader, cov_len)?,
+                }))
+            }
+            1 => {
+                let dim = read_u32(&mut reader)? as usize;
+                let patch_size = read_u32(&mut reader)?;
+                let count = read_u32(&mut reader)? as usize;
+                if dim == 0 || count == 0 {
+                    return Err(invalid("dim"));
+                }
+                let len = count.checked_mul(dim).ok_or_else(|| invalid("size"))?;
+                Ok(AnomalyModel::MemoryBank(MemoryBank {
+                    width,
+                    height,
+                    dim,
+                    patch_size,
+                    patches: read_f32s(&mut reader, len)?,
+                }))
+            }
+            _ => Err(invalid("model kind")),
+        }
+    }
+}
+
+/// 1次元のガウシアンフィルタを縦横に掛ける、端は端の値をくり返す
+fn gaussian_blur(data: &[f32], width: u32, height: u32, sigma: f32) -> Vec<f32> {
+    if sigma <= 0. {
+        return data.to_vec();
+    }
+    let radius = (sigma * 3.).ceil() as i64;
+    let kernel: Vec<f32> = (-radius..=radius)
+        .map(|i| (-((i * i) as f32) / (2. * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = kernel.iter().sum();
+    let (w, h) = (width as i64, height as i64);
+    let pass = |src: &[f32], horizontal: bool| -> Vec<f32> {
+        let mut dst = vec![0f32; src.len()];
+        for y in 0..h {
+            for x in 0..w {
+                let value: f32 = kernel
+                    .iter()
+                    .zip(-radius..=radius)
+                    .map(|(k, i)| {
+                        let (sx, sy) = if horizontal {
+                            ((x + i).clamp(0, w - 1), y)
+                        } else {
+                            (x, (y + i).clamp(0, h - 1))
+                        };
+                        k * src[(sy * w + sx) as usize]
+                    })
+                    .sum();
+                dst[(y * w + x) as usize] = value / total;
+            }
+        }
+        dst
+    };
+    pass(&pass(data, true), false)
+}
+
+/// 位置ごとの異常度
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnomalyMap {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<f32>,
+}
+
+impl AnomalyMap {
+    pub fn get(&self, x: u32, y: u32) -> f32 {
+        self.data[(y * self.width + x) as usize]
+    }
+
+    /// 画像全体の異常度(最大値)
+    pub fn score(&self) -> f32 {
+        self.data.iter().copied().fold(0., f32::max)
+    }
+
+    /// 前処理後の画像に対するマップを、transformを使って元画像の解像度に戻す(バイリニア)
+    pub fn to_source(&self, transform: &Transform) -> AnomalyMap {
+        let (width, height) = transform.source_size;
+        AnomalyMap {
+            width,
+            height,
+            data: resample_to_source(&self.data, self.width, self.height, transform),
+        }
+    }
+
+    /// 0~maxをカラーマップで塗った画像
+    pub fn colorize(&self, colormap: Colormap, max: f32) -> RgbaImage {
+        let max = if max > 0. { max } else { 1. };
+        RgbaImage::from_fn(self.width, self.height, |x, y| {
+            let [r, g, b] = colormap.color(self.get(x, y) / max);
+            Rgba([r, g, b, 255])
+        })
+    }
+
+    /// thresholdを超える領域
+    pub fn to_mask(&self, threshold: f32) -> BitMask {
+        BitMask::from_fn(self.width, self.height, |x, y| self.get(x, y) > threshold)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct AnomalyDetectorBuilder<P>
+where
+    P: AsRef<Path> + Default + Debug,
+{
+    prototxt: Option<P>,
+    onnx: P,
+    env_id: Option<i32>,
+    num_threads: Option<i32>,
+    input_width: u32,
+    input_height: u32,
+    normalize: Option<Normalize>,
+    preprocess: Option<Preprocess>,
+    layers: Vec<String>,
+    method: Option<AnomalyMethod>,
+    sigma: Option<f32>,
+}
+
+impl<P: AsRef<Path> + Default + Debug> AnomalyDetectorBuilder<P> {
+    crate::impl_option!(prototxt, P);
+    crate::impl_non_option!(onnx, P);
+    crate::impl_option!(env_id, i32);
+    crate::impl_option!(num_threads, i32);
+    crate::impl_non_option!(input_width, u32);
+    crate::impl_non_option!(input_height, u32);
+    // 既定の前処理(input_width x input_heightへのリサイズ)の正規化、既定値はImageNet
+    crate::impl_option!(normalize, Normalize);
+    // 既定の前処理の代わりに使うパイプライン
+    crate::impl_option!(preprocess, Preprocess);
+    // 特徴量として読む中間Blobの名前、1番目のBlobの解像度にそろえる
+    crate::impl_non_option!(layers, Vec<String>);
+    crate::impl_option!(method, AnomalyMethod);
+    // マップを平滑化するガウシアンフィルタの標準偏差(入力画像の画素)、既定値は4
+    crate::impl_option!(sigma, f32);
+
+    pub fn build(self) -> Result<AnomalyDetector, AiliaError> {
+        if self.layers.is_empty() {
+            return Err(AiliaError::AiliaStausInvaildArgument);
+        }
+        let net = Network::ailia_create(
+            self.env_id.unwrap_or(AILIA_ENVIRONMENT_ID_AUTO),
+            self.num_threads
+                .unwrap_or_else(|| AILIA_MULTITHREAD_AUTO.try_into().unwrap()),
+        )?;
+        // 中間のBlobは既定のメモリモードでは解放される
+        net.set_memory_mode(AILIA_MEMORY_NO_OPTIMIZATION)?;
+        net.open_model_files(self.prototxt, self.onnx)?;
+        let layers = self
+            .layers
+            .iter()
+            .map(|name| net.find_blob_idx_by_name(name))
+            .collect::<Result<Vec<_>, _>>()?;
+        let preprocess = self.preprocess.unwrap_or_else(|| {
+            Preprocess::new()
+                .resize(self.input_width, self.input_height, FilterType::Triangle)
+                .normalize(self.normalize.unwrap_or(Normalize::ImageNet))
+        });
+        Ok(AnomalyDetector {
+            net,
+            preprocess,
+            layers,
+            method: self.method.unwrap_or_default(),
+            sigma: self.sigma.unwrap_or(4.),
+            model: None,
+        })
+    }
+}
+
+pub struct AnomalyDetector {
+    net: Network,
+    preprocess: Preprocess,
+    layers: Vec<u32>,
+    method: AnomalyMethod,
+    sigma: f32,
+    model: Option<AnomalyModel>,
+}
+
+impl AnomalyDetector {
+    /// 中間Blobを連結した特徴量と前処理の変換
+    pub fn features(&self, image: &RgbImage) -> Result<(FeatureMap, Transform), AnomalyError> {
+        let (tensor, transform) = self.preprocess.run_rgb(image)?;
+        let input_idx = self.net.get_input_blob_index_by_index(0)?;
+        tensor.set_input(&self.net, input_idx)?;
+        self.net.update()?;
+        let mut blobs = Vec::with_capacity(self.layers.len());
+        for &idx in &self.layers {
+            blobs.push((
+                self.net.get_output_blob_by_index::<f32>(idx)?,
+                self.net.get_blob_shape_nd(idx)?,
+            ));
+        }
+        Ok((FeatureMap::from_blobs(&blobs)?, transform))
+    }
+
+    /// 正常画像から学習する
+    pub fn fit(&mut self, images: &[RgbImage]) -> Result<(), AnomalyError> {
+        let mut fitter = Fitter::new(&self.method)?;
+        for image in images {
+            fitter.add(&self.features(image)?.0)?;
+        }
+        self.model = Some(fitter.finish()?);
+        Ok(())
+    }
+
+    /// ディレクトリ内の正常画像から学習し、使った画像の枚数を返す
+    pub fn fit_dir<Q: AsRef<Path>>(&mut self, dir: Q) -> Result<usize, AnomalyError> {
+        let mut source = ImageDirSource::new(dir, 1.)?;
+        let mut fitter = Fitter::new(&self.method)?;
+        let mut count = 0;
+        while let Some((image, _)) = source.read_frame()? {
+            let rgb = DynamicImage::ImageRgba8(image.to_rgba_image()?).to_rgb8();
+            fitter.add(&self.features(&rgb)?.0)?;
+            count += 1;
+        }
+        self.model = Some(fitter.finish()?);
+        Ok(count)
+    }
+
+    pub fn model(&self) -> Option<&AnomalyModel> {
+        self.model.as_ref()
+    }
+
+    pub fn set_model(&mut self, model: AnomalyModel) {
+        self.model = Some(model);
+    }
+
+    pub fn save_model<Q: AsRef<Path>>(&self, path: Q) -> Result<(), AnomalyError> {
+        self.model
+            .as_ref()
+            .ok_or(AnomalyError::NotFitted)?
+            .save(path)
+    }
+
+    pub fn load_model<Q: AsRef<Path>>(&mut self, path: Q) -> Result<(), AnomalyError> {
+        self.model = Some(AnomalyModel::load(path)?);
+        Ok(())
+    }
+
+    /// 元画像と同じ解像度の異常度のマップを返す、画像全体の異常度はAnomalyMap::score
+    pub fn detect(&self, image: &ImageView) -> Result<AnomalyMap, AnomalyError> {
+        self.detect_rgb(&DynamicImage::ImageRgba8(image.to_rgba_image()?).to_rgb8())
+    }
+
+    pub fn detect_rgb(&self, image: &RgbImage) -> Result<AnomalyMap, AnomalyError> {
+        let model = self.model.as_ref().ok_or(AnomalyError::NotFitted)?;
+        let (features, transform) = self.features(image)?;
+        let map = AnomalyMap {
+            width: features.width,
+            height: features.height,
+            data: model.score(&features)?,
+        }
+        .to_source(&transform);
+        // sigmaは入力画像の画素単位なので元画像の解像度に合わせる
+        let sigma = self.sigma / transform.scale.0.max(transform.scale.1);
+        Ok(AnomalyMap {
+            data: gaussian_blur(&map.data, map.width, map.height, sigma),
+            ..map
+        })
+    }
+}
+
+impl Deref for AnomalyDetector {
+    type Target = Network;
+    fn deref(&self) -> &Self::Target {
+        &self.net
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// 位置(x, y)ごとに平均の異なる正規分布に近い特徴量
+    fn synthetic(seed: u64, width: u32, height: u32, dim: usize) -> FeatureMap {
+        let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+        let mut noise = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 40) as f32 / (1u64 << 24) as f32 - 0.5
+        };
+        let mut data = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                for c in 0..dim {
+                    data.push((x + y) as f32 * 0.1 + c as f32 + noise() * 0.2);
+                }
+            }
+        }
+        FeatureMap {
+            width,
+            height,
+            dim,
+            data,
+        }
+    }
+
+    fn with_defect(mut features: FeatureMap, x: u32, y: u32) -> FeatureMap {
+        let offset = (y * features.width + x) as usize * features.dim;
+        features.data[offset..offset + features.dim]
+            .iter_mut()
+            .for_each(|value| *value += 3.);
+        features
+    }
+
+    #[test]
+    fn from_blobs() {
+        // 2x2と1x1のBlobを連結する
+        let blobs = [
+            (vec![1., 2., 3., 4.], vec![1, 1, 2, 2]),
+            (vec![5., 6.], vec![1, 2, 1, 1]),
+        ];
+        let features = FeatureMap::from_blobs(&blobs).unwrap();
+        assert_eq!((features.width, features.height, features.dim), (2, 2, 3));
+        assert_eq!(features.get(1, 0), [2., 5., 6.]);
+        assert_eq!(features.get(0, 1), [3., 5., 6.]);
+        assert!(FeatureMap::from_blobs(&[(vec![0.; 3], vec![1, 1, 2, 2])]).is_err());
+    }
+
+    #[test]
+    fn invert() {
+        let matrix = [4., 2., 2., 3.];
+        let inverse = invert_spd(&matrix, 2).unwrap();
+        let expected = [0.375, -0.25, -0.25, 0.5];
+        for (a, b) in inverse.iter().zip(expected) {
+            assert!((a - b).abs() < 1e-9);
+        }
+        assert!(invert_spd(&[1., 2., 2., 1.], 2).is_none());
+        assert_eq!(random_subset(10, 4, 1).len(), 4);
+    }
+
+    #[test]
+    fn padim_and_patchcore() {
+        let normal: Vec<FeatureMap> = (0..20).map(|i| synthetic(i, 4, 3, 5)).collect();
+        let methods = [
+            AnomalyMethod::Padim {
+                dim: Some(3),
+                eps: 0.01,
+            },
+            AnomalyMethod::PatchCore {
+                coreset_ratio: 0.5,
+                patch_size: 1,
+            },
+        ];
+        for method in methods {
+            let model = AnomalyModel::fit(&method, &normal).unwrap();
+            let good = model.score(&synthetic(100, 4, 3, 5)).unwrap();
+            let bad = model
+                .score(&with_defect(synthetic(100, 4, 3, 5), 2, 1))
+                .unwrap();
+            let max_good = good.iter().copied().fold(0., f32::max);
+            // 欠陥のある位置だけが正常な画像のどの位置よりも大きくなる
+            assert!(bad[6] > max_good * 2., "{:?} {:?}", method, bad);
+            assert!(bad
+                .iter()
+                .enumerate()
+                .all(|(i, &score)| i == 6 || score <= max_good));
+        }
+        assert!(matches!(
+            AnomalyModel::fit(&AnomalyMethod::default(), &[]),
+            Err(AnomalyError::NoImages)
+        ));
+        let zero = AnomalyMethod::Padim {
+            dim: Some(0),
+            eps: 0.01,
+        };
+        assert!(matches!(
+            AnomalyModel::fit(&zero, &[synthetic(0, 2, 2, 3)]),
+            Err(AnomalyError::InvalidMethod(_))
+        ));
+        let patchcore = AnomalyMethod::PatchCore {
+            coreset_ratio: 0.5,
+            patch_size: 3,
+        };
+        assert!(matches!(
+            AnomalyModel::fit(&patchcore, &[synthetic(0, 2, 2, 0)]),
+            Err(AnomalyError::InvalidShape(_))
+        ));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let normal: Vec<FeatureMap> = (0..5).map(|i| synthetic(i, 2, 2, 3)).collect();
+        let dir = std::env::temp_dir();
+        for (i, method) in [
+            AnomalyMethod::Padim {
+                dim: None,
+                eps: 0.01,
+            },
+            AnomalyMethod::PatchCore {
+                coreset_ratio: 0.3,
+                patch_size: 3,
+            },
+        ]
+        .iter()
+        .enumerate()
+        {
+            let model = AnomalyModel::fit(method, &normal).unwrap();
+            let path = dir.join(format!(
+                "ailia_anomaly_save_{}_{}.bin",
+                std::process::id(),
+                i
+            ));
+            model.save(&path).unwrap();
+            let loaded = AnomalyModel::load(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(loaded, model);
+            assert!(matches!(
+                loaded.score(&synthetic(0, 3, 2, 3)),
+                Err(AnomalyError::FeatureMismatch)
+            ));
+        }
+
+        // 大きさが壊れたファイルは確保する前にエラーにする
+        let path = dir.join(format!("ailia_anomaly_invalid_{}.bin", std::process::id()));
+        let header = |values: &[u32]| {
+            let mut data = MODEL_MAGIC.to_vec();
+            write_u32(&mut data, MODEL_VERSION).unwrap();
+            data.push(0);
+            for &value in values {
+                write_u32(&mut data, value).unwrap();
+            }
+            data
+        };
+        std::fs::write(&path, header(&[u32::MAX, u32::MAX, 1, u32::MAX])).unwrap();
+        assert!(matches!(
+            AnomalyModel::load(&path),
+            Err(AnomalyError::InvalidModel(_))
+        ));
+        std::fs::write(&path, header(&[1 << 16, 1 << 16, 1, 1, 0])).unwrap();
+        assert!(matches!(
+            AnomalyModel::load(&path),
+            Err(AnomalyError::Io(_))
+        ));
+        // 次元が0のモデルは読み込まない
+        std::fs::write(&path, header(&[2, 2, 3, 0])).unwrap();
+        assert!(matches!(
+            AnomalyModel::load(&path),
+            Err(AnomalyError::InvalidModel(_))
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn map() {
+        let map = AnomalyMap {
+            width: 2,
+            height: 1,
+            data: vec![1., 3.],
+        };
+        assert_eq!(map.score(), 3.);
+        assert!(map.to_mask(2.).get(1, 0));
+        let blurred = gaussian_blur(&[0., 0., 9., 0., 0.], 5, 1, 1.);
+        assert!(blurred[2] < 9. && blurred[1] > 0. && (blurred[1] - blurred[3]).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,393 @@
+//! tokioなどの非同期ランタイムから使うためのハンドル
+//!
+//! ailiaのオブジェクトはスレッド間で移動できないため、専用のスレッド上で作成して保持し、
+//! 有界のキューでリクエストを受け取る。返されるfutureをdropするとキューに残っている処理は実行されない。
+//! 既に実行中の推論は中断できない。
+
+use std::fmt::Debug;
+use std::path::Path;
+use std::thread;
+
+use thiserror::Error;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::classifier::{Class, Classifier, ClassifierBuilder};
+use crate::detector::{Detector, DetectorBuilder, Object};
+use crate::network::Network;
+use crate::video::ImageView;
+use crate::AiliaError;
+
+#[derive(Debug, Error)]
+pub enum AsyncError {
+    #[error(transparent)]
+    Ailia(#[from] AiliaError),
+    #[error("ワーカースレッドが終了しています")]
+    Closed,
+    #[error("キューが一杯です")]
+    Full,
+}
+
+type Job<T> = Box<dyn FnOnce(&mut T) + Send>;
+
+/// 専用のスレッドでTを保持し、キューに入れられた処理を順に実行する
+/// ハンドルを全てdropするとキューに残っている処理を終えてからスレッドが終了する
+pub struct AsyncWorker<T> {
+    sender: mpsc::Sender<Job<T>>,
+}
+
+impl<T> Clone for AsyncWorker<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<T: 'static> AsyncWorker<T> {
+    /// ワーカースレッド上でinitを呼んでTを作成する
+    pub async fn spawn<F>(queue_size: usize, init: F) -> Result<Self, AsyncError>
+    where
+        F: FnOnce() -> Result<T, AiliaError> + Send + 'static,
+    {
+        let (sender, mut receiver) = mpsc::channel::<Job<T>>(queue_size.max(1));
+        let (ready, created) = oneshot::channel();
+        thread::Builder::new()
+            .name("ailia-worker".to_string())
+            .spawn(move || {
+                let mut object = match init() {
+                    Ok(object) => {
+                        let _ = ready.send(Ok(()));
+                        object
+                    }
+                    Err(err) => {
+                        let _ = ready.send(Err(err));
+                        return;
+                    }
+                };
+                while let Some(job) = receiver.blocking_recv() {
+                    job(&mut object);
+                }
+            })
+            .map_err(|_| AiliaError::ThreadError)?;
+        created.await.map_err(|_| AsyncError::Closed)??;
+        Ok(Self { sender })
+    }
+
+    fn job<R, F>(f: F) -> (Job<T>, oneshot::Receiver<Result<R, AiliaError>>)
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut T) -> Result<R, AiliaError> + Send + 'static,
+    {
+        let (reply, result) = oneshot::channel();
+        let job: Job<T> = Box::new(move |object| {
+            // 結果を待つfutureが既にdropされていれば実行しない
+            if reply.is_closed() {
+                return;
+            }
+            let _ = reply.send(f(object));
+        });
+        (job, result)
+    }
+
+    /// ワーカースレッド上でfを実行する、キューが一杯の場合は空くまで待つ
+    pub async fn call<R, F>(&self, f: F) -> Result<R, AsyncError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut T) -> Result<R, AiliaError> + Send + 'static,
+    {
+        let (job, result) = Self::job(f);
+        self.sender
+            .send(job)
+            .await
+            .map_err(|_| AsyncError::Closed)?;
+        Ok(result.await.map_err(|_| AsyncError::Closed)??)
+    }
+
+    /// callと同じだが、キューが一杯の場合は待たずにAsyncError::Fullを返す
+    pub async fn try_call<R, F>(&self, f: F) -> Result<R, AsyncError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut T) -> Result<R, AiliaError> + Send + 'static,
+    {
+        let (job, result) = Self::job(f);
+        self.sender.try_send(job).map_err(|err| match err {
+            mpsc::error::TrySendError::Full(_) => AsyncError::Full,
+            mpsc::error::TrySendError::Closed(_) => AsyncError::Closed,
+        })?;
+        Ok(result.await.map_err(|_| AsyncError::Closed)??)
+    }
+
+    /// キューの空き
+    pub fn capacity(&self) -> usize {
+        self.sender.capacity()
+    }
+}
+
+/// Detectorの非同期ハンドル
+#[derive(Clone)]
+pub struct AsyncDetector {
+    worker: AsyncWorker<Detector>,
+}
+
+impl AsyncDetector {
+    pub async fn new<P>(builder: DetectorBuilder<P>, queue_size: usize) -> Result<Self, AsyncError>
+    where
+        P: AsRef<Path> + Default + Debug + Send + 'static,
+    {
+        Self::spawn(queue_size, move || builder.build()).await
+    }
+
+    /// set_input_shapeなど作成後の設定が必要な場合に使う
+    pub async fn spawn<F>(queue_size: usize, init: F) -> Result<Self, AsyncError>
+    where
+        F: FnOnce() -> Result<Detector, AiliaError> + Send + 'static,
+    {
+        Ok(Self {
+            worker: AsyncWorker::spawn(queue_size, init).await?,
+        })
+    }
+
+    pub async fn predict(
+        &self,
+        image: ImageView,
+        threshold: f32,
+        iou: f32,
+    ) -> Result<Vec<Object>, AsyncError> {
+        self.worker
+            .call(move |detector| {
+                detector.predict(
+                    image.as_ptr(),
+                    image.stride(),
+                    image.width,
+                    image.height,
+                    image.format(),
+                    threshold,
+                    iou,
+                )
+            })
+            .await
+    }
+
+    /// その他のDetectorのAPIをワーカースレッド上で呼ぶ
+    pub async fn call<R, F>(&self, f: F) -> Result<R, AsyncError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut Detector) -> Result<R, AiliaError> + Send + 'static,
+    {
+        self.worker.call(f).await
+    }
+}
+
+/// Classifierの非同期ハンドル
+#[derive(Clone)]
+pub struct AsyncClassifier {
+    worker: AsyncWorker<Classifier>,
+}
+
+impl AsyncClassifier {
+    pub async fn new<P>(
+        builder: ClassifierBuilder<P>,
+        queue_size: usize,
+    ) -> Result<Self, AsyncError>
+    where
+        P: AsRef<Path> + Default + Debug + Send + 'static,
+    {
+        Self::spawn(queue_size, move || builder.build()).await
+    }
+
+    pub async fn spawn<F>(queue_size: usize, init: F) -> Result<Self, AsyncError>
+    where
+        F: FnOnce() -> Result<Classifier, AiliaError> + Send + 'static,
+    {
+        Ok(Self {
+            worker: AsyncWorker::spawn(queue_size, init).await?,
+        })
+    }
+
+    /// 確率の高い順にtop_k個のクラスを返す
+    pub async fn classify(&self, image: ImageView, top_k: u32) -> Result<Vec<Class>, AsyncError> {
+        self.worker
+            .call(move |classifier| {
+                classifier.compute(
+                    image.as_ptr(),
+                    image.stride(),
+                    image.width,
+                    image.height,
+                    image.format(),
+                    top_k,
+                )?;
+                (0..classifier.get_class_count()?)
+                    .map(|idx| classifier.get_class(idx))
+                    .collect()
+            })
+            .await
+    }
+
+    pub async fn call<R, F>(&self, f: F) -> Result<R, AsyncError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut Classifier) -> Result<R, AiliaError> + Send + 'static,
+    {
+        self.worker.call(f).await
+    }
+}
+
+/// Networkの非同期ハンドル
+#[derive(Clone)]
+pub struct AsyncNetwork {
+    worker: AsyncWorker<Network>,
+}
+
+impl AsyncNetwork {
+    pub async fn from_onnx<P>(
+        env_id: i32,
+        num_threads: i32,
+        model_path: P,
+        queue_size: usize,
+    ) -> Result<Self, AsyncError>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        Self::spawn(queue_size, move || {
+            Network::from_onnx(env_id, num_threads, model_path)
+        })
+        .await
+    }
+
+    pub async fn spawn<F>(queue_size: usize, init: F) -> Result<Self, AsyncError>
+    where
+        F: FnOnce() -> Result<Network, AiliaError> + Send + 'static,
+    {
+        Ok(Self {
+            worker: AsyncWorker::spawn(queue_size, init).await?,
+        })
+    }
+
+    /// 入力Blobの順にデータを設定して推論し、出力Blobの順に結果を返す
+    /// 入力の形状を変える場合やf32以外の入力がある場合はcallを使う
+    pub async fn predict(&self, inputs: Vec<Vec<f32>>) -> Result<Vec<Vec<f32>>, AsyncError> {
+        self.worker
+            .call(move |net| {
+                let input_indexes = net.get_input_indexs()?;
+                if input_indexes.len() != inputs.len() {
+                    return Err(AiliaError::AiliaStausInvaildArgument);
+                }
+                for (input, idx) in inputs.iter().zip(input_indexes) {
+                    net.set_input_data_blob(input.as_ptr(), input.len() as u32, idx)?;
+                }
+                net.update()?;
+                net.get_output_indexs()?
+                    .into_iter()
+                    .map(|idx| net.get_output_blob_by_index(idx))
+                    .collect()
+            })
+            .await
+    }
+
+    pub async fn call<R, F>(&self, f: F) -> Result<R, AsyncError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut Network) -> Result<R, AiliaError> + Send + 'static,
+    {
+        self.worker.call(f).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn call_on_worker_thread() {
+        let worker = AsyncWorker::spawn(4, || Ok(Vec::<u32>::new()))
+            .await
+            .unwrap();
+        let caller = thread::current().id();
+        let other = worker
+            .call(move |_| Ok(thread::current().id() != caller))
+            .await
+            .unwrap();
+        assert!(other);
+
+        for i in 0..3 {
+            worker
+                .call(move |values| {
+                    values.push(i);
+                    Ok(())
+                })
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            worker.call(|values| Ok(values.clone())).await.unwrap(),
+            vec![0, 1, 2]
+        );
+
+        let err = worker
+            .call(|_| Err::<(), _>(AiliaError::NotFound))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AsyncError::Ailia(AiliaError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn init_error() {
+        let err = AsyncWorker::<()>::spawn(1, || Err(AiliaError::Broken))
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, AsyncError::Ailia(AiliaError::Broken)));
+    }
+
+    #[tokio::test]
+    async fn bounded_queue_and_cancellation() {
+        let worker = AsyncWorker::spawn(1, || Ok(())).await.unwrap();
+        let (release, blocked) = std::sync::mpsc::channel::<()>();
+        let (started, wait_started) = oneshot::channel();
+
+        // 1つ目でワーカーを止め、2つ目でキューを埋める
+        let busy = tokio::spawn({
+            let worker = worker.clone();
+            async move {
+                worker
+                    .call(move |_| {
+                        let _ = started.send(());
+                        let _ = blocked.recv();
+                        Ok(())
+                    })
+                    .await
+            }
+        });
+        wait_started.await.unwrap();
+        let executed = Arc::new(AtomicUsize::new(0));
+        let queued = tokio::spawn({
+            let worker = worker.clone();
+            let executed = executed.clone();
+            async move {
+                worker
+                    .call(move |_| {
+                        executed.fetch_add(1, Ordering::SeqCst);
+                        Ok(())
+                    })
+                    .await
+            }
+        });
+        while worker.capacity() > 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        let full = worker.try_call(|_| Ok(())).await.unwrap_err();
+        assert!(matches!(full, AsyncError::Full));
+
+        // キューにある処理のfutureをdropすると実行されない
+        queued.abort();
+        let _ = queued.await;
+        release.send(()).unwrap();
+        busy.await.unwrap().unwrap();
+        worker.call(|_| Ok(())).await.unwrap();
+        assert_eq!(executed.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,421 @@
+use std::time::{Duration, Instant};
+
+use ailia_sys::*;
+
+use crate::classifier::Classifier;
+use crate::detector::Detector;
+use crate::environment::{get_environment_count, Environment};
+use crate::network::Network;
+use crate::pose_estimator::{ObjectTrait, PoseEstimator};
+use crate::video::ImageView;
+use crate::AiliaError;
+
+/// 計測の段階
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Preprocess,
+    Inference,
+    Postprocess,
+}
+
+/// ウォームアップと計測の回数
+#[derive(Clone, Copy, Debug)]
+pub struct BenchConfig {
+    warmup: u32,
+    iterations: u32,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        Self {
+            warmup: 10,
+            iterations: 100,
+        }
+    }
+}
+
+impl BenchConfig {
+    crate::impl_non_option!(warmup, u32);
+    crate::impl_non_option!(iterations, u32);
+}
+
+/// ミリ秒単位のレイテンシの統計
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LatencyStats {
+    pub mean_ms: f64,
+    pub min_ms: f64,
+    pub max_ms: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+}
+
+impl LatencyStats {
+    pub fn from_durations(durations: &[Duration]) -> Self {
+        if durations.is_empty() {
+            return Self::default();
+        }
+        let mut ms: Vec<f64> = durations.iter().map(|d| d.as_secs_f64() * 1000.).collect();
+        ms.sort_by(f64::total_cmp);
+        // nearest-rank
+        let percentile =
+            |p: f64| ms[((p * ms.len() as f64).ceil() as usize).clamp(1, ms.len()) - 1];
+        Self {
+            mean_ms: ms.iter().sum::<f64>() / ms.len() as f64,
+            min_ms: ms[0],
+            max_ms: ms[ms.len() - 1],
+            p50_ms: percentile(0.5),
+            p90_ms: percentile(0.9),
+            p99_ms: percentile(0.99),
+        }
+    }
+}
+
+/// 1回の計測結果
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BenchReport {
+    pub warmup: u32,
+    pub iterations: u32,
+    pub total: LatencyStats,
+    pub preprocess: LatencyStats,
+    pub inference: LatencyStats,
+    pub postprocess: LatencyStats,
+    /// 1イテレーションの平均時間から求めたFPS
+    pub fps: f64,
+    /// warmupから計測終了までの最大RSS
+    /// 最大値をリセットできない環境ではプロセス全体の最大値になるためNone
+    pub peak_rss_bytes: Option<u64>,
+}
+
+/// 1イテレーション内の各段階の時間を計測する
+#[derive(Debug, Default)]
+pub struct StageTimer {
+    preprocess: Duration,
+    inference: Duration,
+    postprocess: Duration,
+}
+
+impl StageTimer {
+    pub fn stage<T>(&mut self, stage: Stage, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let res = f();
+        let elapsed = start.elapsed();
+        match stage {
+            Stage::Preprocess => self.preprocess += elapsed,
+            Stage::Inference => self.inference += elapsed,
+            Stage::Postprocess => self.postprocess += elapsed,
+        }
+        res
+    }
+}
+
+/// warmup回実行した後にiterations回計測する
+/// iterationの中でStageTimer::stageを使うと段階ごとの内訳も計測される
+pub fn run<E, F>(config: &BenchConfig, mut iteration: F) -> Result<BenchReport, E>
+where
+    F: FnMut(&mut StageTimer) -> Result<(), E>,
+{
+    let rss_reset = reset_peak_rss();
+    for _ in 0..config.warmup {
+        iteration(&mut StageTimer::default())?;
+    }
+    let capacity = config.iterations as usize;
+    let (mut total, mut pre, mut infer, mut post) = (
+        Vec::with_capacity(capacity),
+        Vec::with_capacity(capacity),
+        Vec::with_capacity(capacity),
+        Vec::with_capacity(capacity),
+    );
+    for _ in 0..config.iterations {
+        let mut timer = StageTimer::default();
+        let start = Instant::now();
+        iteration(&mut timer)?;
+        total.push(start.elapsed());
+        pre.push(timer.preprocess);
+        infer.push(timer.inference);
+        post.push(timer.postprocess);
+    }
+    let total = LatencyStats::from_durations(&total);
+    Ok(BenchReport {
+        warmup: config.warmup,
+        iterations: config.iterations,
+        total,
+        preprocess: LatencyStats::from_durations(&pre),
+        inference: LatencyStats::from_durations(&infer),
+        postprocess: LatencyStats::from_durations(&post),
+        fps: if total.mean_ms > 0. {
+            1000. / total.mean_ms
+        } else {
+            0.
+        },
+        peak_rss_bytes: rss_reset.then(peak_rss_bytes).flatten(),
+    })
+}
+
+/// peak_rss_bytesを現在のRSSに戻す(/proc/self/clear_refsに5を書く)、できない場合はfalse
+#[cfg(target_os = "linux")]
+pub fn reset_peak_rss() -> bool {
+    std::fs::write("/proc/self/clear_refs", "5").is_ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn reset_peak_rss() -> bool {
+    false
+}
+
+/// プロセスの最大RSS(Linuxの/proc/self/statusのVmHWM)
+/// reset_peak_rssを呼ぶまではプロセス開始からの最大値
+#[cfg(target_os = "linux")]
+pub fn peak_rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn peak_rss_bytes() -> Option<u64> {
+    None
+}
+
+/// 入力Blobの設定、ailiaUpdate、出力Blobの取得をそれぞれ前処理、推論、後処理として計測する
+pub fn bench_network(
+    net: &Network,
+    input: &[f32],
+    config: &BenchConfig,
+) -> Result<BenchReport, AiliaError> {
+    let input_idx = net.get_input_indexs()?[0];
+    let output_indexes = net.get_output_indexs()?;
+    run(config, |timer| {
+        timer.stage(Stage::Preprocess, || {
+            net.set_input_data_blob(input.as_ptr(), input.len() as u32, input_idx)
+        })?;
+        timer.stage(Stage::Inference, || net.update())?;
+        timer.stage(Stage::Postprocess, || {
+            for &idx in &output_indexes {
+                net.get_output_blob_by_index::<f32>(idx)?;
+            }
+            Ok(())
+        })
+    })
+}
+
+/// 画像をsizeにリサイズしたRGBAのバッファを作る処理を前処理として計測する
+/// dataの長さが画像サイズと合わない場合はailiaに渡さずエラーにする
+fn prepare_image(image: &ImageView, size: Option<(u32, u32)>) -> Result<ImageView, AiliaError> {
+    match size {
+        Some((width, height)) => image.resize(width, height).ok(),
+        None => image.is_valid().then(|| image.clone()),
+    }
+    .ok_or(AiliaError::AiliaStausInvaildArgument)
+}
+
+pub fn bench_detector(
+    detector: &Detector,
+    image: &ImageView,
+    size: Option<(u32, u32)>,
+    threshold: f32,
+    iou: f32,
+    config: &BenchConfig,
+) -> Result<BenchReport, AiliaError> {
+    run(config, |timer| {
+        let input = timer.stage(Stage::Preprocess, || prepare_image(image, size))?;
+        timer.stage(Stage::Inference, || {
+            detector.compute(
+                input.as_ptr(),
+                input.stride(),
+                input.width,
+                input.height,
+                input.format(),
+                threshold,
+                iou,
+            )
+        })?;
+        timer.stage(Stage::Postprocess, || {
+            for idx in 0..detector.get_object_count()? {
+                detector.get_object(idx, AILIA_DETECTOR_OBJECT_VERSION)?;
+            }
+            Ok(())
+        })
+    })
+}
+
+pub fn bench_classifier(
+    classifier: &Classifier,
+    image: &ImageView,
+    size: Option<(u32, u32)>,
+    max_class_count: u32,
+    config: &BenchConfig,
+) -> Result<BenchReport, AiliaError> {
+    run(config, |timer| {
+        let input = timer.stage(Stage::Preprocess, || prepare_image(image, size))?;
+        timer.stage(Stage::Inference, || {
+            classifier.compute(
+                input.as_ptr(),
+                input.stride(),
+                input.width,
+                input.height,
+                input.format(),
+                max_class_count,
+            )
+        })?;
+        timer.stage(Stage::Postprocess, || {
+            for idx in 0..classifier.get_class_count()? {
+                classifier.get_class(idx)?;
+            }
+            Ok(())
+        })
+    })
+}
+
+pub fn bench_pose_estimator<O: ObjectTrait>(
+    estimator: &PoseEstimator<O>,
+    image: &ImageView,
+    size: Option<(u32, u32)>,
+    config: &BenchConfig,
+) -> Result<BenchReport, AiliaError> {
+    run(config, |timer| {
+        let input = timer.stage(Stage::Preprocess, || prepare_image(image, size))?;
+        timer.stage(Stage::Inference, || {
+            estimator.compute(
+                input.as_ptr(),
+                input.stride(),
+                input.width,
+                input.height,
+                input.format(),
+            )
+        })?;
+        timer.stage(Stage::Postprocess, || {
+            for idx in 0..estimator.get_object_count()? {
+                O::get_object(estimator, idx)?;
+            }
+            Ok(())
+        })
+    })
+}
+
+/// 環境IDとスレッド数の組み合わせ1つ分の結果
+/// 環境によってはモデルを実行できないため、失敗した場合はerrorに理由が入る
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SweepEntry {
+    pub env_id: i32,
+    pub env_name: Option<String>,
+    pub num_threads: i32,
+    pub report: Option<BenchReport>,
+    pub error: Option<String>,
+}
+
+/// 利用可能な環境のIDと名前の一覧
+pub fn environments() -> Result<Vec<(i32, String)>, AiliaError> {
+    let mut envs = Vec::new();
+    for idx in 0..get_environment_count()? {
+        let env = Environment::get_environment(idx, AILIA_ENVIRONMENT_VERSION)?;
+        envs.push((env.id(), env.name()));
+    }
+    Ok(envs)
+}
+
+/// env_idsとthread_countsの全ての組み合わせでbenchを実行する
+/// benchには環境IDとスレッド数が渡されるので、その設定でモデルを作成して計測する
+pub fn sweep<E, F>(env_ids: &[i32], thread_counts: &[i32], mut bench: F) -> Vec<SweepEntry>
+where
+    E: std::fmt::Display,
+    F: FnMut(i32, i32) -> Result<BenchReport, E>,
+{
+    let names = environments().unwrap_or_default();
+    let mut entries = Vec::with_capacity(env_ids.len() * thread_counts.len());
+    for &env_id in env_ids {
+        let env_name = names
+            .iter()
+            .find(|(id, _)| *id == env_id)
+            .map(|(_, name)| name.clone());
+        for &num_threads in thread_counts {
+            let (report, error) = match bench(env_id, num_threads) {
+                Ok(report) => (Some(report), None),
+                Err(err) => (None, Some(err.to_string())),
+            };
+            entries.push(SweepEntry {
+                env_id,
+                env_name: env_name.clone(),
+                num_threads,
+                report,
+                error,
+            });
+        }
+    }
+    entries
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn latency_stats() {
+        let durations: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        let stats = LatencyStats::from_durations(&durations);
+        assert_eq!(stats.min_ms, 1.);
+        assert_eq!(stats.max_ms, 100.);
+        assert_eq!(stats.p50_ms, 50.);
+        assert_eq!(stats.p90_ms, 90.);
+        assert_eq!(stats.p99_ms, 99.);
+        assert!((stats.mean_ms - 50.5).abs() < 1e-9);
+        assert_eq!(LatencyStats::from_durations(&[]), LatencyStats::default());
+    }
+
+    #[test]
+    fn run_counts_iterations() {
+        let mut count = 0;
+        let report = run::<(), _>(&BenchConfig::default().warmup(2).iterations(5), |timer| {
+            count += 1;
+            timer.stage(Stage::Inference, || {
+                std::thread::sleep(Duration::from_millis(2))
+            });
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(count, 7);
+        assert_eq!(report.iterations, 5);
+        assert!(report.inference.min_ms >= 2.);
+        assert!(report.total.mean_ms >= report.inference.mean_ms);
+        assert_eq!(report.preprocess.max_ms, 0.);
+        assert!(report.fps > 0.);
+        #[cfg(target_os = "linux")]
+        assert!(report.peak_rss_bytes.is_some());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn peak_rss_is_reset() {
+        // 計測前に確保して解放したメモリは計測中の最大値に含まれない
+        let size = 256 * 1024 * 1024;
+        drop(std::hint::black_box(vec![1u8; size]));
+        let lifetime = peak_rss_bytes().unwrap();
+        let report = run::<(), _>(&BenchConfig::default().iterations(1), |_| Ok(())).unwrap();
+        assert!(report.peak_rss_bytes.unwrap() + size as u64 / 2 < lifetime);
+    }
+
+    #[test]
+    fn sweep_records_errors() {
+        let entries = sweep(&[0, 1], &[1, 4], |env_id, num_threads| {
+            if env_id == 1 {
+                Err(AiliaError::GpuUnsupportLayer)
+            } else {
+                run::<AiliaError, _>(
+                    &BenchConfig::default()
+                        .warmup(0)
+                        .iterations(num_threads as u32),
+                    |_| Ok(()),
+                )
+            }
+        });
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[1].num_threads, 4);
+        assert_eq!(entries[1].report.as_ref().unwrap().iterations, 4);
+        assert!(entries[2].report.is_none());
+        assert!(entries[3].error.is_some());
+    }
+}
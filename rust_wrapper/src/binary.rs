@@ -0,0 +1,82 @@
+//! 独自のバイナリ形式(リトルエンディアン)の読み書き
+//!
+//! 長さはファイルから読んだ値のため信用せず、実際に読めた分だけ確保する。
+
+use std::io::{self, Read, Write};
+
+pub(crate) fn write_u32<W: Write>(writer: &mut W, value: u32) -> Result<(), io::Error> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+pub(crate) fn read_u32<R: Read>(reader: &mut R) -> Result<u32, io::Error> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+pub(crate) fn write_f32s<W: Write>(writer: &mut W, values: &[f32]) -> Result<(), io::Error> {
+    for value in values {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// len個のf32を読む
+pub(crate) fn read_f32s<R: Read>(reader: &mut R, len: usize) -> Result<Vec<f32>, io::Error> {
+    let bytes = len
+        .checked_mul(4)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "length overflow"))?;
+    Ok(read_bytes(reader, bytes)?
+        .chunks_exact(4)
+        .map(|value| f32::from_le_bytes(value.try_into().unwrap()))
+        .collect())
+}
+
+/// lenバイト読む、途中で終わった場合はUnexpectedEof
+pub(crate) fn read_bytes<R: Read>(reader: &mut R, len: usize) -> Result<Vec<u8>, io::Error> {
+    let mut buf = Vec::new();
+    reader.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(buf)
+}
+
+/// 長さ(u32)とUTF-8のバイト列
+pub(crate) fn write_str<W: Write>(writer: &mut W, value: &str) -> Result<(), io::Error> {
+    write_u32(writer, value.len() as u32)?;
+    writer.write_all(value.as_bytes())
+}
+
+/// write_strで書いた文字列、UTF-8でなければInvalidData
+pub(crate) fn read_string<R: Read>(reader: &mut R) -> Result<String, io::Error> {
+    let len = read_u32(reader)? as usize;
+    String::from_utf8(read_bytes(reader, len)?)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn truncated_lengths() {
+        let mut data = Vec::new();
+        write_str(&mut data, "abc").unwrap();
+        write_f32s(&mut data, &[1.5, -2.]).unwrap();
+        let mut reader = data.as_slice();
+        assert_eq!(read_string(&mut reader).unwrap(), "abc");
+        assert_eq!(read_f32s(&mut reader, 2).unwrap(), [1.5, -2.]);
+
+        // 実際より長い長さを読んでも確保せずにエラーになる
+        let mut data = Vec::new();
+        write_u32(&mut data, u32::MAX).unwrap();
+        data.extend_from_slice(b"abc");
+        let err = read_string(&mut data.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let err = read_f32s(&mut [0u8; 8].as_slice(), usize::MAX).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = read_f32s(&mut [0u8; 8].as_slice(), usize::MAX / 4).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
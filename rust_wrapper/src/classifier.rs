@@ -4,6 +4,7 @@ use std::ops::Deref;
 use std::path::Path;
 use std::ptr::NonNull;
 
+use crate::format::{ChannelOrder, ImageFormat, ImageRange, NetworkImageFormat};
 use crate::network::Network;
 use crate::AiliaError;
 
@@ -15,23 +16,23 @@ pub struct ClassifierBuilder<P>
 where
     P: AsRef<Path> + Default + Debug,
 {
-    prototxt: P,
+    prototxt: Option<P>,
     onnx: P,
     env_id: Option<i32>,
     num_threads: Option<i32>,
-    format: Option<u32>,
-    channel: Option<u32>,
-    range: Option<u32>,
+    format: Option<NetworkImageFormat>,
+    channel: Option<ChannelOrder>,
+    range: Option<ImageRange>,
 }
 
 impl<P: AsRef<Path> + Default + Debug> ClassifierBuilder<P> {
-    crate::impl_non_option!(prototxt, P);
+    crate::impl_option!(prototxt, P);
     crate::impl_non_option!(onnx, P);
     crate::impl_option!(env_id, i32);
     crate::impl_option!(num_threads, i32);
-    crate::impl_option!(format, u32);
-    crate::impl_option!(channel, u32);
-    crate::impl_option!(range, u32);
+    crate::impl_option!(format, NetworkImageFormat);
+    crate::impl_option!(channel, ChannelOrder);
+    crate::impl_option!(range, ImageRange);
 
     pub fn build(self) -> Result<Classifier, AiliaError> {
         let net = Network::ailia_create(
@@ -39,14 +40,12 @@ impl<P: AsRef<Path> + Default + Debug> ClassifierBuilder<P> {
             self.num_threads
                 .unwrap_or_else(|| AILIA_MULTITHREAD_AUTO.try_into().unwrap()),
         )?;
-        net.open_stream_file_a(self.prototxt)?;
-        net.open_weight_file_a(self.onnx)?;
+        net.open_model_files(self.prototxt, self.onnx)?;
         Classifier::new(
             net,
-            self.format.unwrap_or(AILIA_NETWORK_IMAGE_FORMAT_RGB),
-            self.channel.unwrap_or(AILIA_NETWORK_IMAGE_CHANNEL_FIRST),
-            self.range
-                .unwrap_or(AILIA_NETWORK_IMAGE_RANGE_UNSIGNED_INT8),
+            self.format.unwrap_or_default(),
+            self.channel.unwrap_or_default(),
+            self.range.unwrap_or_default(),
         )
     }
 }
@@ -58,6 +57,7 @@ pub struct Classifier {
 }
 
 #[derive(Clone, Debug, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Class {
     pub category: i32,
     pub prob: f32,
@@ -89,15 +89,20 @@ impl From<MaybeUninit<AILIAClassifierClass>> for Class {
 }
 
 impl Classifier {
-    pub fn new(net: Network, format: u32, channel: u32, range: u32) -> Result<Self, AiliaError> {
+    pub fn new(
+        net: Network,
+        format: NetworkImageFormat,
+        channel: ChannelOrder,
+        range: ImageRange,
+    ) -> Result<Self, AiliaError> {
         let mut ptr: *mut AILIAClassifier = std::ptr::null::<AILIAClassifier>() as *mut _;
         match unsafe {
             ailiaCreateClassifier(
                 &mut ptr as *mut *mut _,
                 net.as_ptr(),
-                format,
-                channel,
-                range,
+                format.into(),
+                channel.into(),
+                range.into(),
             )
         } {
             0 => Ok(Self {
@@ -114,7 +119,7 @@ impl Classifier {
         stride: u32,
         width: u32,
         height: u32,
-        format: u32,
+        format: ImageFormat,
         max_class_count: u32,
     ) -> Result<(), AiliaError> {
         crate::invoke_ailia_fn_result!(
@@ -124,7 +129,7 @@ impl Classifier {
             stride,
             width,
             height,
-            format,
+            u32::from(format),
             max_class_count
         );
     }
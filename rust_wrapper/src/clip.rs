@@ -0,0 +1,706 @@
+//! CLIPによるゼロショット画像分類
+//!
+//! ailia-modelsのclip(ViT-B32-encode_image.onnx, ViT-B32-encode_text.onnx)に対応する。
+//! テキストはバイト単位のBPEでトークンIDにし、画像の特徴量とのコサイン類似度をsoftmaxでスコアにする。
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs::read_to_string;
+use std::io;
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+
+use image::imageops::FilterType;
+use image::RgbImage;
+
+use thiserror::Error;
+
+use crate::embedding::{
+    cosine_similarity, l2_normalize, Embedder, EmbedderBuilder, EmbeddingError,
+};
+use crate::network::{DataType, Network};
+use crate::preprocess::{Normalize, Preprocess};
+use crate::video::ImageView;
+use crate::AiliaError;
+
+use ailia_sys::*;
+
+pub const CLIP_MEAN: [f32; 3] = [0.481_454_66, 0.457_827_5, 0.408_210_73];
+pub const CLIP_STD: [f32; 3] = [0.268_629_54, 0.261_302_6, 0.275_777_1];
+
+const START_OF_TEXT: &str = "<|startoftext|>";
+const END_OF_TEXT: &str = "<|endoftext|>";
+/// OpenAIのCLIPの語彙に含まれるmergeの数
+const CLIP_NUM_MERGES: usize = 49152 - 256 - 2;
+
+#[derive(Debug, Error)]
+pub enum ClipError {
+    #[error("語彙ファイルが不正です: {0}")]
+    InvalidVocab(String),
+    #[error("出力の形状が不正です: {0:?}")]
+    InvalidShape(Vec<u32>),
+    #[error("トークンIDを入力できないデータ型です: {0:?}")]
+    UnsupportedDataType(DataType),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Embedding(#[from] EmbeddingError),
+    #[error(transparent)]
+    Ailia(#[from] AiliaError),
+}
+
+/// バイトをBPEで扱う文字に対応させる(GPT-2のbytes_to_unicode)
+fn bytes_to_unicode() -> [char; 256] {
+    let mut table = ['\0'; 256];
+    let mut extra = 0;
+    for byte in 0..=255u8 {
+        let printable = matches!(byte, b'!'..=b'~' | 0xa1..=0xac | 0xae..=0xff);
+        table[byte as usize] = if printable {
+            byte as char
+        } else {
+            extra += 1;
+            char::from_u32(255 + extra).unwrap()
+        };
+    }
+    table
+}
+
+/// 語彙に並べる順序、印字可能な文字が先
+fn byte_order() -> impl Iterator<Item = u8> {
+    let printable = |byte: &u8| matches!(byte, b'!'..=b'~' | 0xa1..=0xac | 0xae..=0xff);
+    (0..=255u8)
+        .filter(printable)
+        .chain((0..=255u8).filter(move |byte| !printable(byte)))
+}
+
+/// CLIPのバイト単位のBPEトークナイザ
+#[derive(Clone, Debug, PartialEq)]
+pub struct BpeTokenizer {
+    encoder: HashMap<String, i64>,
+    decoder: HashMap<i64, String>,
+    ranks: HashMap<(String, String), usize>,
+    byte_encoder: [char; 256],
+    start_of_text: i64,
+    end_of_text: i64,
+}
+
+impl BpeTokenizer {
+    /// HuggingFace形式のvocab.jsonとmerges.txtを読み込む
+    pub fn load<P: AsRef<Path>, Q: AsRef<Path>>(vocab: P, merges: Q) -> Result<Self, ClipError> {
+        let encoder = parse_vocab(&read_to_string(vocab)?)
+            .ok_or_else(|| ClipError::InvalidVocab("vocab.json".to_string()))?;
+        let merges = parse_merges(&read_to_string(merges)?, usize::MAX)?;
+        Self::new(encoder, merges)
+    }
+
+    /// OpenAI形式のmergesファイル(bpe_simple_vocab_16e6.txtを展開したもの)から語彙を組み立てる
+    pub fn from_merges<P: AsRef<Path>>(merges: P) -> Result<Self, ClipError> {
+        let merges = parse_merges(&read_to_string(merges)?, CLIP_NUM_MERGES)?;
+        let byte_encoder = bytes_to_unicode();
+        let tokens = byte_order()
+            .map(|byte| byte_encoder[byte as usize].to_string())
+            .chain(byte_order().map(|byte| format!("{}</w>", byte_encoder[byte as usize])))
+            .chain(merges.iter().map(|(a, b)| format!("{}{}", a, b)))
+            .chain([START_OF_TEXT.to_string(), END_OF_TEXT.to_string()]);
+        let encoder = tokens.zip(0..).collect();
+        Self::new(encoder, merges)
+    }
+
+    fn new(
+        encoder: HashMap<String, i64>,
+        merges: Vec<(String, String)>,
+    ) -> Result<Self, ClipError> {
+        let special = |token: &str| {
+            encoder
+                .get(token)
+                .copied()
+                .ok_or_else(|| ClipError::InvalidVocab(format!("{} is missing", token)))
+        };
+        Ok(BpeTokenizer {
+            start_of_text: special(START_OF_TEXT)?,
+            end_of_text: special(END_OF_TEXT)?,
+            decoder: encoder.iter().map(|(k, &v)| (v, k.clone())).collect(),
+            encoder,
+            ranks: merges.into_iter().zip(0..).collect(),
+            byte_encoder: bytes_to_unicode(),
+        })
+    }
+
+    pub fn start_of_text(&self) -> i64 {
+        self.start_of_text
+    }
+
+    pub fn end_of_text(&self) -> i64 {
+        self.end_of_text
+    }
+
+    /// 特殊トークンを含まないトークンID
+    pub fn encode(&self, text: &str) -> Vec<i64> {
+        let mut ids = Vec::new();
+        for piece in pre_tokenize(&clean_text(text)) {
+            match piece.as_str() {
+                START_OF_TEXT => ids.push(self.start_of_text),
+                END_OF_TEXT => ids.push(self.end_of_text),
+                _ => {
+                    let piece = piece
+                        .bytes()
+                        .map(|byte| self.byte_encoder[byte as usize])
+                        .collect::<String>();
+                    ids.extend(
+                        self.bpe(&piece)
+                            .iter()
+                            .filter_map(|token| self.encoder.get(token)),
+                    );
+                }
+            }
+        }
+        ids
+    }
+
+    /// 開始と終了のトークンを付けてcontext_lengthに切り詰め、0で埋める
+    pub fn tokenize(&self, text: &str, context_length: usize) -> Vec<i64> {
+        let mut ids = vec![self.start_of_text];
+        ids.extend(self.encode(text));
+        ids.push(self.end_of_text);
+        if ids.len() > context_length {
+            ids.truncate(context_length);
+            if let Some(last) = ids.last_mut() {
+                *last = self.end_of_text;
+            }
+        }
+        ids.resize(context_length, 0);
+        ids
+    }
+
+    pub fn decode(&self, ids: &[i64]) -> String {
+        let byte_decoder = self
+            .byte_encoder
+            .iter()
+            .zip(0..=255u8)
+            .map(|(&c, byte)| (c, byte))
+            .collect::<HashMap<_, _>>();
+        let text = ids
+            .iter()
+            .filter(|&&id| id != self.start_of_text && id != self.end_of_text)
+            .filter_map(|id| self.decoder.get(id))
+            .map(String::as_str)
+            .collect::<String>();
+        let mut bytes = Vec::new();
+        for word in text.split_inclusive("</w>") {
+            let (word, end) = match word.strip_suffix("</w>") {
+                Some(word) => (word, true),
+                None => (word, false),
+            };
+            bytes.extend(word.chars().filter_map(|c| byte_decoder.get(&c)));
+            if end {
+                bytes.push(b' ');
+            }
+        }
+        String::from_utf8_lossy(&bytes).trim_end().to_string()
+    }
+
+    fn bpe(&self, piece: &str) -> Vec<String> {
+        let mut word = piece.chars().map(String::from).collect::<Vec<_>>();
+        if let Some(last) = word.last_mut() {
+            last.push_str("</w>");
+        }
+        while word.len() > 1 {
+            let best = word
+                .windows(2)
+                .filter_map(|pair| {
+                    self.ranks
+                        .get(&(pair[0].clone(), pair[1].clone()))
+                        .map(|&rank| (rank, pair[0].clone(), pair[1].clone()))
+                })
+                .min();
+            let Some((_, first, second)) = best else {
+                break;
+            };
+            let mut merged = Vec::with_capacity(word.len());
+            let mut i = 0;
+            while i < word.len() {
+                if i + 1 < word.len() && word[i] == first && word[i + 1] == second {
+                    merged.push(format!("{}{}", first, second));
+                    i += 2;
+                } else {
+                    merged.push(word[i].clone());
+                    i += 1;
+                }
+            }
+            word = merged;
+        }
+        word
+    }
+}
+
+/// 空白をまとめて小文字にする
+fn clean_text(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// CLIPの正規表現`'s|'t|'re|'ve|'m|'ll|'d|[\p{L}]+|[\p{N}]|[^\s\p{L}\p{N}]+`に相当する分割
+fn pre_tokenize(text: &str) -> Vec<String> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut pieces = Vec::new();
+    let mut i = 0;
+    'outer: while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let rest = chars[i..].iter().collect::<String>();
+        for special in [START_OF_TEXT, END_OF_TEXT] {
+            if rest.starts_with(special) {
+                pieces.push(special.to_string());
+                i += special.chars().count();
+                continue 'outer;
+            }
+        }
+        if c == '\'' {
+            for suffix in ["s", "t", "re", "ve", "m", "ll", "d"] {
+                if rest[1..].starts_with(suffix) {
+                    pieces.push(format!("'{}", suffix));
+                    i += 1 + suffix.len();
+                    continue 'outer;
+                }
+            }
+        }
+        let end = if c.is_alphabetic() {
+            chars[i..]
+                .iter()
+                .position(|c| !c.is_alphabetic())
+                .map_or(chars.len(), |n| i + n)
+        } else if c.is_numeric() {
+            i + 1
+        } else {
+            chars[i..]
+                .iter()
+                .position(|c| c.is_whitespace() || c.is_alphabetic() || c.is_numeric())
+                .map_or(chars.len(), |n| i + n)
+        };
+        pieces.push(chars[i..end].iter().collect());
+        i = end;
+    }
+    pieces
+}
+
+/// merges.txtの各行"a b"、#versionの行は読み飛ばす
+fn parse_merges(text: &str, limit: usize) -> Result<Vec<(String, String)>, ClipError> {
+    text.lines()
+        .filter(|line| !line.starts_with("#version") && !line.is_empty())
+        .take(limit)
+        .map(|line| {
+            line.split_once(' ')
+                .map(|(a, b)| (a.to_string(), b.to_string()))
+                .ok_or_else(|| ClipError::InvalidVocab(format!("merge {:?}", line)))
+        })
+        .collect()
+}
+
+/// JSONの\uXXXXの16進数4桁
+fn read_hex(chars: &mut Peekable<Chars>) -> Option<u32> {
+    let digits = (0..4).map(|_| chars.next()).collect::<Option<String>>()?;
+    u32::from_str_radix(&digits, 16).ok()
+}
+
+/// {"token": id, ...}の形のJSONを読む
+fn parse_vocab(text: &str) -> Option<HashMap<String, i64>> {
+    let mut chars = text.trim().chars().peekable();
+    let mut vocab = HashMap::new();
+    let skip_ws = |chars: &mut Peekable<Chars>| {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+    };
+    if chars.next()? != '{' {
+        return None;
+    }
+    loop {
+        skip_ws(&mut chars);
+        match chars.next()? {
+            '}' => break,
+            '"' => {}
+            _ => return None,
+        }
+        let mut key = String::new();
+        loop {
+            match chars.next()? {
+                '"' => break,
+                '\\' => match chars.next()? {
+                    'n' => key.push('\n'),
+                    't' => key.push('\t'),
+                    'r' => key.push('\r'),
+                    'b' => key.push('\u{8}'),
+                    'f' => key.push('\u{c}'),
+                    'u' => {
+                        let high = read_hex(&mut chars)?;
+                        let code = if (0xd800..0xdc00).contains(&high) {
+                            if chars.next()? != '\\' || chars.next()? != 'u' {
+                                return None;
+                            }
+                            let low = read_hex(&mut chars)?;
+                            if !(0xdc00..0xe000).contains(&low) {
+                                return None;
+                            }
+                            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+                        } else {
+                            high
+                        };
+                        key.push(char::from_u32(code)?);
+                    }
+                    c => key.push(c),
+                },
+                c => key.push(c),
+            }
+        }
+        skip_ws(&mut chars);
+        if chars.next()? != ':' {
+            return None;
+        }
+        skip_ws(&mut chars);
+        let mut value = String::new();
+        while chars
+            .peek()
+            .is_some_and(|c| c.is_ascii_digit() || *c == '-')
+        {
+            value.push(chars.next()?);
+        }
+        vocab.insert(key, value.parse().ok()?);
+        skip_ws(&mut chars);
+        match chars.next()? {
+            ',' => {}
+            '}' => break,
+            _ => return None,
+        }
+    }
+    Some(vocab)
+}
+
+/// テキストの特徴量を計算する
+pub struct TextEncoder {
+    net: Network,
+    tokenizer: BpeTokenizer,
+    context_length: usize,
+}
+
+impl TextEncoder {
+    pub fn new(net: Network, tokenizer: BpeTokenizer, context_length: usize) -> Self {
+        Self {
+            net,
+            tokenizer,
+            context_length,
+        }
+    }
+
+    pub fn network(&self) -> &Network {
+        &self.net
+    }
+
+    pub fn tokenizer(&self) -> &BpeTokenizer {
+        &self.tokenizer
+    }
+
+    /// textsをまとめて推論し、L2正規化した特徴量を返す
+    pub fn encode(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, ClipError> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+        let tokens = texts
+            .iter()
+            .flat_map(|text| self.tokenizer.tokenize(text, self.context_length))
+            .collect::<Vec<_>>();
+        let shape = [texts.len() as u32, self.context_length as u32];
+        let input_idx = self.net.get_input_blob_index_by_index(0)?;
+        match self.net.get_blob_data_type(input_idx)? {
+            DataType::Int64 => self.net.set_input_tensor(&tokens, &shape, input_idx)?,
+            DataType::Int32 => {
+                let tokens = tokens.iter().map(|&id| id as i32).collect::<Vec<_>>();
+                self.net.set_input_tensor(&tokens, &shape, input_idx)?
+            }
+            DataType::Float => {
+                let tokens = tokens.iter().map(|&id| id as f32).collect::<Vec<_>>();
+                self.net.set_input_tensor(&tokens, &shape, input_idx)?
+            }
+            data_type => return Err(ClipError::UnsupportedDataType(data_type)),
+        }
+        self.net.update()?;
+        let output_idx = self.net.get_output_blob_index_by_index(0)?;
+        let output_shape = self.net.get_blob_shape_nd(output_idx)?;
+        let output = self.net.get_output_blob_by_index::<f32>(output_idx)?;
+        if output.is_empty() || output.len() % texts.len() != 0 {
+            return Err(ClipError::InvalidShape(output_shape));
+        }
+        Ok(output
+            .chunks_exact(output.len() / texts.len())
+            .map(|embedding| {
+                let mut embedding = embedding.to_vec();
+                l2_normalize(&mut embedding);
+                embedding
+            })
+            .collect())
+    }
+}
+
+/// テンプレートの{}をラベルで置き換える
+pub fn apply_template(template: &str, label: &str) -> String {
+    template.replace("{}", label)
+}
+
+/// logit_scale倍したコサイン類似度のsoftmax
+pub fn zero_shot_scores(
+    image_embedding: &[f32],
+    text_embeddings: &[Vec<f32>],
+    logit_scale: f32,
+) -> Vec<f32> {
+    let logits = text_embeddings
+        .iter()
+        .map(|text| logit_scale * cosine_similarity(image_embedding, text))
+        .collect::<Vec<_>>();
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let exp = logits.iter().map(|l| (l - max).exp()).collect::<Vec<_>>();
+    let sum = exp.iter().sum::<f32>();
+    exp.into_iter().map(|e| e / sum).collect()
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ZeroShotClassifierBuilder<P>
+where
+    P: AsRef<Path> + Default + Debug,
+{
+    image_prototxt: Option<P>,
+    image_onnx: P,
+    text_prototxt: Option<P>,
+    text_onnx: P,
+    vocab: Option<P>,
+    merges: P,
+    env_id: Option<i32>,
+    num_threads: Option<i32>,
+    input_size: Option<u32>,
+    context_length: Option<usize>,
+    templates: Option<Vec<String>>,
+    logit_scale: Option<f32>,
+}
+
+impl<P: AsRef<Path> + Default + Debug> ZeroShotClassifierBuilder<P> {
+    crate::impl_option!(image_prototxt, P);
+    crate::impl_non_option!(image_onnx, P);
+    crate::impl_option!(text_prototxt, P);
+    crate::impl_non_option!(text_onnx, P);
+    // HuggingFace形式のvocab.json、指定しない場合はmergesから語彙を組み立てる
+    crate::impl_option!(vocab, P);
+    crate::impl_non_option!(merges, P);
+    crate::impl_option!(env_id, i32);
+    crate::impl_option!(num_threads, i32);
+    // 画像エンコーダの入力サイズ、既定値は224
+    crate::impl_option!(input_size, u32);
+    // トークン列の長さ、既定値は77
+    crate::impl_option!(context_length, usize);
+    // classify_labelsで使うテンプレート、既定値は"a photo of a {}."
+    crate::impl_option!(templates, Vec<String>);
+    // 既定値は100
+    crate::impl_option!(logit_scale, f32);
+
+    pub fn build(self) -> Result<ZeroShotClassifier, ClipError> {
+        let env_id = self.env_id.unwrap_or(AILIA_ENVIRONMENT_ID_AUTO);
+        let num_threads = self
+            .num_threads
+            .unwrap_or_else(|| AILIA_MULTITHREAD_AUTO.try_into().unwrap());
+        let size = self.input_size.unwrap_or(224);
+        let mut image = EmbedderBuilder::default()
+            .onnx(self.image_onnx)
+            .env_id(env_id)
+            .num_threads(num_threads)
+            .preprocess(
+                Preprocess::new()
+                    .resize_shorter(size, FilterType::CatmullRom)
+                    .center_crop(size, size)
+                    .normalize(Normalize::MeanStd {
+                        mean: CLIP_MEAN,
+                        std: CLIP_STD,
+                    }),
+            );
+        if let Some(prototxt) = self.image_prototxt {
+            image = image.prototxt(prototxt);
+        }
+        let text = Network::ailia_create(env_id, num_threads)?;
+        text.open_model_files(self.text_prototxt, self.text_onnx)?;
+        let tokenizer = match self.vocab {
+            Some(vocab) => BpeTokenizer::load(vocab, self.merges)?,
+            None => BpeTokenizer::from_merges(self.merges)?,
+        };
+        Ok(ZeroShotClassifier {
+            image: image.build()?,
+            text: TextEncoder::new(text, tokenizer, self.context_length.unwrap_or(77)),
+            templates: self
+                .templates
+                .unwrap_or_else(|| vec!["a photo of a {}.".to_string()]),
+            logit_scale: self.logit_scale.unwrap_or(100.),
+        })
+    }
+}
+
+/// 任意のテキストをクラスとして画像を分類する
+pub struct ZeroShotClassifier {
+    image: Embedder,
+    text: TextEncoder,
+    templates: Vec<String>,
+    logit_scale: f32,
+}
+
+impl ZeroShotClassifier {
+    pub fn image_encoder(&self) -> &Embedder {
+        &self.image
+    }
+
+    pub fn text_encoder(&self) -> &TextEncoder {
+        &self.text
+    }
+
+    /// promptsごとのスコア(合計1)
+    pub fn classify(&self, image: &ImageView, prompts: &[&str]) -> Result<Vec<f32>, ClipError> {
+        let text_embeddings = self.text.encode(prompts)?;
+        Ok(self.scores(&self.image.embed(image)?, &text_embeddings))
+    }
+
+    pub fn classify_rgb(&self, image: &RgbImage, prompts: &[&str]) -> Result<Vec<f32>, ClipError> {
+        let text_embeddings = self.text.encode(prompts)?;
+        Ok(self.scores(&self.image.embed_rgb(image)?, &text_embeddings))
+    }
+
+    /// ラベルをテンプレートに当てはめて平均した特徴量、計算しておけばscoresで繰り返し使える
+    pub fn label_embeddings(&self, labels: &[&str]) -> Result<Vec<Vec<f32>>, ClipError> {
+        labels
+            .iter()
+            .map(|label| {
+                let prompts = self
+                    .templates
+                    .iter()
+                    .map(|template| apply_template(template, label))
+                    .collect::<Vec<_>>();
+                let prompts = prompts.iter().map(String::as_str).collect::<Vec<_>>();
+                let embeddings = self.text.encode(&prompts)?;
+                let mut mean = vec![0.; embeddings.first().map_or(0, Vec::len)];
+                for embedding in &embeddings {
+                    mean.iter_mut().zip(embedding).for_each(|(m, e)| *m += e);
+                }
+                l2_normalize(&mut mean);
+                Ok(mean)
+            })
+            .collect()
+    }
+
+    /// ラベルをテンプレートに当てはめて分類する
+    pub fn classify_labels(
+        &self,
+        image: &ImageView,
+        labels: &[&str],
+    ) -> Result<Vec<f32>, ClipError> {
+        let text_embeddings = self.label_embeddings(labels)?;
+        Ok(self.scores(&self.image.embed(image)?, &text_embeddings))
+    }
+
+    pub fn scores(&self, image_embedding: &[f32], text_embeddings: &[Vec<f32>]) -> Vec<f32> {
+        zero_shot_scores(image_embedding, text_embeddings, self.logit_scale)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// "low", "lower", "newest"などを分割できる小さな語彙
+    fn tokenizer() -> BpeTokenizer {
+        let path =
+            std::env::temp_dir().join(format!("ailia_clip_merges_{}.txt", std::process::id()));
+        std::fs::write(
+            &path,
+            "#version: 0.2\nl o\nlo w</w>\nlo w\ne r</w>\nn e\nne w\ne s\nes t</w>\n",
+        )
+        .unwrap();
+        let tokenizer = BpeTokenizer::from_merges(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        tokenizer
+    }
+
+    #[test]
+    fn byte_table() {
+        let table = bytes_to_unicode();
+        assert_eq!(table[b'a' as usize], 'a');
+        assert_eq!(table[b' ' as usize], 'Ġ');
+        assert_eq!(table[0], 'Ā');
+        let mut unique = table.to_vec();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), 256);
+    }
+
+    #[test]
+    fn pre_tokenizer() {
+        assert_eq!(
+            pre_tokenize("it's a 12px <|endoftext|> photo!!"),
+            [
+                "it",
+                "'s",
+                "a",
+                "1",
+                "2",
+                "px",
+                "<|endoftext|>",
+                "photo",
+                "!!"
+            ]
+        );
+    }
+
+    #[test]
+    fn bpe_round_trip() {
+        let tokenizer = tokenizer();
+        assert_eq!(tokenizer.bpe("lower"), ["low", "er</w>"]);
+        assert_eq!(tokenizer.bpe("newest"), ["new", "est</w>"]);
+        assert_eq!(tokenizer.bpe("low"), ["low</w>"]);
+
+        // 語彙は256バイト + </w>付きの256バイト + merges + 特殊トークン
+        assert_eq!(tokenizer.start_of_text(), 512 + 8);
+        assert_eq!(tokenizer.end_of_text(), 512 + 9);
+        let ids = tokenizer.encode("  Lower  newest, LOW ");
+        assert_eq!(ids.len(), 6);
+        assert_eq!(tokenizer.decode(&ids), "lower newest , low");
+
+        let tokens = tokenizer.tokenize("low low low", 4);
+        assert_eq!(tokens, [520, ids[5], ids[5], 521]);
+        assert_eq!(tokenizer.tokenize("low", 5)[3..], [0, 0]);
+    }
+
+    #[test]
+    fn vocab_json() {
+        let vocab =
+            parse_vocab(r#"{"a": 0, "é</w>": 1, "\"q\"": 2 , "😀":-3, "\u00e9\ud83d\ude00": 4}"#)
+                .unwrap();
+        assert_eq!(vocab["a"], 0);
+        assert_eq!(vocab["é</w>"], 1);
+        assert_eq!(vocab["\"q\""], 2);
+        assert_eq!(vocab["😀"], -3);
+        assert_eq!(vocab["é😀"], 4);
+        assert_eq!(parse_vocab("{\"a\" 0}"), None);
+        // 上位サロゲートの後に下位サロゲート以外が続く
+        assert_eq!(parse_vocab(r#"{"\ud83d\u0041": 0}"#), None);
+    }
+
+    #[test]
+    fn scores() {
+        let image = [1., 0.];
+        let texts = [vec![1., 0.], vec![0., 1.]];
+        let scores = zero_shot_scores(&image, &texts, 100.);
+        assert!((scores.iter().sum::<f32>() - 1.).abs() < 1e-6);
+        assert!(scores[0] > 0.999);
+        assert_eq!(
+            apply_template("a photo of a {}.", "cat"),
+            "a photo of a cat."
+        );
+    }
+}
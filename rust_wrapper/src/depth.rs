@@ -0,0 +1,492 @@
+//! 単眼深度推定(MiDaS, DPTなど)
+//!
+//! 入力は32の倍数に合わせてリサイズし、出力の逆深度(視差)を元画像の解像度に戻す。
+
+use std::fmt::Debug;
+use std::ops::Deref;
+use std::path::Path;
+
+use image::{RgbImage, Rgba, RgbaImage};
+
+use thiserror::Error;
+
+use crate::detector::Object;
+use crate::network::Network;
+use crate::preprocess::{
+    resample_to_source, FilterType, ImageTensor, Normalize, Preprocess, PreprocessError, Transform,
+};
+use crate::video::ImageView;
+use crate::AiliaError;
+
+use ailia_sys::*;
+
+#[derive(Debug, Error)]
+pub enum DepthError {
+    #[error("出力の形状が不正です: {0:?}")]
+    InvalidShape(Vec<u32>),
+    #[error(transparent)]
+    Preprocess(#[from] PreprocessError),
+    #[error(transparent)]
+    Ailia(#[from] AiliaError),
+}
+
+/// 入力画像のリサイズ方法、いずれもmultiple_ofの倍数に丸める
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DepthResize {
+    /// input_width x input_heightにする(縦横比を保たない)
+    #[default]
+    Fixed,
+    /// 縦横比を保ち、両辺がinput_width, input_height以上になる最小のサイズにする
+    LowerBound,
+    /// 縦横比を保ち、両辺がinput_width, input_height以下になる最大のサイズにする
+    UpperBound,
+}
+
+impl DepthResize {
+    /// 元画像のサイズからネットワークに入力するサイズを決める
+    pub fn fit(
+        &self,
+        (width, height): (u32, u32),
+        (input_width, input_height): (u32, u32),
+        multiple_of: u32,
+    ) -> (u32, u32) {
+        let multiple_of = multiple_of.max(1);
+        let (scale_x, scale_y) = (
+            input_width as f32 / width as f32,
+            input_height as f32 / height as f32,
+        );
+        let (scale_x, scale_y) = match self {
+            DepthResize::Fixed => (scale_x, scale_y),
+            DepthResize::LowerBound => (scale_x.max(scale_y), scale_x.max(scale_y)),
+            DepthResize::UpperBound => (scale_x.min(scale_y), scale_x.min(scale_y)),
+        };
+        let constrain = |size: f32| {
+            let size = match self {
+                DepthResize::Fixed => (size / multiple_of as f32).round(),
+                DepthResize::LowerBound => (size / multiple_of as f32).ceil(),
+                DepthResize::UpperBound => (size / multiple_of as f32).floor(),
+            };
+            (size as u32).max(1) * multiple_of
+        };
+        (
+            constrain(width as f32 * scale_x),
+            constrain(height as f32 * scale_y),
+        )
+    }
+}
+
+/// 出力の値の意味
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DepthKind {
+    /// 逆深度(視差)、大きいほど近い(MiDaS, DPT)
+    #[default]
+    Inverse,
+    /// 深度、大きいほど遠い
+    Depth,
+}
+
+/// 深度推定の結果、値のスケールはモデルに依存する相対値
+#[derive(Clone, Debug, PartialEq)]
+pub struct DepthMap {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<f32>,
+    pub kind: DepthKind,
+}
+
+impl DepthMap {
+    /// Networkの出力((1, 1, H, W), (1, H, W), (H, W)のいずれか)から作る
+    pub fn from_output(data: Vec<f32>, shape: &[u32], kind: DepthKind) -> Result<Self, DepthError> {
+        let mut dims = shape;
+        while dims.len() > 2 && dims[0] == 1 {
+            dims = &dims[1..];
+        }
+        match dims {
+            [height, width] if data.len() == (height * width) as usize => Ok(Self {
+                width: *width,
+                height: *height,
+                data,
+                kind,
+            }),
+            _ => Err(DepthError::InvalidShape(shape.to_vec())),
+        }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> f32 {
+        self.data[(y * self.width + x) as usize]
+    }
+
+    /// 前処理後の画像に対する深度を、transformを使って元画像の解像度に戻す(バイリニア)
+    pub fn to_source(&self, transform: &Transform) -> DepthMap {
+        let (width, height) = transform.source_size;
+        DepthMap {
+            width,
+            height,
+            data: resample_to_source(&self.data, self.width, self.height, transform),
+            kind: self.kind,
+        }
+    }
+
+    pub fn min_max(&self) -> (f32, f32) {
+        self.data
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), &value| {
+                (min.min(value), max.max(value))
+            })
+    }
+
+    /// 0~1に正規化する、近いほど1になるように向きをそろえる
+    pub fn normalized(&self) -> Vec<f32> {
+        let (min, max) = self.min_max();
+        let range = if max > min { max - min } else { 1. };
+        self.data
+            .iter()
+            .map(|&value| match self.kind {
+                DepthKind::Inverse => (value - min) / range,
+                DepthKind::Depth => (max - value) / range,
+            })
+            .collect()
+    }
+
+    /// 近いほど明るくなるようにカラーマップで塗った画像
+    pub fn colorize(&self, colormap: Colormap) -> RgbaImage {
+        let normalized = self.normalized();
+        RgbaImage::from_fn(self.width, self.height, |x, y| {
+            let [r, g, b] = colormap.color(normalized[(y * self.width + x) as usize]);
+            Rgba([r, g, b, 255])
+        })
+    }
+
+    /// 画素座標の(x1, y1, x2, y2)の範囲の値の中央値、範囲が空の場合はNone
+    pub fn median_in(&self, bbox: [f32; 4]) -> Option<f32> {
+        let clamp = |value: f32, size: u32| (value.round().max(0.) as u32).min(size);
+        let (x1, x2) = (clamp(bbox[0], self.width), clamp(bbox[2], self.width));
+        let (y1, y2) = (clamp(bbox[1], self.height), clamp(bbox[3], self.height));
+        let mut values: Vec<f32> = (y1..y2)
+            .flat_map(|y| (x1..x2).map(move |x| (x, y)))
+            .map(|(x, y)| self.get(x, y))
+            .collect();
+        if values.is_empty() {
+            return None;
+        }
+        let mid = values.len() / 2;
+        let (_, median, _) = values.select_nth_unstable_by(mid, f32::total_cmp);
+        Some(*median)
+    }
+
+    /// 検出結果(相対座標)の矩形内の中央値
+    pub fn object_depth(&self, object: &Object) -> Option<f32> {
+        let (width, height) = (self.width as f32, self.height as f32);
+        self.median_in([
+            object.x * width,
+            object.y * height,
+            (object.x + object.w) * width,
+            (object.y + object.h) * height,
+        ])
+    }
+
+    /// 検出結果を近い順に並べ替えたインデックス、深度を取れないものは最後になる
+    pub fn nearest_first(&self, objects: &[Object]) -> Vec<usize> {
+        let depths: Vec<Option<f32>> = objects.iter().map(|o| self.object_depth(o)).collect();
+        let mut order: Vec<usize> = (0..objects.len()).collect();
+        order.sort_by(|&a, &b| match (depths[a], depths[b]) {
+            (Some(a), Some(b)) => match self.kind {
+                DepthKind::Inverse => b.total_cmp(&a),
+                DepthKind::Depth => a.total_cmp(&b),
+            },
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => std::cmp::Ordering::Equal,
+        });
+        order
+    }
+}
+
+/// 深度の表示に使うカラーマップ
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Colormap {
+    #[default]
+    Turbo,
+    Inferno,
+    Gray,
+}
+
+impl Colormap {
+    /// 0~1の値を色にする
+    pub fn color(&self, value: f32) -> [u8; 3] {
+        let t = value.clamp(0., 1.);
+        let rgb = match self {
+            // Google AIのTurboの多項式近似
+            Colormap::Turbo => [
+                polynomial(
+                    t,
+                    &[
+                        0.13572138,
+                        4.6153926,
+                        -42.66032258,
+                        132.13108234,
+                        -152.94239396,
+                        59.28637943,
+                    ],
+                ),
+                polynomial(
+                    t,
+                    &[
+                        0.09140261,
+                        2.19418839,
+                        4.84296658,
+                        -14.18503333,
+                        4.27729857,
+                        2.82956604,
+                    ],
+                ),
+                polynomial(
+                    t,
+                    &[
+                        0.1066733,
+                        12.64194608,
+                        -60.58204836,
+                        110.36276771,
+                        -89.90310912,
+                        27.34824973,
+                    ],
+                ),
+            ],
+            // matplotlibのinfernoの多項式近似
+            Colormap::Inferno => [
+                polynomial(
+                    t,
+                    &[
+                        0.0002189404,
+                        0.1065134195,
+                        11.6024930825,
+                        -41.7039961314,
+                        77.1629356994,
+                        -71.319428245,
+                        25.1311262248,
+                    ],
+                ),
+                polynomial(
+                    t,
+                    &[
+                        0.0016510046,
+                        0.5639564368,
+                        -3.9728539657,
+                        17.4363988821,
+                        -33.4023589421,
+                        32.626064264,
+                        -12.2426689524,
+                    ],
+                ),
+                polynomial(
+                    t,
+                    &[
+                        -0.0194808984,
+                        3.9327123889,
+                        -15.9423941063,
+                        44.3541451987,
+                        -81.8073092574,
+                        73.209519858,
+                        -23.0703250029,
+                    ],
+                ),
+            ],
+            Colormap::Gray => [t, t, t],
+        };
+        rgb.map(|value| (value.clamp(0., 1.) * 255.).round() as u8)
+    }
+}
+
+/// 係数を低次から並べた多項式
+fn polynomial(t: f32, coefficients: &[f64]) -> f32 {
+    let t = t as f64;
+    coefficients.iter().rev().fold(0., |acc, &c| acc * t + c) as f32
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DepthEstimatorBuilder<P>
+where
+    P: AsRef<Path> + Default + Debug,
+{
+    prototxt: Option<P>,
+    onnx: P,
+    env_id: Option<i32>,
+    num_threads: Option<i32>,
+    input_width: u32,
+    input_height: u32,
+    resize: Option<DepthResize>,
+    multiple_of: Option<u32>,
+    normalize: Option<Normalize>,
+    kind: Option<DepthKind>,
+}
+
+impl<P: AsRef<Path> + Default + Debug> DepthEstimatorBuilder<P> {
+    crate::impl_option!(prototxt, P);
+    crate::impl_non_option!(onnx, P);
+    crate::impl_option!(env_id, i32);
+    crate::impl_option!(num_threads, i32);
+    crate::impl_non_option!(input_width, u32);
+    crate::impl_non_option!(input_height, u32);
+    crate::impl_option!(resize, DepthResize);
+    // 既定値は32
+    crate::impl_option!(multiple_of, u32);
+    // 既定値はImageNet、DPTはSigned
+    crate::impl_option!(normalize, Normalize);
+    crate::impl_option!(kind, DepthKind);
+
+    pub fn build(self) -> Result<DepthEstimator, AiliaError> {
+        let net = Network::ailia_create(
+            self.env_id.unwrap_or(AILIA_ENVIRONMENT_ID_AUTO),
+            self.num_threads
+                .unwrap_or_else(|| AILIA_MULTITHREAD_AUTO.try_into().unwrap()),
+        )?;
+        net.open_model_files(self.prototxt, self.onnx)?;
+        Ok(DepthEstimator {
+            net,
+            input_size: (self.input_width, self.input_height),
+            resize: self.resize.unwrap_or_default(),
+            multiple_of: self.multiple_of.unwrap_or(32),
+            normalize: self.normalize.unwrap_or(Normalize::ImageNet),
+            kind: self.kind.unwrap_or_default(),
+        })
+    }
+}
+
+pub struct DepthEstimator {
+    net: Network,
+    input_size: (u32, u32),
+    resize: DepthResize,
+    multiple_of: u32,
+    normalize: Normalize,
+    kind: DepthKind,
+}
+
+impl DepthEstimator {
+    /// 画像のサイズに合わせた前処理
+    pub fn preprocess(&self, width: u32, height: u32) -> Preprocess {
+        let (input_width, input_height) =
+            self.resize
+                .fit((width, height), self.input_size, self.multiple_of);
+        Preprocess::new()
+            .resize(input_width, input_height, FilterType::CatmullRom)
+            .normalize(self.normalize)
+    }
+
+    /// 元画像と同じ解像度の深度を返す
+    pub fn estimate(&self, image: &ImageView) -> Result<DepthMap, DepthError> {
+        let (tensor, transform) = self.preprocess(image.width, image.height).run(image)?;
+        self.infer(tensor, transform)
+    }
+
+    pub fn estimate_rgb(&self, image: &RgbImage) -> Result<DepthMap, DepthError> {
+        let (tensor, transform) = self
+            .preprocess(image.width(), image.height())
+            .run_rgb(image)?;
+        self.infer(tensor, transform)
+    }
+
+    fn infer(&self, tensor: ImageTensor, transform: Transform) -> Result<DepthMap, DepthError> {
+        let input_idx = self.net.get_input_blob_index_by_index(0)?;
+        tensor.set_input(&self.net, input_idx)?;
+        self.net.update()?;
+        let output_idx = self.net.get_output_blob_index_by_index(0)?;
+        let shape = self.net.get_blob_shape_nd(output_idx)?;
+        let output = self.net.get_output_blob_by_index::<f32>(output_idx)?;
+        Ok(DepthMap::from_output(output, &shape, self.kind)?.to_source(&transform))
+    }
+}
+
+impl Deref for DepthEstimator {
+    type Target = Network;
+    fn deref(&self) -> &Self::Target {
+        &self.net
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fit() {
+        assert_eq!(
+            DepthResize::Fixed.fit((640, 480), (384, 384), 32),
+            (384, 384)
+        );
+        assert_eq!(
+            DepthResize::LowerBound.fit((640, 480), (384, 384), 32),
+            (512, 384)
+        );
+        assert_eq!(
+            DepthResize::UpperBound.fit((640, 480), (384, 384), 32),
+            (384, 288)
+        );
+        assert_eq!(
+            DepthResize::LowerBound.fit((100, 300), (256, 256), 32),
+            (256, 768)
+        );
+    }
+
+    #[test]
+    fn to_source() {
+        // 2x2の出力を4x4の元画像に戻す
+        let depth =
+            DepthMap::from_output(vec![0., 1., 2., 3.], &[1, 1, 2, 2], DepthKind::Inverse).unwrap();
+        let transform = Transform {
+            scale: (0.5, 0.5),
+            offset: (0., 0.),
+            source_size: (4, 4),
+            size: (2, 2),
+        };
+        let source = depth.to_source(&transform);
+        assert_eq!((source.width, source.height), (4, 4));
+        assert_eq!(source.get(0, 0), 0.);
+        assert_eq!(source.get(3, 3), 3.);
+        assert_eq!(source.get(1, 0), 0.25);
+        assert_eq!(source.get(2, 0), 0.75);
+        assert_eq!(source.min_max(), (0., 3.));
+
+        assert!(DepthMap::from_output(vec![0.; 3], &[1, 2, 2], DepthKind::Inverse).is_err());
+    }
+
+    #[test]
+    fn objects() {
+        // 左半分が近く(逆深度が大きい)、右半分が遠い
+        let depth = DepthMap {
+            width: 4,
+            height: 2,
+            data: vec![9., 8., 1., 2., 9., 7., 1., 1.],
+            kind: DepthKind::Inverse,
+        };
+        let object = |x: f32| Object {
+            category: 0,
+            prob: 1.,
+            x,
+            y: 0.,
+            w: 0.5,
+            h: 1.,
+        };
+        let objects = [object(0.5), object(0.), object(1.)];
+        assert_eq!(depth.object_depth(&objects[0]), Some(1.));
+        assert_eq!(depth.object_depth(&objects[1]), Some(9.));
+        assert_eq!(depth.object_depth(&objects[2]), None);
+        assert_eq!(depth.nearest_first(&objects), vec![1, 0, 2]);
+
+        let normalized = depth.normalized();
+        assert_eq!((normalized[0], normalized[2]), (1., 0.));
+    }
+
+    #[test]
+    fn colormap() {
+        assert_eq!(Colormap::Gray.color(0.5), [128, 128, 128]);
+        let [r, g, b] = Colormap::Inferno.color(0.);
+        assert!(r < 5 && g < 5 && b < 10);
+        let [r, g, b] = Colormap::Inferno.color(1.);
+        assert!(r > 240 && g > 240 && b > 150);
+        // turboは青から赤
+        let [r, _, b] = Colormap::Turbo.color(0.1);
+        assert!(b > r);
+        let [r, _, b] = Colormap::Turbo.color(0.9);
+        assert!(r > b);
+    }
+}
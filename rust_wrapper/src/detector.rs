@@ -11,33 +11,43 @@ use image::RgbaImage;
 use opencv::core::Mat;
 use opencv::prelude::*;
 
+use crate::format::{ChannelOrder, ImageFormat, ImageRange, NetworkImageFormat};
 use crate::network::Network;
 use crate::AiliaError;
 
-pub use ailia_sys::AILIA_NETWORK_IMAGE_FORMAT_BGR;
-pub use ailia_sys::AILIA_NETWORK_IMAGE_FORMAT_GRAY;
-pub use ailia_sys::AILIA_NETWORK_IMAGE_FORMAT_GRAY_EQUALIZE;
-pub use ailia_sys::AILIA_NETWORK_IMAGE_FORMAT_RGB;
+pub use ailia_sys::AILIA_DETECTOR_OBJECT_VERSION;
 
-pub use ailia_sys::AILIA_NETWORK_IMAGE_CHANNEL_FIRST;
-pub use ailia_sys::AILIA_NETWORK_IMAGE_CHANNEL_LAST;
+/// 検出アルゴリズム (AILIA_DETECTOR_ALGORITHM_*)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DetectorAlgorithm {
+    #[default]
+    Yolov1,
+    Yolov2,
+    Yolov3,
+    Yolov4,
+    Yolox,
+    Ssd,
+}
 
-pub use ailia_sys::AILIA_NETWORK_IMAGE_RANGE_IMAGENET;
-pub use ailia_sys::AILIA_NETWORK_IMAGE_RANGE_SIGNED_FP32;
-pub use ailia_sys::AILIA_NETWORK_IMAGE_RANGE_SIGNED_INT8;
-pub use ailia_sys::AILIA_NETWORK_IMAGE_RANGE_UNSIGNED_FP32;
-pub use ailia_sys::AILIA_NETWORK_IMAGE_RANGE_UNSIGNED_INT8;
+crate::impl_ailia_enum!(DetectorAlgorithm {
+    Yolov1 => AILIA_DETECTOR_ALGORITHM_YOLOV1,
+    Yolov2 => AILIA_DETECTOR_ALGORITHM_YOLOV2,
+    Yolov3 => AILIA_DETECTOR_ALGORITHM_YOLOV3,
+    Yolov4 => AILIA_DETECTOR_ALGORITHM_YOLOV4,
+    Yolox => AILIA_DETECTOR_ALGORITHM_YOLOX,
+    Ssd => AILIA_DETECTOR_ALGORITHM_SSD,
+});
 
-pub use ailia_sys::AILIA_DETECTOR_OBJECT_VERSION;
-
-pub use ailia_sys::AILIA_DETECTOR_ALGORITHM_SSD;
-pub use ailia_sys::AILIA_DETECTOR_ALGORITHM_YOLOV1;
-pub use ailia_sys::AILIA_DETECTOR_ALGORITHM_YOLOV2;
-pub use ailia_sys::AILIA_DETECTOR_ALGORITHM_YOLOV3;
-pub use ailia_sys::AILIA_DETECTOR_ALGORITHM_YOLOV4;
-pub use ailia_sys::AILIA_DETECTOR_ALGORITHM_YOLOX;
+/// 追加オプションフラグ (AILIA_DETECTOR_FLAG_*)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DetectorFlags {
+    #[default]
+    Normal,
+}
 
-pub use ailia_sys::AILIA_DETECTOR_FLAG_NORMAL;
+crate::impl_ailia_enum!(DetectorFlags {
+    Normal => AILIA_DETECTOR_FLAG_NORMAL,
+});
 
 // TODO:Option以外は設定されていない場合buildを呼べないようにする(型によって制限をかける)
 #[derive(Clone, Copy, Debug, Default)]
@@ -45,29 +55,29 @@ pub struct DetectorBuilder<P>
 where
     P: AsRef<Path> + Default + Debug,
 {
-    prototxt: P,
+    prototxt: Option<P>,
     onnx: P,
     env_id: Option<i32>,
     num_threads: Option<i32>,
-    format: Option<u32>,
-    channel: Option<u32>,
-    range: Option<u32>,
-    algorithm: u32,
+    format: Option<NetworkImageFormat>,
+    channel: Option<ChannelOrder>,
+    range: Option<ImageRange>,
+    algorithm: DetectorAlgorithm,
     category_count: u32,
-    flags: Option<u32>,
+    flags: Option<DetectorFlags>,
 }
 
 impl<P: AsRef<Path> + Default + Debug> DetectorBuilder<P> {
-    crate::impl_non_option!(prototxt, P);
+    crate::impl_option!(prototxt, P);
     crate::impl_non_option!(onnx, P);
     crate::impl_option!(env_id, i32);
     crate::impl_option!(num_threads, i32);
-    crate::impl_option!(format, u32);
-    crate::impl_option!(channel, u32);
-    crate::impl_option!(range, u32);
-    crate::impl_non_option!(algorithm, u32);
+    crate::impl_option!(format, NetworkImageFormat);
+    crate::impl_option!(channel, ChannelOrder);
+    crate::impl_option!(range, ImageRange);
+    crate::impl_non_option!(algorithm, DetectorAlgorithm);
     crate::impl_non_option!(category_count, u32);
-    crate::impl_option!(flags, u32);
+    crate::impl_option!(flags, DetectorFlags);
 
     pub fn build(self) -> Result<Detector, AiliaError> {
         let net = Network::ailia_create(
@@ -75,17 +85,15 @@ impl<P: AsRef<Path> + Default + Debug> DetectorBuilder<P> {
             self.num_threads
                 .unwrap_or_else(|| AILIA_MULTITHREAD_AUTO.try_into().unwrap()),
         )?;
-        net.open_stream_file_a(self.prototxt)?;
-        net.open_weight_file_a(self.onnx)?;
+        net.open_model_files(self.prototxt, self.onnx)?;
         Detector::new(
             net,
-            self.format.unwrap_or(AILIA_NETWORK_IMAGE_FORMAT_RGB),
-            self.channel.unwrap_or(AILIA_NETWORK_IMAGE_CHANNEL_FIRST),
-            self.range
-                .unwrap_or(AILIA_NETWORK_IMAGE_RANGE_UNSIGNED_INT8),
+            self.format.unwrap_or_default(),
+            self.channel.unwrap_or_default(),
+            self.range.unwrap_or_default(),
             self.algorithm,
             self.category_count,
-            self.flags.unwrap_or(AILIA_DETECTOR_FLAG_NORMAL),
+            self.flags.unwrap_or_default(),
         )
     }
 }
@@ -99,24 +107,24 @@ pub struct Detector {
 impl Detector {
     pub fn new(
         net: Network,
-        format: u32,
-        channel: u32,
-        range: u32,
-        algorithm: u32,
+        format: NetworkImageFormat,
+        channel: ChannelOrder,
+        range: ImageRange,
+        algorithm: DetectorAlgorithm,
         category_count: u32,
-        flags: u32,
+        flags: DetectorFlags,
     ) -> Result<Self, AiliaError> {
         let mut ptr: *mut AILIADetector = std::ptr::null::<AILIADetector>() as *mut _;
         unsafe {
             match ailiaCreateDetector(
                 (&mut ptr) as *mut *mut _,
                 net.as_ptr() as *mut _,
-                format,
-                channel,
-                range,
-                algorithm,
+                format.into(),
+                channel.into(),
+                range.into(),
+                algorithm.into(),
                 category_count,
-                flags,
+                flags.into(),
             ) {
                 0 => Ok(Self {
                     inner: NonNull::new_unchecked(ptr),
@@ -137,7 +145,7 @@ impl Detector {
         stride: u32,
         width: u32,
         height: u32,
-        format: u32,
+        format: ImageFormat,
         threshold: f32,
         iou: f32,
     ) -> Result<Vec<Object>, AiliaError> {
@@ -162,7 +170,7 @@ impl Detector {
             image.width() * 4,
             image.width(),
             image.height(),
-            AILIA_IMAGE_FORMAT_RGBA,
+            ImageFormat::Rgba,
             threshold,
             iou,
         )
@@ -187,7 +195,7 @@ impl Detector {
             size.height
                 .try_into()
                 .expect("can't convert image.height to usize"),
-            AILIA_IMAGE_FORMAT_RGBA,
+            ImageFormat::Rgba,
             threshold,
             iou,
         )
@@ -200,7 +208,7 @@ impl Detector {
         stride: u32,
         width: u32,
         height: u32,
-        format: u32,
+        format: ImageFormat,
         threshold: f32,
         iou: f32,
     ) -> Result<(), AiliaError> {
@@ -211,7 +219,7 @@ impl Detector {
             stride,
             width,
             height,
-            format,
+            u32::from(format),
             threshold,
             iou
         );
@@ -230,7 +238,7 @@ impl Detector {
             image.width() * 4,
             image.width(),
             image.height(),
-            AILIA_IMAGE_FORMAT_RGBA,
+            ImageFormat::Rgba,
             threshold,
             iou,
         )
@@ -249,7 +257,7 @@ impl Detector {
             (size.width * 4).try_into().unwrap(),
             size.width.try_into().unwrap(),
             size.height.try_into().unwrap(),
-            AILIA_IMAGE_FORMAT_RGBA,
+            ImageFormat::Rgba,
             threshold,
             iou,
         )
@@ -288,6 +296,7 @@ impl Deref for Detector {
 }
 
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Object {
     pub category: u32,
     pub prob: f32,
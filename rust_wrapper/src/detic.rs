@@ -0,0 +1,471 @@
+//! Detic(オープン語彙の物体検出とインスタンスセグメンテーション)
+//!
+//! ailia-modelsのdeticに対応する。入力は長辺800のBGR(0~255)と(高さ, 幅)のint64で、
+//! 出力のboxes, scores, classes, masksをInstanceにする。
+
+use std::fmt::Debug;
+use std::fs::read_to_string;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+
+use image::imageops::FilterType;
+use image::RgbImage;
+
+use thiserror::Error;
+
+use crate::detector::Object;
+use crate::instance::{BitMask, Instance};
+use crate::network::{DataType, Network};
+use crate::preprocess::{ColorOrder, ImageTensor, Preprocess, PreprocessError, Transform};
+use crate::video::ImageView;
+use crate::AiliaError;
+
+use ailia_sys::*;
+
+#[derive(Debug, Error)]
+pub enum DeticError {
+    #[error("出力の形状が不正です: {0:?}")]
+    InvalidShape(Vec<u32>),
+    #[error("出力の数が不正です: {0}")]
+    OutputCount(usize),
+    #[error("クラスの出力のデータ型が不正です: {0:?}")]
+    UnsupportedDataType(DataType),
+    #[error("語彙ファイルを読み込めません: {0}")]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Preprocess(#[from] PreprocessError),
+    #[error(transparent)]
+    Ailia(#[from] AiliaError),
+}
+
+/// モデルが学習時に使ったクラスの語彙
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DeticVocabulary {
+    /// LVISの1203クラス
+    #[default]
+    Lvis,
+    /// ImageNet-21Kのクラス
+    In21k,
+}
+
+impl DeticVocabulary {
+    /// ailia-modelsで公開されているモデルのファイル名
+    pub fn onnx(&self) -> &'static str {
+        match self {
+            DeticVocabulary::Lvis => "Detic_C2_SwinB_896_4x_IN-21K+COCO_lvis.onnx",
+            DeticVocabulary::In21k => "Detic_C2_SwinB_896_4x_IN-21K+COCO_in21k.onnx",
+        }
+    }
+
+    pub fn prototxt(&self) -> &'static str {
+        match self {
+            DeticVocabulary::Lvis => "Detic_C2_SwinB_896_4x_IN-21K+COCO_lvis.onnx.prototxt",
+            DeticVocabulary::In21k => "Detic_C2_SwinB_896_4x_IN-21K+COCO_in21k.onnx.prototxt",
+        }
+    }
+
+    /// 同梱しているクラス名、IN-21Kは数が多いため同梱せずNone
+    pub fn class_names(&self) -> Option<ClassNames> {
+        match self {
+            DeticVocabulary::Lvis => Some(ClassNames::from_names(LVIS_NAMES.lines())),
+            DeticVocabulary::In21k => None,
+        }
+    }
+}
+
+/// LVIS v1のカテゴリ名(category_idの順)
+const LVIS_NAMES: &str = include_str!("lvis_names.txt");
+
+/// クラス番号とクラス名の対応
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClassNames {
+    names: Vec<String>,
+}
+
+impl ClassNames {
+    /// 1行1クラスのファイルを読み込む
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, DeticError> {
+        Ok(Self::from_names(
+            read_to_string(path)?
+                .lines()
+                .map(|line| line.trim_end_matches('\r')),
+        ))
+    }
+
+    pub fn from_names<I, S>(names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        ClassNames {
+            names: names.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn name(&self, category: u32) -> Option<&str> {
+        self.names.get(category as usize).map(String::as_str)
+    }
+
+    /// render::draw_objectsなどのlabelsに渡す
+    pub fn as_labels(&self) -> Vec<&str> {
+        self.names.iter().map(String::as_str).collect()
+    }
+}
+
+/// 出力をInstanceにする
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DeticDecoder {
+    pub threshold: f32,
+    pub mask_threshold: f32,
+}
+
+impl Default for DeticDecoder {
+    fn default() -> Self {
+        DeticDecoder {
+            threshold: 0.5,
+            mask_threshold: 0.5,
+        }
+    }
+}
+
+impl DeticDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn mask_threshold(mut self, mask_threshold: f32) -> Self {
+        self.mask_threshold = mask_threshold;
+        self
+    }
+
+    /// boxesは入力画像の画素座標の(x1, y1, x2, y2)、masksは(N, 1, mask_height, mask_width)
+    ///
+    /// マスクが入力画像と同じ大きさなら画像全体のマスク、それ以外は矩形内のマスク(28x28など)として扱う。
+    pub fn decode(
+        &self,
+        boxes: &[f32],
+        scores: &[f32],
+        classes: &[i64],
+        masks: &[f32],
+        mask_size: (u32, u32),
+        transform: &Transform,
+    ) -> Vec<Instance> {
+        let (width, height) = transform.source_size;
+        let mask_len = (mask_size.0 * mask_size.1) as usize;
+        let full_image = mask_size == transform.size;
+        boxes
+            .chunks_exact(4)
+            .zip(scores)
+            .zip(classes)
+            .enumerate()
+            .filter(|(_, ((_, &score), &class))| score >= self.threshold && class >= 0)
+            .filter_map(|(idx, ((bbox, &score), &class))| {
+                let bbox = [bbox[0], bbox[1], bbox[2], bbox[3]];
+                let [x1, y1, x2, y2] = transform.to_source_box(bbox);
+                let (x1, x2) = (x1.clamp(0., width as f32), x2.clamp(0., width as f32));
+                let (y1, y2) = (y1.clamp(0., height as f32), y2.clamp(0., height as f32));
+                if x2 <= x1 || y2 <= y1 {
+                    return None;
+                }
+                let probs = masks.get(idx * mask_len..(idx + 1) * mask_len)?;
+                let region = if full_image {
+                    [0., 0., transform.size.0 as f32, transform.size.1 as f32]
+                } else {
+                    bbox
+                };
+                let mask = paste_mask(
+                    probs,
+                    mask_size,
+                    region,
+                    [x1, y1, x2, y2],
+                    transform,
+                    self.mask_threshold,
+                );
+                Some(Instance {
+                    object: Object {
+                        category: class as u32,
+                        prob: score,
+                        x: x1 / width as f32,
+                        y: y1 / height as f32,
+                        w: (x2 - x1) / width as f32,
+                        h: (y2 - y1) / height as f32,
+                    },
+                    mask,
+                })
+            })
+            .collect()
+    }
+}
+
+/// region(入力画像の画素座標)に引き伸ばしたマスクを、元画像のclip(元画像の画素座標)の内側だけ2値化する
+fn paste_mask(
+    probs: &[f32],
+    (mask_width, mask_height): (u32, u32),
+    region: [f32; 4],
+    clip: [f32; 4],
+    transform: &Transform,
+    threshold: f32,
+) -> BitMask {
+    let (width, height) = transform.source_size;
+    let mut mask = BitMask::new(width, height);
+    if mask_width == 0 || mask_height == 0 {
+        return mask;
+    }
+    let sample = |x: f32, y: f32| {
+        let x = x.clamp(0., (mask_width - 1) as f32);
+        let y = y.clamp(0., (mask_height - 1) as f32);
+        let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(mask_width - 1), (y0 + 1).min(mask_height - 1));
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+        let at = |x: u32, y: u32| probs[(y * mask_width + x) as usize];
+        (at(x0, y0) * (1. - fx) + at(x1, y0) * fx) * (1. - fy)
+            + (at(x0, y1) * (1. - fx) + at(x1, y1) * fx) * fy
+    };
+    let [rx1, ry1, rx2, ry2] = region;
+    let scale_x = mask_width as f32 / (rx2 - rx1).max(f32::EPSILON);
+    let scale_y = mask_height as f32 / (ry2 - ry1).max(f32::EPSILON);
+    let pixels = |start: f32, end: f32, size: u32| {
+        let start = (start - 0.5).ceil().max(0.) as u32;
+        let end = ((end - 0.5).ceil().max(0.) as u32).min(size);
+        start..end
+    };
+    let [x1, y1, x2, y2] = clip;
+    for y in pixels(y1, y2, height) {
+        let dst_y = (y as f32 + 0.5) * transform.scale.1 + transform.offset.1;
+        for x in pixels(x1, x2, width) {
+            let dst_x = (x as f32 + 0.5) * transform.scale.0 + transform.offset.0;
+            let value = sample((dst_x - rx1) * scale_x - 0.5, (dst_y - ry1) * scale_y - 0.5);
+            if value >= threshold {
+                mask.set(x, y, true);
+            }
+        }
+    }
+    mask
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct OpenVocabDetectorBuilder<P>
+where
+    P: AsRef<Path> + Default + Debug,
+{
+    prototxt: Option<P>,
+    onnx: P,
+    env_id: Option<i32>,
+    num_threads: Option<i32>,
+    names: Option<P>,
+    vocabulary: Option<DeticVocabulary>,
+    max_size: Option<u32>,
+    decoder: Option<DeticDecoder>,
+}
+
+impl<P: AsRef<Path> + Default + Debug> OpenVocabDetectorBuilder<P> {
+    crate::impl_option!(prototxt, P);
+    crate::impl_non_option!(onnx, P);
+    crate::impl_option!(env_id, i32);
+    crate::impl_option!(num_threads, i32);
+    // 1行1クラスのクラス名のファイル、モデルの語彙(LVIS, IN-21K)に合わせる
+    crate::impl_option!(names, P);
+    // namesがない場合に同梱のクラス名を使う語彙、既定値はLVIS
+    crate::impl_option!(vocabulary, DeticVocabulary);
+    // 入力画像の長辺、既定値は800
+    crate::impl_option!(max_size, u32);
+    crate::impl_option!(decoder, DeticDecoder);
+
+    pub fn build(self) -> Result<OpenVocabDetector, DeticError> {
+        let net = Network::ailia_create(
+            self.env_id.unwrap_or(AILIA_ENVIRONMENT_ID_AUTO),
+            self.num_threads
+                .unwrap_or_else(|| AILIA_MULTITHREAD_AUTO.try_into().unwrap()),
+        )?;
+        net.open_model_files(self.prototxt, self.onnx)?;
+        let names = match self.names {
+            Some(path) => ClassNames::load(path)?,
+            None => self
+                .vocabulary
+                .unwrap_or_default()
+                .class_names()
+                .unwrap_or_default(),
+        };
+        Ok(OpenVocabDetector {
+            net,
+            preprocess: Preprocess::new()
+                .resize_longer(self.max_size.unwrap_or(800), FilterType::Triangle)
+                .color_order(ColorOrder::Bgr),
+            names,
+            decoder: self.decoder.unwrap_or_default(),
+        })
+    }
+}
+
+/// Deticで物体の矩形、クラス、マスクを求める
+pub struct OpenVocabDetector {
+    net: Network,
+    preprocess: Preprocess,
+    names: ClassNames,
+    decoder: DeticDecoder,
+}
+
+impl OpenVocabDetector {
+    pub fn names(&self) -> &ClassNames {
+        &self.names
+    }
+
+    /// Instanceの座標は元画像に対する相対座標、マスクは元画像の解像度
+    pub fn detect(&self, image: &ImageView) -> Result<Vec<Instance>, DeticError> {
+        let (tensor, transform) = self.preprocess.run(image)?;
+        self.infer(tensor, transform)
+    }
+
+    pub fn detect_rgb(&self, image: &RgbImage) -> Result<Vec<Instance>, DeticError> {
+        let (tensor, transform) = self.preprocess.run_rgb(image)?;
+        self.infer(tensor, transform)
+    }
+
+    fn infer(
+        &self,
+        tensor: ImageTensor,
+        transform: Transform,
+    ) -> Result<Vec<Instance>, DeticError> {
+        let image_idx = self.net.get_input_blob_index_by_index(0)?;
+        tensor.set_input(&self.net, image_idx)?;
+        let hw_idx = self.net.get_input_blob_index_by_index(1)?;
+        let im_hw = [transform.size.1 as i64, transform.size.0 as i64];
+        self.net.set_input_tensor(&im_hw, &[2], hw_idx)?;
+        self.net.update()?;
+
+        let outputs = self.net.get_output_indexs()?;
+        let [boxes_idx, scores_idx, classes_idx, masks_idx] = outputs[..] else {
+            return Err(DeticError::OutputCount(outputs.len()));
+        };
+        let boxes_shape = self.net.get_blob_shape_nd(boxes_idx)?;
+        let count = match boxes_shape[..] {
+            [n, 4] => n as usize,
+            _ => return Err(DeticError::InvalidShape(boxes_shape)),
+        };
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        let masks_shape = self.net.get_blob_shape_nd(masks_idx)?;
+        let mask_size = match masks_shape[..] {
+            [n, 1, h, w] | [n, h, w] if n as usize == count => (w, h),
+            _ => return Err(DeticError::InvalidShape(masks_shape)),
+        };
+        let boxes = self.net.get_output_blob_by_index::<f32>(boxes_idx)?;
+        let scores = self.net.get_output_blob_by_index::<f32>(scores_idx)?;
+        let classes = match self.net.get_blob_data_type(classes_idx)? {
+            DataType::Int64 => self.net.get_output_blob_by_index::<i64>(classes_idx)?,
+            DataType::Int32 => self
+                .net
+                .get_output_blob_by_index::<i32>(classes_idx)?
+                .into_iter()
+                .map(i64::from)
+                .collect(),
+            DataType::Float => self
+                .net
+                .get_output_blob_by_index::<f32>(classes_idx)?
+                .into_iter()
+                .map(|class| class as i64)
+                .collect(),
+            data_type => return Err(DeticError::UnsupportedDataType(data_type)),
+        };
+        let masks = self.net.get_output_blob_by_index::<f32>(masks_idx)?;
+        if scores.len() != count || classes.len() != count {
+            return Err(DeticError::InvalidShape(
+                self.net.get_blob_shape_nd(scores_idx)?,
+            ));
+        }
+        Ok(self
+            .decoder
+            .decode(&boxes, &scores, &classes, &masks, mask_size, &transform))
+    }
+}
+
+impl Deref for OpenVocabDetector {
+    type Target = Network;
+    fn deref(&self) -> &Self::Target {
+        &self.net
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// 200x100の元画像を長辺800にリサイズした入力
+    fn transform() -> Transform {
+        Transform {
+            scale: (4., 4.),
+            offset: (0., 0.),
+            source_size: (200, 100),
+            size: (800, 400),
+        }
+    }
+
+    #[test]
+    fn decode_roi_masks() {
+        // 2x2のマスクの左半分だけが前景
+        let boxes = [40., 40., 200., 120., 0., 0., 8., 8., 400., 0., 400., 100.];
+        let scores = [0.9, 0.3, 0.8];
+        let classes = [5, 1, 2];
+        let masks = [1., 0., 1., 0.].repeat(3);
+        let instances =
+            DeticDecoder::new().decode(&boxes, &scores, &classes, &masks, (2, 2), &transform());
+        // 2番目はスコア、3番目は幅0で除かれる
+        assert_eq!(instances.len(), 1);
+        let instance = &instances[0];
+        assert_eq!(instance.object.category, 5);
+        assert_eq!(
+            [
+                instance.object.x,
+                instance.object.y,
+                instance.object.w,
+                instance.object.h
+            ],
+            [0.05, 0.1, 0.2, 0.2]
+        );
+        // 元画像で(10, 10)-(50, 30)の矩形の左半分
+        assert_eq!((instance.mask.width, instance.mask.height), (200, 100));
+        assert_eq!(instance.mask.bbox(), Some([10, 10, 30, 30]));
+        assert_eq!(instance.mask.area(), 20 * 20);
+    }
+
+    #[test]
+    fn decode_full_masks() {
+        let boxes = [0., 0., 800., 400.];
+        let masks = (0..800 * 400)
+            .map(|idx| if idx % 800 < 400 { 1. } else { 0. })
+            .collect::<Vec<f32>>();
+        let instances =
+            DeticDecoder::new().decode(&boxes, &[0.6], &[3], &masks, (800, 400), &transform());
+        assert_eq!(instances[0].mask.area(), 100 * 100);
+        assert_eq!(instances[0].mask.bbox(), Some([0, 0, 100, 100]));
+    }
+
+    #[test]
+    fn class_names() {
+        let names = ClassNames::from_names(["aerosol_can", "air_conditioner"]);
+        assert_eq!(names.name(1), Some("air_conditioner"));
+        assert_eq!(names.name(2), None);
+        assert_eq!(names.as_labels(), ["aerosol_can", "air_conditioner"]);
+
+        let lvis = DeticVocabulary::Lvis.class_names().unwrap();
+        assert_eq!(lvis.len(), 1203);
+        assert_eq!(lvis.name(0), Some("aerosol_can"));
+        assert_eq!(lvis.name(1202), Some("zucchini"));
+        assert!(DeticVocabulary::In21k.class_names().is_none());
+    }
+}
@@ -0,0 +1,882 @@
+//! 画像の特徴量の抽出と近傍探索
+//!
+//! Embedderは出力Blob(または名前で指定した中間Blob)を特徴量として取り出す。
+//! VectorIndexはL2正規化した特徴量をコサイン類似度で検索する。
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::Deref;
+use std::path::Path;
+
+use image::imageops::FilterType;
+use image::RgbImage;
+
+use thiserror::Error;
+
+use crate::binary::{read_f32s, read_string, read_u32, write_f32s, write_str, write_u32};
+use crate::network::Network;
+use crate::preprocess::{ImageTensor, Normalize, Preprocess, PreprocessError};
+use crate::video::ImageView;
+use crate::AiliaError;
+
+use ailia_sys::*;
+
+const INDEX_MAGIC: &[u8; 4] = b"AVIX";
+const INDEX_VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum EmbeddingError {
+    #[error("出力の形状が不正です: {0:?}")]
+    InvalidShape(Vec<u32>),
+    #[error("特徴量の次元が一致しません: {0}, {1}")]
+    DimensionMismatch(usize, usize),
+    #[error("インデックスのファイルが不正です: {0}")]
+    InvalidIndex(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Preprocess(#[from] PreprocessError),
+    #[error(transparent)]
+    Ailia(#[from] AiliaError),
+}
+
+/// L2ノルムを1にする、ゼロベクトルはそのまま
+pub fn l2_normalize(embedding: &mut [f32]) {
+    let norm = embedding.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm > 0. {
+        embedding.iter_mut().for_each(|v| *v /= norm);
+    }
+}
+
+/// 正規化済みの特徴量どうしのコサイン類似度
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+/// Blobを特徴量のベクトルにする方法
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Pooling {
+    /// そのまま並べる
+    #[default]
+    Flatten,
+    /// (1, C, H, W, ...)の空間方向の平均
+    Average,
+    /// (1, C, H, W, ...)の空間方向の最大値
+    Max,
+}
+
+impl Pooling {
+    pub fn apply(&self, data: &[f32], shape: &[u32]) -> Result<Vec<f32>, EmbeddingError> {
+        if data.len() != shape.iter().product::<u32>() as usize {
+            return Err(EmbeddingError::InvalidShape(shape.to_vec()));
+        }
+        let channels = match (self, shape) {
+            (Pooling::Flatten, _) => return Ok(data.to_vec()),
+            (_, [1, channels, _, ..]) => *channels as usize,
+            _ => return Err(EmbeddingError::InvalidShape(shape.to_vec())),
+        };
+        let spatial = data.len() / channels.max(1);
+        Ok(data
+            .chunks_exact(spatial.max(1))
+            .map(|values| match self {
+                Pooling::Max => values.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+                _ => values.iter().sum::<f32>() / values.len() as f32,
+            })
+            .collect())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct EmbedderBuilder<P>
+where
+    P: AsRef<Path> + Default + Debug,
+{
+    prototxt: Option<P>,
+    onnx: P,
+    env_id: Option<i32>,
+    num_threads: Option<i32>,
+    input_width: u32,
+    input_height: u32,
+    normalize: Option<Normalize>,
+    preprocess: Option<Preprocess>,
+    blob: Option<String>,
+    pooling: Option<Pooling>,
+    normalize_l2: Option<bool>,
+}
+
+impl<P: AsRef<Path> + Default + Debug> EmbedderBuilder<P> {
+    crate::impl_option!(prototxt, P);
+    crate::impl_non_option!(onnx, P);
+    crate::impl_option!(env_id, i32);
+    crate::impl_option!(num_threads, i32);
+    crate::impl_non_option!(input_width, u32);
+    crate::impl_non_option!(input_height, u32);
+    // 既定の前処理(input_width x input_heightへのリサイズ)の正規化、既定値はImageNet
+    crate::impl_option!(normalize, Normalize);
+    // 既定の前処理の代わりに使うパイプライン
+    crate::impl_option!(preprocess, Preprocess);
+    // 特徴量として読むBlobの名前、指定しない場合は1番目の出力
+    crate::impl_option!(blob, String);
+    crate::impl_option!(pooling, Pooling);
+    // 特徴量をL2正規化する、既定値はtrue
+    crate::impl_option!(normalize_l2, bool);
+
+    pub fn build(self) -> Result<Embedder, AiliaError> {
+        let net = Network::ailia_create(
+            self.env_id.unwrap_or(AILIA_ENVIRONMENT_ID_AUTO),
+            self.num_threads
+                .unwrap_or_else(|| AILIA_MULTITHREAD_AUTO.try_into().unwrap()),
+        )?;
+        if self.blob.is_some() {
+            // 中間のBlobは既定のメモリモードでは解放される
+            net.set_memory_mode(AILIA_MEMORY_NO_OPTIMIZATION)?;
+        }
+        net.open_model_files(self.prototxt, self.onnx)?;
+        let blob_idx = match &self.blob {
+            Some(name) => Some(net.find_blob_idx_by_name(name)?),
+            None => None,
+        };
+        let preprocess = self.preprocess.unwrap_or_else(|| {
+            Preprocess::new()
+                .resize(self.input_width, self.input_height, FilterType::Triangle)
+                .normalize(self.normalize.unwrap_or(Normalize::ImageNet))
+        });
+        Ok(Embedder {
+            net,
+            preprocess,
+            blob_idx,
+            pooling: self.pooling.unwrap_or_default(),
+            normalize_l2: self.normalize_l2.unwrap_or(true),
+        })
+    }
+}
+
+/// 画像から特徴量を計算する
+pub struct Embedder {
+    net: Network,
+    preprocess: Preprocess,
+    blob_idx: Option<u32>,
+    pooling: Pooling,
+    normalize_l2: bool,
+}
+
+impl Embedder {
+    pub fn embed(&self, image: &ImageView) -> Result<Vec<f32>, EmbeddingError> {
+        let (tensor, _) = self.preprocess.run(image)?;
+        self.infer(tensor)
+    }
+
+    pub fn embed_rgb(&self, image: &RgbImage) -> Result<Vec<f32>, EmbeddingError> {
+        let (tensor, _) = self.preprocess.run_rgb(image)?;
+        self.infer(tensor)
+    }
+
+    fn infer(&self, tensor: ImageTensor) -> Result<Vec<f32>, EmbeddingError> {
+        let input_idx = self.net.get_input_blob_index_by_index(0)?;
+        tensor.set_input(&self.net, input_idx)?;
+        self.net.update()?;
+        let blob_idx = match self.blob_idx {
+            Some(idx) => idx,
+            None => self.net.get_output_blob_index_by_index(0)?,
+        };
+        let shape = self.net.get_blob_shape_nd(blob_idx)?;
+        let data = self.net.get_output_blob_by_index::<f32>(blob_idx)?;
+        let mut embedding = self.pooling.apply(&data, &shape)?;
+        if self.normalize_l2 {
+            l2_normalize(&mut embedding);
+        }
+        Ok(embedding)
+    }
+}
+
+impl Deref for Embedder {
+    type Target = Network;
+    fn deref(&self) -> &Self::Target {
+        &self.net
+    }
+}
+
+/// 検索結果、indexは追加した順番
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Neighbor {
+    pub index: usize,
+    pub id: String,
+    pub similarity: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HnswParams {
+    /// 1つの層で張るリンクの数、0層目はこの2倍まで
+    pub m: usize,
+    pub ef_construction: usize,
+    pub ef_search: usize,
+}
+
+impl Default for HnswParams {
+    fn default() -> Self {
+        HnswParams {
+            m: 16,
+            ef_construction: 200,
+            ef_search: 64,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IndexKind {
+    /// 全件との総当たり
+    #[default]
+    Flat,
+    /// Hierarchical Navigable Small Worldグラフによる近似探索
+    Hnsw(HnswParams),
+}
+
+/// 類似度と要素の番号、類似度の順に並ぶ
+#[derive(Clone, Copy, Debug)]
+struct Scored(f32, u32);
+
+impl PartialEq for Scored {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .total_cmp(&other.0)
+            .then_with(|| other.1.cmp(&self.1))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Hnsw {
+    params: HnswParams,
+    // 要素ごと、層ごとのリンク先
+    links: Vec<Vec<Vec<u32>>>,
+    entry: Option<u32>,
+    max_level: usize,
+    rng: u64,
+}
+
+impl Hnsw {
+    fn new(params: HnswParams) -> Self {
+        Hnsw {
+            params: HnswParams {
+                m: params.m.max(2),
+                ..params
+            },
+            links: Vec::new(),
+            entry: None,
+            max_level: 0,
+            rng: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+
+    fn random_level(&mut self) -> usize {
+        // xorshift64
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        let uniform = ((self.rng >> 11) as f64 + 1.) / (1u64 << 53) as f64;
+        (-uniform.ln() / (self.params.m as f64).ln()).floor() as usize
+    }
+
+    fn search_layer<F: Fn(u32) -> f32>(
+        &self,
+        similarity: &F,
+        entries: &[u32],
+        ef: usize,
+        level: usize,
+    ) -> Vec<Scored> {
+        let mut visited = entries.iter().copied().collect::<HashSet<_>>();
+        let mut candidates = BinaryHeap::new();
+        let mut results = BinaryHeap::new();
+        for &entry in entries {
+            let scored = Scored(similarity(entry), entry);
+            candidates.push(scored);
+            results.push(Reverse(scored));
+        }
+        while let Some(candidate) = candidates.pop() {
+            let worst = results
+                .peek()
+                .map_or(f32::NEG_INFINITY, |r: &Reverse<Scored>| r.0 .0);
+            if results.len() >= ef && candidate.0 < worst {
+                break;
+            }
+            for &neighbor in &self.links[candidate.1 as usize][level] {
+                if !visited.insert(neighbor) {
+                    continue;
+                }
+                let scored = Scored(similarity(neighbor), neighbor);
+                let worst = results.peek().map_or(f32::NEG_INFINITY, |r| r.0 .0);
+                if results.len() < ef || scored.0 > worst {
+                    candidates.push(scored);
+                    results.push(Reverse(scored));
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+        let mut results = results.into_iter().map(|r| r.0).collect::<Vec<_>>();
+        results.sort_by(|a, b| b.cmp(a));
+        results
+    }
+
+    /// 最上位の層から貪欲に降りて、levelの層の入口を求める
+    fn descend<F: Fn(u32) -> f32>(&self, similarity: &F, level: usize) -> Option<Vec<u32>> {
+        let mut entries = vec![self.entry?];
+        for l in (level + 1..=self.max_level).rev() {
+            entries = vec![self.search_layer(similarity, &entries, 1, l)[0].1];
+        }
+        Some(entries)
+    }
+
+    fn insert(&mut self, idx: u32, vectors: &[f32], dim: usize) {
+        let vector = |i: u32| &vectors[i as usize * dim..(i as usize + 1) * dim];
+        let query = vector(idx);
+        let similarity = |i: u32| cosine_similarity(query, vector(i));
+        let level = self.random_level();
+        self.links.push(vec![Vec::new(); level + 1]);
+        let Some(mut entries) = self.descend(&similarity, level) else {
+            self.entry = Some(idx);
+            self.max_level = level;
+            return;
+        };
+        for l in (0..=level.min(self.max_level)).rev() {
+            let found = self.search_layer(&similarity, &entries, self.params.ef_construction, l);
+            let max_links = if l == 0 {
+                self.params.m * 2
+            } else {
+                self.params.m
+            };
+            let neighbors = found
+                .iter()
+                .take(self.params.m)
+                .map(|s| s.1)
+                .collect::<Vec<_>>();
+            for &neighbor in &neighbors {
+                let links = &mut self.links[neighbor as usize][l];
+                links.push(idx);
+                if links.len() > max_links {
+                    // リンクが多すぎる場合は近いものだけ残す
+                    let base = vector(neighbor);
+                    let mut scored = links
+                        .iter()
+                        .map(|&i| Scored(cosine_similarity(base, vector(i)), i))
+                        .collect::<Vec<_>>();
+                    scored.sort_by(|a, b| b.cmp(a));
+                    *links = scored.into_iter().take(max_links).map(|s| s.1).collect();
+                }
+            }
+            self.links[idx as usize][l] = neighbors;
+            entries = found.into_iter().map(|s| s.1).collect();
+        }
+        if level > self.max_level {
+            self.max_level = level;
+            self.entry = Some(idx);
+        }
+    }
+
+    fn search<F: Fn(u32) -> f32>(&self, similarity: &F, k: usize) -> Vec<Scored> {
+        let Some(entries) = self.descend(similarity, 0) else {
+            return Vec::new();
+        };
+        let mut found = self.search_layer(similarity, &entries, self.params.ef_search.max(k), 0);
+        found.truncate(k);
+        found
+    }
+}
+
+/// 特徴量を保存して近傍を探す、特徴量は追加時にL2正規化する
+#[derive(Clone, Debug, PartialEq)]
+pub struct VectorIndex {
+    dim: usize,
+    ids: Vec<String>,
+    vectors: Vec<f32>,
+    hnsw: Option<Hnsw>,
+}
+
+impl VectorIndex {
+    pub fn new(dim: usize, kind: IndexKind) -> Self {
+        VectorIndex {
+            dim,
+            ids: Vec::new(),
+            vectors: Vec::new(),
+            hnsw: match kind {
+                IndexKind::Flat => None,
+                IndexKind::Hnsw(params) => Some(Hnsw::new(params)),
+            },
+        }
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn kind(&self) -> IndexKind {
+        match &self.hnsw {
+            Some(hnsw) => IndexKind::Hnsw(hnsw.params),
+            None => IndexKind::Flat,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn id(&self, index: usize) -> Option<&str> {
+        self.ids.get(index).map(String::as_str)
+    }
+
+    /// 正規化済みの特徴量
+    pub fn vector(&self, index: usize) -> Option<&[f32]> {
+        self.vectors.get(index * self.dim..(index + 1) * self.dim)
+    }
+
+    /// 特徴量を追加し、その番号を返す
+    pub fn add(&mut self, id: &str, vector: &[f32]) -> Result<usize, EmbeddingError> {
+        let mut vector = self.check(vector)?;
+        l2_normalize(&mut vector);
+        Ok(self.push(id, &vector))
+    }
+
+    /// fがtrueを返したものだけを残す、番号は詰め直しHNSWのグラフも作り直す
+    pub fn retain<F: FnMut(&str) -> bool>(&mut self, mut f: F) {
+        let mut index = VectorIndex::new(self.dim, self.kind());
+        for (i, id) in self.ids.iter().enumerate() {
+            if f(id) {
+                index.push(id, &self.vectors[i * self.dim..(i + 1) * self.dim]);
+            }
+        }
+        *self = index;
+    }
+
+    /// 正規化済みの特徴量を追加する
+    fn push(&mut self, id: &str, vector: &[f32]) -> usize {
+        let index = self.ids.len();
+        self.ids.push(id.to_string());
+        self.vectors.extend_from_slice(vector);
+        if let Some(hnsw) = &mut self.hnsw {
+            hnsw.insert(index as u32, &self.vectors, self.dim);
+        }
+        index
+    }
+
+    /// threshold以上の類似度のものがなければ追加する、あればそれを返す
+    pub fn add_unique(
+        &mut self,
+        id: &str,
+        vector: &[f32],
+        threshold: f32,
+    ) -> Result<Result<usize, Neighbor>, EmbeddingError> {
+        match self.find_duplicate(vector, threshold)? {
+            Some(found) => Ok(Err(found)),
+            None => Ok(Ok(self.add(id, vector)?)),
+        }
+    }
+
+    /// 類似度の高い順にk件返す
+    pub fn search(&self, query: &[f32], k: usize) -> Result<Vec<Neighbor>, EmbeddingError> {
+        let mut query = self.check(query)?;
+        l2_normalize(&mut query);
+        Ok(self
+            .nearest(&query, k)
+            .into_iter()
+            .map(|Scored(similarity, index)| Neighbor {
+                index: index as usize,
+                id: self.ids[index as usize].clone(),
+                similarity,
+            })
+            .collect())
+    }
+
+    /// 類似度がthreshold以上で最も近いもの
+    pub fn find_duplicate(
+        &self,
+        query: &[f32],
+        threshold: f32,
+    ) -> Result<Option<Neighbor>, EmbeddingError> {
+        Ok(self
+            .search(query, 1)?
+            .pop()
+            .filter(|found| found.similarity >= threshold))
+    }
+
+    /// 類似度がthreshold以上でつながる要素をまとめる、2件以上のグループだけを返す
+    pub fn duplicates(&self, threshold: f32) -> Vec<Vec<usize>> {
+        let k = match &self.hnsw {
+            Some(hnsw) => hnsw.params.ef_search,
+            None => self.len(),
+        };
+        let mut parent = (0..self.len()).collect::<Vec<_>>();
+        fn root(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        for i in 0..self.len() {
+            let query = &self.vectors[i * self.dim..(i + 1) * self.dim];
+            for Scored(similarity, j) in self.nearest(query, k) {
+                if similarity < threshold {
+                    break;
+                }
+                let (a, b) = (root(&mut parent, i), root(&mut parent, j as usize));
+                parent[a.max(b)] = a.min(b);
+            }
+        }
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        let mut group_of = vec![usize::MAX; self.len()];
+        for i in 0..self.len() {
+            let r = root(&mut parent, i);
+            if group_of[r] == usize::MAX {
+                group_of[r] = groups.len();
+                groups.push(Vec::new());
+            }
+            groups[group_of[r]].push(i);
+        }
+        groups.retain(|group| group.len() > 1);
+        groups
+    }
+
+    fn check(&self, vector: &[f32]) -> Result<Vec<f32>, EmbeddingError> {
+        if vector.len() != self.dim {
+            return Err(EmbeddingError::DimensionMismatch(vector.len(), self.dim));
+        }
+        Ok(vector.to_vec())
+    }
+
+    fn nearest(&self, query: &[f32], k: usize) -> Vec<Scored> {
+        let similarity = |i: u32| {
+            let i = i as usize;
+            cosine_similarity(query, &self.vectors[i * self.dim..(i + 1) * self.dim])
+        };
+        match &self.hnsw {
+            Some(hnsw) => hnsw.search(&similarity, k),
+            None => {
+                let mut scored = (0..self.len() as u32)
+                    .map(|i| Scored(similarity(i), i))
+                    .collect::<Vec<_>>();
+                scored.sort_by(|a, b| b.cmp(a));
+                scored.truncate(k);
+                scored
+            }
+        }
+    }
+
+    /// 独自のバイナリ形式で保存する、HNSWのグラフも保存する
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), EmbeddingError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(INDEX_MAGIC)?;
+        write_u32(&mut writer, INDEX_VERSION)?;
+        write_u32(&mut writer, self.dim as u32)?;
+        write_u32(&mut writer, self.len() as u32)?;
+        for id in &self.ids {
+            write_str(&mut writer, id)?;
+        }
+        write_f32s(&mut writer, &self.vectors)?;
+        match &self.hnsw {
+            None => writer.write_all(&[0])?,
+            Some(hnsw) => {
+                writer.write_all(&[1])?;
+                write_u32(&mut writer, hnsw.params.m as u32)?;
+                write_u32(&mut writer, hnsw.params.ef_construction as u32)?;
+                write_u32(&mut writer, hnsw.params.ef_search as u32)?;
+                writer.write_all(&hnsw.rng.to_le_bytes())?;
+                write_u32(&mut writer, hnsw.entry.unwrap_or(u32::MAX))?;
+                write_u32(&mut writer, hnsw.max_level as u32)?;
+                for levels in &hnsw.links {
+                    write_u32(&mut writer, levels.len() as u32)?;
+                    for links in levels {
+                        write_u32(&mut writer, links.len() as u32)?;
+                        for &link in links {
+                            write_u32(&mut writer, link)?;
+                        }
+                    }
+                }
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// 長さや番号はファイルの中身と照合し、不正な場合はInvalidIndexを返す
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, EmbeddingError> {
+        let invalid = |message: &str| EmbeddingError::InvalidIndex(message.to_string());
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != INDEX_MAGIC {
+            return Err(invalid("magic"));
+        }
+        let version = read_u32(&mut reader)?;
+        if version != INDEX_VERSION {
+            return Err(EmbeddingError::InvalidIndex(format!("version {}", version)));
+        }
+        let dim = read_u32(&mut reader)? as usize;
+        let count = read_u32(&mut reader)? as usize;
+        let len = count.checked_mul(dim).ok_or_else(|| invalid("size"))?;
+        // countはファイルの値なので先に確保しない
+        let mut ids = Vec::new();
+        for _ in 0..count {
+            ids.push(read_string(&mut reader).map_err(|err| match err.kind() {
+                io::ErrorKind::InvalidData => invalid("id is not utf-8"),
+                _ => err.into(),
+            })?);
+        }
+        let vectors = read_f32s(&mut reader, len)?;
+        let mut kind = [0u8];
+        reader.read_exact(&mut kind)?;
+        let hnsw = match kind[0] {
+            0 => None,
+            1 => {
+                let params = HnswParams {
+                    m: read_u32(&mut reader)? as usize,
+                    ef_construction: read_u32(&mut reader)? as usize,
+                    ef_search: read_u32(&mut reader)? as usize,
+                };
+                // Hnsw::newと同じ条件、m < 2だとrandom_levelが0で割る
+                if params.m < 2 || params.ef_construction == 0 || params.ef_search == 0 {
+                    return Err(invalid("hnsw params"));
+                }
+                let mut rng = [0u8; 8];
+                reader.read_exact(&mut rng)?;
+                let entry = Some(read_u32(&mut reader)?).filter(|&entry| entry != u32::MAX);
+                let max_level = read_u32(&mut reader)? as usize;
+                let mut links = Vec::new();
+                for _ in 0..count {
+                    let num_levels = read_u32(&mut reader)? as usize;
+                    if num_levels == 0 || num_levels > max_level + 1 {
+                        return Err(invalid("level count"));
+                    }
+                    let mut levels = Vec::new();
+                    for _ in 0..num_levels {
+                        let mut level = Vec::new();
+                        for _ in 0..read_u32(&mut reader)? {
+                            let link = read_u32(&mut reader)?;
+                            if link as usize >= count {
+                                return Err(invalid("link out of range"));
+                            }
+                            level.push(link);
+                        }
+                        levels.push(level);
+                    }
+                    links.push(levels);
+                }
+                if entry.map_or(count > 0, |entry| {
+                    entry as usize >= count || links[entry as usize].len() != max_level + 1
+                }) {
+                    return Err(invalid("entry point"));
+                }
+                // l層のリンク先はl層を持っていないとsearch_layerで範囲外になる
+                let has_level = |target: u32, l: usize| links[target as usize].len() > l;
+                if !links.iter().all(|levels| {
+                    levels
+                        .iter()
+                        .enumerate()
+                        .all(|(l, level)| level.iter().all(|&target| has_level(target, l)))
+                }) {
+                    return Err(invalid("link level"));
+                }
+                Some(Hnsw {
+                    params,
+                    links,
+                    entry,
+                    max_level,
+                    rng: u64::from_le_bytes(rng),
+                })
+            }
+            _ => return Err(invalid("index kind")),
+        };
+        Ok(VectorIndex {
+            dim,
+            ids,
+            vectors,
+            hnsw,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// 再現性のある疑似乱数のベクトル
+    fn random_vectors(count: usize, dim: usize) -> Vec<Vec<f32>> {
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        (0..count)
+            .map(|_| {
+                (0..dim)
+                    .map(|_| {
+                        state ^= state << 13;
+                        state ^= state >> 7;
+                        state ^= state << 17;
+                        (state >> 40) as f32 / (1u64 << 24) as f32 - 0.5
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pooling() {
+        let data = [1., 2., 3., 4., 5., 6., 7., 8.];
+        assert_eq!(
+            Pooling::Average.apply(&data, &[1, 2, 2, 2]).unwrap(),
+            [2.5, 6.5]
+        );
+        assert_eq!(Pooling::Max.apply(&data, &[1, 2, 2, 2]).unwrap(), [4., 8.]);
+        assert_eq!(Pooling::Flatten.apply(&data, &[1, 8]).unwrap(), data);
+        assert!(Pooling::Average.apply(&data, &[1, 8]).is_err());
+    }
+
+    #[test]
+    fn hnsw_matches_flat() {
+        let vectors = random_vectors(500, 16);
+        let mut flat = VectorIndex::new(16, IndexKind::Flat);
+        let mut hnsw = VectorIndex::new(16, IndexKind::Hnsw(HnswParams::default()));
+        for (i, vector) in vectors.iter().enumerate() {
+            flat.add(&i.to_string(), vector).unwrap();
+            hnsw.add(&i.to_string(), vector).unwrap();
+        }
+        let queries = random_vectors(520, 16).split_off(500);
+        let mut hits = 0;
+        for query in &queries {
+            let expected = flat.search(query, 5).unwrap();
+            let found = hnsw.search(query, 5).unwrap();
+            assert_eq!(found.len(), 5);
+            hits += found.iter().filter(|n| expected.contains(n)).count();
+        }
+        assert!(hits >= 95, "recall {}/100", hits);
+
+        // 同じベクトルは類似度1で見つかる
+        let found = hnsw.search(&vectors[42], 1).unwrap();
+        assert_eq!(found[0].id, "42");
+        assert!((found[0].similarity - 1.).abs() < 1e-5);
+        assert!(matches!(
+            flat.search(&[0.; 3], 1),
+            Err(EmbeddingError::DimensionMismatch(3, 16))
+        ));
+    }
+
+    #[test]
+    fn dedup() {
+        let mut index = VectorIndex::new(3, IndexKind::Flat);
+        index.add("a", &[1., 0., 0.]).unwrap();
+        index.add("b", &[0., 1., 0.]).unwrap();
+        index.add("a2", &[0.99, 0.05, 0.]).unwrap();
+        index.add("c", &[0., 0., 1.]).unwrap();
+        index.add("a3", &[0.98, 0., 0.1]).unwrap();
+        assert_eq!(index.duplicates(0.95), vec![vec![0, 2, 4]]);
+
+        let found = index.add_unique("a4", &[2., 0., 0.], 0.95).unwrap();
+        assert_eq!(found.unwrap_err().id, "a");
+        assert_eq!(index.add_unique("d", &[1., 1., 1.], 0.95).unwrap(), Ok(5));
+
+        index.retain(|id| !id.starts_with('a'));
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.id(1), Some("c"));
+        assert_eq!(index.vector(1), Some(&[0., 0., 1.][..]));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path =
+            std::env::temp_dir().join(format!("ailia_vector_index_{}.bin", std::process::id()));
+        for kind in [IndexKind::Flat, IndexKind::Hnsw(HnswParams::default())] {
+            let mut index = VectorIndex::new(8, kind);
+            for (i, vector) in random_vectors(50, 8).iter().enumerate() {
+                index.add(&format!("item{}", i), vector).unwrap();
+            }
+            index.save(&path).unwrap();
+            let loaded = VectorIndex::load(&path).unwrap();
+            assert_eq!(loaded, index);
+            assert_eq!(loaded.kind(), kind);
+        }
+
+        // 件数が大きすぎるファイルは先に確保せずにエラーにする
+        let mut data = INDEX_MAGIC.to_vec();
+        for value in [INDEX_VERSION, 1 << 20, u32::MAX] {
+            write_u32(&mut data, value).unwrap();
+        }
+        std::fs::write(&path, data).unwrap();
+        assert!(matches!(
+            VectorIndex::load(&path),
+            Err(EmbeddingError::Io(_))
+        ));
+
+        // 不正なHNSWのパラメータやリンクは読み込み時に弾く
+        let header = |m: u32, max_level: u32| {
+            let mut data = INDEX_MAGIC.to_vec();
+            for value in [INDEX_VERSION, 1, 2] {
+                write_u32(&mut data, value).unwrap();
+            }
+            for id in ["a", "b"] {
+                write_str(&mut data, id).unwrap();
+            }
+            write_f32s(&mut data, &[1., 1.]).unwrap();
+            data.push(1);
+            for value in [m, 8, 8] {
+                write_u32(&mut data, value).unwrap();
+            }
+            data.extend_from_slice(&0u64.to_le_bytes());
+            for value in [0, max_level] {
+                write_u32(&mut data, value).unwrap();
+            }
+            data
+        };
+        let links = |node_levels: &[&[&[u32]]]| {
+            let mut data = Vec::new();
+            for levels in node_levels {
+                write_u32(&mut data, levels.len() as u32).unwrap();
+                for level in *levels {
+                    write_u32(&mut data, level.len() as u32).unwrap();
+                    for &link in *level {
+                        write_u32(&mut data, link).unwrap();
+                    }
+                }
+            }
+            data
+        };
+        for data in [
+            // m == 1
+            [header(1, 1), links(&[&[&[1], &[]], &[&[0]]])].concat(),
+            // 1層しかない要素へ1層目からリンクしている
+            [header(2, 1), links(&[&[&[1], &[1]], &[&[0]]])].concat(),
+            // max_levelより多い層
+            [header(2, 0), links(&[&[&[1]], &[&[0], &[]]])].concat(),
+        ] {
+            std::fs::write(&path, data).unwrap();
+            assert!(matches!(
+                VectorIndex::load(&path),
+                Err(EmbeddingError::InvalidIndex(_))
+            ));
+        }
+        std::fs::write(
+            &path,
+            [header(2, 1), links(&[&[&[1], &[]], &[&[0]]])].concat(),
+        )
+        .unwrap();
+        assert_eq!(VectorIndex::load(&path).unwrap().len(), 2);
+        std::fs::remove_file(path).unwrap();
+    }
+}
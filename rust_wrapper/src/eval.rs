@@ -0,0 +1,739 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::classifier::{Class, Classifier};
+use crate::detector::Detector;
+use crate::export::{CocoDetection, CocoKeypoints, COCO_KEYPOINT_COUNT};
+use crate::format::ImageFormat;
+use crate::pose_estimator::{Pose, PoseEstimator};
+use crate::AiliaError;
+
+/// COCOの評価で使うIoU(OKS)の閾値 0.50:0.05:0.95
+pub const IOU_THRESHOLDS: [f64; 10] = [0.5, 0.55, 0.6, 0.65, 0.7, 0.75, 0.8, 0.85, 0.9, 0.95];
+
+/// 画像あたりカテゴリあたりの最大検出数
+pub const MAX_DETECTIONS: usize = 100;
+
+/// キーポイントの評価での画像あたりの最大検出数(pycocotoolsのkeypointsのmaxDets)
+pub const MAX_KEYPOINT_DETECTIONS: usize = 20;
+
+const RECALL_POINTS: usize = 101;
+
+/// COCOのキーポイントのsigma
+pub const COCO_KEYPOINT_SIGMAS: [f64; COCO_KEYPOINT_COUNT] = [
+    0.026, 0.025, 0.025, 0.035, 0.035, 0.079, 0.079, 0.072, 0.072, 0.062, 0.062, 0.107, 0.107,
+    0.087, 0.087, 0.089, 0.089,
+];
+
+#[derive(Debug, Error)]
+pub enum EvalError {
+    #[error("アノテーションの形式が不正です: {0}")]
+    InvalidAnnotation(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Image(#[from] image::ImageError),
+    #[error(transparent)]
+    Ailia(#[from] AiliaError),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CocoImage {
+    pub id: u64,
+    pub file_name: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CocoAnnotation {
+    #[serde(default)]
+    pub id: u64,
+    pub image_id: u64,
+    pub category_id: u32,
+    /// ピクセル単位の[x, y, width, height]
+    pub bbox: [f64; 4],
+    #[serde(default)]
+    pub area: f64,
+    #[serde(default)]
+    pub iscrowd: u8,
+    #[serde(default)]
+    pub keypoints: Vec<f64>,
+    #[serde(default)]
+    pub num_keypoints: u32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CocoCategory {
+    pub id: u32,
+    pub name: String,
+}
+
+/// COCO形式のアノテーションファイル(instances_*.json, person_keypoints_*.json)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CocoDataset {
+    pub images: Vec<CocoImage>,
+    pub annotations: Vec<CocoAnnotation>,
+    pub categories: Vec<CocoCategory>,
+}
+
+impl CocoDataset {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, EvalError> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn category_name(&self, category_id: u32) -> Option<&str> {
+        self.categories
+            .iter()
+            .find(|category| category.id == category_id)
+            .map(|category| category.name.as_str())
+    }
+}
+
+/// カテゴリごとのAP
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClassAp {
+    pub category_id: u32,
+    pub name: String,
+    pub num_gt: usize,
+    /// AP@[.5:.95]
+    pub ap: f64,
+    pub ap50: f64,
+    pub ap75: f64,
+    /// IoU 0.5での101点補間の(recall, precision)
+    pub pr_curve: Vec<(f64, f64)>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ApMetrics {
+    /// mAP@[.5:.95]
+    pub map: f64,
+    pub ap50: f64,
+    pub ap75: f64,
+    pub per_class: Vec<ClassAp>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct ClassificationMetrics {
+    pub count: usize,
+    pub top1: f64,
+    pub top5: f64,
+}
+
+/// 評価対象の予測結果
+trait Prediction {
+    fn image_id(&self) -> u64;
+    fn category_id(&self) -> u32;
+    fn score(&self) -> f32;
+}
+
+impl Prediction for CocoDetection {
+    fn image_id(&self) -> u64 {
+        self.image_id
+    }
+
+    fn category_id(&self) -> u32 {
+        self.category_id
+    }
+
+    fn score(&self) -> f32 {
+        self.score
+    }
+}
+
+impl Prediction for CocoKeypoints {
+    fn image_id(&self) -> u64 {
+        self.image_id
+    }
+
+    fn category_id(&self) -> u32 {
+        self.category_id
+    }
+
+    fn score(&self) -> f32 {
+        self.score
+    }
+}
+
+/// 検出結果の矩形のIoU、crowdの場合は検出結果の面積に対する重なりの割合
+pub fn bbox_iou(det: &[f64; 4], gt: &[f64; 4], iscrowd: bool) -> f64 {
+    let width = (det[0] + det[2]).min(gt[0] + gt[2]) - det[0].max(gt[0]);
+    let height = (det[1] + det[3]).min(gt[1] + gt[3]) - det[1].max(gt[1]);
+    if width <= 0. || height <= 0. {
+        return 0.;
+    }
+    let intersection = width * height;
+    let det_area = det[2] * det[3];
+    let union = if iscrowd {
+        det_area
+    } else {
+        det_area + gt[2] * gt[3] - intersection
+    };
+    if union <= 0. {
+        0.
+    } else {
+        intersection / union
+    }
+}
+
+/// COCOのObject Keypoint Similarity
+///
+/// 検出の点数が足りない場合は0、正解の点数が足りない場合は足りない点を不可視として扱う
+pub fn keypoint_oks(det: &[f32], gt: &CocoAnnotation) -> f64 {
+    if det.len() < COCO_KEYPOINT_COUNT * 3 {
+        return 0.;
+    }
+    let area = if gt.area > 0. {
+        gt.area
+    } else {
+        gt.bbox[2] * gt.bbox[3]
+    };
+    let num_visible = (0..COCO_KEYPOINT_COUNT)
+        .filter(|&k| gt.keypoints.get(k * 3 + 2).is_some_and(|&v| v > 0.))
+        .count();
+    // 可視点がない場合は矩形を3倍に広げた領域の外側への距離を使う
+    let [bx, by, bw, bh] = gt.bbox;
+    let mut sum = 0.;
+    for (k, sigma) in COCO_KEYPOINT_SIGMAS.iter().enumerate() {
+        let xd = det[k * 3] as f64;
+        let yd = det[k * 3 + 1] as f64;
+        let (dx, dy) = if num_visible > 0 {
+            match gt.keypoints.get(k * 3..k * 3 + 3) {
+                Some(&[xg, yg, v]) if v > 0. => (xd - xg, yd - yg),
+                _ => continue,
+            }
+        } else {
+            (
+                (bx - bw - xd).max(0.) + (xd - (bx + 2. * bw)).max(0.),
+                (by - bh - yd).max(0.) + (yd - (by + 2. * bh)).max(0.),
+            )
+        };
+        let var = (2. * sigma).powi(2);
+        let e = (dx * dx + dy * dy) / var / (area + f64::EPSILON) / 2.;
+        sum += (-e).exp();
+    }
+    let count = if num_visible > 0 {
+        num_visible
+    } else {
+        COCO_KEYPOINT_COUNT
+    };
+    sum / count as f64
+}
+
+/// 1画像1カテゴリ分のマッチング結果
+struct ImageEval {
+    // [閾値][検出]
+    matched: Vec<Vec<bool>>,
+    ignored: Vec<Vec<bool>>,
+    num_gt: usize,
+}
+
+/// COCOevalのevaluateImgと同じ手順で、スコアの高い順に検出結果を正解に割り当てる
+fn evaluate_image<D>(
+    gts: &[&CocoAnnotation],
+    gt_ignore: &[bool],
+    dts: &[&D],
+    similarity: &impl Fn(&D, &CocoAnnotation) -> f64,
+) -> ImageEval {
+    // ignoreでない正解を先に並べる
+    let mut gt_order: Vec<usize> = (0..gts.len()).collect();
+    gt_order.sort_by_key(|&g| gt_ignore[g]);
+    let sims: Vec<Vec<f64>> = dts
+        .iter()
+        .map(|dt| gt_order.iter().map(|&g| similarity(dt, gts[g])).collect())
+        .collect();
+
+    let mut matched = vec![vec![false; dts.len()]; IOU_THRESHOLDS.len()];
+    let mut ignored = vec![vec![false; dts.len()]; IOU_THRESHOLDS.len()];
+    for (t, threshold) in IOU_THRESHOLDS.iter().enumerate() {
+        let mut gt_matched = vec![false; gt_order.len()];
+        for d in 0..dts.len() {
+            let mut best = threshold.min(1. - 1e-10);
+            let mut m: Option<usize> = None;
+            for (o, &g) in gt_order.iter().enumerate() {
+                let iscrowd = gts[g].iscrowd != 0;
+                if gt_matched[o] && !iscrowd {
+                    continue;
+                }
+                // ignoreでない正解とマッチ済みならignoreの正解は見ない
+                if let Some(prev) = m {
+                    if !gt_ignore[gt_order[prev]] && gt_ignore[g] {
+                        break;
+                    }
+                }
+                if sims[d][o] < best {
+                    continue;
+                }
+                best = sims[d][o];
+                m = Some(o);
+            }
+            if let Some(o) = m {
+                gt_matched[o] = true;
+                matched[t][d] = true;
+                ignored[t][d] = gt_ignore[gt_order[o]];
+            }
+        }
+    }
+    ImageEval {
+        matched,
+        ignored,
+        num_gt: gt_ignore.iter().filter(|&&ignore| !ignore).count(),
+    }
+}
+
+/// スコア順に並べた検出結果から101点補間のprecisionを計算する
+fn interpolated_precision(
+    order: &[usize],
+    matched: &[bool],
+    ignored: &[bool],
+    num_gt: usize,
+) -> Vec<f64> {
+    let mut recall = Vec::with_capacity(order.len());
+    let mut precision = Vec::with_capacity(order.len());
+    let (mut tp, mut fp) = (0usize, 0usize);
+    for &d in order {
+        if ignored[d] {
+            continue;
+        }
+        if matched[d] {
+            tp += 1;
+        } else {
+            fp += 1;
+        }
+        recall.push(tp as f64 / num_gt as f64);
+        precision.push(tp as f64 / (tp + fp) as f64);
+    }
+    for i in (1..precision.len()).rev() {
+        if precision[i] > precision[i - 1] {
+            precision[i - 1] = precision[i];
+        }
+    }
+    (0..RECALL_POINTS)
+        .map(|i| {
+            let r = i as f64 / (RECALL_POINTS - 1) as f64;
+            let idx = recall.partition_point(|&rc| rc < r);
+            precision.get(idx).copied().unwrap_or(0.)
+        })
+        .collect()
+}
+
+fn evaluate<D: Prediction>(
+    dataset: &CocoDataset,
+    predictions: &[D],
+    max_detections: usize,
+    gt_ignore: impl Fn(&CocoAnnotation) -> bool,
+    similarity: impl Fn(&D, &CocoAnnotation) -> f64,
+) -> ApMetrics {
+    let mut gts: HashMap<(u64, u32), Vec<&CocoAnnotation>> = HashMap::new();
+    for ann in &dataset.annotations {
+        gts.entry((ann.image_id, ann.category_id))
+            .or_default()
+            .push(ann);
+    }
+    let mut dts: HashMap<(u64, u32), Vec<&D>> = HashMap::new();
+    for pred in predictions {
+        dts.entry((pred.image_id(), pred.category_id()))
+            .or_default()
+            .push(pred);
+    }
+
+    let category_ids: BTreeSet<u32> = dataset
+        .categories
+        .iter()
+        .map(|category| category.id)
+        .chain(dataset.annotations.iter().map(|ann| ann.category_id))
+        .collect();
+    let image_ids: BTreeSet<u64> = dataset.images.iter().map(|image| image.id).collect();
+
+    let mut per_class = Vec::new();
+    for &category_id in &category_ids {
+        let mut scores = Vec::new();
+        let mut matched = vec![Vec::new(); IOU_THRESHOLDS.len()];
+        let mut ignored = vec![Vec::new(); IOU_THRESHOLDS.len()];
+        let mut num_gt = 0;
+        for &image_id in &image_ids {
+            let key = (image_id, category_id);
+            let image_gts = gts.get(&key).map(Vec::as_slice).unwrap_or(&[]);
+            let mut image_dts = dts.get(&key).cloned().unwrap_or_default();
+            if image_gts.is_empty() && image_dts.is_empty() {
+                continue;
+            }
+            image_dts.sort_by(|a, b| b.score().total_cmp(&a.score()));
+            image_dts.truncate(max_detections);
+            let ignore: Vec<bool> = image_gts.iter().map(|gt| gt_ignore(gt)).collect();
+            let eval = evaluate_image(image_gts, &ignore, &image_dts, &similarity);
+
+            scores.extend(image_dts.iter().map(|dt| dt.score()));
+            for t in 0..IOU_THRESHOLDS.len() {
+                matched[t].extend(&eval.matched[t]);
+                ignored[t].extend(&eval.ignored[t]);
+            }
+            num_gt += eval.num_gt;
+        }
+        // 正解が存在しないカテゴリは評価しない
+        if num_gt == 0 {
+            continue;
+        }
+
+        let mut order: Vec<usize> = (0..scores.len()).collect();
+        order.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+        let precisions: Vec<Vec<f64>> = (0..IOU_THRESHOLDS.len())
+            .map(|t| interpolated_precision(&order, &matched[t], &ignored[t], num_gt))
+            .collect();
+        let mean = |p: &Vec<f64>| p.iter().sum::<f64>() / p.len() as f64;
+        let aps: Vec<f64> = precisions.iter().map(mean).collect();
+        per_class.push(ClassAp {
+            category_id,
+            name: dataset
+                .category_name(category_id)
+                .unwrap_or_default()
+                .to_string(),
+            num_gt,
+            ap: aps.iter().sum::<f64>() / aps.len() as f64,
+            ap50: aps[0],
+            ap75: aps[5],
+            pr_curve: precisions[0]
+                .iter()
+                .enumerate()
+                .map(|(i, &p)| (i as f64 / (RECALL_POINTS - 1) as f64, p))
+                .collect(),
+        });
+    }
+
+    let mean = |f: fn(&ClassAp) -> f64| {
+        if per_class.is_empty() {
+            0.
+        } else {
+            per_class.iter().map(f).sum::<f64>() / per_class.len() as f64
+        }
+    };
+    ApMetrics {
+        map: mean(|c| c.ap),
+        ap50: mean(|c| c.ap50),
+        ap75: mean(|c| c.ap75),
+        per_class,
+    }
+}
+
+/// 矩形のAP(COCOのbbox評価)を計算する
+pub fn evaluate_detections(dataset: &CocoDataset, detections: &[CocoDetection]) -> ApMetrics {
+    evaluate(
+        dataset,
+        detections,
+        MAX_DETECTIONS,
+        |gt| gt.iscrowd != 0,
+        |dt, gt| {
+            let bbox = dt.bbox.map(|v| v as f64);
+            bbox_iou(&bbox, &gt.bbox, gt.iscrowd != 0)
+        },
+    )
+}
+
+/// OKSによるキーポイントのAP(COCOのkeypoints評価)を計算する
+pub fn evaluate_keypoints(dataset: &CocoDataset, keypoints: &[CocoKeypoints]) -> ApMetrics {
+    evaluate(
+        dataset,
+        keypoints,
+        MAX_KEYPOINT_DETECTIONS,
+        |gt| gt.iscrowd != 0 || gt.num_keypoints == 0,
+        |dt, gt| keypoint_oks(&dt.keypoints, gt),
+    )
+}
+
+/// 予測結果(確率の高い順)と正解のカテゴリからtop-1/top-5の正解率を計算する
+pub fn evaluate_classification(results: &[(Vec<Class>, i32)]) -> ClassificationMetrics {
+    if results.is_empty() {
+        return ClassificationMetrics::default();
+    }
+    let hit = |k: usize| {
+        results
+            .iter()
+            .filter(|(classes, gt)| classes.iter().take(k).any(|c| c.category == *gt))
+            .count() as f64
+            / results.len() as f64
+    };
+    ClassificationMetrics {
+        count: results.len(),
+        top1: hit(1),
+        top5: hit(5),
+    }
+}
+
+/// データセットの各画像で検出を行いCOCOの結果形式で返す
+/// category_idsを指定した場合は検出結果のcategoryをCOCOのcategory_idに変換する
+pub fn predict_detections<P: AsRef<Path>>(
+    detector: &Detector,
+    dataset: &CocoDataset,
+    image_dir: P,
+    threshold: f32,
+    iou: f32,
+    category_ids: Option<&[u32]>,
+) -> Result<Vec<CocoDetection>, EvalError> {
+    let mut detections = Vec::new();
+    for image in &dataset.images {
+        let img = image::open(image_dir.as_ref().join(&image.file_name))?.into_rgba8();
+        let (width, height) = img.dimensions();
+        for obj in detector.predict_image(img, threshold, iou)? {
+            let detection = CocoDetection::from_object(&obj, image.id, width, height, category_ids)
+                .ok_or_else(|| {
+                    EvalError::InvalidAnnotation(format!(
+                        "category {} is out of category_ids",
+                        obj.category
+                    ))
+                })?;
+            detections.push(detection);
+        }
+    }
+    Ok(detections)
+}
+
+/// データセットの各画像で姿勢推定を行いCOCOのキーポイントの結果形式で返す
+pub fn predict_keypoints<P: AsRef<Path>>(
+    estimator: &PoseEstimator<Pose>,
+    dataset: &CocoDataset,
+    image_dir: P,
+) -> Result<Vec<CocoKeypoints>, EvalError> {
+    let mut keypoints = Vec::new();
+    for image in &dataset.images {
+        let img = image::open(image_dir.as_ref().join(&image.file_name))?.into_rgba8();
+        let (width, height) = img.dimensions();
+        let poses = estimator.predict(img.as_ptr(), width * 4, width, height, ImageFormat::Rgba)?;
+        for pose in poses {
+            keypoints.push(CocoKeypoints::from_pose(&pose, image.id, width, height));
+        }
+    }
+    Ok(keypoints)
+}
+
+/// `<画像のパス> <カテゴリ番号>`の行からなるリストを読み込む
+pub fn load_classification_list<P: AsRef<Path>>(path: P) -> Result<Vec<(String, i32)>, EvalError> {
+    let text = std::fs::read_to_string(path)?;
+    let mut list = Vec::new();
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let (file, category) = line
+            .trim()
+            .rsplit_once(char::is_whitespace)
+            .ok_or_else(|| EvalError::InvalidAnnotation(line.to_string()))?;
+        let category = category
+            .parse()
+            .map_err(|_| EvalError::InvalidAnnotation(line.to_string()))?;
+        list.push((file.trim().to_string(), category));
+    }
+    Ok(list)
+}
+
+/// リストの各画像で分類を行いtop-1/top-5の正解率を計算する
+pub fn predict_classification<P: AsRef<Path>>(
+    classifier: &Classifier,
+    list: &[(String, i32)],
+    image_dir: P,
+) -> Result<ClassificationMetrics, EvalError> {
+    let mut results = Vec::with_capacity(list.len());
+    for (file, category) in list {
+        let img = image::open(image_dir.as_ref().join(file))?.into_rgba8();
+        let (width, height) = img.dimensions();
+        classifier.compute(img.as_ptr(), width * 4, width, height, ImageFormat::Rgba, 5)?;
+        let count = classifier.get_class_count()?;
+        let classes = (0..count)
+            .map(|idx| classifier.get_class(idx))
+            .collect::<Result<Vec<_>, _>>()?;
+        results.push((classes, *category));
+    }
+    Ok(evaluate_classification(&results))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DATASET: &str = r#"{
+        "images": [
+            {"id": 1, "file_name": "1.jpg", "width": 100, "height": 100},
+            {"id": 2, "file_name": "2.jpg", "width": 100, "height": 100}
+        ],
+        "annotations": [
+            {"id": 1, "image_id": 1, "category_id": 1, "bbox": [0, 0, 10, 10], "area": 100},
+            {"id": 2, "image_id": 2, "category_id": 1, "bbox": [50, 50, 20, 20], "area": 400},
+            {"id": 3, "image_id": 2, "category_id": 3, "bbox": [0, 0, 30, 30], "area": 900},
+            {"id": 4, "image_id": 1, "category_id": 3, "bbox": [60, 0, 40, 40], "area": 1600, "iscrowd": 1}
+        ],
+        "categories": [
+            {"id": 1, "name": "person"},
+            {"id": 3, "name": "car"},
+            {"id": 5, "name": "airplane"}
+        ]
+    }"#;
+
+    fn dataset() -> CocoDataset {
+        serde_json::from_str(DATASET).unwrap()
+    }
+
+    fn det(image_id: u64, category_id: u32, bbox: [f32; 4], score: f32) -> CocoDetection {
+        CocoDetection {
+            image_id,
+            category_id,
+            bbox,
+            score,
+        }
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn perfect_detections() {
+        let dets = vec![
+            det(1, 1, [0., 0., 10., 10.], 0.9),
+            det(2, 1, [50., 50., 20., 20.], 0.8),
+            det(2, 3, [0., 0., 30., 30.], 0.7),
+        ];
+        let metrics = evaluate_detections(&dataset(), &dets);
+        // 正解のないairplaneは評価しない
+        assert_eq!(metrics.per_class.len(), 2);
+        assert_close(metrics.map, 1.);
+        assert_close(metrics.ap50, 1.);
+        assert_eq!(metrics.per_class[0].name, "person");
+        assert_eq!(metrics.per_class[0].pr_curve.len(), 101);
+    }
+
+    #[test]
+    fn partial_overlap() {
+        // IoU 0.62 は閾値 0.5, 0.55, 0.6 でのみ正解になる
+        let dets = vec![
+            det(1, 1, [0., 0., 10., 6.2], 0.9),
+            det(2, 1, [50., 50., 20., 20.], 0.8),
+            det(2, 3, [0., 0., 30., 30.], 0.7),
+        ];
+        let metrics = evaluate_detections(&dataset(), &dets);
+        let person = &metrics.per_class[0];
+        assert_close(person.ap50, 1.);
+        // 誤検出が先頭になり recall 0.5 までは precision 0.5、その先は 0
+        assert_close(person.ap75, 0.5 * 51. / 101.);
+        assert_close(person.ap, (3. + 7. * 0.5 * 51. / 101.) / 10.);
+    }
+
+    #[test]
+    fn false_positive_ranked_first() {
+        let dets = vec![
+            det(1, 1, [80., 80., 10., 10.], 0.95),
+            det(1, 1, [0., 0., 10., 10.], 0.9),
+            det(2, 1, [50., 50., 20., 20.], 0.8),
+        ];
+        let metrics = evaluate_detections(&dataset(), &dets);
+        let person = &metrics.per_class[0];
+        // precisionは [0, 1/2, 2/3] -> 単調化して [2/3, 2/3, 2/3]
+        assert_close(person.ap, 2. / 3.);
+        // carは検出なし
+        assert_close(metrics.per_class[1].ap, 0.);
+    }
+
+    #[test]
+    fn crowd_is_ignored() {
+        let dets = vec![
+            det(2, 3, [0., 0., 30., 30.], 0.9),
+            // crowdの領域内の検出は誤検出として数えない
+            det(1, 3, [70., 10., 10., 10.], 0.95),
+        ];
+        let metrics = evaluate_detections(&dataset(), &dets);
+        let car = metrics
+            .per_class
+            .iter()
+            .find(|c| c.category_id == 3)
+            .unwrap();
+        assert_eq!(car.num_gt, 1);
+        assert_close(car.ap, 1.);
+    }
+
+    #[test]
+    fn keypoints_oks() {
+        let mut keypoints = vec![0.; COCO_KEYPOINT_COUNT * 3];
+        for k in 0..COCO_KEYPOINT_COUNT {
+            keypoints[k * 3] = 10. + k as f64;
+            keypoints[k * 3 + 1] = 20. + k as f64;
+            keypoints[k * 3 + 2] = 2.;
+        }
+        let gt = CocoAnnotation {
+            id: 1,
+            image_id: 1,
+            category_id: 1,
+            bbox: [0., 0., 50., 50.],
+            area: 2500.,
+            iscrowd: 0,
+            keypoints: keypoints.clone(),
+            num_keypoints: COCO_KEYPOINT_COUNT as u32,
+        };
+        let dataset = CocoDataset {
+            images: vec![CocoImage {
+                id: 1,
+                file_name: "1.jpg".to_string(),
+                width: 100,
+                height: 100,
+            }],
+            annotations: vec![gt.clone()],
+            categories: vec![CocoCategory {
+                id: 1,
+                name: "person".to_string(),
+            }],
+        };
+        let det_keypoints: Vec<f32> = keypoints.iter().map(|&v| v as f32).collect();
+        assert_close(keypoint_oks(&det_keypoints, &gt), 1.);
+
+        let far: Vec<f32> = det_keypoints.iter().map(|v| v + 30.).collect();
+        assert!(keypoint_oks(&far, &gt) < 0.5);
+
+        // 点数の足りないアノテーションや検出でもパニックしない
+        let short = CocoAnnotation {
+            keypoints: keypoints[..7].to_vec(),
+            ..gt.clone()
+        };
+        assert_close(keypoint_oks(&det_keypoints, &short), 1.);
+        assert_close(keypoint_oks(&det_keypoints[..5], &gt), 0.);
+
+        let mut preds = vec![CocoKeypoints {
+            image_id: 1,
+            category_id: 1,
+            keypoints: det_keypoints,
+            score: 0.9,
+        }];
+        assert_close(evaluate_keypoints(&dataset, &preds).map, 1.);
+
+        // スコアの高い誤検出が20個あると正解の検出は評価から外れる
+        for i in 0..MAX_KEYPOINT_DETECTIONS {
+            preds.push(CocoKeypoints {
+                keypoints: far.clone(),
+                score: 0.95 + i as f32 * 0.001,
+                ..preds[0].clone()
+            });
+        }
+        assert_close(evaluate_keypoints(&dataset, &preds).map, 0.);
+    }
+
+    #[test]
+    fn classification_accuracy() {
+        let classes = |categories: &[i32]| {
+            categories
+                .iter()
+                .map(|&category| Class { category, prob: 0. })
+                .collect::<Vec<_>>()
+        };
+        let results = vec![
+            (classes(&[1, 2, 3, 4, 5]), 1),
+            (classes(&[1, 2, 3, 4, 5]), 5),
+            (classes(&[1, 2, 3, 4, 5, 6]), 6),
+            (classes(&[7]), 8),
+        ];
+        let metrics = evaluate_classification(&results);
+        assert_eq!(metrics.count, 4);
+        assert_close(metrics.top1, 0.25);
+        assert_close(metrics.top5, 0.5);
+    }
+}
@@ -0,0 +1,370 @@
+use std::io::{self, Write};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::classifier::Class;
+use crate::detector::Object;
+use crate::pose_estimator::Pose;
+use crate::video::Frame;
+
+/// yolox等のCOCOの80クラスの番号からCOCOのcategory_idへの変換表
+pub const COCO_CATEGORY_IDS: [u32; 80] = [
+    1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 27, 28,
+    31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 46, 47, 48, 49, 50, 51, 52, 53, 54, 55,
+    56, 57, 58, 59, 60, 61, 62, 63, 64, 65, 67, 70, 72, 73, 74, 75, 76, 77, 78, 79, 80, 81, 82, 84,
+    85, 86, 87, 88, 89, 90,
+];
+
+/// COCOのpersonのcategory_id
+pub const COCO_PERSON_CATEGORY_ID: u32 = 1;
+
+/// COCOのキーポイントの数、ailiaのPoseの先頭17点はCOCOと同じ順序
+pub const COCO_KEYPOINT_COUNT: usize = 17;
+
+const POSE_KEYPOINT_COUNT: usize = ailia_sys::AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_CNT as usize;
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// COCOのdetection resultsの1要素
+/// bboxはピクセル単位の[x, y, width, height]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CocoDetection {
+    pub image_id: u64,
+    pub category_id: u32,
+    pub bbox: [f32; 4],
+    pub score: f32,
+}
+
+impl CocoDetection {
+    /// category_idsを指定した場合はObjectのcategoryを添字としてcategory_idに変換する
+    /// categoryがcategory_idsの範囲外の場合はNone
+    pub fn from_object(
+        obj: &Object,
+        image_id: u64,
+        width: u32,
+        height: u32,
+        category_ids: Option<&[u32]>,
+    ) -> Option<Self> {
+        let category_id = match category_ids {
+            Some(ids) => *ids.get(obj.category as usize)?,
+            None => obj.category,
+        };
+        Some(Self {
+            image_id,
+            category_id,
+            bbox: [
+                obj.x * width as f32,
+                obj.y * height as f32,
+                obj.w * width as f32,
+                obj.h * height as f32,
+            ],
+            score: obj.prob,
+        })
+    }
+}
+
+/// COCOのkeypoint resultsの1要素
+/// keypointsはピクセル単位の[x, y, v]の17点分
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CocoKeypoints {
+    pub image_id: u64,
+    pub category_id: u32,
+    pub keypoints: Vec<f32>,
+    pub score: f32,
+}
+
+impl CocoKeypoints {
+    pub fn from_pose(pose: &Pose, image_id: u64, width: u32, height: u32) -> Self {
+        let mut keypoints = Vec::with_capacity(COCO_KEYPOINT_COUNT * 3);
+        for point in &pose.points[..COCO_KEYPOINT_COUNT] {
+            if point.score > 0. {
+                keypoints.extend([point.x * width as f32, point.y * height as f32, 2.]);
+            } else {
+                keypoints.extend([0., 0., 0.]);
+            }
+        }
+        Self {
+            image_id,
+            category_id: COCO_PERSON_CATEGORY_ID,
+            keypoints,
+            score: pose.total_score,
+        }
+    }
+}
+
+/// COCOのresults形式(配列)で書き出す
+pub fn write_coco_json<W: Write, T: Serialize>(
+    writer: W,
+    results: &[T],
+) -> Result<(), ExportError> {
+    serde_json::to_writer(writer, results)?;
+    Ok(())
+}
+
+/// 1フレーム分の結果
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FrameRecord<T> {
+    pub frame: u64,
+    pub timestamp_ms: f64,
+    pub results: Vec<T>,
+}
+
+impl<T> FrameRecord<T> {
+    pub fn new(frame: u64, timestamp: Duration, results: Vec<T>) -> Self {
+        Self {
+            frame,
+            timestamp_ms: timestamp.as_secs_f64() * 1000.,
+            results,
+        }
+    }
+
+    pub fn from_frame(frame: &Frame, results: Vec<T>) -> Self {
+        Self::new(frame.index, frame.timestamp, results)
+    }
+}
+
+/// 1行に1レコードのJSON Linesで書き出す
+pub struct JsonLinesWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonLinesWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn write<T: Serialize>(&mut self, record: &T) -> Result<(), ExportError> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// CSVの1行として書き出せる結果
+pub trait CsvRow {
+    fn csv_header() -> Vec<String>;
+    fn csv_fields(&self, labels: Option<&[&str]>) -> Vec<String>;
+}
+
+fn label_field(category: usize, labels: Option<&[&str]>) -> String {
+    labels
+        .and_then(|labels| labels.get(category))
+        .map(|label| label.to_string())
+        .unwrap_or_default()
+}
+
+impl CsvRow for Object {
+    fn csv_header() -> Vec<String> {
+        ["category", "label", "prob", "x", "y", "w", "h"]
+            .map(String::from)
+            .to_vec()
+    }
+
+    fn csv_fields(&self, labels: Option<&[&str]>) -> Vec<String> {
+        vec![
+            self.category.to_string(),
+            label_field(self.category as usize, labels),
+            self.prob.to_string(),
+            self.x.to_string(),
+            self.y.to_string(),
+            self.w.to_string(),
+            self.h.to_string(),
+        ]
+    }
+}
+
+impl CsvRow for Class {
+    fn csv_header() -> Vec<String> {
+        ["category", "label", "prob"].map(String::from).to_vec()
+    }
+
+    fn csv_fields(&self, labels: Option<&[&str]>) -> Vec<String> {
+        vec![
+            self.category.to_string(),
+            label_field(self.category.max(0) as usize, labels),
+            self.prob.to_string(),
+        ]
+    }
+}
+
+impl CsvRow for Pose {
+    fn csv_header() -> Vec<String> {
+        let mut header = vec!["id".to_string(), "total_score".to_string()];
+        for idx in 0..POSE_KEYPOINT_COUNT {
+            header.push(format!("x{}", idx));
+            header.push(format!("y{}", idx));
+            header.push(format!("score{}", idx));
+        }
+        header
+    }
+
+    fn csv_fields(&self, _labels: Option<&[&str]>) -> Vec<String> {
+        let mut fields = vec![self.id.to_string(), self.total_score.to_string()];
+        for point in &self.points {
+            fields.push(point.x.to_string());
+            fields.push(point.y.to_string());
+            fields.push(point.score.to_string());
+        }
+        fields
+    }
+}
+
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// 1結果1行のCSVで書き出す、先頭の列はフレーム番号とタイムスタンプ(ms)
+pub struct CsvWriter<W: Write> {
+    writer: W,
+    header_written: bool,
+}
+
+impl<W: Write> CsvWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            header_written: false,
+        }
+    }
+
+    pub fn write<T: CsvRow>(
+        &mut self,
+        record: &FrameRecord<T>,
+        labels: Option<&[&str]>,
+    ) -> Result<(), ExportError> {
+        if !self.header_written {
+            let mut header = vec!["frame".to_string(), "timestamp_ms".to_string()];
+            header.extend(T::csv_header());
+            self.write_line(&header)?;
+            self.header_written = true;
+        }
+        for result in &record.results {
+            let mut fields = vec![record.frame.to_string(), record.timestamp_ms.to_string()];
+            fields.extend(result.csv_fields(labels));
+            self.write_line(&fields)?;
+        }
+        Ok(())
+    }
+
+    fn write_line(&mut self, fields: &[String]) -> Result<(), ExportError> {
+        let line: Vec<String> = fields.iter().map(|field| escape_csv(field)).collect();
+        writeln!(self.writer, "{}", line.join(","))?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::pose_estimator::{Face, KeyPoint};
+
+    fn object() -> Object {
+        Object {
+            category: 11,
+            prob: 0.5,
+            x: 0.25,
+            y: 0.5,
+            w: 0.5,
+            h: 0.25,
+        }
+    }
+
+    #[test]
+    fn coco_detection() {
+        let det =
+            CocoDetection::from_object(&object(), 42, 640, 480, Some(&COCO_CATEGORY_IDS)).unwrap();
+        assert_eq!(det.category_id, 13);
+        let unknown = Object {
+            category: 80,
+            ..object()
+        };
+        assert!(
+            CocoDetection::from_object(&unknown, 42, 640, 480, Some(&COCO_CATEGORY_IDS)).is_none()
+        );
+        assert_eq!(det.bbox, [160., 240., 320., 120.]);
+        let json = serde_json::to_string(&[det]).unwrap();
+        assert_eq!(
+            json,
+            r#"[{"image_id":42,"category_id":13,"bbox":[160.0,240.0,320.0,120.0],"score":0.5}]"#
+        );
+    }
+
+    #[test]
+    fn coco_keypoints() {
+        let mut points = [KeyPoint::default(); 19];
+        points[0] = KeyPoint {
+            x: 0.5,
+            y: 0.5,
+            score: 0.9,
+            ..Default::default()
+        };
+        let pose = Pose {
+            points,
+            total_score: 0.8,
+            num_valid_points: 1,
+            id: 0,
+            angle: [0.; 3],
+        };
+        let kp = CocoKeypoints::from_pose(&pose, 1, 100, 200);
+        assert_eq!(kp.keypoints.len(), 51);
+        assert_eq!(&kp.keypoints[..6], &[50., 100., 2., 0., 0., 0.]);
+    }
+
+    #[test]
+    fn jsonl_and_csv() {
+        let record = FrameRecord::new(3, Duration::from_millis(100), vec![object()]);
+        let mut jsonl = JsonLinesWriter::new(Vec::new());
+        jsonl.write(&record).unwrap();
+        jsonl.write(&record).unwrap();
+        let text = String::from_utf8(jsonl.into_inner()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let parsed: FrameRecord<Object> = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(parsed.frame, 3);
+        assert_eq!(parsed.results[0].category, 11);
+
+        let labels = ["a"; 12];
+        let mut labels = labels.to_vec();
+        labels[11] = "stop, sign";
+        let mut csv = CsvWriter::new(Vec::new());
+        csv.write(&record, Some(&labels)).unwrap();
+        let text = String::from_utf8(csv.into_inner()).unwrap();
+        assert_eq!(
+            text,
+            "frame,timestamp_ms,category,label,prob,x,y,w,h\n3,100,11,\"stop, sign\",0.5,0.25,0.5,0.5,0.25\n"
+        );
+    }
+
+    #[test]
+    fn face_round_trip() {
+        let face = Face {
+            points: [KeyPoint::default(); 68],
+            total_score: 1.,
+        };
+        let json = serde_json::to_string(&face).unwrap();
+        let parsed: Face = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.points.len(), 68);
+        assert!(serde_json::from_str::<Face>(r#"{"points":[],"total_score":1.0}"#).is_err());
+    }
+}
@@ -0,0 +1,475 @@
+//! 顔認証(ランドマークによる位置合わせ、ArcFace系の特徴量、ギャラリーとの照合)
+//!
+//! `PoseEstimator<Face>`の68点のランドマークから5点を取り出し、相似変換で顔を切り出して特徴量を計算する。
+
+use std::fmt::Debug;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+
+use image::imageops::flip_horizontal;
+use image::RgbImage;
+
+use thiserror::Error;
+
+pub use crate::embedding::{cosine_similarity, l2_normalize};
+use crate::embedding::{EmbeddingError, IndexKind, VectorIndex};
+use crate::network::Network;
+use crate::pose_estimator::Face;
+use crate::preprocess::{sample_bilinear, to_rgb, Normalize, Preprocess, PreprocessError};
+use crate::video::ImageView;
+use crate::AiliaError;
+
+use ailia_sys::*;
+
+/// ArcFaceの112x112の切り出し画像での両目、鼻、口の両端の位置
+pub const ARCFACE_TEMPLATE: [(f32, f32); 5] = [
+    (38.2946, 51.6963),
+    (73.5318, 51.5014),
+    (56.0252, 71.7366),
+    (41.5493, 92.3655),
+    (70.7299, 92.2041),
+];
+
+#[derive(Debug, Error)]
+pub enum FaceError {
+    #[error("ランドマークから変換を求められませんでした")]
+    DegenerateLandmarks,
+    #[error("特徴量の次元が一致しません: {0} != {1}")]
+    DimensionMismatch(usize, usize),
+    #[error("ギャラリーのファイルが不正です: {0}")]
+    InvalidGallery(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Preprocess(#[from] PreprocessError),
+    #[error(transparent)]
+    Ailia(#[from] AiliaError),
+}
+
+/// 回転、等倍拡大、平行移動 `(x, y) -> (a x - b y + tx, b x + a y + ty)`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Similarity {
+    pub a: f32,
+    pub b: f32,
+    pub tx: f32,
+    pub ty: f32,
+}
+
+impl Similarity {
+    /// srcをdstに写す最小二乗の相似変換、点が1か所に集まっている場合はNone
+    pub fn estimate(src: &[(f32, f32)], dst: &[(f32, f32)]) -> Option<Self> {
+        if src.len() != dst.len() || src.is_empty() {
+            return None;
+        }
+        let n = src.len() as f32;
+        let mean = |points: &[(f32, f32)]| {
+            let (x, y) = points
+                .iter()
+                .fold((0., 0.), |(sx, sy), &(x, y)| (sx + x, sy + y));
+            (x / n, y / n)
+        };
+        let (src_mean, dst_mean) = (mean(src), mean(dst));
+        let (mut dot, mut cross, mut norm) = (0., 0., 0.);
+        for (&(x, y), &(u, v)) in src.iter().zip(dst) {
+            let (x, y) = (x - src_mean.0, y - src_mean.1);
+            let (u, v) = (u - dst_mean.0, v - dst_mean.1);
+            dot += x * u + y * v;
+            cross += x * v - y * u;
+            norm += x * x + y * y;
+        }
+        if norm <= f32::EPSILON {
+            return None;
+        }
+        let (a, b) = (dot / norm, cross / norm);
+        Some(Self {
+            a,
+            b,
+            tx: dst_mean.0 - (a * src_mean.0 - b * src_mean.1),
+            ty: dst_mean.1 - (b * src_mean.0 + a * src_mean.1),
+        })
+    }
+
+    pub fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        (
+            self.a * x - self.b * y + self.tx,
+            self.b * x + self.a * y + self.ty,
+        )
+    }
+
+    pub fn inverse(&self) -> Self {
+        let det = self.a * self.a + self.b * self.b;
+        let (a, b) = (self.a / det, -self.b / det);
+        Self {
+            a,
+            b,
+            tx: -(a * self.tx - b * self.ty),
+            ty: -(b * self.tx + a * self.ty),
+        }
+    }
+}
+
+/// 68点のランドマークから両目の中心、鼻先、口の両端を画素座標で取り出す
+/// ランドマークはwidth, heightに対する相対座標
+pub fn face_landmarks5(face: &Face, width: u32, height: u32) -> [(f32, f32); 5] {
+    let point = |idx: usize| {
+        let p = &face.points[idx];
+        (p.x * width as f32, p.y * height as f32)
+    };
+    let center = |range: std::ops::Range<usize>| {
+        let n = range.len() as f32;
+        let (x, y) = range
+            .map(point)
+            .fold((0., 0.), |(sx, sy), (x, y)| (sx + x, sy + y));
+        (x / n, y / n)
+    };
+    [
+        center(36..42),
+        center(42..48),
+        point(30),
+        point(48),
+        point(54),
+    ]
+}
+
+/// landmarksがARCFACE_TEMPLATE(sizeに合わせて拡大)に重なるように顔を切り出す
+pub fn align(
+    image: &RgbImage,
+    landmarks: &[(f32, f32); 5],
+    size: u32,
+) -> Result<RgbImage, FaceError> {
+    let scale = size as f32 / 112.;
+    let template = ARCFACE_TEMPLATE.map(|(x, y)| (x * scale, y * scale));
+    let to_source = Similarity::estimate(landmarks, &template)
+        .ok_or(FaceError::DegenerateLandmarks)?
+        .inverse();
+    Ok(RgbImage::from_fn(size, size, |x, y| {
+        let (sx, sy) = to_source.apply(x as f32 + 0.5, y as f32 + 0.5);
+        sample_bilinear(image, sx - 0.5, sy - 0.5)
+    }))
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FaceEmbedderBuilder<P>
+where
+    P: AsRef<Path> + Default + Debug,
+{
+    prototxt: Option<P>,
+    onnx: P,
+    env_id: Option<i32>,
+    num_threads: Option<i32>,
+    size: Option<u32>,
+    preprocess: Option<Preprocess>,
+    flip: bool,
+}
+
+impl<P: AsRef<Path> + Default + Debug> FaceEmbedderBuilder<P> {
+    crate::impl_option!(prototxt, P);
+    crate::impl_non_option!(onnx, P);
+    crate::impl_option!(env_id, i32);
+    crate::impl_option!(num_threads, i32);
+    // 切り出す画像の一辺、既定値は112
+    crate::impl_option!(size, u32);
+    // 切り出した画像に行う前処理、既定値は-1~1への正規化
+    crate::impl_option!(preprocess, Preprocess);
+    // 左右反転した画像の特徴量も足し合わせる
+    crate::impl_non_option!(flip, bool);
+
+    pub fn build(self) -> Result<FaceEmbedder, AiliaError> {
+        let net = Network::ailia_create(
+            self.env_id.unwrap_or(AILIA_ENVIRONMENT_ID_AUTO),
+            self.num_threads
+                .unwrap_or_else(|| AILIA_MULTITHREAD_AUTO.try_into().unwrap()),
+        )?;
+        net.open_model_files(self.prototxt, self.onnx)?;
+        Ok(FaceEmbedder {
+            net,
+            size: self.size.unwrap_or(112),
+            preprocess: self
+                .preprocess
+                .unwrap_or_else(|| Preprocess::new().normalize(Normalize::Signed)),
+            flip: self.flip,
+        })
+    }
+}
+
+/// 顔の切り出し画像からL2正規化した特徴量を計算する
+pub struct FaceEmbedder {
+    net: Network,
+    size: u32,
+    preprocess: Preprocess,
+    flip: bool,
+}
+
+impl FaceEmbedder {
+    /// 位置合わせ済みの画像の特徴量
+    pub fn embed_aligned(&self, crop: &RgbImage) -> Result<Vec<f32>, FaceError> {
+        let mut embedding = self.infer(crop)?;
+        if self.flip {
+            let flipped = self.infer(&flip_horizontal(crop))?;
+            if flipped.len() != embedding.len() {
+                return Err(FaceError::DimensionMismatch(flipped.len(), embedding.len()));
+            }
+            embedding
+                .iter_mut()
+                .zip(flipped)
+                .for_each(|(value, flipped)| *value += flipped);
+        }
+        l2_normalize(&mut embedding);
+        Ok(embedding)
+    }
+
+    /// 5点のランドマーク(画素座標)で位置合わせをしてから特徴量を計算する
+    pub fn embed(
+        &self,
+        image: &RgbImage,
+        landmarks: &[(f32, f32); 5],
+    ) -> Result<Vec<f32>, FaceError> {
+        self.embed_aligned(&align(image, landmarks, self.size)?)
+    }
+
+    /// PoseEstimator<Face>の結果を使って特徴量を計算する
+    pub fn embed_face(&self, image: &ImageView, face: &Face) -> Result<Vec<f32>, FaceError> {
+        let rgb = to_rgb(image)?;
+        self.embed(&rgb, &face_landmarks5(face, image.width, image.height))
+    }
+
+    fn infer(&self, crop: &RgbImage) -> Result<Vec<f32>, FaceError> {
+        let (tensor, _) = self.preprocess.run_rgb(crop)?;
+        let input_idx = self.net.get_input_blob_index_by_index(0)?;
+        tensor.set_input(&self.net, input_idx)?;
+        self.net.update()?;
+        let output_idx = self.net.get_output_blob_index_by_index(0)?;
+        Ok(self.net.get_output_blob_by_index::<f32>(output_idx)?)
+    }
+}
+
+impl Deref for FaceEmbedder {
+    type Target = Network;
+    fn deref(&self) -> &Self::Target {
+        &self.net
+    }
+}
+
+/// 照合の結果
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FaceMatch {
+    pub id: String,
+    pub similarity: f32,
+}
+
+/// 登録済みの顔の特徴量、1つのIDに複数の特徴量を登録できる
+///
+/// 全件探索の`VectorIndex`に保存し、ファイルの形式もそれと同じ
+#[derive(Clone, Debug, PartialEq)]
+pub struct FaceGallery {
+    index: VectorIndex,
+}
+
+impl Default for FaceGallery {
+    fn default() -> Self {
+        Self {
+            index: VectorIndex::new(0, IndexKind::Flat),
+        }
+    }
+}
+
+impl FaceGallery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// 登録されているID(重複なし、登録順)
+    pub fn ids(&self) -> Vec<&str> {
+        let mut ids: Vec<&str> = Vec::new();
+        for id in (0..self.len()).filter_map(|i| self.index.id(i)) {
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+        ids
+    }
+
+    /// 特徴量を正規化して登録する、次元は最初に登録したものにそろえる
+    pub fn enroll(&mut self, id: &str, embedding: &[f32]) -> Result<(), FaceError> {
+        if self.index.is_empty() {
+            self.index = VectorIndex::new(embedding.len(), IndexKind::Flat);
+        }
+        self.index.add(id, embedding).map_err(gallery_error)?;
+        Ok(())
+    }
+
+    /// idの特徴量をすべて削除し、削除した数を返す
+    pub fn remove(&mut self, id: &str) -> usize {
+        let len = self.len();
+        self.index.retain(|entry| entry != id);
+        len - self.len()
+    }
+
+    /// IDごとの最大の類似度がthreshold以上のものを類似度の高い順にk件返す
+    pub fn search(
+        &self,
+        embedding: &[f32],
+        k: usize,
+        threshold: f32,
+    ) -> Result<Vec<FaceMatch>, FaceError> {
+        if self.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut matches: Vec<FaceMatch> = Vec::new();
+        // 類似度の高い順なので、IDごとに最初のものが最大
+        for neighbor in self
+            .index
+            .search(embedding, self.len())
+            .map_err(gallery_error)?
+        {
+            if neighbor.similarity < threshold || matches.len() == k {
+                break;
+            }
+            if !matches.iter().any(|m| m.id == neighbor.id) {
+                matches.push(FaceMatch {
+                    id: neighbor.id,
+                    similarity: neighbor.similarity,
+                });
+            }
+        }
+        Ok(matches)
+    }
+
+    /// 最も近いIDを返す、threshold未満の場合はNone
+    pub fn identify(
+        &self,
+        embedding: &[f32],
+        threshold: f32,
+    ) -> Result<Option<FaceMatch>, FaceError> {
+        Ok(self.search(embedding, 1, threshold)?.pop())
+    }
+
+    /// `VectorIndex`の形式で保存する
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), FaceError> {
+        self.index.save(path).map_err(gallery_error)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, FaceError> {
+        let index = VectorIndex::load(path).map_err(gallery_error)?;
+        if index.kind() != IndexKind::Flat {
+            return Err(FaceError::InvalidGallery("index kind".to_string()));
+        }
+        Ok(Self { index })
+    }
+}
+
+fn gallery_error(err: EmbeddingError) -> FaceError {
+    match err {
+        EmbeddingError::DimensionMismatch(len, dim) => FaceError::DimensionMismatch(len, dim),
+        EmbeddingError::Io(err) => FaceError::Io(err),
+        err => FaceError::InvalidGallery(err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pose_estimator::KeyPoint;
+    use image::Rgb;
+
+    fn close(a: (f32, f32), b: (f32, f32)) -> bool {
+        (a.0 - b.0).abs() < 1e-3 && (a.1 - b.1).abs() < 1e-3
+    }
+
+    #[test]
+    fn similarity() {
+        let expected = Similarity {
+            a: 0.5,
+            b: 0.25,
+            tx: 10.,
+            ty: -3.,
+        };
+        let src = [(0., 0.), (10., 0.), (3., 7.), (-4., 2.)];
+        let dst = src.map(|(x, y)| expected.apply(x, y));
+        let estimated = Similarity::estimate(&src, &dst).unwrap();
+        for (s, d) in src.iter().zip(&dst) {
+            assert!(close(estimated.apply(s.0, s.1), *d));
+            assert!(close(estimated.inverse().apply(d.0, d.1), *s));
+        }
+        assert_eq!(Similarity::estimate(&[(1., 1.); 3], &dst[..3]), None);
+    }
+
+    #[test]
+    fn align_and_landmarks() {
+        // テンプレートを2倍、(20, 10)だけずらした位置に顔がある
+        let landmarks = ARCFACE_TEMPLATE.map(|(x, y)| (x * 2. + 20., y * 2. + 10.));
+        let image = RgbImage::from_fn(300, 300, |x, y| {
+            Rgb([if x < 132 { 255 } else { 0 }, (y / 2) as u8, 0])
+        });
+        let crop = align(&image, &landmarks, 112).unwrap();
+        assert_eq!(crop.dimensions(), (112, 112));
+        // 元画像のx=132は切り出し画像のx=56
+        assert_eq!(crop.get_pixel(50, 0)[0], 255);
+        assert_eq!(crop.get_pixel(60, 0)[0], 0);
+
+        let mut face = Face {
+            points: [KeyPoint::default(); 68],
+            total_score: 1.,
+        };
+        for (idx, point) in face.points.iter_mut().enumerate() {
+            point.x = idx as f32 / 100.;
+            point.y = 0.5;
+        }
+        let points = face_landmarks5(&face, 100, 200);
+        assert!(close(points[0], (38.5, 100.)));
+        assert!(close(points[1], (44.5, 100.)));
+        assert!(close(points[2], (30., 100.)));
+        assert!(close(points[4], (54., 100.)));
+    }
+
+    #[test]
+    fn gallery() {
+        let mut gallery = FaceGallery::new();
+        gallery.enroll("alice", &[1., 0., 0.]).unwrap();
+        gallery.enroll("alice", &[0.8, 0.6, 0.]).unwrap();
+        gallery.enroll("bob", &[0., 2., 0.]).unwrap();
+        gallery.enroll("carol", &[0., 0., 1.]).unwrap();
+        assert!(matches!(
+            gallery.enroll("dave", &[1., 0.]),
+            Err(FaceError::DimensionMismatch(2, 3))
+        ));
+        assert_eq!(gallery.ids(), vec!["alice", "bob", "carol"]);
+
+        let matches = gallery.search(&[0.6, 0.8, 0.], 2, 0.).unwrap();
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].id, "alice");
+        assert!((matches[0].similarity - 0.96).abs() < 1e-6);
+        assert_eq!(matches[1].id, "bob");
+
+        assert_eq!(
+            gallery.identify(&[0., 0., 3.], 0.5).unwrap().unwrap().id,
+            "carol"
+        );
+        assert_eq!(gallery.identify(&[1., 1., 1.], 0.9).unwrap(), None);
+
+        let path =
+            std::env::temp_dir().join(format!("ailia_face_gallery_{}.bin", std::process::id()));
+        gallery.save(&path).unwrap();
+        let loaded = FaceGallery::load(&path).unwrap();
+        assert_eq!(loaded, gallery);
+        std::fs::write(&path, b"XXXX").unwrap();
+        assert!(matches!(
+            FaceGallery::load(&path),
+            Err(FaceError::InvalidGallery(_))
+        ));
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(gallery.remove("alice"), 2);
+        assert_eq!(gallery.len(), 2);
+    }
+}
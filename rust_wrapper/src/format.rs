@@ -0,0 +1,421 @@
+//! 画像形式の定数のenumとailiaFormatConvertによる画像からテンソルへの変換
+//!
+//! Classifier/Detectorが内部で行うのと同じ前処理をNetworkを直接使う場合にも行える。
+
+use ailia_sys::*;
+
+use crate::preprocess::{IMAGENET_MEAN, IMAGENET_STD};
+use crate::video::ImageView;
+use crate::AiliaError;
+
+/// predictやcomputeに渡す画像の形式 (AILIA_IMAGE_FORMAT_*)、どれも1画素8bit x チャンネル数
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ImageFormat {
+    #[default]
+    Rgba,
+    Bgra,
+    Rgb,
+    Bgr,
+    /// 下の行から順に並んだRGBA
+    RgbaB2t,
+    /// 下の行から順に並んだBGRA
+    BgraB2t,
+}
+
+impl ImageFormat {
+    pub fn channels(&self) -> u32 {
+        match self {
+            ImageFormat::Rgb | ImageFormat::Bgr => 3,
+            _ => 4,
+        }
+    }
+}
+
+crate::impl_ailia_enum!(ImageFormat {
+    Rgba => AILIA_IMAGE_FORMAT_RGBA,
+    Bgra => AILIA_IMAGE_FORMAT_BGRA,
+    Rgb => AILIA_IMAGE_FORMAT_RGB,
+    Bgr => AILIA_IMAGE_FORMAT_BGR,
+    RgbaB2t => AILIA_IMAGE_FORMAT_RGBA_B2T,
+    BgraB2t => AILIA_IMAGE_FORMAT_BGRA_B2T,
+});
+
+/// ネットワークに入力する画像の形式 (AILIA_NETWORK_IMAGE_FORMAT_*)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum NetworkImageFormat {
+    Bgr,
+    #[default]
+    Rgb,
+    Gray,
+    /// ヒストグラム平坦化したグレースケール
+    GrayEqualize,
+}
+
+impl NetworkImageFormat {
+    pub fn channels(&self) -> u32 {
+        match self {
+            NetworkImageFormat::Bgr | NetworkImageFormat::Rgb => 3,
+            NetworkImageFormat::Gray | NetworkImageFormat::GrayEqualize => 1,
+        }
+    }
+}
+
+crate::impl_ailia_enum!(NetworkImageFormat {
+    Bgr => AILIA_NETWORK_IMAGE_FORMAT_BGR,
+    Rgb => AILIA_NETWORK_IMAGE_FORMAT_RGB,
+    Gray => AILIA_NETWORK_IMAGE_FORMAT_GRAY,
+    GrayEqualize => AILIA_NETWORK_IMAGE_FORMAT_GRAY_EQUALIZE,
+});
+
+/// チャンネルの配置 (AILIA_NETWORK_IMAGE_CHANNEL_*)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ChannelOrder {
+    /// (C, H, W)
+    #[default]
+    First,
+    /// (H, W, C)
+    Last,
+}
+
+crate::impl_ailia_enum!(ChannelOrder {
+    First => AILIA_NETWORK_IMAGE_CHANNEL_FIRST,
+    Last => AILIA_NETWORK_IMAGE_CHANNEL_LAST,
+});
+
+/// 画素値の範囲 (AILIA_NETWORK_IMAGE_RANGE_*)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ImageRange {
+    /// 0~255
+    #[default]
+    UnsignedInt8,
+    /// -128~127
+    SignedInt8,
+    /// 0~1
+    UnsignedFp32,
+    /// -1~1
+    SignedFp32,
+    /// ImageNetの平均と標準偏差で正規化する
+    ImageNet,
+}
+
+crate::impl_ailia_enum!(ImageRange {
+    UnsignedInt8 => AILIA_NETWORK_IMAGE_RANGE_UNSIGNED_INT8,
+    SignedInt8 => AILIA_NETWORK_IMAGE_RANGE_SIGNED_INT8,
+    UnsignedFp32 => AILIA_NETWORK_IMAGE_RANGE_UNSIGNED_FP32,
+    SignedFp32 => AILIA_NETWORK_IMAGE_RANGE_SIGNED_FP32,
+    ImageNet => AILIA_NETWORK_IMAGE_RANGE_IMAGENET,
+});
+
+impl ImageRange {
+    /// colorはRGBのどのチャンネルか、グレースケールの場合はNone
+    fn apply(&self, value: f32, color: Option<usize>) -> f32 {
+        match self {
+            ImageRange::UnsignedInt8 => value,
+            ImageRange::SignedInt8 => value - 128.,
+            ImageRange::UnsignedFp32 => value / 255.,
+            ImageRange::SignedFp32 => value / 127.5 - 1.,
+            ImageRange::ImageNet => {
+                // グレースケールの場合は3チャンネルの平均を使う
+                let (mean, std) = match color {
+                    Some(color) => (IMAGENET_MEAN[color], IMAGENET_STD[color]),
+                    None => (
+                        IMAGENET_MEAN.iter().sum::<f32>() / 3.,
+                        IMAGENET_STD.iter().sum::<f32>() / 3.,
+                    ),
+                };
+                (value / 255. - mean) / std
+            }
+        }
+    }
+
+    /// applyの逆で0~1の値に戻す、範囲外はそのまま
+    pub(crate) fn invert(&self, value: f32, color: Option<usize>) -> f32 {
+        match self {
+            ImageRange::UnsignedInt8 => value / 255.,
+            ImageRange::SignedInt8 => (value + 128.) / 255.,
+            ImageRange::UnsignedFp32 => value,
+            ImageRange::SignedFp32 => (value + 1.) / 2.,
+            ImageRange::ImageNet => {
+                let (mean, std) = match color {
+                    Some(color) => (IMAGENET_MEAN[color], IMAGENET_STD[color]),
+                    None => (
+                        IMAGENET_MEAN.iter().sum::<f32>() / 3.,
+                        IMAGENET_STD.iter().sum::<f32>() / 3.,
+                    ),
+                };
+                value * std + mean
+            }
+        }
+    }
+}
+
+/// 入力と出力のサイズを確認して出力の要素数を返す
+fn output_len(
+    image: &ImageView,
+    dst_width: u32,
+    dst_height: u32,
+    dst_format: NetworkImageFormat,
+) -> Result<usize, AiliaError> {
+    let expected = (image.stride() as usize).checked_mul(image.height as usize);
+    if image.width == 0 || image.height == 0 || expected != Some(image.data.len()) {
+        return Err(AiliaError::AiliaStausInvaildArgument);
+    }
+    if dst_width == 0 || dst_height == 0 {
+        return Err(AiliaError::AiliaStausInvaildArgument);
+    }
+    (dst_width as usize)
+        .checked_mul(dst_height as usize)
+        .and_then(|len| len.checked_mul(dst_format.channels() as usize))
+        .filter(|&len| u32::try_from(len).is_ok())
+        .ok_or(AiliaError::AiliaStausInvaildArgument)
+}
+
+/// 画像をdst_width x dst_heightにリサイズしてf32のテンソルに変換する
+/// 結果はNetwork::set_input_data_blobにそのまま渡せる
+pub fn format_convert(
+    image: &ImageView,
+    dst_width: u32,
+    dst_height: u32,
+    dst_format: NetworkImageFormat,
+    dst_channel: ChannelOrder,
+    dst_range: ImageRange,
+) -> Result<Vec<f32>, AiliaError> {
+    let len = output_len(image, dst_width, dst_height, dst_format)?;
+    let mut dst = vec![0f32; len];
+    let status = unsafe {
+        ailiaFormatConvert(
+            dst.as_mut_ptr() as *mut _,
+            dst_width,
+            dst_height,
+            dst_format.into(),
+            dst_channel.into(),
+            dst_range.into(),
+            image.as_ptr() as *const _,
+            image.stride() as i32,
+            image.width,
+            image.height,
+            image.format().into(),
+        )
+    };
+    match status {
+        0 => Ok(dst),
+        i => Err(i.into()),
+    }
+}
+
+/// format_convertと同じ変換をRustで行う、結果の比較やailiaを使わない環境向け
+/// リサイズはバイリニアで行うためailiaの結果とは端数が異なる場合がある
+pub fn format_convert_rust(
+    image: &ImageView,
+    dst_width: u32,
+    dst_height: u32,
+    dst_format: NetworkImageFormat,
+    dst_channel: ChannelOrder,
+    dst_range: ImageRange,
+) -> Result<Vec<f32>, AiliaError> {
+    let len = output_len(image, dst_width, dst_height, dst_format)?;
+    let resized = if (image.width, image.height) == (dst_width, dst_height) {
+        image.clone()
+    } else {
+        image
+            .resize(dst_width, dst_height)
+            .map_err(|_| AiliaError::AiliaStausInvaildArgument)?
+    };
+    let plane = (dst_width * dst_height) as usize;
+    let mut dst = vec![0f32; len];
+    match dst_format {
+        NetworkImageFormat::Rgb | NetworkImageFormat::Bgr => {
+            for (idx, pixel) in resized.data.chunks_exact(4).enumerate() {
+                for (color, &value) in pixel[..3].iter().enumerate() {
+                    let channel = match dst_format {
+                        NetworkImageFormat::Bgr => 2 - color,
+                        _ => color,
+                    };
+                    let value = dst_range.apply(value as f32, Some(color));
+                    match dst_channel {
+                        ChannelOrder::First => dst[channel * plane + idx] = value,
+                        ChannelOrder::Last => dst[idx * 3 + channel] = value,
+                    }
+                }
+            }
+        }
+        NetworkImageFormat::Gray | NetworkImageFormat::GrayEqualize => {
+            let mut gray: Vec<u8> = resized
+                .data
+                .chunks_exact(4)
+                .map(|pixel| {
+                    (0.299 * pixel[0] as f32 + 0.587 * pixel[1] as f32 + 0.114 * pixel[2] as f32)
+                        .round() as u8
+                })
+                .collect();
+            if dst_format == NetworkImageFormat::GrayEqualize {
+                equalize(&mut gray);
+            }
+            // 1チャンネルなのでFirstとLastで配置は変わらない
+            for (dst, value) in dst.iter_mut().zip(gray) {
+                *dst = dst_range.apply(value as f32, None);
+            }
+        }
+    }
+    Ok(dst)
+}
+
+/// ヒストグラム平坦化
+fn equalize(gray: &mut [u8]) {
+    let mut cdf = [0usize; 256];
+    for &value in gray.iter() {
+        cdf[value as usize] += 1;
+    }
+    for idx in 1..256 {
+        cdf[idx] += cdf[idx - 1];
+    }
+    let min = cdf.iter().copied().find(|&count| count > 0).unwrap_or(0);
+    let total = gray.len();
+    if total == min {
+        return;
+    }
+    for value in gray.iter_mut() {
+        let count = cdf[*value as usize] - min;
+        *value = (count as f32 * 255. / (total - min) as f32).round() as u8;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn image(pixels: &[[u8; 4]], width: u32) -> ImageView {
+        ImageView {
+            data: pixels.concat(),
+            width,
+            height: pixels.len() as u32 / width,
+        }
+    }
+
+    #[test]
+    fn ailia_constants() {
+        assert_eq!(u32::from(ImageFormat::Bgra), AILIA_IMAGE_FORMAT_BGRA);
+        assert_eq!(
+            u32::from(NetworkImageFormat::Rgb),
+            AILIA_NETWORK_IMAGE_FORMAT_RGB
+        );
+        assert_eq!(
+            ImageRange::try_from(AILIA_NETWORK_IMAGE_RANGE_IMAGENET).unwrap(),
+            ImageRange::ImageNet
+        );
+        assert_eq!(
+            ChannelOrder::try_from(u32::from(ChannelOrder::Last)).unwrap(),
+            ChannelOrder::Last
+        );
+        assert!(NetworkImageFormat::try_from(100).is_err());
+        // 以前は型が同じu32だったため取り違えてもコンパイルできていた
+        assert_ne!(
+            u32::from(ImageFormat::Rgba),
+            u32::from(NetworkImageFormat::Rgb)
+        );
+    }
+
+    #[test]
+    fn rust_layout_and_range() {
+        let src = image(&[[0, 100, 255, 255], [255, 50, 0, 255]], 2);
+        let chw = format_convert_rust(
+            &src,
+            2,
+            1,
+            NetworkImageFormat::Rgb,
+            ChannelOrder::First,
+            ImageRange::UnsignedInt8,
+        )
+        .unwrap();
+        assert_eq!(chw, vec![0., 255., 100., 50., 255., 0.]);
+
+        let hwc = format_convert_rust(
+            &src,
+            2,
+            1,
+            NetworkImageFormat::Bgr,
+            ChannelOrder::Last,
+            ImageRange::SignedFp32,
+        )
+        .unwrap();
+        assert_eq!(
+            hwc,
+            vec![1., 100. / 127.5 - 1., -1., -1., 50. / 127.5 - 1., 1.]
+        );
+
+        let imagenet = format_convert_rust(
+            &src,
+            2,
+            1,
+            NetworkImageFormat::Bgr,
+            ChannelOrder::First,
+            ImageRange::ImageNet,
+        )
+        .unwrap();
+        // 出力の先頭はBのチャンネル
+        assert!((imagenet[0] - (1. - 0.406) / 0.225).abs() < 1e-6);
+        assert!((imagenet[4] + 0.485 / 0.229).abs() < 1e-6);
+    }
+
+    #[test]
+    fn rust_gray() {
+        let src = image(
+            &[
+                [10, 10, 10, 255],
+                [10, 10, 10, 255],
+                [20, 20, 20, 255],
+                [40, 40, 40, 255],
+            ],
+            2,
+        );
+        let gray = format_convert_rust(
+            &src,
+            2,
+            2,
+            NetworkImageFormat::Gray,
+            ChannelOrder::First,
+            ImageRange::SignedInt8,
+        )
+        .unwrap();
+        assert_eq!(gray, vec![-118., -118., -108., -88.]);
+
+        let equalized = format_convert_rust(
+            &src,
+            2,
+            2,
+            NetworkImageFormat::GrayEqualize,
+            ChannelOrder::First,
+            ImageRange::UnsignedInt8,
+        )
+        .unwrap();
+        assert_eq!(equalized, vec![0., 0., 128., 255.]);
+    }
+
+    #[test]
+    fn invalid_size() {
+        let mut src = image(&[[0, 0, 0, 255]; 4], 2);
+        for (width, height) in [(0, 1), (1, 0)] {
+            let err = format_convert(
+                &src,
+                width,
+                height,
+                NetworkImageFormat::Rgb,
+                ChannelOrder::First,
+                ImageRange::UnsignedInt8,
+            )
+            .unwrap_err();
+            assert!(matches!(err, AiliaError::AiliaStausInvaildArgument));
+        }
+        src.data.pop();
+        let err = format_convert_rust(
+            &src,
+            2,
+            2,
+            NetworkImageFormat::Rgb,
+            ChannelOrder::First,
+            ImageRange::UnsignedInt8,
+        )
+        .unwrap_err();
+        assert!(matches!(err, AiliaError::AiliaStausInvaildArgument));
+    }
+}
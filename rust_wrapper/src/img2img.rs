@@ -0,0 +1,380 @@
+//! 画像から画像への変換(超解像、ノイズ除去、カラー化、スタイル変換など)
+//!
+//! 大きな画像は重なりのあるタイルに分けて推論し、重なり部分を線形の重みでなめらかにつなぐ。
+
+use std::fmt::Debug;
+use std::ops::Deref;
+use std::path::Path;
+
+use image::imageops::crop_imm;
+use image::{Rgb, RgbImage};
+
+use thiserror::Error;
+
+use crate::format::ImageRange;
+use crate::network::Network;
+use crate::preprocess::{to_rgb, ColorOrder, Normalize, Preprocess, PreprocessError};
+use crate::video::ImageView;
+use crate::AiliaError;
+
+use ailia_sys::*;
+
+#[derive(Debug, Error)]
+pub enum Img2ImgError {
+    #[error("出力の形状が不正です: {0:?}")]
+    InvalidShape(Vec<u32>),
+    #[error("出力の倍率が一致しません: {0}, {1}")]
+    ScaleMismatch(u32, u32),
+    #[error(transparent)]
+    Preprocess(#[from] PreprocessError),
+    #[error(transparent)]
+    Ailia(#[from] AiliaError),
+}
+
+/// タイルの分け方
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tiling {
+    /// タイルの一辺(入力画像の画素数)
+    pub tile_size: u32,
+    /// となりのタイルとの重なり
+    pub overlap: u32,
+}
+
+impl Default for Tiling {
+    fn default() -> Self {
+        Tiling {
+            tile_size: 256,
+            overlap: 16,
+        }
+    }
+}
+
+impl Tiling {
+    pub fn new(tile_size: u32, overlap: u32) -> Self {
+        Tiling { tile_size, overlap }
+    }
+
+    /// 画像全体を覆うタイルの(x, y, width, height)、最後のタイルは画像の端にそろえる
+    pub fn tiles(&self, width: u32, height: u32) -> Vec<[u32; 4]> {
+        let xs = self.positions(width);
+        let ys = self.positions(height);
+        ys.iter()
+            .flat_map(|&(y, h)| xs.iter().map(move |&(x, w)| [x, y, w, h]))
+            .collect()
+    }
+
+    fn positions(&self, len: u32) -> Vec<(u32, u32)> {
+        let tile = self.tile_size.max(1);
+        if len <= tile {
+            return vec![(0, len)];
+        }
+        let stride = tile.saturating_sub(self.overlap).max(1);
+        let mut positions = Vec::new();
+        let mut pos = 0;
+        while pos + tile < len {
+            positions.push((pos, tile));
+            pos += stride;
+        }
+        positions.push((len - tile, tile));
+        positions
+    }
+}
+
+/// タイルの端からの距離に応じた重み、画像の端に接する辺は重みを下げない
+fn feather(pos: u32, len: u32, at_start: bool, at_end: bool, ramp: f32) -> f32 {
+    if ramp <= 0. {
+        return 1.;
+    }
+    let mut weight = 1f32;
+    if !at_start {
+        weight = weight.min((pos as f32 + 0.5) / ramp);
+    }
+    if !at_end {
+        weight = weight.min((len as f32 - pos as f32 - 0.5) / ramp);
+    }
+    weight.max(f32::EPSILON)
+}
+
+/// タイルごとにinferを呼び、倍率scaleの出力をつなぎ合わせる
+///
+/// inferは(1, C, H, W)のCHWの出力とその形状を返す。Cは1または3。
+fn run_tiles<F>(
+    image: &RgbImage,
+    tiling: Option<Tiling>,
+    multiple_of: u32,
+    scale: Option<u32>,
+    range: ImageRange,
+    mut infer: F,
+) -> Result<RgbImage, Img2ImgError>
+where
+    F: FnMut(&RgbImage) -> Result<(Vec<f32>, Vec<u32>), Img2ImgError>,
+{
+    let (width, height) = image.dimensions();
+    let tiling = tiling.unwrap_or(Tiling {
+        tile_size: width.max(height),
+        overlap: 0,
+    });
+    let multiple_of = multiple_of.max(1);
+    let mut scale = scale;
+    let mut sum: Vec<f32> = Vec::new();
+    let mut weights: Vec<f32> = Vec::new();
+    for [x, y, w, h] in tiling.tiles(width, height) {
+        // 入力サイズの制約に合わせて端の画素をくり返して広げる
+        let padded_width = w.div_ceil(multiple_of) * multiple_of;
+        let padded_height = h.div_ceil(multiple_of) * multiple_of;
+        let tile = crop_imm(image, x, y, w, h).to_image();
+        let tile = if (padded_width, padded_height) == (w, h) {
+            tile
+        } else {
+            RgbImage::from_fn(padded_width, padded_height, |px, py| {
+                *tile.get_pixel(px.min(w - 1), py.min(h - 1))
+            })
+        };
+        let (output, shape) = infer(&tile)?;
+        let (channels, out_height, out_width) = match shape[..] {
+            [1, c, h, w] | [c, h, w] if c == 1 || c == 3 => (c, h, w),
+            _ => return Err(Img2ImgError::InvalidShape(shape)),
+        };
+        if output.len() != (channels * out_height * out_width) as usize
+            || out_width % padded_width != 0
+            || out_width / padded_width != out_height / padded_height
+            || out_height % padded_height != 0
+        {
+            return Err(Img2ImgError::InvalidShape(shape));
+        }
+        let tile_scale = out_width / padded_width;
+        let scale = *scale.get_or_insert(tile_scale);
+        if tile_scale != scale {
+            return Err(Img2ImgError::ScaleMismatch(tile_scale, scale));
+        }
+        let (dst_width, dst_height) = (width * scale, height * scale);
+        if sum.is_empty() {
+            sum = vec![0.; (dst_width * dst_height * 3) as usize];
+            weights = vec![0.; (dst_width * dst_height) as usize];
+        }
+
+        let ramp = (tiling.overlap * scale) as f32;
+        let plane = (out_width * out_height) as usize;
+        let (tile_width, tile_height) = (w * scale, h * scale);
+        for ty in 0..tile_height {
+            let wy = feather(ty, tile_height, y == 0, y + h == height, ramp);
+            for tx in 0..tile_width {
+                let weight = wy * feather(tx, tile_width, x == 0, x + w == width, ramp);
+                let src = (ty * out_width + tx) as usize;
+                let dst = ((y * scale + ty) * dst_width + x * scale + tx) as usize;
+                for c in 0..3 {
+                    let (channel, color) = if channels == 1 {
+                        (0, None)
+                    } else {
+                        (c, Some(c))
+                    };
+                    let value = range.invert(output[channel * plane + src], color);
+                    sum[dst * 3 + c] += value * weight;
+                }
+                weights[dst] += weight;
+            }
+        }
+    }
+    let scale = scale.unwrap_or(1);
+    Ok(RgbImage::from_fn(width * scale, height * scale, |x, y| {
+        let idx = (y * width * scale + x) as usize;
+        let weight = weights[idx].max(f32::EPSILON);
+        Rgb([0, 1, 2].map(|c| (sum[idx * 3 + c] / weight * 255.).round().clamp(0., 255.) as u8))
+    }))
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ImageTransformerBuilder<P>
+where
+    P: AsRef<Path> + Default + Debug,
+{
+    prototxt: Option<P>,
+    onnx: P,
+    env_id: Option<i32>,
+    num_threads: Option<i32>,
+    normalize: Option<Normalize>,
+    color_order: Option<ColorOrder>,
+    output_range: Option<ImageRange>,
+    tiling: Option<Tiling>,
+    multiple_of: Option<u32>,
+    scale: Option<u32>,
+}
+
+impl<P: AsRef<Path> + Default + Debug> ImageTransformerBuilder<P> {
+    crate::impl_option!(prototxt, P);
+    crate::impl_non_option!(onnx, P);
+    crate::impl_option!(env_id, i32);
+    crate::impl_option!(num_threads, i32);
+    // 入力の正規化、既定値は0~1
+    crate::impl_option!(normalize, Normalize);
+    // 入力と出力のチャンネルの順序、既定値はRGB
+    crate::impl_option!(color_order, ColorOrder);
+    // 出力の画素値の範囲、既定値は0~1
+    crate::impl_option!(output_range, ImageRange);
+    // 指定しない場合は画像全体を1回で推論する
+    crate::impl_option!(tiling, Tiling);
+    // タイルの幅と高さをこの倍数に広げる、既定値は1
+    crate::impl_option!(multiple_of, u32);
+    // 出力の倍率、指定しない場合は出力の形状から求める
+    crate::impl_option!(scale, u32);
+
+    pub fn build(self) -> Result<ImageTransformer, AiliaError> {
+        let net = Network::ailia_create(
+            self.env_id.unwrap_or(AILIA_ENVIRONMENT_ID_AUTO),
+            self.num_threads
+                .unwrap_or_else(|| AILIA_MULTITHREAD_AUTO.try_into().unwrap()),
+        )?;
+        net.open_model_files(self.prototxt, self.onnx)?;
+        let color_order = self.color_order.unwrap_or_default();
+        Ok(ImageTransformer {
+            net,
+            preprocess: Preprocess::new()
+                .normalize(self.normalize.unwrap_or(Normalize::Unit))
+                .color_order(color_order),
+            color_order,
+            output_range: self.output_range.unwrap_or(ImageRange::UnsignedFp32),
+            tiling: self.tiling,
+            multiple_of: self.multiple_of.unwrap_or(1),
+            scale: self.scale,
+        })
+    }
+}
+
+/// Networkの1番目の入力に画像を入れ、1番目の出力(CHW)を画像にする
+pub struct ImageTransformer {
+    net: Network,
+    preprocess: Preprocess,
+    color_order: ColorOrder,
+    output_range: ImageRange,
+    tiling: Option<Tiling>,
+    multiple_of: u32,
+    scale: Option<u32>,
+}
+
+impl ImageTransformer {
+    pub fn transform(&self, image: &ImageView) -> Result<RgbImage, Img2ImgError> {
+        self.transform_rgb(&to_rgb(image)?)
+    }
+
+    pub fn transform_rgb(&self, image: &RgbImage) -> Result<RgbImage, Img2ImgError> {
+        run_tiles(
+            image,
+            self.tiling,
+            self.multiple_of,
+            self.scale,
+            self.output_range,
+            |tile| self.infer(tile),
+        )
+    }
+
+    fn infer(&self, tile: &RgbImage) -> Result<(Vec<f32>, Vec<u32>), Img2ImgError> {
+        let (tensor, _) = self.preprocess.run_rgb(tile)?;
+        let input_idx = self.net.get_input_blob_index_by_index(0)?;
+        tensor.set_input(&self.net, input_idx)?;
+        self.net.update()?;
+        let output_idx = self.net.get_output_blob_index_by_index(0)?;
+        let shape = self.net.get_blob_shape_nd(output_idx)?;
+        let mut output = self.net.get_output_blob_by_index::<f32>(output_idx)?;
+        if self.color_order == ColorOrder::Bgr && matches!(shape[..], [1, 3, _, _] | [3, _, _]) {
+            let plane = output.len() / 3;
+            let (blue, rest) = output.split_at_mut(plane);
+            blue.swap_with_slice(&mut rest[plane..]);
+        }
+        Ok((output, shape))
+    }
+}
+
+impl Deref for ImageTransformer {
+    type Target = Network;
+    fn deref(&self) -> &Self::Target {
+        &self.net
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// 最近傍で2倍に拡大する疑似的なモデル
+    fn upscale(tile: &RgbImage) -> Result<(Vec<f32>, Vec<u32>), Img2ImgError> {
+        let (width, height) = (tile.width() * 2, tile.height() * 2);
+        let mut output = Vec::new();
+        for c in 0..3 {
+            for y in 0..height {
+                for x in 0..width {
+                    output.push(tile.get_pixel(x / 2, y / 2)[c] as f32 / 255.);
+                }
+            }
+        }
+        Ok((output, vec![1, 3, height, width]))
+    }
+
+    fn gradient(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| Rgb([x as u8, y as u8, (x + y) as u8]))
+    }
+
+    #[test]
+    fn tiles() {
+        let tiling = Tiling::new(64, 16);
+        assert_eq!(tiling.positions(50), [(0, 50)]);
+        assert_eq!(tiling.positions(100), [(0, 64), (36, 64)]);
+        assert_eq!(tiling.positions(150), [(0, 64), (48, 64), (86, 64)]);
+        assert_eq!(tiling.tiles(100, 50).len(), 2);
+    }
+
+    #[test]
+    fn tiled_matches_whole() {
+        let image = gradient(100, 70);
+        let whole = run_tiles(&image, None, 1, None, ImageRange::UnsignedFp32, upscale).unwrap();
+        assert_eq!(whole.dimensions(), (200, 140));
+        assert_eq!(whole.get_pixel(21, 41), &Rgb([10, 20, 30]));
+
+        // 重なりをなめらかにつないでも同じ値になる
+        let tiled = run_tiles(
+            &image,
+            Some(Tiling::new(32, 8)),
+            8,
+            None,
+            ImageRange::UnsignedFp32,
+            upscale,
+        )
+        .unwrap();
+        assert_eq!(tiled, whole);
+    }
+
+    #[test]
+    fn feathered_seams() {
+        // タイルごとに明るさの異なるモデルでも境界は段階的に変化する
+        let image = RgbImage::new(48, 16);
+        let mut count = 0;
+        let output = run_tiles(
+            &image,
+            Some(Tiling::new(32, 16)),
+            1,
+            Some(1),
+            ImageRange::UnsignedInt8,
+            |tile| {
+                count += 1;
+                let value = if count == 1 { 0. } else { 255. };
+                let (w, h) = tile.dimensions();
+                Ok((vec![value; (w * h) as usize], vec![1, 1, h, w]))
+            },
+        )
+        .unwrap();
+        let row = (0..48)
+            .map(|x| output.get_pixel(x, 8)[0])
+            .collect::<Vec<_>>();
+        assert_eq!(row[..16], [0; 16]);
+        assert_eq!(row[32..], [255; 16]);
+        assert!(row[16..32].windows(2).all(|w| w[0] <= w[1]));
+        assert!(row[20] > 0 && row[28] < 255);
+
+        assert!(matches!(
+            run_tiles(&image, None, 1, Some(2), ImageRange::UnsignedFp32, |tile| {
+                let (w, h) = tile.dimensions();
+                Ok((vec![0.; (w * h * 3) as usize], vec![1, 3, h, w]))
+            }),
+            Err(Img2ImgError::ScaleMismatch(1, 2))
+        ));
+    }
+}
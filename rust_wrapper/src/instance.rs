@@ -0,0 +1,827 @@
+//! インスタンスセグメンテーション(YOLOv8-seg, YOLACT)
+//!
+//! 検出ヘッドの矩形とマスク係数をプロトタイプマスクと掛け合わせ、物体ごとのマスクを作る。
+
+use std::fmt::Debug;
+use std::ops::Deref;
+use std::path::Path;
+
+use image::{GrayImage, Luma, RgbImage};
+
+use thiserror::Error;
+
+use crate::detector::Object;
+use crate::network::Network;
+use crate::preprocess::{
+    Align, ImageTensor, Layout, Normalize, Preprocess, PreprocessError, Transform,
+};
+use crate::segment::{trace_polygons, Polygon};
+use crate::video::ImageView;
+use crate::AiliaError;
+
+use ailia_sys::*;
+
+#[derive(Debug, Error)]
+pub enum InstanceError {
+    #[error("出力の形状が不正です: {0:?}")]
+    InvalidShape(Vec<u32>),
+    #[error("出力の数が不正です: {0}")]
+    OutputCount(usize),
+    #[error(transparent)]
+    Preprocess(#[from] PreprocessError),
+    #[error(transparent)]
+    Ailia(#[from] AiliaError),
+}
+
+/// 1画素1ビットの2値マスク
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BitMask {
+    pub width: u32,
+    pub height: u32,
+    bits: Vec<u64>,
+}
+
+impl BitMask {
+    pub fn new(width: u32, height: u32) -> Self {
+        let len = (width as usize * height as usize).div_ceil(64);
+        Self {
+            width,
+            height,
+            bits: vec![0; len],
+        }
+    }
+
+    pub fn from_fn<F>(width: u32, height: u32, f: F) -> Self
+    where
+        F: Fn(u32, u32) -> bool,
+    {
+        let mut mask = Self::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                if f(x, y) {
+                    mask.set(x, y, true);
+                }
+            }
+        }
+        mask
+    }
+
+    fn index(&self, x: u32, y: u32) -> (usize, u64) {
+        let idx = y as usize * self.width as usize + x as usize;
+        (idx / 64, 1 << (idx % 64))
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> bool {
+        let (word, bit) = self.index(x, y);
+        self.bits[word] & bit != 0
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, value: bool) {
+        let (word, bit) = self.index(x, y);
+        if value {
+            self.bits[word] |= bit;
+        } else {
+            self.bits[word] &= !bit;
+        }
+    }
+
+    /// 前景の画素数
+    pub fn area(&self) -> u32 {
+        self.bits.iter().map(|word| word.count_ones()).sum()
+    }
+
+    /// 前景を囲む(x1, y1, x2, y2)、x2とy2は含まない
+    pub fn bbox(&self) -> Option<[u32; 4]> {
+        let mut bbox: Option<[u32; 4]> = None;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if !self.get(x, y) {
+                    continue;
+                }
+                bbox = Some(match bbox {
+                    Some([x1, y1, x2, y2]) => [x1.min(x), y1.min(y), x2.max(x + 1), y2.max(y + 1)],
+                    None => [x, y, x + 1, y + 1],
+                });
+            }
+        }
+        bbox
+    }
+
+    /// 同じサイズのマスクとのIoU
+    pub fn iou(&self, other: &BitMask) -> f32 {
+        assert_eq!(
+            (self.width, self.height),
+            (other.width, other.height),
+            "mask sizes differ"
+        );
+        let (intersection, union) = self
+            .bits
+            .iter()
+            .zip(&other.bits)
+            .fold((0, 0), |(i, u), (a, b)| {
+                (i + (a & b).count_ones(), u + (a | b).count_ones())
+            });
+        if union == 0 {
+            0.
+        } else {
+            intersection as f32 / union as f32
+        }
+    }
+
+    /// COCO形式(列優先、背景から始まる)のランレングス符号
+    pub fn to_rle(&self) -> Rle {
+        let mut counts = Vec::new();
+        let (mut current, mut run) = (false, 0);
+        for x in 0..self.width {
+            for y in 0..self.height {
+                let value = self.get(x, y);
+                if value != current {
+                    counts.push(run);
+                    (current, run) = (value, 0);
+                }
+                run += 1;
+            }
+        }
+        counts.push(run);
+        Rle {
+            size: [self.height, self.width],
+            counts,
+        }
+    }
+
+    /// 画素数を超える分のcountsは無視する
+    pub fn from_rle(rle: &Rle) -> Self {
+        let [height, width] = rle.size;
+        let mut mask = Self::new(width, height);
+        let total = width as usize * height as usize;
+        let mut pos = 0usize;
+        for (i, &count) in rle.counts.iter().enumerate() {
+            let end = pos
+                .checked_add(count as usize)
+                .map_or(total, |end| end.min(total));
+            if i % 2 == 1 {
+                for p in pos..end {
+                    mask.set(
+                        (p / height as usize) as u32,
+                        (p % height as usize) as u32,
+                        true,
+                    );
+                }
+            }
+            pos = end;
+        }
+        mask
+    }
+
+    /// 前景の輪郭、穴はPolygon::holeがtrueになる
+    pub fn contours(&self) -> Vec<Polygon> {
+        trace_polygons(self.width, self.height, |x, y| self.get(x, y))
+    }
+
+    /// 前景を255、背景を0にした画像
+    pub fn to_image(&self) -> GrayImage {
+        GrayImage::from_fn(self.width, self.height, |x, y| {
+            Luma([if self.get(x, y) { 255 } else { 0 }])
+        })
+    }
+}
+
+/// COCOのsegmentationに使われるランレングス符号
+/// sizeは(height, width)
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rle {
+    pub size: [u32; 2],
+    pub counts: Vec<u32>,
+}
+
+impl Rle {
+    /// pycocotoolsの圧縮した文字列表現
+    pub fn to_coco_string(&self) -> String {
+        let mut s = String::new();
+        for (i, &count) in self.counts.iter().enumerate() {
+            let mut x = count as i64;
+            if i > 2 {
+                x -= self.counts[i - 2] as i64;
+            }
+            loop {
+                let mut c = (x & 0x1f) as u8;
+                x >>= 5;
+                let more = if c & 0x10 != 0 { x != -1 } else { x != 0 };
+                if more {
+                    c |= 0x20;
+                }
+                s.push((c + 48) as char);
+                if !more {
+                    break;
+                }
+            }
+        }
+        s
+    }
+
+    /// 圧縮した文字列表現から戻す、不正な文字列の場合はNone
+    pub fn from_coco_string(size: [u32; 2], s: &str) -> Option<Self> {
+        let bytes = s.as_bytes();
+        let mut counts: Vec<u32> = Vec::new();
+        let mut p = 0;
+        while p < bytes.len() {
+            let (mut x, mut k) = (0i64, 0);
+            loop {
+                let c = bytes.get(p)?.checked_sub(48)? as i64;
+                x |= (c & 0x1f) << (5 * k);
+                p += 1;
+                k += 1;
+                // 符号拡張のシフトが64bitを超えないようにする
+                if k >= 13 {
+                    return None;
+                }
+                if c & 0x20 == 0 {
+                    if c & 0x10 != 0 {
+                        x |= -1i64 << (5 * k);
+                    }
+                    break;
+                }
+            }
+            if counts.len() > 2 {
+                x += counts[counts.len() - 2] as i64;
+            }
+            counts.push(u32::try_from(x).ok()?);
+        }
+        Some(Self { size, counts })
+    }
+}
+
+/// プロトタイプマスク、YOLOv8-segは(1, nm, H, W)、YOLACTは(1, H, W, nm)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Prototypes {
+    pub data: Vec<f32>,
+    pub channels: u32,
+    pub width: u32,
+    pub height: u32,
+    pub layout: Layout,
+}
+
+impl Prototypes {
+    pub fn from_output(
+        data: Vec<f32>,
+        shape: &[u32],
+        layout: Layout,
+    ) -> Result<Self, InstanceError> {
+        let dims = match shape {
+            [1, rest @ ..] if rest.len() == 3 => rest,
+            dims => dims,
+        };
+        let (channels, height, width) = match (dims, layout) {
+            ([channels, height, width], Layout::Chw) => (*channels, *height, *width),
+            ([height, width, channels], Layout::Hwc) => (*channels, *height, *width),
+            _ => return Err(InstanceError::InvalidShape(shape.to_vec())),
+        };
+        if data.len() != (channels * height * width) as usize {
+            return Err(InstanceError::InvalidShape(shape.to_vec()));
+        }
+        Ok(Self {
+            data,
+            channels,
+            width,
+            height,
+            layout,
+        })
+    }
+
+    fn at(&self, channel: u32, x: u32, y: u32) -> f32 {
+        let idx = match self.layout {
+            Layout::Chw => (channel * self.height + y) * self.width + x,
+            Layout::Hwc => (y * self.width + x) * self.channels + channel,
+        };
+        self.data[idx as usize]
+    }
+
+    /// 係数との線形結合にsigmoidをかけた、プロトタイプの解像度のマスク
+    pub fn combine(&self, coefficients: &[f32]) -> Vec<f32> {
+        let mut mask = Vec::with_capacity((self.width * self.height) as usize);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let value: f32 = coefficients
+                    .iter()
+                    .enumerate()
+                    .map(|(channel, coefficient)| coefficient * self.at(channel as u32, x, y))
+                    .sum();
+                mask.push(1. / (1. + (-value).exp()));
+            }
+        }
+        mask
+    }
+
+    /// bbox(前処理後の画素座標の(x1, y1, x2, y2))の内側だけを元画像の解像度で2値化する
+    pub fn assemble(
+        &self,
+        coefficients: &[f32],
+        bbox: [f32; 4],
+        transform: &Transform,
+        threshold: f32,
+    ) -> BitMask {
+        let (width, height) = transform.source_size;
+        let mut mask = BitMask::new(width, height);
+        if self.width == 0 || self.height == 0 {
+            return mask;
+        }
+        let probs = self.combine(coefficients);
+        let sample = |x: f32, y: f32| {
+            let x = x.clamp(0., (self.width - 1) as f32);
+            let y = y.clamp(0., (self.height - 1) as f32);
+            let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+            let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+            let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+            let at = |x: u32, y: u32| probs[(y * self.width + x) as usize];
+            (at(x0, y0) * (1. - fx) + at(x1, y0) * fx) * (1. - fy)
+                + (at(x0, y1) * (1. - fx) + at(x1, y1) * fx) * fy
+        };
+        let scale_x = self.width as f32 / transform.size.0 as f32;
+        let scale_y = self.height as f32 / transform.size.1 as f32;
+        let [x1, y1, x2, y2] = transform.to_source_box(bbox);
+        let pixels = |start: f32, end: f32, size: u32| {
+            let start = (start - 0.5).ceil().max(0.) as u32;
+            let end = ((end - 0.5).ceil().max(0.) as u32).min(size);
+            start..end
+        };
+        for y in pixels(y1, y2, height) {
+            let dst_y = (y as f32 + 0.5) * transform.scale.1 + transform.offset.1;
+            for x in pixels(x1, x2, width) {
+                let dst_x = (x as f32 + 0.5) * transform.scale.0 + transform.offset.0;
+                if sample(dst_x * scale_x - 0.5, dst_y * scale_y - 0.5) >= threshold {
+                    mask.set(x, y, true);
+                }
+            }
+        }
+        mask
+    }
+}
+
+/// 物体の矩形、クラス、マスク
+#[derive(Clone, Debug)]
+pub struct Instance {
+    /// 座標は元画像に対する相対座標
+    pub object: Object,
+    pub mask: BitMask,
+}
+
+/// Networkの出力の形式
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstanceHead {
+    /// 出力は(1, 4 + num_classes + nm, N)と(1, nm, H, W)
+    /// 矩形は前処理後の画素座標の(cx, cy, w, h)
+    Yolov8 { num_classes: u32 },
+    /// 出力はloc(1, N, 4)、conf(1, N, C + 1)、mask(1, N, nm)、priors(N, 4)、proto(1, H, W, nm)
+    /// confはsoftmax済みで0番目が背景
+    Yolact,
+}
+
+/// 検出ヘッドの出力から矩形とマスク係数を取り出し、NMSをかけてマスクを作る
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InstanceDecoder {
+    head: InstanceHead,
+    threshold: f32,
+    iou: f32,
+    mask_threshold: f32,
+}
+
+impl Default for InstanceDecoder {
+    fn default() -> Self {
+        Self::new(InstanceHead::Yolov8 { num_classes: 80 })
+    }
+}
+
+/// NMS前の候補、bboxは前処理後の画素座標の(x1, y1, x2, y2)
+struct Candidate {
+    category: u32,
+    prob: f32,
+    bbox: [f32; 4],
+    coefficients: Vec<f32>,
+}
+
+impl InstanceDecoder {
+    pub fn new(head: InstanceHead) -> Self {
+        Self {
+            head,
+            threshold: 0.25,
+            iou: 0.45,
+            mask_threshold: 0.5,
+        }
+    }
+
+    crate::impl_non_option!(threshold, f32);
+    crate::impl_non_option!(iou, f32);
+    crate::impl_non_option!(mask_threshold, f32);
+
+    /// outputsはNetworkの出力の(データ, 形状)を出力の順に並べたもの
+    pub fn decode(
+        &self,
+        outputs: &[(Vec<f32>, Vec<u32>)],
+        transform: &Transform,
+    ) -> Result<Vec<Instance>, InstanceError> {
+        let (candidates, prototypes) = match self.head {
+            InstanceHead::Yolov8 { num_classes } => self.yolov8(outputs, num_classes)?,
+            InstanceHead::Yolact => self.yolact(outputs, transform)?,
+        };
+        let (source_width, source_height) = (
+            transform.source_size.0 as f32,
+            transform.source_size.1 as f32,
+        );
+        Ok(nms(candidates, self.iou)
+            .into_iter()
+            .map(|candidate| {
+                let [x1, y1, x2, y2] = transform.to_source_box(candidate.bbox);
+                let object = Object {
+                    category: candidate.category,
+                    prob: candidate.prob,
+                    x: x1 / source_width,
+                    y: y1 / source_height,
+                    w: (x2 - x1) / source_width,
+                    h: (y2 - y1) / source_height,
+                };
+                let mask = prototypes.assemble(
+                    &candidate.coefficients,
+                    candidate.bbox,
+                    transform,
+                    self.mask_threshold,
+                );
+                Instance { object, mask }
+            })
+            .collect())
+    }
+
+    fn yolov8(
+        &self,
+        outputs: &[(Vec<f32>, Vec<u32>)],
+        num_classes: u32,
+    ) -> Result<(Vec<Candidate>, Prototypes), InstanceError> {
+        let [(boxes, box_shape), (proto, proto_shape)] = outputs else {
+            return Err(InstanceError::OutputCount(outputs.len()));
+        };
+        let prototypes = Prototypes::from_output(proto.clone(), proto_shape, Layout::Chw)?;
+        let rows = 4 + num_classes + prototypes.channels;
+        // (1, rows, N)が標準、(1, N, rows)にも対応する
+        let (count, transposed) = match box_shape.as_slice() {
+            [1, r, n] if *r == rows => (*n, false),
+            [1, n, r] if *r == rows => (*n, true),
+            _ => return Err(InstanceError::InvalidShape(box_shape.clone())),
+        };
+        if boxes.len() != (rows * count) as usize {
+            return Err(InstanceError::InvalidShape(box_shape.clone()));
+        }
+        let at = |row: u32, idx: u32| {
+            let i = if transposed {
+                idx * rows + row
+            } else {
+                row * count + idx
+            };
+            boxes[i as usize]
+        };
+        let mut candidates = Vec::new();
+        for idx in 0..count {
+            let (category, prob) = (0..num_classes)
+                .map(|class| (class, at(4 + class, idx)))
+                .fold((0, f32::NEG_INFINITY), |best, item| {
+                    if item.1 > best.1 {
+                        item
+                    } else {
+                        best
+                    }
+                });
+            if prob < self.threshold {
+                continue;
+            }
+            let (cx, cy, w, h) = (at(0, idx), at(1, idx), at(2, idx), at(3, idx));
+            candidates.push(Candidate {
+                category,
+                prob,
+                bbox: [cx - w / 2., cy - h / 2., cx + w / 2., cy + h / 2.],
+                coefficients: (0..prototypes.channels)
+                    .map(|k| at(4 + num_classes + k, idx))
+                    .collect(),
+            });
+        }
+        Ok((candidates, prototypes))
+    }
+
+    fn yolact(
+        &self,
+        outputs: &[(Vec<f32>, Vec<u32>)],
+        transform: &Transform,
+    ) -> Result<(Vec<Candidate>, Prototypes), InstanceError> {
+        let [(loc, _), (conf, conf_shape), (coefficients, _), (priors, _), (proto, proto_shape)] =
+            outputs
+        else {
+            return Err(InstanceError::OutputCount(outputs.len()));
+        };
+        let prototypes = Prototypes::from_output(proto.clone(), proto_shape, Layout::Hwc)?;
+        let nm = prototypes.channels as usize;
+        let classes = match conf_shape.last() {
+            Some(&classes) if classes > 1 => classes as usize,
+            _ => return Err(InstanceError::InvalidShape(conf_shape.clone())),
+        };
+        let count = conf.len() / classes;
+        if loc.len() != count * 4 || priors.len() != count * 4 || coefficients.len() != count * nm {
+            return Err(InstanceError::InvalidShape(conf_shape.clone()));
+        }
+        let (width, height) = (transform.size.0 as f32, transform.size.1 as f32);
+        let mut candidates = Vec::new();
+        for idx in 0..count {
+            let scores = &conf[idx * classes + 1..(idx + 1) * classes];
+            let (category, prob) =
+                scores
+                    .iter()
+                    .copied()
+                    .enumerate()
+                    .fold((0, f32::NEG_INFINITY), |best, item| {
+                        if item.1 > best.1 {
+                            item
+                        } else {
+                            best
+                        }
+                    });
+            if prob < self.threshold {
+                continue;
+            }
+            // SSDと同じ分散(0.1, 0.2)で符号化されている
+            let (l, p) = (&loc[idx * 4..idx * 4 + 4], &priors[idx * 4..idx * 4 + 4]);
+            let cx = p[0] + l[0] * 0.1 * p[2];
+            let cy = p[1] + l[1] * 0.1 * p[3];
+            let w = p[2] * (l[2] * 0.2).exp();
+            let h = p[3] * (l[3] * 0.2).exp();
+            candidates.push(Candidate {
+                category: category as u32,
+                prob,
+                bbox: [
+                    (cx - w / 2.) * width,
+                    (cy - h / 2.) * height,
+                    (cx + w / 2.) * width,
+                    (cy + h / 2.) * height,
+                ],
+                coefficients: coefficients[idx * nm..(idx + 1) * nm].to_vec(),
+            });
+        }
+        Ok((candidates, prototypes))
+    }
+}
+
+fn box_iou(a: &[f32; 4], b: &[f32; 4]) -> f32 {
+    let w = (a[2].min(b[2]) - a[0].max(b[0])).max(0.);
+    let h = (a[3].min(b[3]) - a[1].max(b[1])).max(0.);
+    let intersection = w * h;
+    let union = (a[2] - a[0]) * (a[3] - a[1]) + (b[2] - b[0]) * (b[3] - b[1]) - intersection;
+    if union <= 0. {
+        0.
+    } else {
+        intersection / union
+    }
+}
+
+/// クラスごとのNMS、確率の高い順に返す
+fn nms(mut candidates: Vec<Candidate>, iou: f32) -> Vec<Candidate> {
+    candidates.sort_by(|a, b| b.prob.total_cmp(&a.prob));
+    let mut kept: Vec<Candidate> = Vec::new();
+    for candidate in candidates {
+        if kept
+            .iter()
+            .all(|k| k.category != candidate.category || box_iou(&k.bbox, &candidate.bbox) <= iou)
+        {
+            kept.push(candidate);
+        }
+    }
+    kept
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct InstanceSegmenterBuilder<P>
+where
+    P: AsRef<Path> + Default + Debug,
+{
+    prototxt: Option<P>,
+    onnx: P,
+    env_id: Option<i32>,
+    num_threads: Option<i32>,
+    input_width: u32,
+    input_height: u32,
+    normalize: Option<Normalize>,
+    preprocess: Option<Preprocess>,
+    decoder: Option<InstanceDecoder>,
+}
+
+impl<P: AsRef<Path> + Default + Debug> InstanceSegmenterBuilder<P> {
+    crate::impl_option!(prototxt, P);
+    crate::impl_non_option!(onnx, P);
+    crate::impl_option!(env_id, i32);
+    crate::impl_option!(num_threads, i32);
+    crate::impl_non_option!(input_width, u32);
+    crate::impl_non_option!(input_height, u32);
+    // 既定の前処理(input_width x input_heightへのletterbox)の正規化、既定値はUnit
+    crate::impl_option!(normalize, Normalize);
+    crate::impl_option!(preprocess, Preprocess);
+    crate::impl_option!(decoder, InstanceDecoder);
+
+    pub fn build(self) -> Result<InstanceSegmenter, AiliaError> {
+        let net = Network::ailia_create(
+            self.env_id.unwrap_or(AILIA_ENVIRONMENT_ID_AUTO),
+            self.num_threads
+                .unwrap_or_else(|| AILIA_MULTITHREAD_AUTO.try_into().unwrap()),
+        )?;
+        net.open_model_files(self.prototxt, self.onnx)?;
+        let preprocess = self.preprocess.unwrap_or_else(|| {
+            Preprocess::new()
+                .letterbox(
+                    self.input_width,
+                    self.input_height,
+                    [114, 114, 114],
+                    Align::Center,
+                )
+                .normalize(self.normalize.unwrap_or(Normalize::Unit))
+        });
+        Ok(InstanceSegmenter {
+            net,
+            preprocess,
+            decoder: self.decoder.unwrap_or_default(),
+        })
+    }
+}
+
+/// Networkの1番目の入力に画像を入れ、全ての出力をInstanceDecoderに渡す
+pub struct InstanceSegmenter {
+    net: Network,
+    preprocess: Preprocess,
+    decoder: InstanceDecoder,
+}
+
+impl InstanceSegmenter {
+    pub fn new(net: Network, preprocess: Preprocess, decoder: InstanceDecoder) -> Self {
+        Self {
+            net,
+            preprocess,
+            decoder,
+        }
+    }
+
+    /// マスクは元画像と同じ解像度
+    pub fn segment(&self, image: &ImageView) -> Result<Vec<Instance>, InstanceError> {
+        let (tensor, transform) = self.preprocess.run(image)?;
+        self.infer(tensor, transform)
+    }
+
+    pub fn segment_rgb(&self, image: &RgbImage) -> Result<Vec<Instance>, InstanceError> {
+        let (tensor, transform) = self.preprocess.run_rgb(image)?;
+        self.infer(tensor, transform)
+    }
+
+    fn infer(
+        &self,
+        tensor: ImageTensor,
+        transform: Transform,
+    ) -> Result<Vec<Instance>, InstanceError> {
+        let input_idx = self.net.get_input_blob_index_by_index(0)?;
+        tensor.set_input(&self.net, input_idx)?;
+        self.net.update()?;
+        let mut outputs = Vec::new();
+        for idx in self.net.get_output_indexs()? {
+            let shape = self.net.get_blob_shape_nd(idx)?;
+            outputs.push((self.net.get_output_blob_by_index::<f32>(idx)?, shape));
+        }
+        self.decoder.decode(&outputs, &transform)
+    }
+}
+
+impl Deref for InstanceSegmenter {
+    type Target = Network;
+    fn deref(&self) -> &Self::Target {
+        &self.net
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn identity(width: u32, height: u32) -> Transform {
+        Transform {
+            scale: (1., 1.),
+            offset: (0., 0.),
+            source_size: (width, height),
+            size: (width, height),
+        }
+    }
+
+    /// 4x4のプロトタイプ、0番目は左半分、1番目は上半分が正
+    fn prototypes() -> (Vec<f32>, Vec<u32>) {
+        let mut data = Vec::new();
+        for channel in 0..2 {
+            for y in 0..4 {
+                for x in 0..4 {
+                    let positive = if channel == 0 { x < 2 } else { y < 2 };
+                    data.push(if positive { 10. } else { -10. });
+                }
+            }
+        }
+        (data, vec![1, 2, 4, 4])
+    }
+
+    #[test]
+    fn assemble() {
+        let (data, shape) = prototypes();
+        let prototypes = Prototypes::from_output(data, &shape, Layout::Chw).unwrap();
+        let transform = identity(8, 8);
+
+        let left = prototypes.assemble(&[1., 0.], [0., 0., 8., 8.], &transform, 0.5);
+        assert_eq!(left.area(), 32);
+        assert_eq!(left.bbox(), Some([0, 0, 4, 8]));
+
+        // 左半分と上半分の両方が正の部分
+        let both = prototypes.assemble(&[1., 1.], [0., 0., 8., 8.], &transform, 0.7);
+        assert_eq!(both.bbox(), Some([0, 0, 4, 4]));
+
+        // 矩形の外側は切り捨てる
+        let cropped = prototypes.assemble(&[1., 0.], [0., 0., 8., 2.], &transform, 0.5);
+        assert_eq!(cropped.area(), 8);
+        assert_eq!(
+            cropped.contours()[0].points,
+            vec![(0, 0), (4, 0), (4, 2), (0, 2)]
+        );
+    }
+
+    #[test]
+    fn decode_yolov8() {
+        // 2クラス、マスク係数2、候補3
+        let rows: [[f32; 3]; 8] = [
+            [2., 2.5, 6.],    // cx
+            [4., 4., 4.],     // cy
+            [4., 3., 4.],     // w
+            [8., 8., 8.],     // h
+            [0.9, 0.8, 0.1],  // class 0
+            [0.05, 0.1, 0.2], // class 1
+            [1., 1., 0.],     // 係数0
+            [0., 0., 1.],     // 係数1
+        ];
+        let boxes = rows.iter().flatten().copied().collect();
+        let outputs = vec![(boxes, vec![1, 8, 3]), prototypes()];
+        // 8x4の画像を8x8にletterbox
+        let transform = Transform {
+            scale: (1., 1.),
+            offset: (0., 2.),
+            source_size: (8, 4),
+            size: (8, 8),
+        };
+        let instances = InstanceDecoder::new(InstanceHead::Yolov8 { num_classes: 2 })
+            .decode(&outputs, &transform)
+            .unwrap();
+        assert_eq!(instances.len(), 1);
+        let instance = &instances[0];
+        assert_eq!(instance.object.category, 0);
+        assert_eq!(instance.object.prob, 0.9);
+        assert_eq!(
+            (
+                instance.object.x,
+                instance.object.y,
+                instance.object.w,
+                instance.object.h
+            ),
+            (0., 0., 0.5, 1.)
+        );
+        assert_eq!((instance.mask.width, instance.mask.height), (8, 4));
+        assert_eq!(instance.mask.area(), 16);
+
+        assert!(matches!(
+            InstanceDecoder::default().decode(&outputs, &transform),
+            Err(InstanceError::InvalidShape(_))
+        ));
+    }
+
+    #[test]
+    fn rle() {
+        // 列優先なので(0, 0), (0, 1), (1, 0), (1, 1)の順
+        let mask = BitMask::from_fn(2, 2, |x, y| (x, y) == (1, 0));
+        let rle = mask.to_rle();
+        assert_eq!(rle.size, [2, 2]);
+        assert_eq!(rle.counts, vec![2, 1, 1]);
+        assert_eq!(BitMask::from_rle(&rle), mask);
+
+        let rle = Rle {
+            size: [100, 100],
+            counts: vec![0, 5, 1000, 3, 20, 4000, 7],
+        };
+        let s = rle.to_coco_string();
+        assert_eq!(Rle::from_coco_string(rle.size, &s), Some(rle));
+        assert_eq!(Rle::from_coco_string([1, 1], "\u{1}"), None);
+        assert_eq!(Rle::from_coco_string([1, 1], "PPPPPPPPPPPP@"), None);
+        // 画素数を超えるcountsは切り捨てる
+        let rle = Rle {
+            size: [2, 2],
+            counts: vec![3, u32::MAX, u32::MAX],
+        };
+        assert_eq!(
+            BitMask::from_rle(&rle),
+            BitMask::from_fn(2, 2, |x, y| (x, y) == (1, 1))
+        );
+
+        let other = BitMask::from_fn(2, 2, |x, _| x == 1);
+        assert_eq!(mask.iou(&other), 0.5);
+    }
+}
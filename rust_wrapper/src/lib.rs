@@ -1,10 +1,36 @@
+pub mod anomaly;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod bench;
+mod binary;
 pub mod classifier;
+pub mod clip;
+pub mod depth;
 pub mod detector;
+pub mod detic;
+pub mod embedding;
 pub mod environment;
+#[cfg(feature = "serde")]
+pub mod eval;
+#[cfg(feature = "serde")]
+pub mod export;
+pub mod face;
+pub mod format;
+pub mod img2img;
+pub mod instance;
 mod macros;
+pub mod matting;
 pub mod network;
+pub mod ocr;
 pub mod pose_estimator;
 pub mod prelude;
+pub mod preprocess;
+pub mod render;
+pub mod segment;
+pub mod sink;
+pub mod video;
+#[cfg(feature = "zoo")]
+pub mod zoo;
 
 use thiserror::Error;
 
@@ -12,28 +38,12 @@ pub use ailia_sys::AILIA_ENVIRONMENT_ID_AUTO;
 pub use ailia_sys::AILIA_ENVIRONMENT_VERSION;
 pub use ailia_sys::AILIA_MULTITHREAD_AUTO;
 
-pub use ailia_sys::AILIA_DATATYPE_BFLOAT16;
-pub use ailia_sys::AILIA_DATATYPE_BOOL;
-pub use ailia_sys::AILIA_DATATYPE_DOUBLE;
-pub use ailia_sys::AILIA_DATATYPE_FLOAT;
-pub use ailia_sys::AILIA_DATATYPE_FLOAT16;
-pub use ailia_sys::AILIA_DATATYPE_INT16;
-pub use ailia_sys::AILIA_DATATYPE_INT32;
-pub use ailia_sys::AILIA_DATATYPE_INT64;
-pub use ailia_sys::AILIA_DATATYPE_INT8;
-pub use ailia_sys::AILIA_DATATYPE_UINT16;
-pub use ailia_sys::AILIA_DATATYPE_UINT32;
-pub use ailia_sys::AILIA_DATATYPE_UINT64;
-pub use ailia_sys::AILIA_DATATYPE_UINT8;
-
 pub use ailia_sys::AILIA_SHAPE_VERSION;
 
-pub use ailia_sys::AILIA_IMAGE_FORMAT_BGR;
-pub use ailia_sys::AILIA_IMAGE_FORMAT_BGRA;
-pub use ailia_sys::AILIA_IMAGE_FORMAT_BGRA_B2T;
-pub use ailia_sys::AILIA_IMAGE_FORMAT_RGB;
-pub use ailia_sys::AILIA_IMAGE_FORMAT_RGBA;
-pub use ailia_sys::AILIA_IMAGE_FORMAT_RGBA_B2T;
+pub use crate::detector::{DetectorAlgorithm, DetectorFlags};
+pub use crate::format::{ChannelOrder, ImageFormat, ImageRange, NetworkImageFormat};
+pub use crate::network::DataType;
+pub use crate::pose_estimator::PoseAlgorithm;
 
 // TODO! 説明ちゃんと書く
 #[derive(Clone, Copy, Debug, Error)]
@@ -118,3 +128,9 @@ impl From<i32> for AiliaError {
         }
     }
 }
+
+/// ailia SDKのバージョン
+pub fn get_version() -> String {
+    let version = unsafe { std::ffi::CStr::from_ptr(ailia_sys::ailiaGetVersion()) };
+    version.to_string_lossy().into_owned()
+}
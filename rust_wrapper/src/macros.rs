@@ -37,3 +37,28 @@ macro_rules! impl_option {
         }
     };
 }
+
+/// ailia SDKの定数に対応するenumとu32の相互変換を実装する
+#[macro_export]
+macro_rules! impl_ailia_enum {
+    ($ty:ident { $($variant:ident => $value:path),* $(,)? }) => {
+        impl From<$ty> for u32 {
+            fn from(value: $ty) -> Self {
+                match value {
+                    $($ty::$variant => $value,)*
+                }
+            }
+        }
+
+        impl TryFrom<u32> for $ty {
+            type Error = $crate::AiliaError;
+
+            fn try_from(value: u32) -> Result<Self, Self::Error> {
+                match value {
+                    $(v if v == $value => Ok($ty::$variant),)*
+                    _ => Err($crate::AiliaError::AiliaStausInvaildArgument),
+                }
+            }
+        }
+    };
+}
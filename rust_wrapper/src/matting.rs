@@ -0,0 +1,497 @@
+//! 背景の切り抜き(アルファマット推定、U^2-Net, MODNet, RVMなど)
+//!
+//! 出力のアルファを元画像の解像度に戻し、元画像をガイドにしたガイデッドフィルタで輪郭を整える。
+//! RVMのような動画向けのモデルでは、フレーム間で隠れ状態を引き継ぐ。
+
+use std::fmt::Debug;
+use std::ops::Deref;
+use std::path::Path;
+
+use image::imageops::{self, FilterType};
+use image::{GrayImage, Luma, Rgb, RgbImage, Rgba, RgbaImage};
+
+use thiserror::Error;
+
+use crate::network::Network;
+use crate::preprocess::{
+    resample_to_source, to_rgb, ImageTensor, Normalize, Preprocess, PreprocessError, Transform,
+};
+use crate::video::ImageView;
+use crate::AiliaError;
+
+use ailia_sys::*;
+
+/// RVMの隠れ状態の入力と出力のBlob名
+const RECURRENT_INPUTS: [&str; 4] = ["r1i", "r2i", "r3i", "r4i"];
+const RECURRENT_OUTPUTS: [&str; 4] = ["r1o", "r2o", "r3o", "r4o"];
+
+#[derive(Debug, Error)]
+pub enum MattingError {
+    #[error("出力の形状が不正です: {0:?}")]
+    InvalidShape(Vec<u32>),
+    #[error(transparent)]
+    Preprocess(#[from] PreprocessError),
+    #[error(transparent)]
+    Ailia(#[from] AiliaError),
+}
+
+/// ガイデッドフィルタのパラメータ
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GuidedFilter {
+    /// 窓の半径(画素)
+    pub radius: u32,
+    /// 正則化の係数、小さいほどガイドの輪郭に沿う
+    pub eps: f32,
+}
+
+impl Default for GuidedFilter {
+    fn default() -> Self {
+        GuidedFilter {
+            radius: 8,
+            eps: 1e-3,
+        }
+    }
+}
+
+impl GuidedFilter {
+    /// 輝度(0~1)をガイドにinputを平滑化する
+    pub fn apply(&self, guide: &[f32], input: &[f32], width: u32, height: u32) -> Vec<f32> {
+        let product =
+            |a: &[f32], b: &[f32]| -> Vec<f32> { a.iter().zip(b).map(|(a, b)| a * b).collect() };
+        let mean_i = box_filter(guide, width, height, self.radius);
+        let mean_p = box_filter(input, width, height, self.radius);
+        let corr_ii = box_filter(&product(guide, guide), width, height, self.radius);
+        let corr_ip = box_filter(&product(guide, input), width, height, self.radius);
+        let mut a = Vec::with_capacity(guide.len());
+        let mut b = Vec::with_capacity(guide.len());
+        for i in 0..guide.len() {
+            let var = corr_ii[i] - mean_i[i] * mean_i[i];
+            let cov = corr_ip[i] - mean_i[i] * mean_p[i];
+            let coef = cov / (var + self.eps);
+            a.push(coef);
+            b.push(mean_p[i] - coef * mean_i[i]);
+        }
+        let mean_a = box_filter(&a, width, height, self.radius);
+        let mean_b = box_filter(&b, width, height, self.radius);
+        guide
+            .iter()
+            .zip(mean_a.iter().zip(&mean_b))
+            .map(|(i, (a, b))| a * i + b)
+            .collect()
+    }
+}
+
+/// 積分画像による(2 * radius + 1)四方の平均、端では画像内の画素のみで平均する
+fn box_filter(data: &[f32], width: u32, height: u32, radius: u32) -> Vec<f32> {
+    let (w, h) = (width as usize, height as usize);
+    let mut integral = vec![0f64; (w + 1) * (h + 1)];
+    for y in 0..h {
+        let mut row = 0f64;
+        for x in 0..w {
+            row += data[y * w + x] as f64;
+            integral[(y + 1) * (w + 1) + x + 1] = integral[y * (w + 1) + x + 1] + row;
+        }
+    }
+    let r = radius as usize;
+    let mut output = Vec::with_capacity(w * h);
+    for y in 0..h {
+        let (y0, y1) = (y.saturating_sub(r), (y + r + 1).min(h));
+        for x in 0..w {
+            let (x0, x1) = (x.saturating_sub(r), (x + r + 1).min(w));
+            let sum = integral[y1 * (w + 1) + x1]
+                - integral[y0 * (w + 1) + x1]
+                - integral[y1 * (w + 1) + x0]
+                + integral[y0 * (w + 1) + x0];
+            output.push((sum / ((x1 - x0) * (y1 - y0)) as f64) as f32);
+        }
+    }
+    output
+}
+
+/// 合成する背景
+#[derive(Clone, Debug, PartialEq)]
+pub enum Background {
+    Color([u8; 3]),
+    /// 縦横比を保って画像全体を覆うように拡大し、中央を使う
+    Image(RgbImage),
+    /// 元画像をガウシアンぼかし(sigma)したもの
+    Blur(f32),
+}
+
+/// 0~1のアルファ、1が前景
+#[derive(Clone, Debug, PartialEq)]
+pub struct AlphaMatte {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<f32>,
+}
+
+impl AlphaMatte {
+    /// Networkの出力((1, 1, H, W), (1, H, W), (H, W)のいずれか)から作る
+    pub fn from_output(data: Vec<f32>, shape: &[u32]) -> Result<Self, MattingError> {
+        let mut dims = shape;
+        while dims.len() > 2 && dims[0] == 1 {
+            dims = &dims[1..];
+        }
+        match dims {
+            [height, width] if data.len() == (height * width) as usize => Ok(Self {
+                width: *width,
+                height: *height,
+                data,
+            }),
+            _ => Err(MattingError::InvalidShape(shape.to_vec())),
+        }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> f32 {
+        self.data[(y * self.width + x) as usize]
+    }
+
+    /// 最小値が0、最大値が1になるように伸ばす(U^2-Net)
+    pub fn stretch(&mut self) {
+        let (min, max) = self
+            .data
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), &value| {
+                (min.min(value), max.max(value))
+            });
+        if max > min {
+            self.data
+                .iter_mut()
+                .for_each(|value| *value = (*value - min) / (max - min));
+        }
+    }
+
+    /// 前処理後の画像に対するアルファを、transformを使って元画像の解像度に戻す(バイリニア)
+    pub fn to_source(&self, transform: &Transform) -> AlphaMatte {
+        let (width, height) = transform.source_size;
+        AlphaMatte {
+            width,
+            height,
+            data: resample_to_source(&self.data, self.width, self.height, transform),
+        }
+    }
+
+    /// 同じ解像度の画像をガイドにガイデッドフィルタで整える
+    pub fn refine(&self, guide: &RgbImage, filter: &GuidedFilter) -> AlphaMatte {
+        let luma: Vec<f32> = guide
+            .pixels()
+            .map(|p| (0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32) / 255.)
+            .collect();
+        let data = filter
+            .apply(&luma, &self.data, self.width, self.height)
+            .into_iter()
+            .map(|value| value.clamp(0., 1.))
+            .collect();
+        AlphaMatte {
+            width: self.width,
+            height: self.height,
+            data,
+        }
+    }
+
+    pub fn to_gray_image(&self) -> GrayImage {
+        GrayImage::from_fn(self.width, self.height, |x, y| {
+            Luma([(self.get(x, y).clamp(0., 1.) * 255.).round() as u8])
+        })
+    }
+
+    /// アルファをAチャンネルにした切り抜き画像
+    pub fn cutout(&self, image: &RgbImage) -> RgbaImage {
+        RgbaImage::from_fn(self.width, self.height, |x, y| {
+            let [r, g, b] = image.get_pixel(x, y).0;
+            Rgba([r, g, b, (self.get(x, y).clamp(0., 1.) * 255.).round() as u8])
+        })
+    }
+
+    /// 前景を背景に合成する、imageはアルファと同じ解像度
+    pub fn composite(&self, image: &RgbImage, background: &Background) -> RgbImage {
+        let background = match background {
+            Background::Color(color) => RgbImage::from_pixel(self.width, self.height, Rgb(*color)),
+            Background::Image(background) => {
+                let scale = (self.width as f32 / background.width() as f32)
+                    .max(self.height as f32 / background.height() as f32);
+                let (width, height) = (
+                    ((background.width() as f32 * scale).ceil() as u32).max(self.width),
+                    ((background.height() as f32 * scale).ceil() as u32).max(self.height),
+                );
+                let resized = imageops::resize(background, width, height, FilterType::Triangle);
+                imageops::crop_imm(
+                    &resized,
+                    (width - self.width) / 2,
+                    (height - self.height) / 2,
+                    self.width,
+                    self.height,
+                )
+                .to_image()
+            }
+            Background::Blur(sigma) => imageops::blur(image, *sigma),
+        };
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            let alpha = self.get(x, y).clamp(0., 1.);
+            let (fg, bg) = (image.get_pixel(x, y), background.get_pixel(x, y));
+            Rgb([0, 1, 2]
+                .map(|c| (fg[c] as f32 * alpha + bg[c] as f32 * (1. - alpha)).round() as u8))
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MattingBuilder<P>
+where
+    P: AsRef<Path> + Default + Debug,
+{
+    prototxt: Option<P>,
+    onnx: P,
+    env_id: Option<i32>,
+    num_threads: Option<i32>,
+    input_width: Option<u32>,
+    input_height: Option<u32>,
+    multiple_of: Option<u32>,
+    normalize: Option<Normalize>,
+    recurrent: Option<bool>,
+    downsample_ratio: Option<f32>,
+    stretch: Option<bool>,
+    refine: Option<bool>,
+    guided_filter: Option<GuidedFilter>,
+}
+
+impl<P: AsRef<Path> + Default + Debug> MattingBuilder<P> {
+    crate::impl_option!(prototxt, P);
+    crate::impl_non_option!(onnx, P);
+    crate::impl_option!(env_id, i32);
+    crate::impl_option!(num_threads, i32);
+    // 指定しない場合は元画像のサイズをmultiple_ofの倍数に丸めて入力する
+    crate::impl_option!(input_width, u32);
+    crate::impl_option!(input_height, u32);
+    // 既定値は32
+    crate::impl_option!(multiple_of, u32);
+    // 既定値はUnit、MODNetはSigned、U^2-NetはImageNet
+    crate::impl_option!(normalize, Normalize);
+    // RVMの隠れ状態(r1i~r4i, r1o~r4o)とdownsample_ratioを使う、アルファはphaから読む
+    crate::impl_option!(recurrent, bool);
+    // RVMのdownsample_ratio、既定値は0.25
+    crate::impl_option!(downsample_ratio, f32);
+    // 出力を最小値0、最大値1に伸ばす(U^2-Net)、既定値はfalse
+    crate::impl_option!(stretch, bool);
+    // ガイデッドフィルタで整える、既定値はtrue
+    crate::impl_option!(refine, bool);
+    crate::impl_option!(guided_filter, GuidedFilter);
+
+    pub fn build(self) -> Result<Matting, AiliaError> {
+        let net = Network::ailia_create(
+            self.env_id.unwrap_or(AILIA_ENVIRONMENT_ID_AUTO),
+            self.num_threads
+                .unwrap_or_else(|| AILIA_MULTITHREAD_AUTO.try_into().unwrap()),
+        )?;
+        net.open_model_files(self.prototxt, self.onnx)?;
+        let input_size = match (self.input_width, self.input_height) {
+            (Some(width), Some(height)) => Some((width, height)),
+            (None, None) => None,
+            _ => return Err(AiliaError::AiliaStausInvaildArgument),
+        };
+        let refine = self.refine.unwrap_or(true);
+        Ok(Matting {
+            net,
+            input_size,
+            multiple_of: self.multiple_of.unwrap_or(32).max(1),
+            normalize: self.normalize.unwrap_or(Normalize::Unit),
+            recurrent: self.recurrent.unwrap_or(false),
+            downsample_ratio: self.downsample_ratio.unwrap_or(0.25),
+            stretch: self.stretch.unwrap_or(false),
+            guided_filter: refine.then(|| self.guided_filter.unwrap_or_default()),
+            state: None,
+        })
+    }
+}
+
+/// 前フレームの隠れ状態、解像度が変わったら捨てる
+struct RecurrentState {
+    size: (u32, u32),
+    tensors: Vec<ImageTensor>,
+}
+
+pub struct Matting {
+    net: Network,
+    input_size: Option<(u32, u32)>,
+    multiple_of: u32,
+    normalize: Normalize,
+    recurrent: bool,
+    downsample_ratio: f32,
+    stretch: bool,
+    guided_filter: Option<GuidedFilter>,
+    state: Option<RecurrentState>,
+}
+
+impl Matting {
+    /// 画像のサイズに合わせた前処理
+    pub fn preprocess(&self, width: u32, height: u32) -> Preprocess {
+        let round = |size: u32| (size.div_ceil(self.multiple_of)).max(1) * self.multiple_of;
+        let (input_width, input_height) = self
+            .input_size
+            .unwrap_or_else(|| (round(width), round(height)));
+        Preprocess::new()
+            .resize(input_width, input_height, FilterType::Triangle)
+            .normalize(self.normalize)
+    }
+
+    /// 静止画のアルファを元画像の解像度で返す、隠れ状態は使わない
+    pub fn matte(&self, image: &ImageView) -> Result<AlphaMatte, MattingError> {
+        self.matte_rgb(&to_rgb(image)?)
+    }
+
+    pub fn matte_rgb(&self, image: &RgbImage) -> Result<AlphaMatte, MattingError> {
+        Ok(self.infer(image, None)?.0)
+    }
+
+    /// 動画のフレームのアルファを返す、recurrentの場合は隠れ状態を次のフレームに引き継ぐ
+    pub fn matte_frame(&mut self, image: &ImageView) -> Result<AlphaMatte, MattingError> {
+        self.matte_frame_rgb(&to_rgb(image)?)
+    }
+
+    pub fn matte_frame_rgb(&mut self, image: &RgbImage) -> Result<AlphaMatte, MattingError> {
+        let state = self
+            .state
+            .take()
+            .filter(|state| state.size == image.dimensions());
+        let (alpha, tensors) = self.infer(image, state.as_ref())?;
+        if self.recurrent {
+            self.state = Some(RecurrentState {
+                size: image.dimensions(),
+                tensors,
+            });
+        }
+        Ok(alpha)
+    }
+
+    /// 隠れ状態を捨てる、シーンが切り替わったときに呼ぶ
+    pub fn reset(&mut self) {
+        self.state = None;
+    }
+
+    fn infer(
+        &self,
+        image: &RgbImage,
+        state: Option<&RecurrentState>,
+    ) -> Result<(AlphaMatte, Vec<ImageTensor>), MattingError> {
+        let (tensor, transform) = self
+            .preprocess(image.width(), image.height())
+            .run_rgb(image)?;
+        let mut tensors = Vec::new();
+        let alpha_idx = if self.recurrent {
+            tensor.set_input(&self.net, self.net.find_blob_idx_by_name("src")?)?;
+            for (i, name) in RECURRENT_INPUTS.iter().enumerate() {
+                let idx = self.net.find_blob_idx_by_name(name)?;
+                match state {
+                    Some(state) => self.net.set_input_tensor(
+                        &state.tensors[i].data,
+                        &state.tensors[i].shape,
+                        idx,
+                    )?,
+                    // 最初のフレームは0の状態から始める
+                    None => self.net.set_input_tensor(&[0f32], &[1, 1, 1, 1], idx)?,
+                }
+            }
+            let ratio_idx = self.net.find_blob_idx_by_name("downsample_ratio")?;
+            self.net
+                .set_input_tensor(&[self.downsample_ratio], &[1], ratio_idx)?;
+            self.net.update()?;
+            for name in RECURRENT_OUTPUTS {
+                let idx = self.net.find_blob_idx_by_name(name)?;
+                tensors.push(ImageTensor {
+                    shape: self.net.get_blob_shape_nd(idx)?,
+                    data: self.net.get_output_blob_by_index::<f32>(idx)?,
+                });
+            }
+            self.net.find_blob_idx_by_name("pha")?
+        } else {
+            tensor.set_input(&self.net, self.net.get_input_blob_index_by_index(0)?)?;
+            self.net.update()?;
+            self.net.get_output_blob_index_by_index(0)?
+        };
+        let shape = self.net.get_blob_shape_nd(alpha_idx)?;
+        let output = self.net.get_output_blob_by_index::<f32>(alpha_idx)?;
+        let mut alpha = AlphaMatte::from_output(output, &shape)?;
+        if self.stretch {
+            alpha.stretch();
+        }
+        let mut alpha = alpha.to_source(&transform);
+        if let Some(filter) = &self.guided_filter {
+            alpha = alpha.refine(image, filter);
+        }
+        Ok((alpha, tensors))
+    }
+}
+
+impl Deref for Matting {
+    type Target = Network;
+    fn deref(&self) -> &Self::Target {
+        &self.net
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn box_filter_mean() {
+        let data = [1., 2., 3., 4., 5., 6., 7., 8., 9.];
+        let mean = box_filter(&data, 3, 3, 1);
+        assert_eq!(mean[4], 5.);
+        assert_eq!(mean[0], 3.);
+        assert_eq!(mean[8], 7.);
+    }
+
+    #[test]
+    fn refine_follows_edges() {
+        // 左半分が黒、右半分が白の画像に、境界がずれたアルファを合わせる
+        let image = RgbImage::from_fn(16, 8, |x, _| {
+            if x < 8 {
+                Rgb([0, 0, 0])
+            } else {
+                Rgb([255, 255, 255])
+            }
+        });
+        let alpha = AlphaMatte {
+            width: 16,
+            height: 8,
+            data: (0..128)
+                .map(|i| ((i % 16) as f32 / 15.).clamp(0., 1.))
+                .collect(),
+        };
+        let refined = alpha.refine(
+            &image,
+            &GuidedFilter {
+                radius: 3,
+                eps: 1e-4,
+            },
+        );
+        // 同じ色の領域内ではほぼ一定になり、境界で大きく変わる
+        assert!((refined.get(4, 4) - refined.get(5, 4)).abs() < 0.05);
+        assert!((refined.get(10, 4) - refined.get(11, 4)).abs() < 0.05);
+        let step = alpha.get(8, 4) - alpha.get(7, 4);
+        assert!(refined.get(8, 4) - refined.get(7, 4) > step * 3.);
+    }
+
+    #[test]
+    fn composite() {
+        let image = RgbImage::from_pixel(4, 2, Rgb([200, 100, 0]));
+        let alpha =
+            AlphaMatte::from_output(vec![1., 0.5, 0., 0., 1., 1., 0., 0.], &[1, 1, 2, 4]).unwrap();
+        let output = alpha.composite(&image, &Background::Color([0, 0, 100]));
+        assert_eq!(output.get_pixel(0, 0), &Rgb([200, 100, 0]));
+        assert_eq!(output.get_pixel(1, 0), &Rgb([100, 50, 50]));
+        assert_eq!(output.get_pixel(2, 0), &Rgb([0, 0, 100]));
+
+        // 背景画像は縦横比を保って覆う
+        let background = RgbImage::from_fn(2, 2, |x, _| Rgb([x as u8 * 255, 0, 0]));
+        let output = alpha.composite(&image, &Background::Image(background));
+        assert_eq!(output.dimensions(), (4, 2));
+        assert_eq!(output.get_pixel(3, 0), &Rgb([255, 0, 0]));
+
+        let cutout = alpha.cutout(&image);
+        assert_eq!(cutout.get_pixel(1, 0), &Rgba([200, 100, 0, 128]));
+        assert!(AlphaMatte::from_output(vec![0.; 3], &[1, 1, 2, 2]).is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use std::ffi::{CStr, CString};
 use std::mem::MaybeUninit;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::ptr::NonNull;
 
 use num_traits::Num;
@@ -13,6 +13,56 @@ pub struct Network {
     inner: NonNull<AILIANetwork>,
 }
 
+/// Blobのデータ型 (AILIA_DATATYPE_*)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DataType {
+    Undefined,
+    #[default]
+    Float,
+    Uint8,
+    Int8,
+    Uint16,
+    Int16,
+    Int32,
+    Int64,
+    Bool,
+    Float16,
+    Double,
+    Uint32,
+    Uint64,
+    Bfloat16,
+}
+
+impl DataType {
+    /// 1要素のバイト数、Undefinedは0
+    pub fn size(&self) -> usize {
+        match self {
+            DataType::Undefined => 0,
+            DataType::Uint8 | DataType::Int8 | DataType::Bool => 1,
+            DataType::Uint16 | DataType::Int16 | DataType::Float16 | DataType::Bfloat16 => 2,
+            DataType::Float | DataType::Int32 | DataType::Uint32 => 4,
+            DataType::Int64 | DataType::Uint64 | DataType::Double => 8,
+        }
+    }
+}
+
+crate::impl_ailia_enum!(DataType {
+    Undefined => AILIA_DATATYPE_UNDEFINED,
+    Float => AILIA_DATATYPE_FLOAT,
+    Uint8 => AILIA_DATATYPE_UINT8,
+    Int8 => AILIA_DATATYPE_INT8,
+    Uint16 => AILIA_DATATYPE_UINT16,
+    Int16 => AILIA_DATATYPE_INT16,
+    Int32 => AILIA_DATATYPE_INT32,
+    Int64 => AILIA_DATATYPE_INT64,
+    Bool => AILIA_DATATYPE_BOOL,
+    Float16 => AILIA_DATATYPE_FLOAT16,
+    Double => AILIA_DATATYPE_DOUBLE,
+    Uint32 => AILIA_DATATYPE_UINT32,
+    Uint64 => AILIA_DATATYPE_UINT64,
+    Bfloat16 => AILIA_DATATYPE_BFLOAT16,
+});
+
 #[derive(Clone, Copy, Debug)]
 pub struct Shape {
     pub x: u32,
@@ -76,6 +126,13 @@ impl Network {
         }
     }
 
+    /// AILIA_MEMORY_XXXの論理和、ailia_createの直後(モデルを開く前)に呼ぶ
+    ///
+    /// 中間のBlobを読む場合はAILIA_MEMORY_NO_OPTIMIZATIONにする。
+    pub fn set_memory_mode(&self, mode: u32) -> Result<(), AiliaError> {
+        crate::invoke_ailia_fn_result!(ailiaSetMemoryMode, self.as_ptr(), mode);
+    }
+
     pub fn open_stream_file_a<P: AsRef<Path>>(&self, prototxt_path: P) -> Result<(), AiliaError> {
         let path_string = prototxt_path.as_ref().to_str().unwrap().to_string();
         let path_cstring = CString::new(path_string).unwrap();
@@ -108,6 +165,34 @@ impl Network {
         Ok(model)
     }
 
+    /// onnxのみのパスでネットワークを作成する
+    /// 隣に`.onnx.prototxt`がある場合はそちらをstreamとして使用する
+    pub fn from_onnx<P: AsRef<Path>>(
+        env_id: i32,
+        num_threads: i32,
+        model_path: P,
+    ) -> Result<Self, AiliaError> {
+        let model = Network::ailia_create(env_id, num_threads)?;
+        model.open_model_files(None::<&Path>, model_path)?;
+        Ok(model)
+    }
+
+    /// prototxtが指定されていない場合は`find_prototxt`で探し、
+    /// 見つからなければonnxをそのままstreamとして開く
+    pub fn open_model_files<S: AsRef<Path>, W: AsRef<Path>>(
+        &self,
+        prototxt_path: Option<S>,
+        model_path: W,
+    ) -> Result<(), AiliaError> {
+        let model_path = model_path.as_ref();
+        let stream_path = match prototxt_path {
+            Some(path) => path.as_ref().to_path_buf(),
+            None => find_prototxt(model_path).unwrap_or_else(|| model_path.to_path_buf()),
+        };
+        self.open_stream_file_a(stream_path)?;
+        self.open_weight_file_a(model_path)
+    }
+
     pub fn as_ptr(&self) -> *mut AILIANetwork {
         self.inner.as_ptr()
     }
@@ -194,6 +279,73 @@ impl Network {
         }
     }
 
+    pub fn get_blob_name(&self, idx: u32) -> Result<String, AiliaError> {
+        let mut len = 0;
+        match unsafe { ailiaGetBlobNameLengthByIndex(self.as_ptr(), idx, &mut len as *mut _) } {
+            0 => {}
+            i => return Err(i.into()),
+        }
+        let mut buffer = vec![0u8; len as usize];
+        match unsafe {
+            ailiaFindBlobNameByIndex(self.as_ptr(), buffer.as_mut_ptr() as *mut _, len, idx)
+        } {
+            0 => Ok(CStr::from_bytes_until_nul(&buffer)
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()),
+            i => Err(i.into()),
+        }
+    }
+
+    /// 各Blobの名前と形状の一覧
+    pub fn summary(&self) -> Result<String, AiliaError> {
+        let mut len = 0;
+        match unsafe { ailiaGetSummaryLength(self.as_ptr(), &mut len as *mut _) } {
+            0 => {}
+            i => return Err(i.into()),
+        }
+        let mut buffer = vec![0u8; len as usize];
+        match unsafe { ailiaSummary(self.as_ptr(), buffer.as_mut_ptr() as *mut _, len) } {
+            0 => Ok(CStr::from_bytes_until_nul(&buffer)
+                .map(|summary| summary.to_string_lossy().into_owned())
+                .unwrap_or_default()),
+            i => Err(i.into()),
+        }
+    }
+
+    /// Blobのデータ型、SDKが未知の型を返した場合はUndefined
+    pub fn get_blob_data_type(&self, idx: u32) -> Result<DataType, AiliaError> {
+        let mut data_type = 0;
+        match unsafe { ailiaGetBlobDataType(self.as_ptr(), &mut data_type as *mut _, idx) } {
+            0 => Ok(DataType::try_from(data_type as u32).unwrap_or(DataType::Undefined)),
+            i => Err(i.into()),
+        }
+    }
+
+    /// numpyと同じ順序の形状、5次元以上でも取得できる
+    pub fn get_blob_shape_nd(&self, idx: u32) -> Result<Vec<u32>, AiliaError> {
+        let mut dim = 0;
+        match unsafe { ailiaGetBlobDim(self.as_ptr(), &mut dim as *mut _, idx) } {
+            0 => {}
+            i => return Err(i.into()),
+        }
+        let mut shape = vec![0; dim as usize];
+        match unsafe { ailiaGetBlobShapeND(self.as_ptr(), shape.as_mut_ptr(), dim, idx) } {
+            0 => Ok(shape),
+            i => Err(i.into()),
+        }
+    }
+
+    /// Blobのデータをバイト列のままコピーする、destのサイズはBlobのサイズと一致させる
+    pub fn get_blob_data_raw(&self, idx: u32, dest: &mut [u8]) -> Result<(), AiliaError> {
+        crate::invoke_ailia_fn_result!(
+            ailiaGetBlobData,
+            self.as_ptr(),
+            dest.as_mut_ptr() as *mut std::os::raw::c_void,
+            dest.len() as u32,
+            idx
+        );
+    }
+
     pub fn get_input_indexs(&self) -> Result<Vec<u32>, AiliaError> {
         let count = self.get_input_blob_count()?;
         let mut indexes = Vec::with_capacity(count.try_into().unwrap());
@@ -213,7 +365,13 @@ impl Network {
     }
 
     pub fn set_input_blob_shape_nd(&self, shape_v: Vec<u32>, idx: u32) -> Result<(), AiliaError> {
-        crate::invoke_ailia_fn_result!(ailiaSetInputBlobShapeND, self.as_ptr(), shape_v.as_ptr(), shape_v.len() as u32, idx);
+        crate::invoke_ailia_fn_result!(
+            ailiaSetInputBlobShapeND,
+            self.as_ptr(),
+            shape_v.as_ptr(),
+            shape_v.len() as u32,
+            idx
+        );
     }
 
     pub fn set_input_blob_shape(&self, shape: Shape, idx: u32) -> Result<(), AiliaError> {
@@ -242,6 +400,23 @@ impl Network {
         );
     }
 
+    /// 形状を設定してデータを入力する、Tのサイズは入力Blobのデータ型(トークンIDならi64)に合わせる
+    pub fn set_input_tensor<T: Copy>(
+        &self,
+        data: &[T],
+        shape: &[u32],
+        idx: u32,
+    ) -> Result<(), AiliaError> {
+        let data_type = self.get_blob_data_type(idx)?;
+        if shape.iter().product::<u32>() as usize != data.len()
+            || data_type.size() != std::mem::size_of::<T>()
+        {
+            return Err(AiliaError::AiliaStausInvaildArgument);
+        }
+        self.set_input_blob_shape_nd(shape.to_vec(), idx)?;
+        self.set_input_data_blob(data.as_ptr(), data.len() as u32, idx)
+    }
+
     pub fn get_output_blob_by_index<T: Num>(&self, idx: u32) -> Result<Vec<T>, AiliaError> {
         let shape = self.get_blob_shape(idx)?;
         let num_elms = shape.num_elms();
@@ -319,6 +494,21 @@ impl Network {
     }
 }
 
+/// onnxファイルの隣にあるprototxtを探す
+/// `yolox_s.opt.onnx`の場合は`yolox_s.opt.onnx.prototxt`、`yolox_s.onnx.prototxt`の順で探す
+pub fn find_prototxt<P: AsRef<Path>>(model_path: P) -> Option<PathBuf> {
+    let model_path = model_path.as_ref();
+    let file_name = model_path.file_name()?.to_str()?;
+    let mut candidates = vec![format!("{}.prototxt", file_name)];
+    if let Some(stem) = file_name.strip_suffix(".opt.onnx") {
+        candidates.push(format!("{}.onnx.prototxt", stem));
+    }
+    candidates
+        .into_iter()
+        .map(|name| model_path.with_file_name(name))
+        .find(|path| path.is_file())
+}
+
 impl Drop for Network {
     fn drop(&mut self) {
         unsafe { ailiaDestroy(self.inner.as_ptr() as *mut _) };
@@ -332,3 +522,21 @@ fn test() {
         .unwrap();
     net.open_weight_file_a("./yolox_s.opt.onnx").unwrap()
 }
+
+#[test]
+fn t_find_prototxt() {
+    let dir = std::env::temp_dir().join(format!("ailia_find_prototxt_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("yolox_s.onnx.prototxt"), "").unwrap();
+    assert_eq!(find_prototxt(dir.join("resnet18.onnx")), None);
+    assert_eq!(
+        find_prototxt(dir.join("yolox_s.opt.onnx")),
+        Some(dir.join("yolox_s.onnx.prototxt"))
+    );
+    std::fs::write(dir.join("yolox_s.opt.onnx.prototxt"), "").unwrap();
+    assert_eq!(
+        find_prototxt(dir.join("yolox_s.opt.onnx")),
+        Some(dir.join("yolox_s.opt.onnx.prototxt"))
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
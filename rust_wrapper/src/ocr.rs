@@ -0,0 +1,832 @@
+//! 文字領域の検出(DB)と文字認識(CTC)
+//!
+//! ailia-modelsのpaddleocrの後処理(DBPostProcess, CTCLabelDecode)に対応する。
+//! 検出で得た四角形を切り出して水平に補正し、認識モデルで文字列に変換する。
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs::read_to_string;
+use std::io;
+use std::path::Path;
+
+use image::imageops::{rotate270, FilterType};
+use image::RgbImage;
+
+use thiserror::Error;
+
+use crate::network::Network;
+use crate::preprocess::{sample_bilinear, to_rgb, Normalize, Preprocess, PreprocessError};
+use crate::video::ImageView;
+use crate::AiliaError;
+
+use ailia_sys::*;
+
+#[derive(Debug, Error)]
+pub enum OcrError {
+    #[error("出力の形状が不正です: {0:?}")]
+    InvalidShape(Vec<u32>),
+    #[error("出力のクラス数と辞書の文字数が一致しません: {0}, {1}")]
+    DictionaryMismatch(usize, usize),
+    #[error("辞書ファイルを読み込めません: {0}")]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Preprocess(#[from] PreprocessError),
+    #[error(transparent)]
+    Ailia(#[from] AiliaError),
+}
+
+/// 検出された文字領域、polygonは左上から時計回り
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextBox {
+    pub polygon: [(f32, f32); 4],
+    pub score: f32,
+}
+
+/// 認識結果の1行
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TextLine {
+    pub polygon: [(f32, f32); 4],
+    pub text: String,
+    pub confidence: f32,
+}
+
+/// 確率マップを二値化して文字領域の四角形を求める
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DbPostprocess {
+    pub threshold: f32,
+    pub box_threshold: f32,
+    pub unclip_ratio: f32,
+    pub min_size: f32,
+    pub max_candidates: usize,
+}
+
+impl Default for DbPostprocess {
+    fn default() -> Self {
+        DbPostprocess {
+            threshold: 0.3,
+            box_threshold: 0.6,
+            unclip_ratio: 1.5,
+            min_size: 3.,
+            max_candidates: 1000,
+        }
+    }
+}
+
+impl DbPostprocess {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn box_threshold(mut self, box_threshold: f32) -> Self {
+        self.box_threshold = box_threshold;
+        self
+    }
+
+    pub fn unclip_ratio(mut self, unclip_ratio: f32) -> Self {
+        self.unclip_ratio = unclip_ratio;
+        self
+    }
+
+    pub fn min_size(mut self, min_size: f32) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    pub fn max_candidates(mut self, max_candidates: usize) -> Self {
+        self.max_candidates = max_candidates;
+        self
+    }
+
+    /// width x heightの確率マップから四角形を求める、座標は確率マップの画素単位
+    pub fn boxes(&self, prob: &[f32], width: u32, height: u32) -> Vec<TextBox> {
+        let (width, height) = (width as usize, height as usize);
+        let mut labels = vec![0usize; width * height];
+        let mut boxes = Vec::new();
+        let mut next = 0;
+        for start in 0..width * height {
+            if labels[start] != 0 || prob[start] <= self.threshold {
+                continue;
+            }
+            next += 1;
+            if next > self.max_candidates {
+                break;
+            }
+            // 8近傍で連結成分を塗る
+            labels[start] = next;
+            let mut stack = vec![start];
+            let mut pixels = Vec::new();
+            while let Some(idx) = stack.pop() {
+                pixels.push(idx);
+                let (x, y) = ((idx % width) as i64, (idx / width) as i64);
+                for (dx, dy) in NEIGHBORS {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 {
+                        continue;
+                    }
+                    let n = ny as usize * width + nx as usize;
+                    if labels[n] == 0 && prob[n] > self.threshold {
+                        labels[n] = next;
+                        stack.push(n);
+                    }
+                }
+            }
+
+            let score = pixels.iter().map(|&idx| prob[idx]).sum::<f32>() / pixels.len() as f32;
+            if score < self.box_threshold {
+                continue;
+            }
+            let corners = pixels
+                .iter()
+                .flat_map(|&idx| {
+                    let (x, y) = ((idx % width) as f32, (idx / width) as f32);
+                    [(x, y), (x + 1., y), (x + 1., y + 1.), (x, y + 1.)]
+                })
+                .collect::<Vec<_>>();
+            let rect = RotatedRect::min_area(&convex_hull(corners));
+            if rect.width.min(rect.height) < self.min_size {
+                continue;
+            }
+            let distance = rect.area() * self.unclip_ratio / rect.perimeter();
+            let rect = RotatedRect {
+                width: rect.width + 2. * distance,
+                height: rect.height + 2. * distance,
+                ..rect
+            };
+            if rect.width.min(rect.height) < self.min_size + 2. {
+                continue;
+            }
+            let polygon = order_points(rect.corners())
+                .map(|(x, y)| (x.clamp(0., width as f32), y.clamp(0., height as f32)));
+            boxes.push(TextBox { polygon, score });
+        }
+        boxes
+    }
+}
+
+const NEIGHBORS: [(i64, i64); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+/// 回転した長方形、angleはwidth方向の傾き(ラジアン)
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct RotatedRect {
+    center: (f32, f32),
+    width: f32,
+    height: f32,
+    angle: f32,
+}
+
+impl RotatedRect {
+    /// 凸包を囲む面積最小の長方形
+    fn min_area(hull: &[(f32, f32)]) -> Self {
+        let mut best = RotatedRect {
+            center: hull.first().copied().unwrap_or_default(),
+            width: 0.,
+            height: 0.,
+            angle: 0.,
+        };
+        let mut best_area = f32::INFINITY;
+        for i in 0..hull.len() {
+            let (p, q) = (hull[i], hull[(i + 1) % hull.len()]);
+            let angle = (q.1 - p.1).atan2(q.0 - p.0);
+            let (sin, cos) = angle.sin_cos();
+            let (mut min_u, mut max_u, mut min_v, mut max_v) = (
+                f32::INFINITY,
+                f32::NEG_INFINITY,
+                f32::INFINITY,
+                f32::NEG_INFINITY,
+            );
+            for &(x, y) in hull {
+                let (u, v) = (x * cos + y * sin, -x * sin + y * cos);
+                min_u = min_u.min(u);
+                max_u = max_u.max(u);
+                min_v = min_v.min(v);
+                max_v = max_v.max(v);
+            }
+            let area = (max_u - min_u) * (max_v - min_v);
+            if area < best_area {
+                best_area = area;
+                let (u, v) = ((min_u + max_u) / 2., (min_v + max_v) / 2.);
+                best = RotatedRect {
+                    center: (u * cos - v * sin, u * sin + v * cos),
+                    width: max_u - min_u,
+                    height: max_v - min_v,
+                    angle,
+                };
+            }
+        }
+        best
+    }
+
+    fn area(&self) -> f32 {
+        self.width * self.height
+    }
+
+    fn perimeter(&self) -> f32 {
+        2. * (self.width + self.height)
+    }
+
+    fn corners(&self) -> [(f32, f32); 4] {
+        let (sin, cos) = self.angle.sin_cos();
+        let (w, h) = (self.width / 2., self.height / 2.);
+        [(-w, -h), (w, -h), (w, h), (-w, h)].map(|(u, v)| {
+            (
+                self.center.0 + u * cos - v * sin,
+                self.center.1 + u * sin + v * cos,
+            )
+        })
+    }
+}
+
+/// Andrewのmonotone chainで凸包を求める
+fn convex_hull(mut points: Vec<(f32, f32)>) -> Vec<(f32, f32)> {
+    points.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+    let cross = |o: (f32, f32), a: (f32, f32), b: (f32, f32)| {
+        (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+    };
+    let mut hull: Vec<(f32, f32)> = Vec::with_capacity(points.len() * 2);
+    for pass in [points.clone(), points.into_iter().rev().collect()] {
+        let start = hull.len();
+        for p in pass {
+            while hull.len() >= start + 2
+                && cross(hull[hull.len() - 2], hull[hull.len() - 1], p) <= 0.
+            {
+                hull.pop();
+            }
+            hull.push(p);
+        }
+        hull.pop();
+    }
+    hull
+}
+
+/// 左上、右上、右下、左下の順に並べる
+fn order_points(mut points: [(f32, f32); 4]) -> [(f32, f32); 4] {
+    points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    let (left, right) = points.split_at(2);
+    let (top_left, bottom_left) = if left[0].1 <= left[1].1 {
+        (left[0], left[1])
+    } else {
+        (left[1], left[0])
+    };
+    let (top_right, bottom_right) = if right[0].1 <= right[1].1 {
+        (right[0], right[1])
+    } else {
+        (right[1], right[0])
+    };
+    [top_left, top_right, bottom_right, bottom_left]
+}
+
+/// 4点の対応から射影変換(3x3、h33=1)を求める
+fn homography(src: &[(f32, f32); 4], dst: &[(f32, f32); 4]) -> Option<[f64; 9]> {
+    let mut a = [[0f64; 9]; 8];
+    for (i, (&(x, y), &(u, v))) in src.iter().zip(dst).enumerate() {
+        let (x, y, u, v) = (x as f64, y as f64, u as f64, v as f64);
+        a[2 * i] = [x, y, 1., 0., 0., 0., -u * x, -u * y, u];
+        a[2 * i + 1] = [0., 0., 0., x, y, 1., -v * x, -v * y, v];
+    }
+    // 部分ピボット選択付きのガウスの消去法
+    for col in 0..8 {
+        let pivot = (col..8).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        let pivot_row = a[col];
+        for (row, values) in a.iter_mut().enumerate() {
+            if row != col {
+                let factor = values[col] / pivot_row[col];
+                for (value, pivot) in values[col..].iter_mut().zip(&pivot_row[col..]) {
+                    *value -= factor * pivot;
+                }
+            }
+        }
+    }
+    let mut h = [1f64; 9];
+    for (i, row) in a.iter().enumerate() {
+        h[i] = row[8] / row[i];
+    }
+    Some(h)
+}
+
+/// 四角形を水平な長方形に切り出す、縦長の領域は90度回転して横書きにする
+pub fn rectify(image: &RgbImage, polygon: &[(f32, f32); 4]) -> RgbImage {
+    let distance =
+        |a: (f32, f32), b: (f32, f32)| ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt();
+    let [tl, tr, br, bl] = *polygon;
+    let width = distance(tl, tr).max(distance(bl, br)).round().max(1.);
+    let height = distance(tl, bl).max(distance(tr, br)).round().max(1.);
+    let rect = [(0., 0.), (width, 0.), (width, height), (0., height)];
+    let crop = match homography(&rect, polygon) {
+        Some(h) => RgbImage::from_fn(width as u32, height as u32, |x, y| {
+            let (x, y) = (x as f64 + 0.5, y as f64 + 0.5);
+            let w = h[6] * x + h[7] * y + h[8];
+            let sx = (h[0] * x + h[1] * y + h[2]) / w;
+            let sy = (h[3] * x + h[4] * y + h[5]) / w;
+            sample_bilinear(image, sx as f32 - 0.5, sy as f32 - 0.5)
+        }),
+        None => RgbImage::new(width as u32, height as u32),
+    };
+    if height >= width * 1.5 {
+        rotate270(&crop)
+    } else {
+        crop
+    }
+}
+
+/// 上から下、同じ行の中では左から右の順に並べる
+///
+/// 左上の点の高さの差が行の高さの半分未満なら同じ行とみなす。
+pub fn reading_order(lines: &mut [TextLine]) {
+    let line_height = |line: &TextLine| {
+        let [tl, _, _, bl] = line.polygon;
+        ((tl.0 - bl.0).powi(2) + (tl.1 - bl.1).powi(2)).sqrt()
+    };
+    lines.sort_by(|a, b| {
+        let (a, b) = (a.polygon[0], b.polygon[0]);
+        (a.1, a.0).partial_cmp(&(b.1, b.0)).unwrap()
+    });
+    for i in 0..lines.len().saturating_sub(1) {
+        for j in (0..=i).rev() {
+            let (a, b) = (&lines[j], &lines[j + 1]);
+            let tolerance = line_height(a).min(line_height(b)) / 2.;
+            if (b.polygon[0].1 - a.polygon[0].1).abs() < tolerance
+                && b.polygon[0].0 < a.polygon[0].0
+            {
+                lines.swap(j, j + 1);
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+/// 認識モデルのクラスと文字の対応、クラス0はCTCのblank
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CharDictionary {
+    chars: Vec<String>,
+}
+
+impl CharDictionary {
+    /// 1行1文字の辞書ファイル(ppocr_keys_v1.txtなど)を読み込む
+    pub fn load<P: AsRef<Path>>(path: P, use_space: bool) -> Result<Self, OcrError> {
+        let text = read_to_string(path)?;
+        Ok(Self::from_chars(
+            text.lines().map(|line| line.trim_end_matches('\r')),
+            use_space,
+        ))
+    }
+
+    /// use_spaceなら末尾に空白を追加する
+    pub fn from_chars<I, S>(chars: I, use_space: bool) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut chars = chars.into_iter().map(Into::into).collect::<Vec<_>>();
+        if use_space {
+            chars.push(" ".to_string());
+        }
+        CharDictionary { chars }
+    }
+
+    /// blankを含むクラス数
+    pub fn num_classes(&self) -> usize {
+        self.chars.len() + 1
+    }
+
+    pub fn get(&self, class: usize) -> Option<&str> {
+        class
+            .checked_sub(1)
+            .and_then(|idx| self.chars.get(idx))
+            .map(String::as_str)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CtcDecoding {
+    /// 各時刻で最大のクラスを選び、連続する同じクラスとblankを除く
+    #[default]
+    Greedy,
+    /// prefix beam search
+    Beam { width: usize },
+}
+
+impl CtcDecoding {
+    /// steps x classesの確率から文字列と信頼度を求める
+    ///
+    /// 信頼度はGreedyなら採用した文字の確率の平均、Beamなら系列の確率の時刻あたりの幾何平均。
+    pub fn decode(
+        &self,
+        probs: &[f32],
+        steps: usize,
+        dictionary: &CharDictionary,
+    ) -> (String, f32) {
+        let classes = dictionary.num_classes();
+        match *self {
+            CtcDecoding::Greedy => ctc_greedy(probs, steps, classes, dictionary),
+            CtcDecoding::Beam { width } => {
+                ctc_beam(probs, steps, classes, width.max(1), dictionary)
+            }
+        }
+    }
+}
+
+fn ctc_greedy(
+    probs: &[f32],
+    steps: usize,
+    classes: usize,
+    dictionary: &CharDictionary,
+) -> (String, f32) {
+    let mut text = String::new();
+    let mut scores = Vec::new();
+    let mut last = 0;
+    for step in probs.chunks_exact(classes).take(steps) {
+        let (class, &prob) = step
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .unwrap();
+        if class != 0 && class != last {
+            if let Some(c) = dictionary.get(class) {
+                text.push_str(c);
+                scores.push(prob);
+            }
+        }
+        last = class;
+    }
+    let confidence = if scores.is_empty() {
+        0.
+    } else {
+        scores.iter().sum::<f32>() / scores.len() as f32
+    };
+    (text, confidence)
+}
+
+fn ctc_beam(
+    probs: &[f32],
+    steps: usize,
+    classes: usize,
+    width: usize,
+    dictionary: &CharDictionary,
+) -> (String, f32) {
+    // prefixごとに(blankで終わる確率, blank以外で終わる確率)
+    let mut beams: Vec<(Vec<usize>, (f64, f64))> = vec![(Vec::new(), (1., 0.))];
+    let mut count = 0;
+    for step in probs.chunks_exact(classes).take(steps) {
+        count += 1;
+        let mut next: HashMap<Vec<usize>, (f64, f64)> = HashMap::new();
+        for (prefix, (blank, non_blank)) in &beams {
+            for (class, &prob) in step.iter().enumerate() {
+                let prob = prob as f64;
+                if prob < 1e-6 {
+                    continue;
+                }
+                if class == 0 {
+                    next.entry(prefix.clone()).or_default().0 += (blank + non_blank) * prob;
+                    continue;
+                }
+                let mut extended = prefix.clone();
+                extended.push(class);
+                if prefix.last() == Some(&class) {
+                    // 同じ文字を続けるにはblankを挟む必要がある
+                    next.entry(extended).or_default().1 += blank * prob;
+                    next.entry(prefix.clone()).or_default().1 += non_blank * prob;
+                } else {
+                    next.entry(extended).or_default().1 += (blank + non_blank) * prob;
+                }
+            }
+        }
+        beams = next.into_iter().collect();
+        beams.sort_by(|a, b| {
+            (b.1 .0 + b.1 .1)
+                .total_cmp(&(a.1 .0 + a.1 .1))
+                .then_with(|| a.0.cmp(&b.0))
+        });
+        beams.truncate(width);
+    }
+    let Some((prefix, (blank, non_blank))) = beams.into_iter().next() else {
+        return (String::new(), 0.);
+    };
+    let text = prefix
+        .into_iter()
+        .filter_map(|class| dictionary.get(class))
+        .collect();
+    let confidence = if count == 0 {
+        0.
+    } else {
+        (blank + non_blank).powf(1. / count as f64) as f32
+    };
+    (text, confidence)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct OcrBuilder<P>
+where
+    P: AsRef<Path> + Default + Debug,
+{
+    detector_prototxt: Option<P>,
+    detector_onnx: P,
+    recognizer_prototxt: Option<P>,
+    recognizer_onnx: P,
+    dictionary: P,
+    use_space: bool,
+    env_id: Option<i32>,
+    num_threads: Option<i32>,
+    limit_side: Option<u32>,
+    rec_height: Option<u32>,
+    rec_max_width: Option<u32>,
+    db: Option<DbPostprocess>,
+    decoding: Option<CtcDecoding>,
+    min_confidence: Option<f32>,
+}
+
+impl<P: AsRef<Path> + Default + Debug> OcrBuilder<P> {
+    crate::impl_option!(detector_prototxt, P);
+    crate::impl_non_option!(detector_onnx, P);
+    crate::impl_option!(recognizer_prototxt, P);
+    crate::impl_non_option!(recognizer_onnx, P);
+    // 1行1文字の辞書ファイル
+    crate::impl_non_option!(dictionary, P);
+    // 辞書の末尾に空白を追加する
+    crate::impl_non_option!(use_space, bool);
+    crate::impl_option!(env_id, i32);
+    crate::impl_option!(num_threads, i32);
+    // 検出モデルに入力する画像の長辺の上限、既定値は960
+    crate::impl_option!(limit_side, u32);
+    // 認識モデルに入力する画像の高さ、既定値は48
+    crate::impl_option!(rec_height, u32);
+    // 認識モデルに入力する画像の幅の上限、既定値は320
+    crate::impl_option!(rec_max_width, u32);
+    crate::impl_option!(db, DbPostprocess);
+    crate::impl_option!(decoding, CtcDecoding);
+    // これより信頼度の低い行は捨てる、既定値は0.5
+    crate::impl_option!(min_confidence, f32);
+
+    pub fn build(self) -> Result<Ocr, OcrError> {
+        let create = || {
+            Network::ailia_create(
+                self.env_id.unwrap_or(AILIA_ENVIRONMENT_ID_AUTO),
+                self.num_threads
+                    .unwrap_or_else(|| AILIA_MULTITHREAD_AUTO.try_into().unwrap()),
+            )
+        };
+        let detector = create()?;
+        detector.open_model_files(self.detector_prototxt, self.detector_onnx)?;
+        let recognizer = create()?;
+        recognizer.open_model_files(self.recognizer_prototxt, self.recognizer_onnx)?;
+        Ok(Ocr {
+            detector,
+            recognizer,
+            dictionary: CharDictionary::load(self.dictionary, self.use_space)?,
+            limit_side: self.limit_side.unwrap_or(960),
+            rec_height: self.rec_height.unwrap_or(48),
+            rec_max_width: self.rec_max_width.unwrap_or(320),
+            db: self.db.unwrap_or_default(),
+            decoding: self.decoding.unwrap_or_default(),
+            min_confidence: self.min_confidence.unwrap_or(0.5),
+        })
+    }
+}
+
+/// PaddleOCRの検出モデルと認識モデルを使って画像中の文字を読む
+pub struct Ocr {
+    detector: Network,
+    recognizer: Network,
+    dictionary: CharDictionary,
+    limit_side: u32,
+    rec_height: u32,
+    rec_max_width: u32,
+    db: DbPostprocess,
+    decoding: CtcDecoding,
+    min_confidence: f32,
+}
+
+impl Ocr {
+    pub fn detector(&self) -> &Network {
+        &self.detector
+    }
+
+    pub fn recognizer(&self) -> &Network {
+        &self.recognizer
+    }
+
+    pub fn dictionary(&self) -> &CharDictionary {
+        &self.dictionary
+    }
+
+    /// 読み順に並べた認識結果を返す
+    pub fn read(&self, image: &ImageView) -> Result<Vec<TextLine>, OcrError> {
+        self.read_rgb(&to_rgb(image)?)
+    }
+
+    pub fn read_rgb(&self, image: &RgbImage) -> Result<Vec<TextLine>, OcrError> {
+        let mut lines = Vec::new();
+        for text_box in self.detect(image)? {
+            let (text, confidence) = self.recognize(&rectify(image, &text_box.polygon))?;
+            if text.is_empty() || confidence < self.min_confidence {
+                continue;
+            }
+            lines.push(TextLine {
+                polygon: text_box.polygon,
+                text,
+                confidence,
+            });
+        }
+        reading_order(&mut lines);
+        Ok(lines)
+    }
+
+    /// 文字領域を検出する、座標は元画像の画素単位
+    pub fn detect(&self, image: &RgbImage) -> Result<Vec<TextBox>, OcrError> {
+        let (width, height) = image.dimensions();
+        let scale = (self.limit_side as f32 / width.max(height) as f32).min(1.);
+        let fit = |size: u32| (((size as f32 * scale) / 32.).round() as u32).max(1) * 32;
+        let (tensor, transform) = Preprocess::new()
+            .resize(fit(width), fit(height), FilterType::Triangle)
+            .normalize(Normalize::ImageNet)
+            .run_rgb(image)?;
+        let input_idx = self.detector.get_input_blob_index_by_index(0)?;
+        tensor.set_input(&self.detector, input_idx)?;
+        self.detector.update()?;
+        let output_idx = self.detector.get_output_blob_index_by_index(0)?;
+        let shape = self.detector.get_blob_shape_nd(output_idx)?;
+        let prob = self.detector.get_output_blob_by_index::<f32>(output_idx)?;
+        let (map_height, map_width) = match shape.as_slice() {
+            [.., 1, h, w] | [h, w] => (*h, *w),
+            _ => return Err(OcrError::InvalidShape(shape)),
+        };
+        if prob.len() != (map_width * map_height) as usize {
+            return Err(OcrError::InvalidShape(shape));
+        }
+        // 確率マップの解像度が入力と異なる場合にも対応する
+        let (scale_x, scale_y) = (
+            transform.size.0 as f32 / map_width as f32,
+            transform.size.1 as f32 / map_height as f32,
+        );
+        Ok(self
+            .db
+            .boxes(&prob, map_width, map_height)
+            .into_iter()
+            .map(|text_box| TextBox {
+                polygon: text_box.polygon.map(|(x, y)| {
+                    let (x, y) = transform.to_source(x * scale_x, y * scale_y);
+                    (x.clamp(0., width as f32), y.clamp(0., height as f32))
+                }),
+                ..text_box
+            })
+            .collect())
+    }
+
+    /// 1行分の切り出し画像を文字列にする
+    pub fn recognize(&self, crop: &RgbImage) -> Result<(String, f32), OcrError> {
+        let (width, height) = crop.dimensions();
+        let rec_width = ((self.rec_height as f32 * width as f32 / height as f32).ceil() as u32)
+            .clamp(1, self.rec_max_width);
+        let (tensor, _) = Preprocess::new()
+            .resize(rec_width, self.rec_height, FilterType::Triangle)
+            .normalize(Normalize::Signed)
+            .run_rgb(crop)?;
+        let input_idx = self.recognizer.get_input_blob_index_by_index(0)?;
+        tensor.set_input(&self.recognizer, input_idx)?;
+        self.recognizer.update()?;
+        let output_idx = self.recognizer.get_output_blob_index_by_index(0)?;
+        let shape = self.recognizer.get_blob_shape_nd(output_idx)?;
+        let probs = self
+            .recognizer
+            .get_output_blob_by_index::<f32>(output_idx)?;
+        let (steps, classes) = match shape.as_slice() {
+            [.., t, c] => (*t as usize, *c as usize),
+            _ => return Err(OcrError::InvalidShape(shape)),
+        };
+        if classes != self.dictionary.num_classes() {
+            return Err(OcrError::DictionaryMismatch(
+                classes,
+                self.dictionary.num_classes(),
+            ));
+        }
+        Ok(self.decoding.decode(&probs, steps, &self.dictionary))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn db_boxes() {
+        // 40x20の確率マップに20x4の文字領域と確率の低い領域
+        let (width, height) = (40, 20);
+        let prob = (0..width * height)
+            .map(|idx| {
+                let (x, y) = (idx % width, idx / width);
+                if (5..25).contains(&x) && (8..12).contains(&y) {
+                    0.9
+                } else if (30..36).contains(&x) && (2..8).contains(&y) {
+                    0.4
+                } else {
+                    0.
+                }
+            })
+            .collect::<Vec<f32>>();
+        let boxes = DbPostprocess::new().boxes(&prob, width as u32, height as u32);
+        assert_eq!(boxes.len(), 1);
+        assert!((boxes[0].score - 0.9).abs() < 1e-5);
+        // 20x4の長方形をarea*1.5/perimeter=2.5だけ広げる
+        let expected = [(2.5, 5.5), (27.5, 5.5), (27.5, 14.5), (2.5, 14.5)];
+        for (p, e) in boxes[0].polygon.iter().zip(expected) {
+            assert!(
+                (p.0 - e.0).abs() < 1e-3 && (p.1 - e.1).abs() < 1e-3,
+                "{p:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn min_area_rect() {
+        let hull = convex_hull(vec![(0., 1.), (1., 0.), (2., 1.), (1., 2.), (1., 1.)]);
+        assert_eq!(hull.len(), 4);
+        let rect = RotatedRect::min_area(&hull);
+        assert!((rect.area() - 2.).abs() < 1e-5);
+        assert!((rect.center.0 - 1.).abs() < 1e-5 && (rect.center.1 - 1.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn rectify_crop() {
+        let image = RgbImage::from_fn(32, 32, |x, y| Rgb([x as u8 * 8, y as u8 * 8, 0]));
+        let crop = rectify(&image, &[(4., 6.), (20., 6.), (20., 14.), (4., 14.)]);
+        assert_eq!(crop.dimensions(), (16, 8));
+        assert_eq!(crop.get_pixel(0, 0), image.get_pixel(4, 6));
+        assert_eq!(crop.get_pixel(15, 7), image.get_pixel(19, 13));
+
+        // 縦長の領域は横向きにする
+        let crop = rectify(&image, &[(4., 0.), (8., 0.), (8., 20.), (4., 20.)]);
+        assert_eq!(crop.dimensions(), (20, 4));
+    }
+
+    #[test]
+    fn ctc() {
+        let dictionary = CharDictionary::from_chars(["a", "b"], true);
+        assert_eq!(dictionary.num_classes(), 4);
+        assert_eq!(dictionary.get(0), None);
+        assert_eq!(dictionary.get(3), Some(" "));
+
+        // a a blank a b -> "aab"
+        let probs = [
+            [0.1, 0.8, 0.05, 0.05],
+            [0.1, 0.8, 0.05, 0.05],
+            [0.8, 0.1, 0.05, 0.05],
+            [0.1, 0.6, 0.2, 0.1],
+            [0.1, 0.1, 0.7, 0.1],
+        ]
+        .concat();
+        let (text, confidence) = CtcDecoding::Greedy.decode(&probs, 5, &dictionary);
+        assert_eq!(text, "aab");
+        assert!((confidence - 0.7).abs() < 1e-5);
+        let (text, _) = CtcDecoding::Beam { width: 4 }.decode(&probs, 5, &dictionary);
+        assert_eq!(text, "aab");
+
+        // greedyはblank blankで空文字列、"a"の確率の合計は0.64で空文字列の0.36より大きい
+        let probs = [[0.6, 0.4, 0., 0.], [0.6, 0.4, 0., 0.]].concat();
+        assert_eq!(CtcDecoding::Greedy.decode(&probs, 2, &dictionary).0, "");
+        let (text, confidence) = CtcDecoding::Beam { width: 2 }.decode(&probs, 2, &dictionary);
+        assert_eq!(text, "a");
+        assert!((confidence - 0.8).abs() < 1e-5);
+    }
+
+    #[test]
+    fn order() {
+        let line = |x: f32, y: f32, text: &str| TextLine {
+            polygon: [(x, y), (x + 30., y), (x + 30., y + 10.), (x, y + 10.)],
+            text: text.to_string(),
+            confidence: 1.,
+        };
+        let mut lines = vec![
+            line(0., 22., "c"),
+            line(50., 0., "b"),
+            line(0., 3., "a"),
+            line(40., 20., "d"),
+        ];
+        reading_order(&mut lines);
+        let texts = lines.iter().map(|l| l.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(texts, ["a", "b", "c", "d"]);
+    }
+}
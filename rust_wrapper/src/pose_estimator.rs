@@ -7,17 +7,34 @@ use std::ptr::NonNull;
 
 use ailia_sys::*;
 
+use crate::format::ImageFormat;
 use crate::network::Network;
 use crate::AiliaError;
 
-pub use ailia_sys::AILIA_POSE_ESTIMATOR_ALGORITHM_ACCULUS_FACE;
-pub use ailia_sys::AILIA_POSE_ESTIMATOR_ALGORITHM_ACCULUS_HAND;
-pub use ailia_sys::AILIA_POSE_ESTIMATOR_ALGORITHM_ACCULUS_POSE;
-pub use ailia_sys::AILIA_POSE_ESTIMATOR_ALGORITHM_ACCULUS_UPPOSE;
-pub use ailia_sys::AILIA_POSE_ESTIMATOR_ALGORITHM_ACCULUS_UPPOSE_FPGA;
-pub use ailia_sys::AILIA_POSE_ESTIMATOR_ALGORITHM_LW_HUMAN_POSE;
-pub use ailia_sys::AILIA_POSE_ESTIMATOR_ALGORITHM_OPEN_POSE;
-pub use ailia_sys::AILIA_POSE_ESTIMATOR_ALGORITHM_OPEN_POSE_SINGLE_SCALE;
+/// 骨格検出・顔特徴点検出のアルゴリズム (AILIA_POSE_ESTIMATOR_ALGORITHM_*)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum PoseAlgorithm {
+    #[default]
+    AcculusPose,
+    AcculusFace,
+    AcculusUpPose,
+    AcculusUpPoseFpga,
+    AcculusHand,
+    OpenPose,
+    LwHumanPose,
+    OpenPoseSingleScale,
+}
+
+crate::impl_ailia_enum!(PoseAlgorithm {
+    AcculusPose => AILIA_POSE_ESTIMATOR_ALGORITHM_ACCULUS_POSE,
+    AcculusFace => AILIA_POSE_ESTIMATOR_ALGORITHM_ACCULUS_FACE,
+    AcculusUpPose => AILIA_POSE_ESTIMATOR_ALGORITHM_ACCULUS_UPPOSE,
+    AcculusUpPoseFpga => AILIA_POSE_ESTIMATOR_ALGORITHM_ACCULUS_UPPOSE_FPGA,
+    AcculusHand => AILIA_POSE_ESTIMATOR_ALGORITHM_ACCULUS_HAND,
+    OpenPose => AILIA_POSE_ESTIMATOR_ALGORITHM_OPEN_POSE,
+    LwHumanPose => AILIA_POSE_ESTIMATOR_ALGORITHM_LW_HUMAN_POSE,
+    OpenPoseSingleScale => AILIA_POSE_ESTIMATOR_ALGORITHM_OPEN_POSE_SINGLE_SCALE,
+});
 
 // TODO:Option以外は設定されていない場合buildを呼べないようにする(型によって制限をかける)
 #[derive(Clone, Copy, Debug, Default)]
@@ -27,9 +44,9 @@ where
 {
     env_id: Option<i32>,
     num_threads: Option<i32>,
-    prototxt: P,
+    prototxt: Option<P>,
     onnx: P,
-    algorithm: u32,
+    algorithm: PoseAlgorithm,
 }
 
 impl<P> PoseEstimatorBuilder<P>
@@ -38,8 +55,8 @@ where
 {
     crate::impl_option!(env_id, i32);
     crate::impl_option!(num_threads, i32);
-    crate::impl_non_option!(algorithm, u32);
-    crate::impl_non_option!(prototxt, P);
+    crate::impl_non_option!(algorithm, PoseAlgorithm);
+    crate::impl_option!(prototxt, P);
     crate::impl_non_option!(onnx, P);
 
     pub fn build<O>(self) -> Result<PoseEstimator<O>, AiliaError> {
@@ -48,8 +65,7 @@ where
             self.num_threads
                 .unwrap_or_else(|| AILIA_MULTITHREAD_AUTO.try_into().unwrap()),
         )?;
-        net.open_stream_file_a(self.prototxt)?;
-        net.open_weight_file_a(self.onnx)?;
+        net.open_model_files(self.prototxt, self.onnx)?;
         PoseEstimator::new(net, self.algorithm)
     }
 }
@@ -68,10 +84,11 @@ impl<O> Deref for PoseEstimator<O> {
 }
 
 impl<O> PoseEstimator<O> {
-    fn new(net: Network, algorithm: u32) -> Result<Self, AiliaError> {
+    fn new(net: Network, algorithm: PoseAlgorithm) -> Result<Self, AiliaError> {
         let mut ptr: *mut AILIAPoseEstimator = std::ptr::null::<AILIAPoseEstimator>() as *mut _;
-        match unsafe { ailiaCreatePoseEstimator(&mut ptr as *mut *mut _, net.as_ptr(), algorithm) }
-        {
+        match unsafe {
+            ailiaCreatePoseEstimator(&mut ptr as *mut *mut _, net.as_ptr(), algorithm.into())
+        } {
             0 => unsafe {
                 Ok(Self {
                     inner: NonNull::new_unchecked(ptr),
@@ -89,7 +106,7 @@ impl<O> PoseEstimator<O> {
         stride: u32,
         width: u32,
         height: u32,
-        format: u32,
+        format: ImageFormat,
     ) -> Result<Vec<O>, AiliaError>
     where
         O: ObjectTrait,
@@ -109,7 +126,7 @@ impl<O> PoseEstimator<O> {
         stride: u32,
         width: u32,
         height: u32,
-        format: u32,
+        format: ImageFormat,
     ) -> Result<(), AiliaError> {
         match unsafe {
             ailiaPoseEstimatorCompute(
@@ -118,7 +135,7 @@ impl<O> PoseEstimator<O> {
                 stride,
                 width,
                 height,
-                format,
+                format.into(),
             )
         } {
             0 => Ok(()),
@@ -247,6 +264,7 @@ impl<O> Drop for PoseEstimator<O> {
 }
 
 #[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct KeyPoint {
     pub x: f32,
     pub y: f32,
@@ -272,6 +290,7 @@ pub trait ObjectTrait: Sized {
 }
 
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Pose {
     pub points: [KeyPoint; 19],
     pub total_score: f32,
@@ -314,6 +333,7 @@ impl ObjectTrait for Pose {
 }
 
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UpPose {
     pub points: [KeyPoint; 15],
     pub total_score: f32,
@@ -356,6 +376,7 @@ impl ObjectTrait for UpPose {
 }
 
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Hand {
     pub points: [KeyPoint; 21],
     pub total_score: f32,
@@ -392,7 +413,9 @@ impl ObjectTrait for Hand {
 }
 
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Face {
+    #[cfg_attr(feature = "serde", serde(with = "big_array"))]
     pub points: [KeyPoint; 68],
     pub total_score: f32,
 }
@@ -426,3 +449,30 @@ impl ObjectTrait for Face {
         }
     }
 }
+
+/// serdeは長さ32までの配列しか扱えないため、Faceのキーポイントはこれを使ってシリアライズする
+#[cfg(feature = "serde")]
+mod big_array {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S, T, const N: usize>(array: &[T; N], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        T: Serialize,
+    {
+        serializer.collect_seq(array.iter())
+    }
+
+    pub fn deserialize<'de, D, T, const N: usize>(deserializer: D) -> Result<[T; N], D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de>,
+    {
+        let points = Vec::<T>::deserialize(deserializer)?;
+        let len = points.len();
+        points.try_into().map_err(|_| {
+            D::Error::invalid_length(len, &format!("an array of length {}", N).as_str())
+        })
+    }
+}
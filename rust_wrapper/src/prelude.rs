@@ -1,5 +1,21 @@
+pub use crate::anomaly::{AnomalyDetector, AnomalyDetectorBuilder, AnomalyMap, AnomalyMethod};
+#[cfg(feature = "async")]
+pub use crate::asynchronous::{AsyncClassifier, AsyncDetector, AsyncError, AsyncNetwork};
 pub use crate::classifier::*;
+pub use crate::clip::{BpeTokenizer, ZeroShotClassifier, ZeroShotClassifierBuilder};
+pub use crate::depth::{DepthEstimator, DepthEstimatorBuilder, DepthMap};
 pub use crate::detector::*;
+pub use crate::detic::{OpenVocabDetector, OpenVocabDetectorBuilder};
+pub use crate::embedding::{Embedder, EmbedderBuilder, IndexKind, Neighbor, VectorIndex};
 pub use crate::environment::*;
+pub use crate::face::{FaceEmbedder, FaceEmbedderBuilder, FaceGallery, FaceMatch};
+pub use crate::format::{ChannelOrder, ImageFormat, ImageRange, NetworkImageFormat};
+pub use crate::img2img::{ImageTransformer, ImageTransformerBuilder, Tiling};
+pub use crate::instance::{BitMask, Instance, InstanceSegmenter, InstanceSegmenterBuilder};
+pub use crate::matting::{AlphaMatte, Background, Matting, MattingBuilder};
 pub use crate::network::*;
+pub use crate::ocr::{Ocr, OcrBuilder, TextLine};
 pub use crate::pose_estimator::*;
+pub use crate::segment::{SegmentationMask, Segmenter, SegmenterBuilder};
+#[cfg(feature = "zoo")]
+pub use crate::zoo::{Downloader, ModelDescriptor, TaskConfig, ZooError};
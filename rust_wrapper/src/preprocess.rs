@@ -0,0 +1,684 @@
+//! Networkに直接入力するための画像の前処理
+//!
+//! ailia-modelsのPythonのユーティリティ(letterbox, normalize_image, transpose)に対応する。
+//! 幾何変換はTransformに記録され、推論結果の座標を元画像の座標に戻すのに使う。
+
+pub use image::imageops::FilterType;
+use image::imageops::{crop_imm, overlay, resize};
+use image::{Rgb, RgbImage};
+
+use thiserror::Error;
+
+use crate::detector::Object;
+use crate::format::{format_convert, ChannelOrder, ImageRange, NetworkImageFormat};
+use crate::network::Network;
+use crate::video::ImageView;
+use crate::AiliaError;
+
+pub const IMAGENET_MEAN: [f32; 3] = [0.485, 0.456, 0.406];
+pub const IMAGENET_STD: [f32; 3] = [0.229, 0.224, 0.225];
+
+#[derive(Debug, Error)]
+pub enum PreprocessError {
+    #[error("画像サイズが不正です: {0}x{1}")]
+    InvalidSize(u32, u32),
+    #[error("切り抜く範囲が画像より大きいです: {0}x{1}")]
+    CropTooLarge(u32, u32),
+    #[error("画素データの長さが画像サイズと一致しません: {0}x{1}")]
+    BufferSize(u32, u32),
+    #[error(transparent)]
+    Ailia(#[from] AiliaError),
+}
+
+/// letterboxで縮小した画像を置く位置
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Align {
+    /// 上下左右に均等に余白を入れる
+    #[default]
+    Center,
+    /// 右と下にだけ余白を入れる(YOLOXなど)
+    TopLeft,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Resize {
+        width: u32,
+        height: u32,
+        filter: FilterType,
+    },
+    ResizeShorter {
+        size: u32,
+        filter: FilterType,
+    },
+    ResizeLonger {
+        size: u32,
+        filter: FilterType,
+    },
+    Letterbox {
+        width: u32,
+        height: u32,
+        color: [u8; 3],
+        align: Align,
+        filter: FilterType,
+    },
+    CenterCrop {
+        width: u32,
+        height: u32,
+    },
+}
+
+/// 画素値の変換、ailia-modelsのnormalize_imageに対応する
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Normalize {
+    /// 0~255のまま
+    #[default]
+    None,
+    /// 0~1
+    Unit,
+    /// -1~1
+    Signed,
+    /// 0~1にした後ImageNetの平均と標準偏差で正規化する
+    ImageNet,
+    /// 0~1にした後指定した平均と標準偏差で正規化する、RGBの順で指定する
+    MeanStd { mean: [f32; 3], std: [f32; 3] },
+}
+
+impl Normalize {
+    fn apply(&self, value: u8, channel: usize) -> f32 {
+        let value = value as f32;
+        match self {
+            Normalize::None => value,
+            Normalize::Unit => value / 255.,
+            Normalize::Signed => value / 127.5 - 1.,
+            Normalize::ImageNet => (value / 255. - IMAGENET_MEAN[channel]) / IMAGENET_STD[channel],
+            Normalize::MeanStd { mean, std } => (value / 255. - mean[channel]) / std[channel],
+        }
+    }
+
+    fn ailia_range(&self) -> Option<ImageRange> {
+        match self {
+            Normalize::None => Some(ImageRange::UnsignedInt8),
+            Normalize::Unit => Some(ImageRange::UnsignedFp32),
+            Normalize::Signed => Some(ImageRange::SignedFp32),
+            Normalize::ImageNet => Some(ImageRange::ImageNet),
+            Normalize::MeanStd { .. } => None,
+        }
+    }
+}
+
+/// テンソルのメモリ配置
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// (1, C, H, W)
+    #[default]
+    Chw,
+    /// (1, H, W, C)
+    Hwc,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorOrder {
+    #[default]
+    Rgb,
+    Bgr,
+}
+
+/// 前処理を行う実装
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    Rust,
+    /// format::format_convert(ailiaFormatConvert)を使う
+    /// 幾何変換がresize1つ以下でNormalize::MeanStdを使わない場合のみ、それ以外はRustで処理する
+    Ailia,
+}
+
+/// 前処理の結果、set_inputでそのままNetworkに入力できる
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImageTensor {
+    pub data: Vec<f32>,
+    pub shape: Vec<u32>,
+}
+
+impl ImageTensor {
+    /// 入力Blobの形状を設定してデータを入力する
+    pub fn set_input(&self, net: &Network, idx: u32) -> Result<(), AiliaError> {
+        net.set_input_blob_shape_nd(self.shape.clone(), idx)?;
+        net.set_input_data_blob(self.data.as_ptr(), self.data.len() as u32, idx)
+    }
+}
+
+/// 元画像の座標から前処理後の座標への変換 `dst = src * scale + offset`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub scale: (f32, f32),
+    pub offset: (f32, f32),
+    /// 元画像のサイズ
+    pub source_size: (u32, u32),
+    /// 前処理後のサイズ
+    pub size: (u32, u32),
+}
+
+impl Transform {
+    fn identity(width: u32, height: u32) -> Self {
+        Self {
+            scale: (1., 1.),
+            offset: (0., 0.),
+            source_size: (width, height),
+            size: (width, height),
+        }
+    }
+
+    fn then(self, scale: (f32, f32), offset: (f32, f32), size: (u32, u32)) -> Self {
+        Self {
+            scale: (self.scale.0 * scale.0, self.scale.1 * scale.1),
+            offset: (
+                self.offset.0 * scale.0 + offset.0,
+                self.offset.1 * scale.1 + offset.1,
+            ),
+            source_size: self.source_size,
+            size,
+        }
+    }
+
+    /// 前処理後の座標を元画像の座標に戻す
+    pub fn to_source(&self, x: f32, y: f32) -> (f32, f32) {
+        (
+            (x - self.offset.0) / self.scale.0,
+            (y - self.offset.1) / self.scale.1,
+        )
+    }
+
+    /// 前処理後の(x1, y1, x2, y2)を元画像の座標に戻し、画像の範囲に収める
+    pub fn to_source_box(&self, bbox: [f32; 4]) -> [f32; 4] {
+        let (x1, y1) = self.to_source(bbox[0], bbox[1]);
+        let (x2, y2) = self.to_source(bbox[2], bbox[3]);
+        let (width, height) = (self.source_size.0 as f32, self.source_size.1 as f32);
+        [
+            x1.clamp(0., width),
+            y1.clamp(0., height),
+            x2.clamp(0., width),
+            y2.clamp(0., height),
+        ]
+    }
+
+    /// 前処理後の画像に対する相対座標のObjectを元画像に対する相対座標に戻す
+    pub fn to_source_object(&self, object: &Object) -> Object {
+        let (width, height) = (self.size.0 as f32, self.size.1 as f32);
+        let [x1, y1, x2, y2] = self.to_source_box([
+            object.x * width,
+            object.y * height,
+            (object.x + object.w) * width,
+            (object.y + object.h) * height,
+        ]);
+        let (width, height) = (self.source_size.0 as f32, self.source_size.1 as f32);
+        Object {
+            x: x1 / width,
+            y: y1 / height,
+            w: (x2 - x1) / width,
+            h: (y2 - y1) / height,
+            ..*object
+        }
+    }
+}
+
+/// 前処理のパイプライン、追加した順に幾何変換を行ってから画素値を変換する
+///
+/// ```ignore
+/// let (tensor, transform) = Preprocess::new()
+///     .letterbox(640, 640, [114, 114, 114], Align::TopLeft)
+///     .color_order(ColorOrder::Bgr)
+///     .run(&image)?;
+/// tensor.set_input(&net, idx)?;
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Preprocess {
+    ops: Vec<Op>,
+    normalize: Normalize,
+    layout: Layout,
+    color_order: ColorOrder,
+    backend: Backend,
+}
+
+impl Preprocess {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    crate::impl_non_option!(normalize, Normalize);
+    crate::impl_non_option!(layout, Layout);
+    crate::impl_non_option!(color_order, ColorOrder);
+    crate::impl_non_option!(backend, Backend);
+
+    /// 縦横比を保たずにリサイズする
+    pub fn resize(mut self, width: u32, height: u32, filter: FilterType) -> Self {
+        self.ops.push(Op::Resize {
+            width,
+            height,
+            filter,
+        });
+        self
+    }
+
+    /// 短辺がsizeになるように縦横比を保ってリサイズする
+    pub fn resize_shorter(mut self, size: u32, filter: FilterType) -> Self {
+        self.ops.push(Op::ResizeShorter { size, filter });
+        self
+    }
+
+    /// 長辺がsizeになるように縦横比を保ってリサイズする
+    pub fn resize_longer(mut self, size: u32, filter: FilterType) -> Self {
+        self.ops.push(Op::ResizeLonger { size, filter });
+        self
+    }
+
+    /// 縦横比を保ってwidth x heightに収まるように縮小し、余白をcolorで埋める
+    pub fn letterbox(mut self, width: u32, height: u32, color: [u8; 3], align: Align) -> Self {
+        self.ops.push(Op::Letterbox {
+            width,
+            height,
+            color,
+            align,
+            filter: FilterType::Triangle,
+        });
+        self
+    }
+
+    /// 中央をwidth x heightで切り抜く
+    pub fn center_crop(mut self, width: u32, height: u32) -> Self {
+        self.ops.push(Op::CenterCrop { width, height });
+        self
+    }
+
+    /// ImageView(RGBA)に前処理を行う
+    pub fn run(&self, image: &ImageView) -> Result<(ImageTensor, Transform), PreprocessError> {
+        if self.backend == Backend::Ailia && image.is_valid() {
+            if let Some(result) = self.run_ailia(image) {
+                return result;
+            }
+        }
+        self.run_rgb(&to_rgb(image)?)
+    }
+
+    /// RgbImageに前処理を行う、Backend::Ailiaは使わない
+    pub fn run_rgb(&self, image: &RgbImage) -> Result<(ImageTensor, Transform), PreprocessError> {
+        let (image, transform) = self.apply_geometry(image)?;
+        Ok((self.to_tensor(&image), transform))
+    }
+
+    /// 幾何変換のみを行う
+    pub fn apply_geometry(
+        &self,
+        image: &RgbImage,
+    ) -> Result<(RgbImage, Transform), PreprocessError> {
+        if image.width() == 0 || image.height() == 0 {
+            return Err(PreprocessError::InvalidSize(image.width(), image.height()));
+        }
+        let mut transform = Transform::identity(image.width(), image.height());
+        let mut image = image.clone();
+        for op in &self.ops {
+            (image, transform) = apply_op(op, image, transform)?;
+        }
+        Ok((image, transform))
+    }
+
+    fn to_tensor(&self, image: &RgbImage) -> ImageTensor {
+        let (width, height) = image.dimensions();
+        let plane = (width * height) as usize;
+        let mut data = vec![0.; plane * 3];
+        for (idx, pixel) in image.pixels().enumerate() {
+            for channel in 0..3 {
+                let value = self.normalize.apply(pixel[channel], channel);
+                let channel = match self.color_order {
+                    ColorOrder::Rgb => channel,
+                    ColorOrder::Bgr => 2 - channel,
+                };
+                match self.layout {
+                    Layout::Chw => data[channel * plane + idx] = value,
+                    Layout::Hwc => data[idx * 3 + channel] = value,
+                }
+            }
+        }
+        ImageTensor {
+            data,
+            shape: self.shape(width, height),
+        }
+    }
+
+    fn shape(&self, width: u32, height: u32) -> Vec<u32> {
+        match self.layout {
+            Layout::Chw => vec![1, 3, height, width],
+            Layout::Hwc => vec![1, height, width, 3],
+        }
+    }
+
+    /// ailiaFormatConvertで処理できない場合はNone
+    fn run_ailia(
+        &self,
+        image: &ImageView,
+    ) -> Option<Result<(ImageTensor, Transform), PreprocessError>> {
+        let range = self.normalize.ailia_range()?;
+        let (width, height) = match self.ops.as_slice() {
+            [] => (image.width, image.height),
+            [Op::Resize { width, height, .. }] => (*width, *height),
+            _ => return None,
+        };
+        if image.width == 0 || image.height == 0 || width == 0 || height == 0 {
+            return Some(Err(PreprocessError::InvalidSize(width, height)));
+        }
+        let format = match self.color_order {
+            ColorOrder::Rgb => NetworkImageFormat::Rgb,
+            ColorOrder::Bgr => NetworkImageFormat::Bgr,
+        };
+        let channel = match self.layout {
+            Layout::Chw => ChannelOrder::First,
+            Layout::Hwc => ChannelOrder::Last,
+        };
+        let data = match format_convert(image, width, height, format, channel, range) {
+            Ok(data) => data,
+            Err(err) => return Some(Err(err.into())),
+        };
+        let transform = Transform::identity(image.width, image.height).then(
+            (
+                width as f32 / image.width as f32,
+                height as f32 / image.height as f32,
+            ),
+            (0., 0.),
+            (width, height),
+        );
+        Some(Ok((
+            ImageTensor {
+                data,
+                shape: self.shape(width, height),
+            },
+            transform,
+        )))
+    }
+}
+
+/// (x, y)の画素値をバイリニア補間で求める、画像の外側は黒
+pub(crate) fn sample_bilinear(image: &RgbImage, x: f32, y: f32) -> Rgb<u8> {
+    let (width, height) = (image.width() as i64, image.height() as i64);
+    let (x0, y0) = (x.floor() as i64, y.floor() as i64);
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+    let at = |x: i64, y: i64| {
+        if x < 0 || y < 0 || x >= width || y >= height {
+            [0.; 3]
+        } else {
+            image.get_pixel(x as u32, y as u32).0.map(|v| v as f32)
+        }
+    };
+    let (p00, p10, p01, p11) = (
+        at(x0, y0),
+        at(x0 + 1, y0),
+        at(x0, y0 + 1),
+        at(x0 + 1, y0 + 1),
+    );
+    let mut pixel = [0u8; 3];
+    for c in 0..3 {
+        let top = p00[c] * (1. - fx) + p10[c] * fx;
+        let bottom = p01[c] * (1. - fx) + p11[c] * fx;
+        pixel[c] = (top * (1. - fy) + bottom * fy).round().clamp(0., 255.) as u8;
+    }
+    Rgb(pixel)
+}
+
+/// ImageView(RGBA)のアルファを除いたRgbImage、dataの長さが合わない場合はエラー
+pub(crate) fn to_rgb(image: &ImageView) -> Result<RgbImage, PreprocessError> {
+    if !image.is_valid() {
+        return Err(PreprocessError::BufferSize(image.width, image.height));
+    }
+    Ok(RgbImage::from_fn(image.width, image.height, |x, y| {
+        let idx = (y as usize * image.width as usize + x as usize) * 4;
+        Rgb([image.data[idx], image.data[idx + 1], image.data[idx + 2]])
+    }))
+}
+
+/// 前処理後の画像に対するwidth x heightの値を、transformを使って元画像の解像度に戻す(バイリニア)
+pub(crate) fn resample_to_source(
+    data: &[f32],
+    width: u32,
+    height: u32,
+    transform: &Transform,
+) -> Vec<f32> {
+    let get = |x: u32, y: u32| data[(y * width + x) as usize];
+    let sample = |x: f32, y: f32| {
+        let x = x.clamp(0., (width - 1) as f32);
+        let y = y.clamp(0., (height - 1) as f32);
+        let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+        (get(x0, y0) * (1. - fx) + get(x1, y0) * fx) * (1. - fy)
+            + (get(x0, y1) * (1. - fx) + get(x1, y1) * fx) * fy
+    };
+    let (source_width, source_height) = transform.source_size;
+    let scale_x = width as f32 / transform.size.0 as f32;
+    let scale_y = height as f32 / transform.size.1 as f32;
+    let mut resampled = Vec::with_capacity((source_width * source_height) as usize);
+    for y in 0..source_height {
+        let dst_y = (y as f32 + 0.5) * transform.scale.1 + transform.offset.1;
+        for x in 0..source_width {
+            let dst_x = (x as f32 + 0.5) * transform.scale.0 + transform.offset.0;
+            resampled.push(sample(dst_x * scale_x - 0.5, dst_y * scale_y - 0.5));
+        }
+    }
+    resampled
+}
+
+fn scaled(width: u32, height: u32, scale: f32) -> (u32, u32) {
+    (
+        ((width as f32 * scale).round() as u32).max(1),
+        ((height as f32 * scale).round() as u32).max(1),
+    )
+}
+
+fn resize_to(
+    image: RgbImage,
+    transform: Transform,
+    width: u32,
+    height: u32,
+    filter: FilterType,
+) -> Result<(RgbImage, Transform), PreprocessError> {
+    if width == 0 || height == 0 {
+        return Err(PreprocessError::InvalidSize(width, height));
+    }
+    let scale = (
+        width as f32 / image.width() as f32,
+        height as f32 / image.height() as f32,
+    );
+    Ok((
+        resize(&image, width, height, filter),
+        transform.then(scale, (0., 0.), (width, height)),
+    ))
+}
+
+fn apply_op(
+    op: &Op,
+    image: RgbImage,
+    transform: Transform,
+) -> Result<(RgbImage, Transform), PreprocessError> {
+    let (src_width, src_height) = image.dimensions();
+    match *op {
+        Op::Resize {
+            width,
+            height,
+            filter,
+        } => resize_to(image, transform, width, height, filter),
+        Op::ResizeShorter { size, filter } => {
+            let scale = size as f32 / src_width.min(src_height) as f32;
+            let (width, height) = scaled(src_width, src_height, scale);
+            resize_to(image, transform, width, height, filter)
+        }
+        Op::ResizeLonger { size, filter } => {
+            let scale = size as f32 / src_width.max(src_height) as f32;
+            let (width, height) = scaled(src_width, src_height, scale);
+            resize_to(image, transform, width, height, filter)
+        }
+        Op::Letterbox {
+            width,
+            height,
+            color,
+            align,
+            filter,
+        } => {
+            if width == 0 || height == 0 {
+                return Err(PreprocessError::InvalidSize(width, height));
+            }
+            let scale = (width as f32 / src_width as f32).min(height as f32 / src_height as f32);
+            let (resized_width, resized_height) = scaled(src_width, src_height, scale);
+            let (resized_width, resized_height) =
+                (resized_width.min(width), resized_height.min(height));
+            let (left, top) = match align {
+                Align::Center => ((width - resized_width) / 2, (height - resized_height) / 2),
+                Align::TopLeft => (0, 0),
+            };
+            let mut canvas = RgbImage::from_pixel(width, height, Rgb(color));
+            let resized = resize(&image, resized_width, resized_height, filter);
+            overlay(&mut canvas, &resized, left as i64, top as i64);
+            let scale = (
+                resized_width as f32 / src_width as f32,
+                resized_height as f32 / src_height as f32,
+            );
+            Ok((
+                canvas,
+                transform.then(scale, (left as f32, top as f32), (width, height)),
+            ))
+        }
+        Op::CenterCrop { width, height } => {
+            if width == 0 || height == 0 {
+                return Err(PreprocessError::InvalidSize(width, height));
+            }
+            if width > src_width || height > src_height {
+                return Err(PreprocessError::CropTooLarge(width, height));
+            }
+            let (left, top) = ((src_width - width) / 2, (src_height - height) / 2);
+            Ok((
+                crop_imm(&image, left, top, width, height).to_image(),
+                transform.then((1., 1.), (-(left as f32), -(top as f32)), (width, height)),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn gradient(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| Rgb([x as u8, y as u8, 200]))
+    }
+
+    #[test]
+    fn letterbox_round_trip() {
+        let (image, transform) = Preprocess::new()
+            .letterbox(64, 64, [114, 114, 114], Align::Center)
+            .apply_geometry(&gradient(32, 16))
+            .unwrap();
+        assert_eq!(image.dimensions(), (64, 64));
+        assert_eq!(transform.scale, (2., 2.));
+        assert_eq!(transform.offset, (0., 16.));
+        assert_eq!(image.get_pixel(0, 0), &Rgb([114, 114, 114]));
+        assert_eq!(image.get_pixel(0, 16)[2], 200);
+
+        assert_eq!(transform.to_source(32., 32.), (16., 8.));
+        assert_eq!(
+            transform.to_source_box([-10., 10., 40., 60.]),
+            [0., 0., 20., 16.]
+        );
+
+        let object = Object {
+            category: 1,
+            prob: 0.5,
+            x: 0.25,
+            y: 0.25,
+            w: 0.5,
+            h: 0.5,
+        };
+        let source = transform.to_source_object(&object);
+        assert_eq!(
+            (source.x, source.y, source.w, source.h),
+            (0.25, 0., 0.5, 1.)
+        );
+        assert_eq!(source.category, 1);
+    }
+
+    #[test]
+    fn resize_and_center_crop() {
+        let (image, transform) = Preprocess::new()
+            .resize_shorter(8, FilterType::Nearest)
+            .center_crop(8, 8)
+            .apply_geometry(&gradient(32, 16))
+            .unwrap();
+        assert_eq!(image.dimensions(), (8, 8));
+        assert_eq!(transform.scale, (0.5, 0.5));
+        assert_eq!(transform.offset, (-4., 0.));
+        assert_eq!(transform.to_source(0., 0.), (8., 0.));
+        assert_eq!(transform.size, (8, 8));
+        assert_eq!(transform.source_size, (32, 16));
+
+        let err = Preprocess::new()
+            .center_crop(64, 8)
+            .apply_geometry(&gradient(32, 16))
+            .unwrap_err();
+        assert!(matches!(err, PreprocessError::CropTooLarge(64, 8)));
+    }
+
+    #[test]
+    fn tensor_layout() {
+        let image = RgbImage::from_fn(2, 1, |x, _| Rgb([x as u8, 100, 255]));
+        let (tensor, _) = Preprocess::new().run_rgb(&image).unwrap();
+        assert_eq!(tensor.shape, vec![1, 3, 1, 2]);
+        assert_eq!(tensor.data, vec![0., 1., 100., 100., 255., 255.]);
+
+        let (tensor, _) = Preprocess::new()
+            .layout(Layout::Hwc)
+            .color_order(ColorOrder::Bgr)
+            .normalize(Normalize::Unit)
+            .run_rgb(&image)
+            .unwrap();
+        assert_eq!(tensor.shape, vec![1, 1, 2, 3]);
+        assert_eq!(
+            tensor.data,
+            vec![1., 100. / 255., 0., 1., 100. / 255., 1. / 255.]
+        );
+
+        let (tensor, _) = Preprocess::new()
+            .normalize(Normalize::ImageNet)
+            .run_rgb(&RgbImage::from_pixel(1, 1, Rgb([255, 0, 0])))
+            .unwrap();
+        assert!((tensor.data[0] - (1. - 0.485) / 0.229).abs() < 1e-6);
+        assert!((tensor.data[1] + 0.456 / 0.224).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ailia_backend_falls_back() {
+        // letterboxはailiaFormatConvertでは処理できないのでRustで処理される
+        let image = ImageView::from(image::RgbaImage::from_pixel(
+            4,
+            2,
+            image::Rgba([10, 20, 30, 255]),
+        ));
+        let (tensor, transform) = Preprocess::new()
+            .letterbox(4, 4, [0, 0, 0], Align::TopLeft)
+            .backend(Backend::Ailia)
+            .run(&image)
+            .unwrap();
+        assert_eq!(tensor.shape, vec![1, 3, 4, 4]);
+        assert_eq!(tensor.data[0], 10.);
+        assert_eq!(tensor.data[15], 0.);
+        assert_eq!(transform.offset, (0., 0.));
+
+        // dataが足りない場合はどちらのバックエンドでもエラー
+        let image = ImageView {
+            data: vec![0; 31],
+            ..image
+        };
+        for backend in [Backend::Ailia, Backend::Rust] {
+            assert!(matches!(
+                Preprocess::new().backend(backend).run(&image),
+                Err(PreprocessError::BufferSize(4, 2))
+            ));
+        }
+    }
+}
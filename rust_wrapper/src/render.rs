@@ -0,0 +1,257 @@
+use opencv::core::{Mat, Point, Rect, Scalar, StsBadArg, CV_8UC3, CV_8UC4};
+use opencv::imgproc::{
+    circle, get_text_size, line, put_text, rectangle, FILLED, FONT_HERSHEY_SIMPLEX, LINE_8,
+};
+use opencv::prelude::*;
+
+use ailia_sys::*;
+
+use crate::classifier::Class;
+use crate::detector::Object;
+use crate::instance::Instance;
+use crate::pose_estimator::{KeyPoint, Pose};
+
+/// ailiaのPoseのキーポイントをつなぐ骨格
+pub const POSE_SKELETON: [(u32, u32); 18] = [
+    (
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_NOSE,
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_SHOULDER_CENTER,
+    ),
+    (
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_SHOULDER_LEFT,
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_SHOULDER_CENTER,
+    ),
+    (
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_SHOULDER_RIGHT,
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_SHOULDER_CENTER,
+    ),
+    (
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_EYE_LEFT,
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_NOSE,
+    ),
+    (
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_EYE_RIGHT,
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_NOSE,
+    ),
+    (
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_EAR_LEFT,
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_EYE_LEFT,
+    ),
+    (
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_EAR_RIGHT,
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_EYE_RIGHT,
+    ),
+    (
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_ELBOW_LEFT,
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_SHOULDER_LEFT,
+    ),
+    (
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_ELBOW_RIGHT,
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_SHOULDER_RIGHT,
+    ),
+    (
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_WRIST_LEFT,
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_ELBOW_LEFT,
+    ),
+    (
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_WRIST_RIGHT,
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_ELBOW_RIGHT,
+    ),
+    (
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_BODY_CENTER,
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_SHOULDER_CENTER,
+    ),
+    (
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_HIP_LEFT,
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_BODY_CENTER,
+    ),
+    (
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_HIP_RIGHT,
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_BODY_CENTER,
+    ),
+    (
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_KNEE_LEFT,
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_HIP_LEFT,
+    ),
+    (
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_ANKLE_LEFT,
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_KNEE_LEFT,
+    ),
+    (
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_KNEE_RIGHT,
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_HIP_RIGHT,
+    ),
+    (
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_ANKLE_RIGHT,
+        AILIA_POSE_ESTIMATOR_POSE_KEYPOINT_KNEE_RIGHT,
+    ),
+];
+
+const PALETTE: [(f64, f64, f64); 10] = [
+    (255., 56., 56.),
+    (255., 157., 151.),
+    (255., 112., 31.),
+    (255., 178., 29.),
+    (207., 210., 49.),
+    (72., 249., 10.),
+    (26., 147., 52.),
+    (0., 212., 187.),
+    (52., 69., 147.),
+    (132., 56., 255.),
+];
+
+/// カテゴリごとの色(RGBAのMatを想定)
+pub fn category_color(category: u32) -> Scalar {
+    let (r, g, b) = PALETTE[category as usize % PALETTE.len()];
+    Scalar::new(r, g, b, 255.)
+}
+
+fn label_text(category: u32, prob: f32, labels: Option<&[&str]>) -> String {
+    match labels.and_then(|labels| labels.get(category as usize)) {
+        Some(label) => format!("{} {:.2}", label, prob),
+        None => format!("{} {:.2}", category, prob),
+    }
+}
+
+/// 背景付きのテキストを描画する
+fn put_label(image: &mut Mat, text: &str, origin: Point, color: Scalar) -> opencv::Result<()> {
+    let mut base_line = 0;
+    let size = get_text_size(text, FONT_HERSHEY_SIMPLEX, 0.5, 1, &mut base_line)?;
+    let y = (origin.y - size.height - base_line).max(0);
+    let background = Rect::new(origin.x, y, size.width, size.height + base_line);
+    rectangle(image, background, color, FILLED, LINE_8, 0)?;
+    put_text(
+        image,
+        text,
+        Point::new(origin.x, y + size.height),
+        FONT_HERSHEY_SIMPLEX,
+        0.5,
+        Scalar::new(255., 255., 255., 255.),
+        1,
+        LINE_8,
+        false,
+    )
+}
+
+/// 検出結果の矩形とラベルを描画する
+/// labelsがNoneの場合はカテゴリ番号を表示する
+pub fn draw_objects(
+    image: &mut Mat,
+    objects: &[Object],
+    labels: Option<&[&str]>,
+) -> opencv::Result<()> {
+    let size = image.size()?;
+    for obj in objects {
+        let rect = Rect::new(
+            (obj.x * size.width as f32) as i32,
+            (obj.y * size.height as f32) as i32,
+            (obj.w * size.width as f32) as i32,
+            (obj.h * size.height as f32) as i32,
+        );
+        let color = category_color(obj.category);
+        rectangle(image, rect, color, 2, LINE_8, 0)?;
+        put_label(
+            image,
+            &label_text(obj.category, obj.prob, labels),
+            Point::new(rect.x, rect.y),
+            color,
+        )?;
+    }
+    Ok(())
+}
+
+/// インスタンスのマスクをalphaの不透明度で塗ってから矩形とラベルを描画する
+/// マスクの大きさが画像と異なる場合は矩形だけを描画する
+/// 画像は連続した8bitの3または4チャンネルのみ対応し、それ以外はエラーを返す
+pub fn draw_instances(
+    image: &mut Mat,
+    instances: &[Instance],
+    labels: Option<&[&str]>,
+    alpha: f32,
+) -> opencv::Result<()> {
+    let size = image.size()?;
+    let (width, height) = (size.width as u32, size.height as u32);
+    let channels = match image.typ() {
+        CV_8UC3 => 3,
+        CV_8UC4 => 4,
+        typ => {
+            return Err(opencv::Error::new(
+                StsBadArg,
+                format!("unsupported mat type: {}", typ),
+            ))
+        }
+    };
+    if !image.is_continuous() {
+        return Err(opencv::Error::new(StsBadArg, "mat is not continuous"));
+    }
+    let data = image.data_bytes_mut()?;
+    for instance in instances {
+        let mask = &instance.mask;
+        if (mask.width, mask.height) != (width, height) {
+            continue;
+        }
+        let (r, g, b) = PALETTE[instance.object.category as usize % PALETTE.len()];
+        let color = [r, g, b];
+        for y in 0..height {
+            for x in 0..width {
+                if !mask.get(x, y) {
+                    continue;
+                }
+                let idx = (y * width + x) as usize * channels;
+                for c in 0..3 {
+                    let value = data[idx + c] as f32 * (1. - alpha) + color[c] as f32 * alpha;
+                    data[idx + c] = value.round().clamp(0., 255.) as u8;
+                }
+            }
+        }
+    }
+    let objects = instances
+        .iter()
+        .map(|instance| instance.object)
+        .collect::<Vec<_>>();
+    draw_objects(image, &objects, labels)
+}
+
+/// 姿勢推定結果の骨格を描画する
+pub fn draw_poses(image: &mut Mat, poses: &[Pose]) -> opencv::Result<()> {
+    let size = image.size()?;
+    let to_point = |point: &KeyPoint| {
+        Point::new(
+            (point.x * size.width as f32) as i32,
+            (point.y * size.height as f32) as i32,
+        )
+    };
+    for (idx, pose) in poses.iter().enumerate() {
+        let color = category_color(idx as u32);
+        for (from, to) in POSE_SKELETON {
+            let from = &pose.points[from as usize];
+            let to = &pose.points[to as usize];
+            if from.score <= 0. || to.score <= 0. {
+                continue;
+            }
+            line(image, to_point(from), to_point(to), color, 2, LINE_8, 0)?;
+        }
+        for point in pose.points.iter().filter(|point| point.score > 0.) {
+            circle(image, to_point(point), 3, color, FILLED, LINE_8, 0)?;
+        }
+    }
+    Ok(())
+}
+
+/// 分類結果の上位を左上に描画する
+pub fn draw_classes(
+    image: &mut Mat,
+    classes: &[Class],
+    labels: Option<&[&str]>,
+) -> opencv::Result<()> {
+    for (idx, class) in classes.iter().enumerate() {
+        let category = class.category.max(0) as u32;
+        put_label(
+            image,
+            &label_text(category, class.prob, labels),
+            Point::new(8, 24 * (idx as i32 + 1)),
+            category_color(idx as u32),
+        )?;
+    }
+    Ok(())
+}
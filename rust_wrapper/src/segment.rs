@@ -0,0 +1,702 @@
+//! セマンティックセグメンテーション(DeepLabV3, U-Net, SegFormer, HRNetなど)
+//!
+//! Networkの出力(クラスごとのロジットや確率)を画素ごとのクラスに変換し、元画像の解像度に戻す。
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::ops::Deref;
+use std::path::Path;
+
+use image::{GrayImage, Luma, RgbImage, Rgba, RgbaImage};
+
+use thiserror::Error;
+
+use crate::network::Network;
+use crate::preprocess::{
+    FilterType, ImageTensor, Layout, Normalize, Preprocess, PreprocessError, Transform,
+};
+use crate::video::ImageView;
+use crate::AiliaError;
+
+use ailia_sys::*;
+
+#[derive(Debug, Error)]
+pub enum SegmentError {
+    #[error("出力の形状が不正です: {0:?}")]
+    InvalidShape(Vec<u32>),
+    #[error("マスクと画像のサイズが異なります: {0}x{1}")]
+    SizeMismatch(u32, u32),
+    #[error(transparent)]
+    Preprocess(#[from] PreprocessError),
+    #[error(transparent)]
+    Ailia(#[from] AiliaError),
+}
+
+/// Networkの出力の種類
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputKind {
+    /// クラスごとのロジット、1チャンネルの場合はsigmoidをかけて2値にする
+    #[default]
+    Logits,
+    /// クラスごとの確率
+    Probabilities,
+    /// 画素ごとのクラス番号(argmax済み)
+    Labels,
+}
+
+/// Networkの出力をSegmentationMaskに変換する
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SegmentDecoder {
+    kind: OutputKind,
+    layout: Layout,
+    threshold: f32,
+}
+
+impl Default for SegmentDecoder {
+    fn default() -> Self {
+        Self {
+            kind: OutputKind::Logits,
+            layout: Layout::Chw,
+            threshold: 0.5,
+        }
+    }
+}
+
+impl SegmentDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    crate::impl_non_option!(kind, OutputKind);
+    crate::impl_non_option!(layout, Layout);
+    // 1チャンネルの出力を前景とみなす確率のしきい値
+    crate::impl_non_option!(threshold, f32);
+
+    /// 出力の解像度のままSegmentationMaskにする
+    /// shapeは先頭のバッチ次元(1)を含んでいてもよい
+    pub fn decode(&self, output: &[f32], shape: &[u32]) -> Result<SegmentationMask, SegmentError> {
+        let invalid = || SegmentError::InvalidShape(shape.to_vec());
+        let mut dims = shape;
+        while dims.len() > 2 && dims[0] == 1 {
+            dims = &dims[1..];
+        }
+        let (channels, height, width) = match (dims, self.layout) {
+            ([height, width], _) => (1, *height, *width),
+            ([channels, height, width], Layout::Chw) => (*channels, *height, *width),
+            ([height, width, channels], Layout::Hwc) => (*channels, *height, *width),
+            _ => return Err(invalid()),
+        };
+        let plane = (width * height) as usize;
+        if channels == 0 || plane == 0 || output.len() != plane * channels as usize {
+            return Err(invalid());
+        }
+        let at = |class: usize, pixel: usize| match self.layout {
+            Layout::Chw => output[class * plane + pixel],
+            Layout::Hwc => output[pixel * channels as usize + class],
+        };
+
+        let mut labels = Vec::with_capacity(plane);
+        let mut scores = Vec::with_capacity(plane);
+        let num_classes = match self.kind {
+            OutputKind::Labels => {
+                if channels != 1 {
+                    return Err(invalid());
+                }
+                for pixel in 0..plane {
+                    labels.push(at(0, pixel).max(0.).round() as u32);
+                    scores.push(1.);
+                }
+                labels.iter().max().map_or(1, |max| max + 1)
+            }
+            _ if channels == 1 => {
+                for pixel in 0..plane {
+                    let value = at(0, pixel);
+                    let prob = match self.kind {
+                        OutputKind::Logits => 1. / (1. + (-value).exp()),
+                        _ => value,
+                    };
+                    let foreground = prob >= self.threshold;
+                    labels.push(foreground as u32);
+                    scores.push(if foreground { prob } else { 1. - prob });
+                }
+                2
+            }
+            kind => {
+                for pixel in 0..plane {
+                    let (mut label, mut max) = (0, f32::NEG_INFINITY);
+                    for class in 0..channels as usize {
+                        let value = at(class, pixel);
+                        if value > max {
+                            (label, max) = (class, value);
+                        }
+                    }
+                    let score = match kind {
+                        OutputKind::Logits => {
+                            let sum: f32 = (0..channels as usize)
+                                .map(|class| (at(class, pixel) - max).exp())
+                                .sum();
+                            1. / sum
+                        }
+                        _ => max,
+                    };
+                    labels.push(label as u32);
+                    scores.push(score);
+                }
+                channels
+            }
+        };
+        Ok(SegmentationMask {
+            width,
+            height,
+            num_classes,
+            labels,
+            scores,
+        })
+    }
+}
+
+/// 画素ごとのクラスと、そのクラスの確率
+#[derive(Clone, Debug, PartialEq)]
+pub struct SegmentationMask {
+    pub width: u32,
+    pub height: u32,
+    pub num_classes: u32,
+    pub labels: Vec<u32>,
+    pub scores: Vec<f32>,
+}
+
+impl SegmentationMask {
+    pub fn label(&self, x: u32, y: u32) -> u32 {
+        self.labels[(y * self.width + x) as usize]
+    }
+
+    pub fn score(&self, x: u32, y: u32) -> f32 {
+        self.scores[(y * self.width + x) as usize]
+    }
+
+    /// 前処理後の画像に対するマスクを、transformを使って元画像の解像度に戻す(最近傍)
+    /// letterboxの余白部分は捨てられる
+    pub fn to_source(&self, transform: &Transform) -> SegmentationMask {
+        let (width, height) = transform.source_size;
+        let scale_x = self.width as f32 / transform.size.0 as f32;
+        let scale_y = self.height as f32 / transform.size.1 as f32;
+        let mut labels = Vec::with_capacity((width * height) as usize);
+        let mut scores = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            let dst_y = (y as f32 + 0.5) * transform.scale.1 + transform.offset.1;
+            let src_y = ((dst_y * scale_y) as u32).min(self.height - 1);
+            for x in 0..width {
+                let dst_x = (x as f32 + 0.5) * transform.scale.0 + transform.offset.0;
+                let src_x = ((dst_x * scale_x) as u32).min(self.width - 1);
+                labels.push(self.label(src_x, src_y));
+                scores.push(self.score(src_x, src_y));
+            }
+        }
+        SegmentationMask {
+            width,
+            height,
+            num_classes: self.num_classes,
+            labels,
+            scores,
+        }
+    }
+
+    /// クラスごとの画素数
+    pub fn pixel_counts(&self) -> Vec<u32> {
+        let mut counts = vec![0; self.num_classes as usize];
+        for &label in &self.labels {
+            if let Some(count) = counts.get_mut(label as usize) {
+                *count += 1;
+            }
+        }
+        counts
+    }
+
+    /// classの画素を255、それ以外を0にした画像
+    pub fn class_mask(&self, class: u32) -> GrayImage {
+        GrayImage::from_fn(self.width, self.height, |x, y| {
+            Luma([if self.label(x, y) == class { 255 } else { 0 }])
+        })
+    }
+
+    /// クラスをpaletteの色で塗った画像、paletteが足りない場合は繰り返して使う
+    ///
+    /// paletteが空の場合はvoc_paletteを使う
+    pub fn colorize(&self, palette: &[[u8; 3]]) -> RgbaImage {
+        let palette = self.palette_or_default(palette);
+        RgbaImage::from_fn(self.width, self.height, |x, y| {
+            let [r, g, b] = palette[self.label(x, y) as usize % palette.len()];
+            Rgba([r, g, b, 255])
+        })
+    }
+
+    /// 画像にクラスの色をalphaの割合で重ねる、backgroundのクラスは塗らない
+    ///
+    /// paletteが空の場合はvoc_paletteを使う
+    pub fn overlay(
+        &self,
+        image: &mut ImageView,
+        palette: &[[u8; 3]],
+        alpha: f32,
+        background: Option<u32>,
+    ) -> Result<(), SegmentError> {
+        if (image.width, image.height) != (self.width, self.height) {
+            return Err(SegmentError::SizeMismatch(image.width, image.height));
+        }
+        let palette = self.palette_or_default(palette);
+        for (pixel, &label) in image.data.chunks_exact_mut(4).zip(&self.labels) {
+            if Some(label) == background {
+                continue;
+            }
+            let color = palette[label as usize % palette.len()];
+            for (value, &color) in pixel[..3].iter_mut().zip(&color) {
+                *value = (*value as f32 * (1. - alpha) + color as f32 * alpha).round() as u8;
+            }
+        }
+        Ok(())
+    }
+
+    fn palette_or_default<'a>(&self, palette: &'a [[u8; 3]]) -> Cow<'a, [[u8; 3]]> {
+        if palette.is_empty() {
+            Cow::Owned(voc_palette(self.num_classes.max(1)))
+        } else {
+            Cow::Borrowed(palette)
+        }
+    }
+
+    /// classの領域の輪郭、穴はPolygon::holeがtrueになる
+    pub fn polygons(&self, class: u32) -> Vec<Polygon> {
+        trace_polygons(self.width, self.height, |x, y| self.label(x, y) == class)
+    }
+}
+
+/// Pascal VOCのカラーパレット
+pub fn voc_palette(num_classes: u32) -> Vec<[u8; 3]> {
+    (0..num_classes)
+        .map(|class| {
+            let mut color = [0u8; 3];
+            let mut class = class;
+            for shift in (0..8).rev() {
+                for (channel, value) in color.iter_mut().enumerate() {
+                    *value |= (((class >> channel) & 1) as u8) << shift;
+                }
+                class >>= 3;
+            }
+            color
+        })
+        .collect()
+}
+
+/// 画素の境界に沿った多角形
+/// 外周は時計回り、穴は反時計回り(画像座標、yが下向き)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Polygon {
+    pub points: Vec<(u32, u32)>,
+    pub hole: bool,
+}
+
+impl Polygon {
+    /// 面積(画素数)
+    pub fn area(&self) -> f32 {
+        signed_area(&self.points).abs()
+    }
+
+    /// Douglas-Peuckerで頂点を間引く
+    pub fn simplify(&self, epsilon: f32) -> Polygon {
+        if self.points.len() <= 4 {
+            return self.clone();
+        }
+        let first = self.points[0];
+        let far = (1..self.points.len())
+            .max_by(|&a, &b| {
+                distance2(self.points[a], first).total_cmp(&distance2(self.points[b], first))
+            })
+            .unwrap();
+        let mut points = Vec::with_capacity(self.points.len());
+        douglas_peucker(&self.points[..=far], epsilon, &mut points);
+        points.pop();
+        let mut rest = self.points[far..].to_vec();
+        rest.push(first);
+        douglas_peucker(&rest, epsilon, &mut points);
+        points.pop();
+        Polygon {
+            points,
+            hole: self.hole,
+        }
+    }
+}
+
+fn signed_area(points: &[(u32, u32)]) -> f32 {
+    let sum: i64 = points
+        .iter()
+        .zip(points.iter().cycle().skip(1))
+        .map(|(&(x0, y0), &(x1, y1))| x0 as i64 * y1 as i64 - x1 as i64 * y0 as i64)
+        .sum();
+    sum as f32 / 2.
+}
+
+fn distance2(a: (u32, u32), b: (u32, u32)) -> f32 {
+    let (dx, dy) = (a.0 as f32 - b.0 as f32, a.1 as f32 - b.1 as f32);
+    dx * dx + dy * dy
+}
+
+/// 始点と終点を含めて間引いた点をpointsに追加する
+fn douglas_peucker(line: &[(u32, u32)], epsilon: f32, points: &mut Vec<(u32, u32)>) {
+    let (first, last) = (line[0], line[line.len() - 1]);
+    let (dx, dy) = (
+        last.0 as f32 - first.0 as f32,
+        last.1 as f32 - first.1 as f32,
+    );
+    let length = (dx * dx + dy * dy).sqrt();
+    let distance = |p: (u32, u32)| {
+        if length == 0. {
+            distance2(p, first).sqrt()
+        } else {
+            (dy * (p.0 as f32 - first.0 as f32) - dx * (p.1 as f32 - first.1 as f32)).abs() / length
+        }
+    };
+    let far = (1..line.len() - 1).max_by(|&a, &b| distance(line[a]).total_cmp(&distance(line[b])));
+    match far {
+        Some(far) if distance(line[far]) > epsilon => {
+            douglas_peucker(&line[..=far], epsilon, points);
+            points.pop();
+            douglas_peucker(&line[far..], epsilon, points);
+        }
+        _ => {
+            points.push(first);
+            points.push(last);
+        }
+    }
+}
+
+/// insideがtrueの画素の領域の輪郭を画素の境界に沿ってたどる
+/// 斜めにだけ接している画素は別の領域として扱う(4近傍)
+pub fn trace_polygons<F>(width: u32, height: u32, inside: F) -> Vec<Polygon>
+where
+    F: Fn(u32, u32) -> bool,
+{
+    let is_inside = |x: i64, y: i64| {
+        x >= 0 && y >= 0 && x < width as i64 && y < height as i64 && inside(x as u32, y as u32)
+    };
+    // 領域を右手に見る向きの辺
+    let mut edges: Vec<((u32, u32), (u32, u32))> = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            if !inside(x, y) {
+                continue;
+            }
+            let (xi, yi) = (x as i64, y as i64);
+            if !is_inside(xi, yi - 1) {
+                edges.push(((x, y), (x + 1, y)));
+            }
+            if !is_inside(xi + 1, yi) {
+                edges.push(((x + 1, y), (x + 1, y + 1)));
+            }
+            if !is_inside(xi, yi + 1) {
+                edges.push(((x + 1, y + 1), (x, y + 1)));
+            }
+            if !is_inside(xi - 1, yi) {
+                edges.push(((x, y + 1), (x, y)));
+            }
+        }
+    }
+    let mut outgoing: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+    for (idx, edge) in edges.iter().enumerate() {
+        outgoing.entry(edge.0).or_default().push(idx);
+    }
+    let direction = |idx: usize| {
+        let (from, to) = edges[idx];
+        (to.0 as i64 - from.0 as i64, to.1 as i64 - from.1 as i64)
+    };
+
+    let mut used = vec![false; edges.len()];
+    let mut polygons = Vec::new();
+    for start in 0..edges.len() {
+        if used[start] {
+            continue;
+        }
+        let mut points = Vec::new();
+        let mut current = start;
+        loop {
+            used[current] = true;
+            points.push(edges[current].0);
+            let (dx, dy) = direction(current);
+            // 右折、直進、左折の順に選ぶ
+            let next = outgoing[&edges[current].1]
+                .iter()
+                .copied()
+                .min_by_key(|&idx| match direction(idx) {
+                    d if d == (-dy, dx) => 0,
+                    d if d == (dx, dy) => 1,
+                    _ => 2,
+                })
+                .unwrap();
+            if next == start {
+                break;
+            }
+            current = next;
+        }
+        let points = remove_collinear(points);
+        let hole = signed_area(&points) < 0.;
+        polygons.push(Polygon { points, hole });
+    }
+    polygons
+}
+
+fn remove_collinear(points: Vec<(u32, u32)>) -> Vec<(u32, u32)> {
+    let n = points.len();
+    (0..n)
+        .filter(|&i| {
+            let (prev, point, next) = (points[(i + n - 1) % n], points[i], points[(i + 1) % n]);
+            let cross = (point.0 as i64 - prev.0 as i64) * (next.1 as i64 - point.1 as i64)
+                - (point.1 as i64 - prev.1 as i64) * (next.0 as i64 - point.0 as i64);
+            cross != 0
+        })
+        .map(|i| points[i])
+        .collect()
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SegmenterBuilder<P>
+where
+    P: AsRef<Path> + Default + Debug,
+{
+    prototxt: Option<P>,
+    onnx: P,
+    env_id: Option<i32>,
+    num_threads: Option<i32>,
+    input_width: u32,
+    input_height: u32,
+    normalize: Option<Normalize>,
+    preprocess: Option<Preprocess>,
+    decoder: Option<SegmentDecoder>,
+}
+
+impl<P: AsRef<Path> + Default + Debug> SegmenterBuilder<P> {
+    crate::impl_option!(prototxt, P);
+    crate::impl_non_option!(onnx, P);
+    crate::impl_option!(env_id, i32);
+    crate::impl_option!(num_threads, i32);
+    crate::impl_non_option!(input_width, u32);
+    crate::impl_non_option!(input_height, u32);
+    // 既定の前処理(input_width x input_heightへのリサイズ)の正規化、既定値はImageNet
+    crate::impl_option!(normalize, Normalize);
+    // 既定の前処理の代わりに使うパイプライン
+    crate::impl_option!(preprocess, Preprocess);
+    crate::impl_option!(decoder, SegmentDecoder);
+
+    pub fn build(self) -> Result<Segmenter, AiliaError> {
+        let net = Network::ailia_create(
+            self.env_id.unwrap_or(AILIA_ENVIRONMENT_ID_AUTO),
+            self.num_threads
+                .unwrap_or_else(|| AILIA_MULTITHREAD_AUTO.try_into().unwrap()),
+        )?;
+        net.open_model_files(self.prototxt, self.onnx)?;
+        let preprocess = self.preprocess.unwrap_or_else(|| {
+            Preprocess::new()
+                .resize(self.input_width, self.input_height, FilterType::Triangle)
+                .normalize(self.normalize.unwrap_or(Normalize::ImageNet))
+        });
+        Ok(Segmenter {
+            net,
+            preprocess,
+            decoder: self.decoder.unwrap_or_default(),
+        })
+    }
+}
+
+/// Networkの1番目の入力に画像を入れ、1番目の出力をSegmentationMaskにする
+pub struct Segmenter {
+    net: Network,
+    preprocess: Preprocess,
+    decoder: SegmentDecoder,
+}
+
+impl Segmenter {
+    pub fn new(net: Network, preprocess: Preprocess, decoder: SegmentDecoder) -> Self {
+        Self {
+            net,
+            preprocess,
+            decoder,
+        }
+    }
+
+    /// 元画像と同じ解像度のマスクを返す
+    pub fn segment(&self, image: &ImageView) -> Result<SegmentationMask, SegmentError> {
+        let (tensor, transform) = self.preprocess.run(image)?;
+        self.infer(tensor, transform)
+    }
+
+    pub fn segment_rgb(&self, image: &RgbImage) -> Result<SegmentationMask, SegmentError> {
+        let (tensor, transform) = self.preprocess.run_rgb(image)?;
+        self.infer(tensor, transform)
+    }
+
+    fn infer(
+        &self,
+        tensor: ImageTensor,
+        transform: Transform,
+    ) -> Result<SegmentationMask, SegmentError> {
+        let input_idx = self.net.get_input_blob_index_by_index(0)?;
+        tensor.set_input(&self.net, input_idx)?;
+        self.net.update()?;
+        let output_idx = self.net.get_output_blob_index_by_index(0)?;
+        let shape = self.net.get_blob_shape_nd(output_idx)?;
+        let output = self.net.get_output_blob_by_index::<f32>(output_idx)?;
+        Ok(self.decoder.decode(&output, &shape)?.to_source(&transform))
+    }
+}
+
+impl Deref for Segmenter {
+    type Target = Network;
+    fn deref(&self) -> &Self::Target {
+        &self.net
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_logits() {
+        // 3クラス、2x2、CHW
+        let logits = [
+            5., 0., 0., 0., //
+            0., 5., 0., 1., //
+            0., 0., 5., 0.,
+        ];
+        let mask = SegmentDecoder::new()
+            .decode(&logits, &[1, 3, 2, 2])
+            .unwrap();
+        assert_eq!((mask.width, mask.height, mask.num_classes), (2, 2, 3));
+        assert_eq!(mask.labels, vec![0, 1, 2, 1]);
+        assert_eq!(mask.pixel_counts(), vec![1, 2, 1]);
+        let expected = 1. / (1. + 2. * (-5f32).exp());
+        assert!((mask.scores[0] - expected).abs() < 1e-6);
+
+        let hwc = [5., 0., 0., 0., 5., 0., 0., 0., 5., 0., 1., 0.];
+        let mask_hwc = SegmentDecoder::new()
+            .layout(Layout::Hwc)
+            .decode(&hwc, &[1, 2, 2, 3])
+            .unwrap();
+        assert_eq!(mask_hwc, mask);
+
+        assert!(SegmentDecoder::new()
+            .decode(&logits, &[1, 3, 2, 3])
+            .is_err());
+    }
+
+    #[test]
+    fn decode_binary_and_labels() {
+        let mask = SegmentDecoder::new()
+            .decode(&[-2., 2., 0.5, -0.5], &[1, 1, 2, 2])
+            .unwrap();
+        assert_eq!(mask.num_classes, 2);
+        assert_eq!(mask.labels, vec![0, 1, 1, 0]);
+        assert!(mask.scores.iter().all(|&score| score >= 0.5));
+
+        let mask = SegmentDecoder::new()
+            .kind(OutputKind::Labels)
+            .decode(&[0., 3., 1., 1.], &[1, 2, 2])
+            .unwrap();
+        assert_eq!(mask.num_classes, 4);
+        assert_eq!(mask.pixel_counts(), vec![1, 2, 0, 1]);
+    }
+
+    #[test]
+    fn to_source_letterbox() {
+        // 4x2の画像を8x8にletterbox(中央寄せ)、出力は4x4
+        let transform = Transform {
+            scale: (2., 2.),
+            offset: (0., 2.),
+            source_size: (4, 2),
+            size: (8, 8),
+        };
+        let labels = vec![
+            9, 9, 9, 9, //
+            1, 1, 2, 2, //
+            3, 3, 4, 4, //
+            9, 9, 9, 9,
+        ];
+        let mask = SegmentationMask {
+            width: 4,
+            height: 4,
+            num_classes: 10,
+            scores: vec![1.; 16],
+            labels,
+        };
+        let source = mask.to_source(&transform);
+        assert_eq!((source.width, source.height), (4, 2));
+        assert_eq!(source.labels, vec![1, 1, 2, 2, 3, 3, 4, 4]);
+    }
+
+    #[test]
+    fn overlay_and_palette() {
+        let palette = voc_palette(3);
+        assert_eq!(palette, vec![[0, 0, 0], [128, 0, 0], [0, 128, 0]]);
+        let mask = SegmentationMask {
+            width: 2,
+            height: 1,
+            num_classes: 3,
+            labels: vec![0, 1],
+            scores: vec![1.; 2],
+        };
+        let mut image = ImageView {
+            data: vec![100; 8],
+            width: 2,
+            height: 1,
+        };
+        mask.overlay(&mut image, &palette, 0.5, Some(0)).unwrap();
+        assert_eq!(image.data, vec![100, 100, 100, 100, 114, 50, 50, 100]);
+        assert_eq!(
+            mask.colorize(&palette).get_pixel(1, 0),
+            &Rgba([128, 0, 0, 255])
+        );
+
+        // 空のpaletteではVOCの色を使う
+        assert_eq!(mask.colorize(&[]).get_pixel(1, 0), &Rgba([128, 0, 0, 255]));
+        mask.overlay(&mut image, &[], 1., None).unwrap();
+        assert_eq!(image.data, vec![0, 0, 0, 100, 128, 0, 0, 100]);
+    }
+
+    #[test]
+    fn polygons() {
+        // 外周5x5の中に1x1の穴、右下に斜めに接する1画素
+        let mask = SegmentationMask {
+            width: 7,
+            height: 7,
+            num_classes: 2,
+            labels: (0..49)
+                .map(|i| {
+                    let (x, y) = (i % 7, i / 7);
+                    let ring = x < 5 && y < 5 && (x, y) != (2, 2);
+                    (ring || (x, y) == (5, 5)) as u32
+                })
+                .collect(),
+            scores: vec![1.; 49],
+        };
+        let mut polygons = mask.polygons(1);
+        polygons.sort_by(|a, b| b.area().total_cmp(&a.area()));
+        assert_eq!(polygons.len(), 3);
+        assert_eq!(polygons[0].points, vec![(0, 0), (5, 0), (5, 5), (0, 5)]);
+        assert!(!polygons[0].hole);
+        assert_eq!(polygons[0].area(), 25.);
+        assert!(polygons
+            .iter()
+            .any(|polygon| polygon.hole && polygon.area() == 1.));
+        assert!(polygons
+            .iter()
+            .any(|polygon| !polygon.hole && polygon.points[0] == (5, 5)));
+
+        let staircase = Polygon {
+            points: vec![(0, 0), (10, 0), (10, 10), (5, 10), (5, 9), (0, 9)],
+            hole: false,
+        };
+        assert_eq!(
+            staircase.simplify(1.5).points,
+            vec![(0, 0), (10, 0), (10, 10), (0, 9)]
+        );
+    }
+}
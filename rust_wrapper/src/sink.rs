@@ -0,0 +1,296 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::Delay;
+
+use opencv::core::{Mat, Size};
+use opencv::imgproc::{cvt_color, COLOR_RGBA2BGR};
+use opencv::prelude::*;
+use opencv::videoio::VideoWriter;
+
+use thiserror::Error;
+
+use crate::video::{ImageView, VideoError};
+
+#[derive(Debug, Error)]
+pub enum SinkError {
+    #[error("出力を開けませんでした: {0}")]
+    NotOpened(String),
+    #[error("フレームのサイズが最初のフレームと異なります")]
+    SizeMismatch,
+    #[error(transparent)]
+    OpenCv(#[from] opencv::Error),
+    #[error(transparent)]
+    Image(#[from] image::ImageError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Video(#[from] VideoError),
+}
+
+/// 描画済みのフレームの出力先
+pub trait FrameSink {
+    fn write_frame(&mut self, image: &ImageView) -> Result<(), SinkError>;
+
+    /// 出力を閉じる、dropでも閉じられるがエラーを受け取りたい場合に呼ぶ
+    fn finish(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+/// opencvのVideoWriterによる動画ファイルの出力
+/// 書き込みは最初のフレームのサイズで開始する
+pub struct VideoFileSink {
+    path: PathBuf,
+    fps: f64,
+    fourcc: [char; 4],
+    writer: Option<(VideoWriter, Size)>,
+}
+
+impl VideoFileSink {
+    /// 拡張子が`.avi`の場合はMJPG、それ以外はmp4vでエンコードする
+    pub fn new<P: AsRef<Path>>(path: P, fps: f64) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let is_avi = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("avi"));
+        let fourcc = if is_avi {
+            ['M', 'J', 'P', 'G']
+        } else {
+            ['m', 'p', '4', 'v']
+        };
+        Self {
+            path,
+            fps,
+            fourcc,
+            writer: None,
+        }
+    }
+
+    crate::impl_non_option!(fourcc, [char; 4]);
+}
+
+impl FrameSink for VideoFileSink {
+    fn write_frame(&mut self, image: &ImageView) -> Result<(), SinkError> {
+        let rgba = image.to_mat()?;
+        let size = rgba.size()?;
+        if self.writer.is_none() {
+            let [c1, c2, c3, c4] = self.fourcc;
+            let name = self.path.to_string_lossy().to_string();
+            let writer = VideoWriter::new(
+                &name,
+                VideoWriter::fourcc(c1, c2, c3, c4)?,
+                self.fps,
+                size,
+                true,
+            )?;
+            if !writer.is_opened()? {
+                return Err(SinkError::NotOpened(name));
+            }
+            self.writer = Some((writer, size));
+        }
+        let (writer, writer_size) = self.writer.as_mut().unwrap();
+        if *writer_size != size {
+            return Err(SinkError::SizeMismatch);
+        }
+        let mut bgr = Mat::default();
+        cvt_color(&rgba, &mut bgr, COLOR_RGBA2BGR, 0)?;
+        writer.write(&bgr)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), SinkError> {
+        if let Some((mut writer, _)) = self.writer.take() {
+            writer.release()?;
+        }
+        Ok(())
+    }
+}
+
+/// 連番の画像ファイルの出力
+/// `out/frame.png`を指定した場合は`out/frame_000000.png`, `out/frame_000001.png`...に書き込む
+pub struct ImageSequenceSink {
+    dir: PathBuf,
+    stem: String,
+    extension: String,
+    index: u64,
+}
+
+impl ImageSequenceSink {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, SinkError> {
+        let path = path.as_ref();
+        let dir = path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("."));
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            stem: path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or("frame")
+                .to_string(),
+            extension: path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .unwrap_or("png")
+                .to_string(),
+            index: 0,
+        })
+    }
+
+    /// ディレクトリに`000000.png`の形式で書き込む
+    pub fn in_dir<P: AsRef<Path>>(dir: P) -> Result<Self, SinkError> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+            stem: String::new(),
+            extension: "png".to_string(),
+            index: 0,
+        })
+    }
+
+    pub fn frame_path(&self, index: u64) -> PathBuf {
+        let name = if self.stem.is_empty() {
+            format!("{:06}.{}", index, self.extension)
+        } else {
+            format!("{}_{:06}.{}", self.stem, index, self.extension)
+        };
+        self.dir.join(name)
+    }
+}
+
+impl FrameSink for ImageSequenceSink {
+    fn write_frame(&mut self, image: &ImageView) -> Result<(), SinkError> {
+        let path = self.frame_path(self.index);
+        let rgba = image.to_rgba_image()?;
+        if matches!(self.extension.to_ascii_lowercase().as_str(), "jpg" | "jpeg") {
+            // JPEGはアルファチャンネルを扱えない
+            image::DynamicImage::ImageRgba8(rgba).to_rgb8().save(path)?;
+        } else {
+            rgba.save(path)?;
+        }
+        self.index += 1;
+        Ok(())
+    }
+}
+
+/// アニメーションGIFの出力
+pub struct GifSink {
+    encoder: Option<GifEncoder<BufWriter<File>>>,
+    delay: Delay,
+}
+
+impl GifSink {
+    pub fn new<P: AsRef<Path>>(path: P, fps: f64) -> Result<Self, SinkError> {
+        if !fps.is_finite() || fps <= 0. {
+            return Err(VideoError::InvalidFps(fps).into());
+        }
+        let mut encoder = GifEncoder::new(BufWriter::new(File::create(path)?));
+        encoder.set_repeat(Repeat::Infinite)?;
+        Ok(Self {
+            encoder: Some(encoder),
+            delay: Delay::from_numer_denom_ms((1000. / fps).round() as u32, 1),
+        })
+    }
+}
+
+impl FrameSink for GifSink {
+    fn write_frame(&mut self, image: &ImageView) -> Result<(), SinkError> {
+        let encoder = self
+            .encoder
+            .as_mut()
+            .ok_or_else(|| SinkError::NotOpened("gif".to_string()))?;
+        let frame = image::Frame::from_parts(image.to_rgba_image()?, 0, 0, self.delay);
+        encoder.encode_frame(frame)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), SinkError> {
+        // GifEncoderはdrop時に終端を書き込む
+        self.encoder.take();
+        Ok(())
+    }
+}
+
+/// 出力先の文字列から適切なFrameSinkを開く
+/// `.gif`はアニメーションGIF、`.png`/`.jpg`/`.jpeg`は連番画像、拡張子なしはディレクトリへの連番画像、
+/// それ以外は動画ファイルとして扱う
+pub fn open_sink(output: &str, fps: f64) -> Result<Box<dyn FrameSink>, SinkError> {
+    let path = Path::new(output);
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("gif") => Ok(Box::new(GifSink::new(path, fps)?)),
+        Some("png") | Some("jpg") | Some("jpeg") => Ok(Box::new(ImageSequenceSink::new(path)?)),
+        None => Ok(Box::new(ImageSequenceSink::in_dir(path)?)),
+        Some(_) => Ok(Box::new(VideoFileSink::new(path, fps))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use image::RgbaImage;
+
+    fn frames() -> Vec<ImageView> {
+        (0..3)
+            .map(|idx| RgbaImage::from_pixel(4, 4, image::Rgba([idx * 80, 0, 0, 255])).into())
+            .collect()
+    }
+
+    #[test]
+    fn image_sequence() {
+        let dir = std::env::temp_dir().join(format!("ailia_sink_sequence_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut sink = open_sink(dir.join("frame.jpg").to_str().unwrap(), 30.).unwrap();
+        for frame in frames() {
+            sink.write_frame(&frame).unwrap();
+        }
+        sink.finish().unwrap();
+        assert!(dir.join("frame_000002.jpg").is_file());
+        assert!(!dir.join("frame_000003.jpg").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn gif() {
+        let dir = std::env::temp_dir().join(format!("ailia_sink_gif_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.gif");
+        let mut sink = open_sink(path.to_str().unwrap(), 10.).unwrap();
+        for frame in frames() {
+            sink.write_frame(&frame).unwrap();
+        }
+        sink.finish().unwrap();
+
+        let mut source = crate::video::AnimationSource::open(&path).unwrap();
+        let mut count = 0;
+        while crate::video::FrameSource::read_frame(&mut source)
+            .unwrap()
+            .is_some()
+        {
+            count += 1;
+        }
+        assert_eq!(count, 3);
+
+        // 不正なfpsではファイルを作らずにエラーにする
+        let invalid = dir.join("invalid.gif");
+        for fps in [0., -1., f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                GifSink::new(&invalid, fps),
+                Err(SinkError::Video(VideoError::InvalidFps(_)))
+            ));
+        }
+        assert!(!invalid.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
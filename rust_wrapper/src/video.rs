@@ -0,0 +1,529 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::imageops::{resize, FilterType};
+use image::{AnimationDecoder, Frames, RgbaImage};
+
+use opencv::core::{Mat, Scalar, CV_8UC4};
+use opencv::imgproc::{cvt_color, COLOR_BGR2RGBA};
+use opencv::prelude::*;
+use opencv::videoio::{self, VideoCapture};
+
+use thiserror::Error;
+
+use crate::format::ImageFormat;
+
+const IMAGE_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "png", "bmp", "tif", "tiff"];
+/// カメラの起動直後などに空のフレームを読み飛ばす最大回数
+const MAX_EMPTY_FRAMES: u32 = 100;
+
+#[derive(Debug, Error)]
+pub enum VideoError {
+    #[error("入力を開けませんでした: {0}")]
+    NotOpened(String),
+    #[error("画像サイズが不正です")]
+    InvalidSize,
+    #[error("fpsが不正です: {0}")]
+    InvalidFps(f64),
+    #[error("空のフレームが続いています")]
+    EmptyFrames,
+    #[error(transparent)]
+    OpenCv(#[from] opencv::Error),
+    #[error(transparent)]
+    Image(#[from] image::ImageError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// RGBAの画像バッファ
+/// `Detector::predict`などへはas_ptr, stride, width, height, formatをそのまま渡せる
+#[derive(Clone, Debug)]
+pub struct ImageView {
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ImageView {
+    pub fn stride(&self) -> u32 {
+        self.width * 4
+    }
+
+    /// dataの長さがwidth x height x 4と一致するか
+    pub fn is_valid(&self) -> bool {
+        (self.width as usize)
+            .checked_mul(self.height as usize)
+            .and_then(|len| len.checked_mul(4))
+            == Some(self.data.len())
+    }
+
+    pub fn format(&self) -> ImageFormat {
+        ImageFormat::Rgba
+    }
+
+    pub fn as_ptr(&self) -> *const u8 {
+        self.data.as_ptr()
+    }
+
+    pub fn resize(&self, width: u32, height: u32) -> Result<ImageView, VideoError> {
+        Ok(ImageView::from(resize(
+            &self.to_rgba_image()?,
+            width,
+            height,
+            FilterType::Triangle,
+        )))
+    }
+
+    pub fn to_rgba_image(&self) -> Result<RgbaImage, VideoError> {
+        RgbaImage::from_raw(self.width, self.height, self.data.clone())
+            .filter(|_| self.is_valid())
+            .ok_or(VideoError::InvalidSize)
+    }
+
+    /// RGBA(CV_8UC4)のMatに変換する
+    pub fn to_mat(&self) -> Result<Mat, VideoError> {
+        if !self.is_valid() {
+            return Err(VideoError::InvalidSize);
+        }
+        let mut mat = Mat::new_rows_cols_with_default(
+            self.height
+                .try_into()
+                .map_err(|_| VideoError::InvalidSize)?,
+            self.width.try_into().map_err(|_| VideoError::InvalidSize)?,
+            CV_8UC4,
+            Scalar::all(0.),
+        )?;
+        mat.data_bytes_mut()?.copy_from_slice(&self.data);
+        Ok(mat)
+    }
+
+    /// opencvで読み込んだBGRのMatから作成する
+    pub fn from_bgr_mat(mat: &Mat) -> Result<Self, VideoError> {
+        let mut rgba = Mat::default();
+        cvt_color(mat, &mut rgba, COLOR_BGR2RGBA, 0)?;
+        Self::from_rgba_mat(&rgba)
+    }
+
+    /// RGBA(CV_8UC4)のMatから作成する、`render`で描画した後に使う
+    pub fn from_rgba_mat(rgba: &Mat) -> Result<Self, VideoError> {
+        let size = rgba.size()?;
+        Ok(Self {
+            data: rgba.data_bytes()?.to_vec(),
+            width: size.width.try_into().map_err(|_| VideoError::InvalidSize)?,
+            height: size
+                .height
+                .try_into()
+                .map_err(|_| VideoError::InvalidSize)?,
+        })
+    }
+}
+
+impl From<RgbaImage> for ImageView {
+    fn from(value: RgbaImage) -> Self {
+        Self {
+            width: value.width(),
+            height: value.height(),
+            data: value.into_raw(),
+        }
+    }
+}
+
+/// 入力のフレーム
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub image: ImageView,
+    /// 入力の先頭からの時刻
+    pub timestamp: Duration,
+    /// 入力の先頭からのフレーム番号(スキップしたフレームも数える)
+    pub index: u64,
+}
+
+/// フレームを順に読み出す入力
+pub trait FrameSource {
+    /// 入力の終端に達した場合はNoneを返す
+    fn read_frame(&mut self) -> Result<Option<(ImageView, Duration)>, VideoError>;
+
+    /// フレームをデコードせずに読み飛ばす、終端に達した場合はfalseを返す
+    fn skip_frame(&mut self) -> Result<bool, VideoError> {
+        Ok(self.read_frame()?.is_some())
+    }
+}
+
+/// opencvのVideoCaptureによる入力(動画ファイル、カメラ、RTSPなどのURL)
+pub struct CaptureSource {
+    cap: VideoCapture,
+    // カメラやストリームの場合は経過時間をタイムスタンプとする
+    started: Option<Instant>,
+}
+
+impl CaptureSource {
+    pub fn from_camera(index: i32) -> Result<Self, VideoError> {
+        let cap = VideoCapture::new(index, videoio::CAP_ANY)?;
+        Self::new(cap, Some(Instant::now()), format!("camera {}", index))
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, VideoError> {
+        let path = path.as_ref().to_string_lossy().to_string();
+        let cap = VideoCapture::from_file(&path, videoio::CAP_ANY)?;
+        Self::new(cap, None, path)
+    }
+
+    pub fn from_url(url: &str) -> Result<Self, VideoError> {
+        let cap = VideoCapture::from_file(url, videoio::CAP_ANY)?;
+        Self::new(cap, Some(Instant::now()), url.to_string())
+    }
+
+    fn new(cap: VideoCapture, started: Option<Instant>, name: String) -> Result<Self, VideoError> {
+        if !cap.is_opened()? {
+            return Err(VideoError::NotOpened(name));
+        }
+        Ok(Self { cap, started })
+    }
+
+    pub fn fps(&self) -> Result<f64, VideoError> {
+        Ok(self.cap.get(videoio::CAP_PROP_FPS)?)
+    }
+}
+
+impl FrameSource for CaptureSource {
+    fn read_frame(&mut self) -> Result<Option<(ImageView, Duration)>, VideoError> {
+        let mut mat = Mat::default();
+        let mut empty_frames = 0;
+        loop {
+            if !self.cap.read(&mut mat)? {
+                return Ok(None);
+            }
+            if !mat.empty() {
+                break;
+            }
+            // カメラの起動直後などは空のフレームが返ることがある
+            empty_frames += 1;
+            if empty_frames >= MAX_EMPTY_FRAMES {
+                return Err(VideoError::EmptyFrames);
+            }
+        }
+        let timestamp = match self.started {
+            Some(started) => started.elapsed(),
+            None => {
+                Duration::from_secs_f64(self.cap.get(videoio::CAP_PROP_POS_MSEC)?.max(0.) / 1000.)
+            }
+        };
+        Ok(Some((ImageView::from_bgr_mat(&mat)?, timestamp)))
+    }
+
+    fn skip_frame(&mut self) -> Result<bool, VideoError> {
+        Ok(self.cap.grab()?)
+    }
+}
+
+/// ディレクトリ内の画像をファイル名順に読み込む入力
+pub struct ImageDirSource {
+    paths: Vec<PathBuf>,
+    pos: usize,
+    frame_interval: Duration,
+}
+
+impl ImageDirSource {
+    /// fpsはタイムスタンプの計算に使用する
+    pub fn new<P: AsRef<Path>>(dir: P, fps: f64) -> Result<Self, VideoError> {
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if is_image_file(&path) {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        Self::from_paths(paths, fps)
+    }
+
+    /// fpsは正の有限値
+    pub fn from_paths(paths: Vec<PathBuf>, fps: f64) -> Result<Self, VideoError> {
+        let frame_interval = Duration::try_from_secs_f64(1. / fps)
+            .ok()
+            .filter(|_| fps.is_finite() && fps > 0.)
+            .ok_or(VideoError::InvalidFps(fps))?;
+        Ok(Self {
+            paths,
+            pos: 0,
+            frame_interval,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+}
+
+impl FrameSource for ImageDirSource {
+    fn read_frame(&mut self) -> Result<Option<(ImageView, Duration)>, VideoError> {
+        let Some(path) = self.paths.get(self.pos) else {
+            return Ok(None);
+        };
+        let image = image::open(path)?.into_rgba8();
+        let timestamp = self.frame_interval * self.pos as u32;
+        self.pos += 1;
+        Ok(Some((image.into(), timestamp)))
+    }
+
+    fn skip_frame(&mut self) -> Result<bool, VideoError> {
+        if self.pos < self.paths.len() {
+            self.pos += 1;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+}
+
+fn is_image_file(path: &Path) -> bool {
+    extension(path).is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// GIF/APNGのアニメーション画像による入力
+pub struct AnimationSource {
+    frames: Frames<'static>,
+    elapsed: Duration,
+}
+
+impl AnimationSource {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, VideoError> {
+        let path = path.as_ref();
+        let reader = BufReader::new(File::open(path)?);
+        let frames = if extension(path).as_deref() == Some("gif") {
+            GifDecoder::new(reader)?.into_frames()
+        } else {
+            PngDecoder::new(reader)?.apng().into_frames()
+        };
+        Ok(Self {
+            frames,
+            elapsed: Duration::ZERO,
+        })
+    }
+}
+
+impl FrameSource for AnimationSource {
+    fn read_frame(&mut self) -> Result<Option<(ImageView, Duration)>, VideoError> {
+        let frame = match self.frames.next() {
+            Some(frame) => frame?,
+            None => return Ok(None),
+        };
+        let timestamp = self.elapsed;
+        self.elapsed += Duration::from(frame.delay());
+        Ok(Some((frame.into_buffer().into(), timestamp)))
+    }
+}
+
+/// 入力の文字列から適切なFrameSourceを開く
+/// 数字はカメラ番号、`://`を含む場合はURL、ディレクトリは画像の連番、
+/// `.gif`/`.apng`とアニメーションPNGはアニメーション画像、その他の画像は1枚のみの入力、
+/// それ以外は動画ファイルとして扱う
+pub fn open_source(input: &str, fps: f64) -> Result<Box<dyn FrameSource>, VideoError> {
+    if let Ok(index) = input.parse::<i32>() {
+        return Ok(Box::new(CaptureSource::from_camera(index)?));
+    }
+    if input.contains("://") {
+        return Ok(Box::new(CaptureSource::from_url(input)?));
+    }
+    let path = Path::new(input);
+    if path.is_dir() {
+        return Ok(Box::new(ImageDirSource::new(path, fps)?));
+    }
+    match extension(path).as_deref() {
+        Some("gif") | Some("apng") => Ok(Box::new(AnimationSource::open(path)?)),
+        Some("png") if PngDecoder::new(BufReader::new(File::open(path)?))?.is_apng() => {
+            Ok(Box::new(AnimationSource::open(path)?))
+        }
+        _ if is_image_file(path) => Ok(Box::new(ImageDirSource::from_paths(
+            vec![path.to_path_buf()],
+            fps,
+        )?)),
+        _ => Ok(Box::new(CaptureSource::from_file(path)?)),
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FrameReaderBuilder {
+    input: String,
+    step: Option<u32>,
+    size: Option<(u32, u32)>,
+    fps: Option<f64>,
+    max_frames: Option<u64>,
+}
+
+impl FrameReaderBuilder {
+    crate::impl_non_option!(input, String);
+    crate::impl_option!(step, u32);
+    crate::impl_option!(size, (u32, u32));
+    crate::impl_option!(fps, f64);
+    crate::impl_option!(max_frames, u64);
+
+    pub fn build(self) -> Result<FrameReader, VideoError> {
+        let source = open_source(&self.input, self.fps.unwrap_or(30.))?;
+        Ok(FrameReader::new(
+            source,
+            self.step.unwrap_or(1),
+            self.size,
+            self.max_frames,
+        ))
+    }
+}
+
+/// FrameSourceからフレームのスキップとリサイズを行いながら読み出す
+pub struct FrameReader {
+    source: Box<dyn FrameSource>,
+    step: u32,
+    size: Option<(u32, u32)>,
+    max_frames: Option<u64>,
+    index: u64,
+    num_read: u64,
+}
+
+impl FrameReader {
+    /// stepフレームごとに1フレームを読み出す(1の場合は全フレーム)
+    pub fn new(
+        source: Box<dyn FrameSource>,
+        step: u32,
+        size: Option<(u32, u32)>,
+        max_frames: Option<u64>,
+    ) -> Self {
+        Self {
+            source,
+            step: step.max(1),
+            size,
+            max_frames,
+            index: 0,
+            num_read: 0,
+        }
+    }
+
+    pub fn read(&mut self) -> Result<Option<Frame>, VideoError> {
+        if self.max_frames.is_some_and(|max| self.num_read >= max) {
+            return Ok(None);
+        }
+        let Some((image, timestamp)) = self.source.read_frame()? else {
+            return Ok(None);
+        };
+        let index = self.index;
+        self.index += 1;
+        for _ in 1..self.step {
+            if !self.source.skip_frame()? {
+                break;
+            }
+            self.index += 1;
+        }
+        self.num_read += 1;
+
+        let image = match self.size {
+            Some((width, height)) if (width, height) != (image.width, image.height) => {
+                image.resize(width, height)?
+            }
+            _ => image,
+        };
+        Ok(Some(Frame {
+            image,
+            timestamp,
+            index,
+        }))
+    }
+}
+
+impl Iterator for FrameReader {
+    type Item = Result<Frame, VideoError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read().transpose()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Counter(u32);
+
+    impl FrameSource for Counter {
+        fn read_frame(&mut self) -> Result<Option<(ImageView, Duration)>, VideoError> {
+            if self.0 == 10 {
+                return Ok(None);
+            }
+            let timestamp = Duration::from_millis(self.0 as u64 * 100);
+            self.0 += 1;
+            Ok(Some((RgbaImage::new(4, 2).into(), timestamp)))
+        }
+    }
+
+    #[test]
+    fn step_and_resize() {
+        let reader = FrameReader::new(Box::new(Counter(0)), 3, Some((2, 1)), None);
+        let frames: Vec<Frame> = reader.map(|frame| frame.unwrap()).collect();
+        let indexes: Vec<u64> = frames.iter().map(|frame| frame.index).collect();
+        assert_eq!(indexes, vec![0, 3, 6, 9]);
+        assert_eq!(frames[1].timestamp, Duration::from_millis(300));
+        assert_eq!((frames[0].image.width, frames[0].image.height), (2, 1));
+        assert_eq!(frames[0].image.data.len(), 2 * 4);
+    }
+
+    #[test]
+    fn max_frames() {
+        let reader = FrameReader::new(Box::new(Counter(0)), 1, None, Some(4));
+        assert_eq!(reader.count(), 4);
+    }
+
+    #[test]
+    fn invalid_buffer() {
+        let image = ImageView {
+            data: vec![0; 7],
+            width: 2,
+            height: 1,
+        };
+        assert!(!image.is_valid());
+        assert!(matches!(
+            image.to_rgba_image(),
+            Err(VideoError::InvalidSize)
+        ));
+        assert!(matches!(image.to_mat(), Err(VideoError::InvalidSize)));
+        assert!(matches!(image.resize(1, 1), Err(VideoError::InvalidSize)));
+    }
+
+    #[test]
+    fn image_dir() {
+        let dir =
+            std::env::temp_dir().join(format!("ailia_video_image_dir_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for idx in 0..3 {
+            RgbaImage::new(3, 3)
+                .save(dir.join(format!("{:04}.png", idx)))
+                .unwrap();
+        }
+        std::fs::write(dir.join("labels.txt"), "").unwrap();
+        let mut source = ImageDirSource::new(&dir, 10.).unwrap();
+        assert_eq!(source.len(), 3);
+        for fps in [0., -1., f64::NAN] {
+            assert!(matches!(
+                ImageDirSource::new(&dir, fps),
+                Err(VideoError::InvalidFps(_))
+            ));
+        }
+        source.read_frame().unwrap();
+        let (image, timestamp) = source.read_frame().unwrap().unwrap();
+        assert_eq!(timestamp, Duration::from_millis(100));
+        assert_eq!(image.width, 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
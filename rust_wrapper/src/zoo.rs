@@ -0,0 +1,439 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::classifier::{Classifier, ClassifierBuilder};
+use crate::detector::{Detector, DetectorAlgorithm, DetectorBuilder};
+use crate::format::{ChannelOrder, ImageRange, NetworkImageFormat};
+use crate::network::{Network, Shape};
+use crate::pose_estimator::{PoseAlgorithm, PoseEstimator, PoseEstimatorBuilder};
+use crate::AiliaError;
+
+use ailia_sys::*;
+
+pub const REMOTE_PATH: &str = "https://storage.googleapis.com/ailia-models/";
+
+/// モデルごとのタスク設定
+#[derive(Clone, Copy, Debug)]
+pub enum TaskConfig {
+    Classifier {
+        format: NetworkImageFormat,
+        channel: ChannelOrder,
+        range: ImageRange,
+        category_count: u32,
+    },
+    Detector {
+        algorithm: DetectorAlgorithm,
+        category_count: u32,
+        input_width: u32,
+        input_height: u32,
+    },
+    PoseEstimator {
+        algorithm: PoseAlgorithm,
+        input_width: u32,
+        input_height: u32,
+    },
+    Network,
+}
+
+/// ailia-modelsのバケット上のモデルの情報
+/// sha256がNoneの場合、Downloader::allow_unverifiedを指定しない限り取得に失敗する
+#[derive(Clone, Copy, Debug)]
+pub struct ModelDescriptor {
+    pub name: &'static str,
+    pub url_dir: &'static str,
+    pub onnx: &'static str,
+    pub prototxt: Option<&'static str>,
+    pub onnx_sha256: Option<&'static str>,
+    pub prototxt_sha256: Option<&'static str>,
+    pub task: TaskConfig,
+}
+
+pub static MODELS: &[ModelDescriptor] = &[
+    ModelDescriptor {
+        name: "yolox_s",
+        url_dir: "yolox",
+        onnx: "yolox_s.opt.onnx",
+        prototxt: Some("yolox_s.opt.onnx.prototxt"),
+        onnx_sha256: None,
+        prototxt_sha256: None,
+        task: TaskConfig::Detector {
+            algorithm: DetectorAlgorithm::Yolox,
+            category_count: 80,
+            input_width: 640,
+            input_height: 640,
+        },
+    },
+    ModelDescriptor {
+        name: "yolox_tiny",
+        url_dir: "yolox",
+        onnx: "yolox_tiny.opt.onnx",
+        prototxt: Some("yolox_tiny.opt.onnx.prototxt"),
+        onnx_sha256: None,
+        prototxt_sha256: None,
+        task: TaskConfig::Detector {
+            algorithm: DetectorAlgorithm::Yolox,
+            category_count: 80,
+            input_width: 416,
+            input_height: 416,
+        },
+    },
+    ModelDescriptor {
+        name: "yolox_nano",
+        url_dir: "yolox",
+        onnx: "yolox_nano.opt.onnx",
+        prototxt: Some("yolox_nano.opt.onnx.prototxt"),
+        onnx_sha256: None,
+        prototxt_sha256: None,
+        task: TaskConfig::Detector {
+            algorithm: DetectorAlgorithm::Yolox,
+            category_count: 80,
+            input_width: 416,
+            input_height: 416,
+        },
+    },
+    ModelDescriptor {
+        name: "resnet18",
+        url_dir: "resnet18",
+        onnx: "resnet18.onnx",
+        prototxt: Some("resnet18.onnx.prototxt"),
+        onnx_sha256: None,
+        prototxt_sha256: None,
+        task: TaskConfig::Classifier {
+            format: NetworkImageFormat::Bgr,
+            channel: ChannelOrder::First,
+            range: ImageRange::ImageNet,
+            category_count: 1000,
+        },
+    },
+    ModelDescriptor {
+        name: "lightweight-human-pose-estimation",
+        url_dir: "lightweight-human-pose-estimation",
+        onnx: "lightweight-human-pose-estimation.onnx",
+        prototxt: Some("lightweight-human-pose-estimation.onnx.prototxt"),
+        onnx_sha256: None,
+        prototxt_sha256: None,
+        task: TaskConfig::PoseEstimator {
+            algorithm: PoseAlgorithm::LwHumanPose,
+            input_width: 320,
+            input_height: 240,
+        },
+    },
+    ModelDescriptor {
+        name: "detic",
+        url_dir: "detic",
+        onnx: "Detic_C2_SwinB_896_4x_IN-21K+COCO_lvis.onnx",
+        prototxt: Some("Detic_C2_SwinB_896_4x_IN-21K+COCO_lvis.onnx.prototxt"),
+        onnx_sha256: None,
+        prototxt_sha256: None,
+        task: TaskConfig::Network,
+    },
+];
+
+/// 登録されているモデルを名前で探す
+pub fn find_model(name: &str) -> Option<&'static ModelDescriptor> {
+    MODELS.iter().find(|desc| desc.name == name)
+}
+
+#[derive(Debug, Error)]
+pub enum ZooError {
+    #[error("登録されていないモデルです: {0}")]
+    UnknownModel(String),
+    #[error("モデルのタスクが異なります: {0}")]
+    TaskMismatch(String),
+    #[error("オフラインモードのためダウンロードできません: {0}")]
+    Offline(PathBuf),
+    #[error("ダウンロードに失敗しました: {0}")]
+    Http(String),
+    #[error("チェックサムが登録されていません: {0}")]
+    MissingChecksum(String),
+    #[error("チェックサムが一致しません: {path} (expected {expected}, actual {actual})")]
+    ChecksumMismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Ailia(#[from] AiliaError),
+}
+
+/// ダウンロード済みのモデルファイルのパス
+#[derive(Clone, Debug)]
+pub struct ModelFiles {
+    pub onnx: PathBuf,
+    pub prototxt: Option<PathBuf>,
+}
+
+/// モデルのダウンローダー
+/// キャッシュディレクトリは`AILIA_MODELS_DIR`、未設定の場合はOSのキャッシュディレクトリ以下の`ailia-models`
+/// `AILIA_MODELS_OFFLINE`が設定されている場合はオフラインモードになる
+/// `AILIA_MODELS_ALLOW_UNVERIFIED`が設定されている場合はsha256が未登録のファイルも使う
+#[derive(Clone, Debug)]
+pub struct Downloader {
+    cache_dir: PathBuf,
+    base_url: String,
+    offline: bool,
+    allow_unverified: bool,
+}
+
+impl Default for Downloader {
+    fn default() -> Self {
+        let cache_dir = std::env::var_os("AILIA_MODELS_DIR")
+            .map(PathBuf::from)
+            .or_else(|| dirs::cache_dir().map(|dir| dir.join("ailia-models")))
+            .unwrap_or_else(|| PathBuf::from("models"));
+        Self {
+            cache_dir,
+            base_url: REMOTE_PATH.to_string(),
+            offline: std::env::var_os("AILIA_MODELS_OFFLINE").is_some(),
+            allow_unverified: std::env::var_os("AILIA_MODELS_ALLOW_UNVERIFIED").is_some(),
+        }
+    }
+}
+
+impl Downloader {
+    crate::impl_non_option!(cache_dir, PathBuf);
+    crate::impl_non_option!(base_url, String);
+    crate::impl_non_option!(offline, bool);
+    // sha256が未登録のファイルを検証せずに使う
+    crate::impl_non_option!(allow_unverified, bool);
+
+    pub fn cache_path(&self, desc: &ModelDescriptor, file_name: &str) -> PathBuf {
+        self.cache_dir.join(desc.url_dir).join(file_name)
+    }
+
+    /// モデルファイルがキャッシュになければダウンロードする
+    pub fn fetch(&self, desc: &ModelDescriptor) -> Result<ModelFiles, ZooError> {
+        let onnx = self.fetch_file(desc, desc.onnx, desc.onnx_sha256)?;
+        let prototxt = match desc.prototxt {
+            Some(file_name) => Some(self.fetch_file(desc, file_name, desc.prototxt_sha256)?),
+            None => None,
+        };
+        Ok(ModelFiles { onnx, prototxt })
+    }
+
+    pub fn fetch_by_name(
+        &self,
+        name: &str,
+    ) -> Result<(&'static ModelDescriptor, ModelFiles), ZooError> {
+        let desc = find_model(name).ok_or_else(|| ZooError::UnknownModel(name.to_string()))?;
+        Ok((desc, self.fetch(desc)?))
+    }
+
+    fn fetch_file(
+        &self,
+        desc: &ModelDescriptor,
+        file_name: &str,
+        sha256: Option<&str>,
+    ) -> Result<PathBuf, ZooError> {
+        if sha256.is_none() && !self.allow_unverified {
+            return Err(ZooError::MissingChecksum(format!(
+                "{}/{}",
+                desc.url_dir, file_name
+            )));
+        }
+        let path = self.cache_path(desc, file_name);
+        if path.is_file() {
+            match verify_checksum(&path, sha256) {
+                Ok(()) => return Ok(path),
+                Err(err) if self.offline => return Err(err),
+                Err(_) => fs::remove_file(&path)?,
+            }
+        }
+        if self.offline {
+            return Err(ZooError::Offline(path));
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let url = format!(
+            "{}/{}/{}",
+            self.base_url.trim_end_matches('/'),
+            desc.url_dir,
+            file_name
+        );
+        let part_path = path.with_file_name(format!("{}.part", file_name));
+        download(&url, &part_path)?;
+        if let Err(err) = verify_checksum(&part_path, sha256) {
+            fs::remove_file(&part_path)?;
+            return Err(err);
+        }
+        fs::rename(&part_path, &path)?;
+        Ok(path)
+    }
+}
+
+/// `.part`ファイルが存在する場合はRangeヘッダで続きからダウンロードする
+fn download(url: &str, part_path: &Path) -> Result<(), ZooError> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(Duration::from_secs(30))
+        .build();
+    let offset = fs::metadata(part_path).map(|meta| meta.len()).unwrap_or(0);
+    let mut request = agent.get(url);
+    if offset > 0 {
+        request = request.set("Range", &format!("bytes={}-", offset));
+    }
+    let response = match request.call() {
+        Ok(response) => response,
+        // 既に全て受信済み
+        Err(ureq::Error::Status(416, _)) if offset > 0 => return Ok(()),
+        Err(ureq::Error::Status(code, _)) => {
+            return Err(ZooError::Http(format!("{} (status {})", url, code)))
+        }
+        Err(err) => return Err(ZooError::Http(format!("{} ({})", url, err))),
+    };
+
+    let mut file = if response.status() == 206 {
+        OpenOptions::new().append(true).open(part_path)?
+    } else {
+        File::create(part_path)?
+    };
+    io::copy(&mut response.into_reader(), &mut file)?;
+    file.flush()?;
+    Ok(())
+}
+
+fn verify_checksum(path: &Path, expected: Option<&str>) -> Result<(), ZooError> {
+    let expected = match expected {
+        Some(expected) => expected,
+        None => return Ok(()),
+    };
+    let actual = sha256_file(path)?;
+    if actual.eq_ignore_ascii_case(expected) {
+        Ok(())
+    } else {
+        Err(ZooError::ChecksumMismatch {
+            path: path.to_path_buf(),
+            expected: expected.to_string(),
+            actual,
+        })
+    }
+}
+
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> Result<String, io::Error> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 16];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+impl Network {
+    pub fn from_zoo(name: &str) -> Result<Self, ZooError> {
+        Self::from_zoo_with(name, &Downloader::default())
+    }
+
+    pub fn from_zoo_with(name: &str, downloader: &Downloader) -> Result<Self, ZooError> {
+        let (_, files) = downloader.fetch_by_name(name)?;
+        let net = Network::ailia_create(
+            AILIA_ENVIRONMENT_ID_AUTO,
+            AILIA_MULTITHREAD_AUTO.try_into().unwrap(),
+        )?;
+        net.open_model_files(files.prototxt, files.onnx)?;
+        Ok(net)
+    }
+}
+
+impl Classifier {
+    pub fn from_zoo(name: &str) -> Result<Self, ZooError> {
+        Self::from_zoo_with(name, &Downloader::default())
+    }
+
+    pub fn from_zoo_with(name: &str, downloader: &Downloader) -> Result<Self, ZooError> {
+        let (desc, files) = downloader.fetch_by_name(name)?;
+        let TaskConfig::Classifier {
+            format,
+            channel,
+            range,
+            ..
+        } = desc.task
+        else {
+            return Err(ZooError::TaskMismatch(name.to_string()));
+        };
+        let mut builder = ClassifierBuilder::default()
+            .onnx(files.onnx)
+            .format(format)
+            .channel(channel)
+            .range(range);
+        if let Some(prototxt) = files.prototxt {
+            builder = builder.prototxt(prototxt);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+impl Detector {
+    pub fn from_zoo(name: &str) -> Result<Self, ZooError> {
+        Self::from_zoo_with(name, &Downloader::default())
+    }
+
+    pub fn from_zoo_with(name: &str, downloader: &Downloader) -> Result<Self, ZooError> {
+        let (desc, files) = downloader.fetch_by_name(name)?;
+        let TaskConfig::Detector {
+            algorithm,
+            category_count,
+            input_width,
+            input_height,
+        } = desc.task
+        else {
+            return Err(ZooError::TaskMismatch(name.to_string()));
+        };
+        let mut builder = DetectorBuilder::default()
+            .onnx(files.onnx)
+            .algorithm(algorithm)
+            .category_count(category_count);
+        if let Some(prototxt) = files.prototxt {
+            builder = builder.prototxt(prototxt);
+        }
+        let detector = builder.build()?;
+        detector.set_input_shape(input_width, input_height)?;
+        Ok(detector)
+    }
+}
+
+impl<O> PoseEstimator<O> {
+    pub fn from_zoo(name: &str) -> Result<Self, ZooError> {
+        Self::from_zoo_with(name, &Downloader::default())
+    }
+
+    pub fn from_zoo_with(name: &str, downloader: &Downloader) -> Result<Self, ZooError> {
+        let (desc, files) = downloader.fetch_by_name(name)?;
+        let TaskConfig::PoseEstimator {
+            algorithm,
+            input_width,
+            input_height,
+        } = desc.task
+        else {
+            return Err(ZooError::TaskMismatch(name.to_string()));
+        };
+        let mut builder = PoseEstimatorBuilder::default()
+            .onnx(files.onnx)
+            .algorithm(algorithm);
+        if let Some(prototxt) = files.prototxt {
+            builder = builder.prototxt(prototxt);
+        }
+        let estimator = builder.build()?;
+        let shape = Shape {
+            x: input_width,
+            y: input_height,
+            z: 3,
+            w: 1,
+            dim: 4,
+        };
+        estimator.set_input_shape(shape)?;
+        Ok(estimator)
+    }
+}
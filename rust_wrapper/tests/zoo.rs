@@ -0,0 +1,167 @@
+#![cfg(feature = "zoo")]
+
+use std::path::PathBuf;
+use std::thread;
+
+use ailia::zoo::{Downloader, ModelDescriptor, TaskConfig, ZooError, MODELS};
+
+const ONNX_BODY: &[u8] = b"dummy onnx weight for zoo downloader test";
+const ONNX_SHA256: &str = "f2e5b06f65564c02f9a945be439f652a05f1882bc019f26deb27494772fb8c1d";
+
+/// fixtureを返すHTTPサーバ、Rangeヘッダにも対応する
+fn serve(num_requests: usize) -> String {
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let addr = server.server_addr().to_ip().unwrap();
+    thread::spawn(move || {
+        for request in server.incoming_requests().take(num_requests) {
+            if !request.url().ends_with("/fixture/model.onnx") {
+                request.respond(tiny_http::Response::empty(404)).unwrap();
+                continue;
+            }
+            let range = request
+                .headers()
+                .iter()
+                .find(|h| h.field.equiv("Range"))
+                .map(|h| h.value.as_str().to_string());
+            let response = match range.and_then(|r| {
+                r.strip_prefix("bytes=")
+                    .and_then(|r| r.strip_suffix('-'))
+                    .and_then(|r| r.parse::<usize>().ok())
+            }) {
+                Some(offset) => {
+                    tiny_http::Response::from_data(&ONNX_BODY[offset..]).with_status_code(206)
+                }
+                None => tiny_http::Response::from_data(ONNX_BODY),
+            };
+            request.respond(response).unwrap();
+        }
+    });
+    format!("http://{}", addr)
+}
+
+fn descriptor(sha256: Option<&'static str>) -> ModelDescriptor {
+    ModelDescriptor {
+        name: "fixture",
+        url_dir: "fixture",
+        onnx: "model.onnx",
+        prototxt: None,
+        onnx_sha256: sha256,
+        prototxt_sha256: None,
+        task: TaskConfig::Network,
+    }
+}
+
+fn cache_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ailia_zoo_test_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn download_and_cache() {
+    let dir = cache_dir("download");
+    let downloader = Downloader::default()
+        .cache_dir(dir.clone())
+        .base_url(serve(1))
+        .offline(false);
+    let desc = descriptor(Some(ONNX_SHA256));
+    let files = downloader.fetch(&desc).unwrap();
+    assert_eq!(files.onnx, dir.join("fixture").join("model.onnx"));
+    assert_eq!(std::fs::read(&files.onnx).unwrap(), ONNX_BODY);
+
+    // キャッシュ済みのためオフラインでも取得できる
+    let files = downloader.offline(true).fetch(&desc).unwrap();
+    assert_eq!(std::fs::read(files.onnx).unwrap(), ONNX_BODY);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn resume_partial_download() {
+    let dir = cache_dir("resume");
+    std::fs::create_dir_all(dir.join("fixture")).unwrap();
+    std::fs::write(
+        dir.join("fixture").join("model.onnx.part"),
+        &ONNX_BODY[..10],
+    )
+    .unwrap();
+    let downloader = Downloader::default()
+        .cache_dir(dir.clone())
+        .base_url(serve(1))
+        .offline(false);
+    let files = downloader.fetch(&descriptor(Some(ONNX_SHA256))).unwrap();
+    assert_eq!(std::fs::read(files.onnx).unwrap(), ONNX_BODY);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn checksum_mismatch() {
+    let dir = cache_dir("mismatch");
+    let downloader = Downloader::default()
+        .cache_dir(dir.clone())
+        .base_url(serve(1))
+        .offline(false);
+    let desc = descriptor(Some(
+        "0000000000000000000000000000000000000000000000000000000000000000",
+    ));
+    match downloader.fetch(&desc) {
+        Err(ZooError::ChecksumMismatch { .. }) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+    assert!(!dir.join("fixture").join("model.onnx").exists());
+    assert!(!dir.join("fixture").join("model.onnx.part").exists());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn offline_without_cache() {
+    let downloader = Downloader::default()
+        .cache_dir(cache_dir("offline"))
+        .offline(true);
+    match downloader.fetch(&descriptor(Some(ONNX_SHA256))) {
+        Err(ZooError::Offline(_)) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+}
+
+#[test]
+fn missing_checksum() {
+    let dir = cache_dir("unverified");
+    let downloader = Downloader::default()
+        .cache_dir(dir.clone())
+        .base_url(serve(1))
+        .offline(false)
+        .allow_unverified(false);
+    match downloader.fetch(&descriptor(None)) {
+        Err(ZooError::MissingChecksum(_)) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+    assert!(!dir.join("fixture").exists());
+
+    // 明示的に許可した場合は検証せずに使う
+    let files = downloader
+        .allow_unverified(true)
+        .fetch(&descriptor(None))
+        .unwrap();
+    assert_eq!(std::fs::read(files.onnx).unwrap(), ONNX_BODY);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+#[ignore = "sha256 of the published zoo files has not been recorded yet"]
+fn registered_checksums() {
+    // 既定ではチェックサムのないファイルは使えないため、登録済みのモデルは全て必要
+    let is_sha256 = |hash: &str| hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit());
+    for desc in MODELS {
+        assert!(
+            desc.onnx_sha256.is_some_and(is_sha256),
+            "{}: onnx_sha256",
+            desc.name
+        );
+        assert_eq!(
+            desc.prototxt_sha256.is_some_and(is_sha256),
+            desc.prototxt.is_some(),
+            "{}: prototxt_sha256",
+            desc.name
+        );
+    }
+}
@@ -1,10 +1,10 @@
 use ailia::prelude::*;
+use ailia::video::FrameReaderBuilder;
 
 use opencv::core::{Mat, Point, Rect, Scalar};
 use opencv::highgui;
-use opencv::imgproc::{cvt_color, put_text, rectangle, COLOR_BGR2RGBA, COLOR_RGBA2BGR};
+use opencv::imgproc::{cvt_color, put_text, rectangle, COLOR_RGBA2BGR};
 use opencv::prelude::*;
-use opencv::videoio::{self, VideoCaptureTrait, VideoCaptureTraitConst};
 
 use anyhow::Result;
 
@@ -130,45 +130,38 @@ fn main() -> Result<()> {
     let detector = DetectorBuilder::default()
         .prototxt("./../models/yolox_s.opt.onnx.prototxt")
         .onnx("./../models/yolox_s.opt.onnx")
-        .algorithm(AILIA_DETECTOR_ALGORITHM_YOLOX)
+        .algorithm(DetectorAlgorithm::Yolox)
         .category_count(COCO_CATEGORY.len().try_into()?)
         .build()?;
     detector.set_input_shape(640, 640)?;
 
+    // 引数がない場合はカメラ0番、動画ファイル、画像ディレクトリ、RTSPのURLなども指定できる
+    let input = std::env::args().nth(1).unwrap_or_else(|| "0".to_string());
+    let reader = FrameReaderBuilder::default().input(input).build()?;
+
     let window = "YOLOX infered by ailia SDK";
     highgui::named_window(window, highgui::WINDOW_AUTOSIZE)?;
-    let mut cam = videoio::VideoCapture::new(0, videoio::CAP_ANY)?; // 0 is the default camera
-    let opened = videoio::VideoCapture::is_opened(&cam)?;
-    if !opened {
-        panic!("Unable to open default camera!");
-    }
-
-    loop {
-        let mut frame = Mat::default();
-        cam.read(&mut frame)?;
-        if frame.size()?.width > 0 {
-            let frame_clone = frame.clone();
 
-            cvt_color(&frame_clone, &mut frame, COLOR_BGR2RGBA, 0)?;
+    for frame in reader {
+        let mut frame = frame?.image.to_mat()?;
 
-            let size = frame.size()?;
+        let size = frame.size()?;
 
-            let objs = detector.predict_opencv_mat(&frame, 0.45, 0.4)?;
+        let objs = detector.predict_opencv_mat(&frame, 0.45, 0.4)?;
 
-            for obj in objs {
-                plot_image(
-                    &mut frame,
-                    &obj,
-                    size.width.try_into()?,
-                    size.height.try_into()?,
-                );
-            }
+        for obj in objs {
+            plot_image(
+                &mut frame,
+                &obj,
+                size.width.try_into()?,
+                size.height.try_into()?,
+            );
+        }
 
-            let frame_clone = frame.clone();
-            cvt_color(&frame_clone, &mut frame, COLOR_RGBA2BGR, 0)?;
+        let frame_clone = frame.clone();
+        cvt_color(&frame_clone, &mut frame, COLOR_RGBA2BGR, 0)?;
 
-            highgui::imshow(window, &frame)?;
-        }
+        highgui::imshow(window, &frame)?;
         let key = highgui::wait_key(10)?;
         if key > 0 && key != 255 {
             break;